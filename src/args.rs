@@ -1,7 +1,19 @@
+use crate::{
+    cpu::Cpu,
+    graph::Graph,
+    machine::{Engine, CLOCK_HZ},
+    patch::Edit,
+    video::Adapter,
+};
+
 #[derive(PartialEq)]
 pub enum AppMode {
     Disassemble,
     Execute,
+    Boot,
+    Rom,
+    Assemble,
+    Patch,
 }
 
 pub struct ArgsConfig {
@@ -10,37 +22,204 @@ pub struct ArgsConfig {
     pub argv: Vec<String>,
     pub envs: Vec<String>,
     pub debug: bool,
+    pub watch: Vec<usize>,
+    // COM port index and backend spec
+    pub serial: Vec<(usize, String)>,
+    // Text adapter shown on the terminal
+    pub video: Option<Adapter>,
+    pub screen_dump: Option<String>,
+    // WAV file for the PC speaker
+    pub speaker: Option<String>,
+    // Scripted keystrokes instead of stdin
+    pub keys: Option<String>,
+    pub cpu: Cpu,
+    // Report the CPU clocks used at exit
+    pub clocks: bool,
+    // CPU clock in Hz to hold execution to, unthrottled if None
+    pub speed: Option<u64>,
+    pub engine: Engine,
+    // Run once on each engine and report instructions per second
+    pub bench: bool,
+    // Disassemble by following control flow instead of a linear sweep
+    pub recursive: bool,
+    // Graph printed in DOT instead of the disassembly
+    pub graph: Option<Graph>,
+    // Executable written by asm and patch
+    pub output: String,
+    // Changes patch makes, in order
+    pub edits: Vec<Edit>,
 }
 
 pub fn parse_args() -> Result<ArgsConfig, String> {
     let mut args: Vec<String> = std::env::args().collect();
     args.remove(0);
 
-    let mut debug = true;
+    let mut debug = false;
+    let mut mode = AppMode::Execute;
+    let mut watch = Vec::new();
+    let mut serial = Vec::new();
+    let mut video = None;
+    let mut screen_dump = None;
+    let mut speaker = None;
+    let mut keys = None;
+    let mut cpu = Cpu::default();
+    let mut clocks = false;
+    let mut speed = None;
+    let mut engine = Engine::default();
+    let mut bench = false;
+    let mut recursive = false;
+    let mut graph = None;
+    let mut output = None;
+    let mut edits = Vec::new();
 
-    let mode = match args.get(0).map(|s| s.as_str()) {
-        Some("-d") => {
-            args.remove(0);
-            AppMode::Disassemble
-        }
-        Some("-m") => {
-            args.remove(0);
-            AppMode::Execute
-        }
-        _ => {
-            debug = false;
-            AppMode::Execute
+    match args.first().map(|arg| arg.as_str()) {
+        Some("asm") => mode = AppMode::Assemble,
+        Some("patch") => mode = AppMode::Patch,
+        _ => {}
+    }
+    if matches!(mode, AppMode::Assemble | AppMode::Patch) {
+        args.remove(0);
+    }
+
+    while let Some(arg) = args.first() {
+        match arg.as_str() {
+            "-d" => {
+                mode = AppMode::Disassemble;
+                debug = true;
+            }
+            "-m" => {
+                debug = true;
+            }
+            "--recursive" => {
+                recursive = true;
+            }
+            "--dot" => {
+                mode = AppMode::Disassemble;
+                args.remove(0);
+                let name = args.first().ok_or("--dot needs cfg or calls")?;
+                graph = Some(Graph::from_name(name).ok_or(format!("Unknown graph: {}", name))?);
+            }
+            "--boot" => {
+                mode = AppMode::Boot;
+            }
+            "--bios" => {
+                mode = AppMode::Rom;
+            }
+            "--watch" => {
+                args.remove(0);
+                let addr = args.first().ok_or("--watch needs an address")?;
+                let digits = addr.trim_start_matches("0x");
+                let addr = usize::from_str_radix(digits, 16)
+                    .map_err(|_| format!("Invalid watch address: {}", addr))?;
+                watch.push(addr);
+            }
+            "--com1" | "--com2" => {
+                let com = if arg == "--com1" { 0 } else { 1 };
+                args.remove(0);
+                let spec = args.first().ok_or("--com1/--com2 need a backend")?;
+                serial.push((com, spec.clone()));
+            }
+            "--video" => {
+                args.remove(0);
+                video = match args.first().map(|s| s.as_str()) {
+                    Some("cga") => Some(Adapter::Cga),
+                    Some("mda") => Some(Adapter::Mda),
+                    _ => return Err("--video needs cga or mda".to_string()),
+                };
+            }
+            "--screen-dump" => {
+                args.remove(0);
+                let path = args.first().ok_or("--screen-dump needs a file")?;
+                screen_dump = Some(path.clone());
+            }
+            "--speaker" => {
+                args.remove(0);
+                let path = args.first().ok_or("--speaker needs a file")?;
+                speaker = Some(path.clone());
+            }
+            "--keys" => {
+                args.remove(0);
+                let path = args.first().ok_or("--keys needs a file")?;
+                keys = Some(path.clone());
+            }
+            "--cpu" => {
+                args.remove(0);
+                let name = args.first().ok_or("--cpu needs a model")?;
+                cpu = Cpu::from_name(name).ok_or(format!("Unknown CPU: {}", name))?;
+            }
+            "--clocks" => {
+                clocks = true;
+            }
+            "--speed" => {
+                args.remove(0);
+                let mhz = args.first().ok_or("--speed needs a clock in MHz")?;
+                speed = Some(match mhz.as_str() {
+                    "4.77" => CLOCK_HZ,
+                    _ => match mhz.parse::<f64>() {
+                        Ok(mhz) if mhz > 0.0 => (mhz * 1_000_000.0) as u64,
+                        _ => return Err(format!("Invalid speed: {}", mhz)),
+                    },
+                });
+            }
+            "--engine" => {
+                args.remove(0);
+                let name = args
+                    .first()
+                    .ok_or("--engine needs interpreter or threaded")?;
+                engine = Engine::from_name(name).ok_or(format!("Unknown engine: {}", name))?;
+            }
+            "--bench" => {
+                bench = true;
+            }
+            "-o" if matches!(mode, AppMode::Assemble | AppMode::Patch) => {
+                args.remove(0);
+                let path = args.first().ok_or("-o needs a file")?;
+                output = Some(path.clone());
+            }
+            _ => break,
         }
-    };
+        args.remove(0);
+    }
 
-    let target = match args.get(0) {
+    let target = match args.first() {
         Some(t) => t.clone(),
         None => return Err("No target specified.".to_string()),
     };
 
+    match mode {
+        AppMode::Assemble => match &args[1..] {
+            [] => {}
+            [flag, path] if flag == "-o" => output = Some(path.clone()),
+            _ => return Err("asm takes one source file".to_string()),
+        },
+        AppMode::Patch => {
+            let mut rest = args[1..].iter();
+            while let Some(arg) = rest.next() {
+                let mut value = || rest.next().cloned().ok_or(format!("{} needs a value", arg));
+                match arg.as_str() {
+                    "-o" => output = Some(value()?),
+                    "--text" => edits.push(Edit::Text(value()?, value()?)),
+                    "--data" => edits.push(Edit::Data(value()?, value()?)),
+                    _ => return Err(format!("Unknown patch option: {}", arg)),
+                }
+            }
+            if edits.is_empty() {
+                return Err("patch needs --text or --data".to_string());
+            }
+        }
+        // --bench runs the program twice, and a booted disk image keeps the
+        // writes of the first run
+        AppMode::Boot if bench => return Err("--bench cannot be used with --boot".to_string()),
+        _ => {}
+    }
+    let output = match mode {
+        // A patched executable goes next to the original
+        AppMode::Patch => output.unwrap_or(format!("{}.patched", target)),
+        _ => output.unwrap_or("a.out".to_string()),
+    };
+
     let argv = args.iter().map(|s| s.to_string()).collect();
-    let mut envs = Vec::new();
-    envs.push("PATH=/usr:/usr/bin".to_string());
+    let envs = vec!["PATH=/usr:/usr/bin".to_string()];
 
     Ok(ArgsConfig {
         mode,
@@ -48,5 +227,20 @@ pub fn parse_args() -> Result<ArgsConfig, String> {
         argv,
         envs,
         debug,
+        watch,
+        serial,
+        video,
+        screen_dump,
+        speaker,
+        keys,
+        cpu,
+        clocks,
+        speed,
+        engine,
+        bench,
+        recursive,
+        graph,
+        output,
+        edits,
     })
 }
@@ -0,0 +1,1444 @@
+use std::collections::{HashMap, HashSet};
+
+use crate::{
+    cpu::Cpu,
+    metadata::{Metadata, Symbol, C_STAT, N_BSS, N_DATA, N_TEXT},
+    opcode::{self, Form},
+    operation::{Operation, OperationType},
+};
+
+// Data, bss, heap and stack share one 64 KiB segment
+const TOTAL: usize = 0x10000;
+
+// Labels move while jumps and displacements find their sizes; this many
+// passes is far more than any program needs
+const MAX_PASSES: usize = 32;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Section {
+    Text,
+    Data,
+    Bss,
+}
+
+#[derive(Debug, Clone)]
+enum Expr {
+    Num(i32),
+    // A symbol if the source defines one by this name, a hexadecimal number otherwise
+    Name(String),
+    // The address of the current statement
+    Here,
+    Neg(Box<Expr>),
+    Binary(Box<Expr>, char, Box<Expr>),
+}
+
+#[derive(Debug, Clone)]
+enum Operand {
+    Reg { reg: u8, w: u8 },
+    Seg(u8),
+    // The rm field of the base and index registers, None for a direct address
+    Mem { rm: Option<u8>, disp: Expr },
+    Imm(Expr),
+    // Segment and offset
+    Far(Expr, Expr),
+}
+
+// An operand with the size and distance written before it
+#[derive(Debug, Clone)]
+struct Arg {
+    operand: Operand,
+    w: Option<u8>,
+    short: bool,
+    far: bool,
+}
+
+#[derive(Debug, Clone)]
+enum Datum {
+    Expr(Expr),
+    Bytes(Vec<u8>),
+}
+
+#[derive(Debug, Clone)]
+enum Kind {
+    Label(String),
+    Equ(String, Expr),
+    Section(Section),
+    // Bytes (w = 0) or words (w = 1)
+    Data(u8, Vec<Datum>),
+    Space(Expr),
+    Align(Expr),
+    Instruction {
+        prefixes: Vec<u8>,
+        mnemonic: Option<String>,
+        args: Vec<Arg>,
+    },
+}
+
+struct Statement {
+    line: usize,
+    kind: Kind,
+}
+
+// Assembles source in the syntax the disassembler prints into a MINIX a.out
+// with separate instruction and data spaces. Labels go into its symbol table.
+pub fn assemble(source: &str, cpu: Cpu) -> Result<Vec<u8>, String> {
+    let output = assemble_output(source, 0, &[], cpu)?;
+    let symbols: Vec<u8> = output.labels.iter().flat_map(|s| s.to_bytes()).collect();
+    let metadata = Metadata::new(
+        output.text.len(),
+        output.data.len(),
+        output.bss_size,
+        TOTAL,
+        symbols.len(),
+    );
+    if output.data.len() + output.bss_size > TOTAL {
+        return Err("Data and bss do not fit in 64 KiB".to_string());
+    }
+    let mut executable = metadata.to_bytes();
+    executable.extend_from_slice(&output.text);
+    executable.extend_from_slice(&output.data);
+    executable.extend_from_slice(&symbols);
+    Ok(executable)
+}
+
+// Assembles code that goes at an offset in the text of an executable and may
+// refer to the symbols of that executable
+pub fn assemble_at(
+    source: &str,
+    origin: usize,
+    symbols: &[Symbol],
+    cpu: Cpu,
+) -> Result<Vec<u8>, String> {
+    let output = assemble_output(source, origin, symbols, cpu)?;
+    if !output.data.is_empty() || output.bss_size != 0 {
+        return Err("Only instructions can be placed in the text".to_string());
+    }
+    Ok(output.text)
+}
+
+fn assemble_output(
+    source: &str,
+    origin: usize,
+    known: &[Symbol],
+    cpu: Cpu,
+) -> Result<Output, String> {
+    let mut statements = Vec::new();
+    for (i, line) in source.lines().enumerate() {
+        parse_line(line, &mut statements).map_err(|e| format!("line {}: {}", i + 1, e))?;
+        for statement in statements.iter_mut().filter(|s| s.line == 0) {
+            statement.line = i + 1;
+        }
+    }
+    let mut defined: HashSet<String> = statements
+        .iter()
+        .filter_map(|s| match &s.kind {
+            Kind::Label(name) | Kind::Equ(name, _) => Some(name.clone()),
+            _ => None,
+        })
+        .collect();
+    let mut symbols = HashMap::new();
+    for symbol in known {
+        // The values of bss symbols already count the data
+        let section = match symbol.section() {
+            N_TEXT => Section::Text,
+            _ => Section::Data,
+        };
+        defined.insert(symbol.name.clone());
+        symbols.insert(symbol.name.clone(), (section, symbol.value as i32));
+    }
+    let mut assembler = Assembler {
+        cpu,
+        origin: origin as i32,
+        defined,
+        symbols,
+        data_size: 0,
+        long: HashSet::new(),
+        final_pass: false,
+    };
+    let mut passes = 0;
+    loop {
+        let symbols = assembler.symbols.clone();
+        let (data_size, long) = (assembler.data_size, assembler.long.len());
+        assembler.pass(&statements)?;
+        if assembler.symbols == symbols
+            && assembler.data_size == data_size
+            && assembler.long.len() == long
+        {
+            break;
+        }
+        passes += 1;
+        if passes == MAX_PASSES {
+            return Err("Labels do not settle".to_string());
+        }
+    }
+    assembler.final_pass = true;
+    assembler.pass(&statements)
+}
+
+// --- Parsing ---
+
+fn parse_line(line: &str, statements: &mut Vec<Statement>) -> Result<(), String> {
+    let mut rest = strip_comment(line).trim();
+    let mut prefixes = Vec::new();
+    // Labels and segment override prefixes
+    while let Some((name, after)) = rest.split_once(':') {
+        let name = name.trim();
+        if !is_identifier(name) {
+            break;
+        }
+        match segment_register(name) {
+            Some(seg) => prefixes.push(0x26 | seg << 3),
+            None => statements.push(Statement {
+                line: 0,
+                kind: Kind::Label(name.to_string()),
+            }),
+        }
+        rest = after.trim();
+    }
+    if rest.is_empty() {
+        if !prefixes.is_empty() {
+            statements.push(Statement {
+                line: 0,
+                kind: Kind::Instruction {
+                    prefixes,
+                    mnemonic: None,
+                    args: Vec::new(),
+                },
+            });
+        }
+        return Ok(());
+    }
+    let (word, operands) = match rest.split_once(char::is_whitespace) {
+        Some((word, operands)) => (word, operands.trim()),
+        None => (rest, ""),
+    };
+    let kind = match word.to_ascii_lowercase().as_str() {
+        ".text" => Kind::Section(Section::Text),
+        ".data" => Kind::Section(Section::Data),
+        ".bss" => Kind::Section(Section::Bss),
+        ".byte" | "db" => Kind::Data(0, parse_data(operands)?),
+        ".word" | "dw" => Kind::Data(1, parse_data(operands)?),
+        ".ascii" | ".asciz" => {
+            let mut bytes = parse_string(operands)?;
+            if word.eq_ignore_ascii_case(".asciz") {
+                bytes.push(0);
+            }
+            Kind::Data(0, vec![Datum::Bytes(bytes)])
+        }
+        ".space" => Kind::Space(parse_expr(operands)?),
+        ".align" => Kind::Align(parse_expr(operands)?),
+        ".equ" => {
+            let (name, value) = operands
+                .split_once(',')
+                .ok_or(".equ needs a name and a value")?;
+            let name = name.trim();
+            if !is_identifier(name) {
+                return Err(format!("Invalid symbol name: {}", name));
+            }
+            Kind::Equ(name.to_string(), parse_expr(value)?)
+        }
+        directive if directive.starts_with('.') => {
+            return Err(format!("Unknown directive: {}", word));
+        }
+        _ => {
+            let mut mnemonic = word.to_ascii_uppercase();
+            let mut operands = operands;
+            // REP, REPNZ and LOCK may share the line with the instruction they prefix
+            while let Some(prefix) = prefix_byte(&mnemonic) {
+                prefixes.push(prefix);
+                let (word, after) = match operands.split_once(char::is_whitespace) {
+                    Some((word, after)) => (word, after.trim()),
+                    None => (operands, ""),
+                };
+                mnemonic = word.to_ascii_uppercase();
+                operands = after;
+            }
+            let args = if operands.is_empty() {
+                Vec::new()
+            } else {
+                split_top_level(operands, ',')
+                    .iter()
+                    .map(|operand| parse_arg(operand))
+                    .collect::<Result<_, _>>()?
+            };
+            Kind::Instruction {
+                prefixes,
+                mnemonic: (!mnemonic.is_empty()).then_some(mnemonic),
+                args,
+            }
+        }
+    };
+    statements.push(Statement { line: 0, kind });
+    Ok(())
+}
+
+fn strip_comment(line: &str) -> &str {
+    let mut quoted = None;
+    for (i, c) in line.char_indices() {
+        match (quoted, c) {
+            (None, ';') => return &line[..i],
+            (None, '"' | '\'') => quoted = Some(c),
+            (Some(q), _) if c == q => quoted = None,
+            _ => {}
+        }
+    }
+    line
+}
+
+fn is_identifier(name: &str) -> bool {
+    let mut chars = name.chars();
+    matches!(chars.next(), Some(c) if c.is_ascii_alphabetic() || c == '_' || c == '.')
+        && chars.all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '.')
+}
+
+// Splits at a separator outside brackets, parentheses and quotes
+fn split_top_level(text: &str, separator: char) -> Vec<&str> {
+    let mut parts = Vec::new();
+    let mut depth = 0;
+    let mut quoted = None;
+    let mut start = 0;
+    for (i, c) in text.char_indices() {
+        match (quoted, c) {
+            (Some(q), _) if c == q => quoted = None,
+            (Some(_), _) => {}
+            (None, '"' | '\'') => quoted = Some(c),
+            (None, '[' | '(') => depth += 1,
+            (None, ']' | ')') => depth -= 1,
+            (None, _) if c == separator && depth == 0 => {
+                parts.push(text[start..i].trim());
+                start = i + 1;
+            }
+            _ => {}
+        }
+    }
+    parts.push(text[start..].trim());
+    parts
+}
+
+fn register(name: &str) -> Option<(u8, u8)> {
+    let names = [
+        "AL", "CL", "DL", "BL", "AH", "CH", "DH", "BH", "AX", "CX", "DX", "BX", "SP", "BP", "SI",
+        "DI",
+    ];
+    let i = names.iter().position(|r| r.eq_ignore_ascii_case(name))? as u8;
+    Some((i & 0b111, i >> 3))
+}
+
+fn segment_register(name: &str) -> Option<u8> {
+    let names = ["ES", "CS", "SS", "DS"];
+    names
+        .iter()
+        .position(|r| r.eq_ignore_ascii_case(name))
+        .map(|i| i as u8)
+}
+
+fn prefix_byte(mnemonic: &str) -> Option<u8> {
+    match mnemonic {
+        "REP" | "REPE" | "REPZ" => Some(0xf3),
+        "REPNE" | "REPNZ" => Some(0xf2),
+        "LOCK" => Some(0xf0),
+        _ => None,
+    }
+}
+
+fn parse_arg(text: &str) -> Result<Arg, String> {
+    let mut arg_text = text.trim();
+    let (mut w, mut short, mut far) = (None, false, false);
+    while let Some((word, after)) = arg_text.split_once(char::is_whitespace) {
+        match word.to_ascii_lowercase().as_str() {
+            "byte" => w = Some(0),
+            "word" => w = Some(1),
+            "short" => short = true,
+            "far" => far = true,
+            _ => break,
+        }
+        arg_text = after.trim();
+    }
+    let operand = if let Some(inner) = arg_text.strip_prefix('[') {
+        let inner = inner.strip_suffix(']').ok_or("Missing ]")?;
+        parse_memory(inner)?
+    } else if let Some((reg, reg_w)) = register(arg_text) {
+        if w.is_some_and(|w| w != reg_w) {
+            return Err(format!("Size does not match {}", arg_text));
+        }
+        Operand::Reg { reg, w: reg_w }
+    } else if let Some(seg) = segment_register(arg_text) {
+        Operand::Seg(seg)
+    } else if let [seg, offset] = split_top_level(arg_text, ':')[..] {
+        Operand::Far(parse_expr(seg)?, parse_expr(offset)?)
+    } else {
+        Operand::Imm(parse_expr(arg_text)?)
+    };
+    Ok(Arg {
+        operand,
+        w,
+        short,
+        far,
+    })
+}
+
+// [BX+SI+disp], [BP-2], [label] and the like
+fn parse_memory(text: &str) -> Result<Operand, String> {
+    let (mut base, mut index) = (None, None);
+    let mut disp = String::new();
+    let mut start = 0;
+    let text = text.trim();
+    let mut terms = Vec::new();
+    for (i, c) in text.char_indices() {
+        if (c == '+' || c == '-') && i > 0 {
+            terms.push(&text[start..i]);
+            start = i;
+        }
+    }
+    terms.push(&text[start..]);
+    for term in terms {
+        let (sign, name) = match term.strip_prefix('+') {
+            Some(name) => ("+", name.trim()),
+            None => match term.strip_prefix('-') {
+                Some(name) => ("-", name.trim()),
+                None => ("+", term.trim()),
+            },
+        };
+        match register(name).map(|(reg, w)| (reg | w << 3, sign)) {
+            Some((0b1011 | 0b1101, "+")) if base.is_none() => base = register(name),
+            Some((0b1110 | 0b1111, "+")) if index.is_none() => index = register(name),
+            Some(_) => return Err(format!("Invalid address: [{}]", text)),
+            None => {
+                disp.push_str(sign);
+                disp.push_str(name);
+            }
+        }
+    }
+    let rm = match (base.map(|(r, _)| r), index.map(|(r, _)| r)) {
+        // BX, BP with SI, DI
+        (Some(0b011), Some(0b110)) => Some(0b000),
+        (Some(0b011), Some(0b111)) => Some(0b001),
+        (Some(0b101), Some(0b110)) => Some(0b010),
+        (Some(0b101), Some(0b111)) => Some(0b011),
+        (None, Some(0b110)) => Some(0b100),
+        (None, Some(0b111)) => Some(0b101),
+        (Some(0b101), None) => Some(0b110),
+        (Some(0b011), None) => Some(0b111),
+        _ => None,
+    };
+    let disp = if disp.is_empty() {
+        Expr::Num(0)
+    } else {
+        parse_expr(&disp)?
+    };
+    Ok(Operand::Mem { rm, disp })
+}
+
+fn parse_data(text: &str) -> Result<Vec<Datum>, String> {
+    split_top_level(text, ',')
+        .iter()
+        .map(|item| {
+            if item.starts_with('"') {
+                parse_string(item).map(Datum::Bytes)
+            } else {
+                parse_expr(item).map(Datum::Expr)
+            }
+        })
+        .collect()
+}
+
+fn parse_string(text: &str) -> Result<Vec<u8>, String> {
+    let inner = text
+        .trim()
+        .strip_prefix('"')
+        .and_then(|t| t.strip_suffix('"'))
+        .ok_or_else(|| format!("Invalid string: {}", text))?;
+    let mut bytes = Vec::new();
+    let mut chars = inner.chars();
+    while let Some(c) = chars.next() {
+        let c = match c {
+            '\\' => match chars.next() {
+                Some('n') => '\n',
+                Some('t') => '\t',
+                Some('r') => '\r',
+                Some('0') => '\0',
+                Some(c @ ('\\' | '"' | '\'')) => c,
+                _ => return Err(format!("Invalid escape in {}", text)),
+            },
+            c => c,
+        };
+        if !c.is_ascii() {
+            return Err(format!("Not ASCII: {}", c));
+        }
+        bytes.push(c as u8);
+    }
+    Ok(bytes)
+}
+
+// Expressions of numbers, symbols, `.` and + - * / with parentheses
+fn parse_expr(text: &str) -> Result<Expr, String> {
+    let tokens = tokenize(text)?;
+    let mut pos = 0;
+    let expr = parse_sum(&tokens, &mut pos)?;
+    if pos != tokens.len() {
+        return Err(format!("Invalid expression: {}", text));
+    }
+    Ok(expr)
+}
+
+fn tokenize(text: &str) -> Result<Vec<String>, String> {
+    let mut tokens = Vec::new();
+    let chars: Vec<char> = text.chars().collect();
+    let mut i = 0;
+    while i < chars.len() {
+        let c = chars[i];
+        if c.is_whitespace() {
+            i += 1;
+        } else if c == '\'' {
+            if chars.get(i + 2) != Some(&'\'') {
+                return Err(format!("Invalid character literal: {}", text));
+            }
+            tokens.push(format!("'{}", chars[i + 1]));
+            i += 3;
+        } else if c.is_ascii_alphanumeric() || c == '_' || c == '.' || c == '$' {
+            let start = i;
+            while i < chars.len()
+                && (chars[i].is_ascii_alphanumeric() || matches!(chars[i], '_' | '.' | '$'))
+            {
+                i += 1;
+            }
+            tokens.push(chars[start..i].iter().collect());
+        } else if "+-*/()".contains(c) {
+            tokens.push(c.to_string());
+            i += 1;
+        } else {
+            return Err(format!("Unexpected {} in {}", c, text));
+        }
+    }
+    Ok(tokens)
+}
+
+fn parse_sum(tokens: &[String], pos: &mut usize) -> Result<Expr, String> {
+    let mut left = parse_product(tokens, pos)?;
+    while let Some(op @ ("+" | "-")) = tokens.get(*pos).map(|t| t.as_str()) {
+        *pos += 1;
+        let right = parse_product(tokens, pos)?;
+        left = Expr::Binary(Box::new(left), op.chars().next().unwrap(), Box::new(right));
+    }
+    Ok(left)
+}
+
+fn parse_product(tokens: &[String], pos: &mut usize) -> Result<Expr, String> {
+    let mut left = parse_term(tokens, pos)?;
+    while let Some(op @ ("*" | "/")) = tokens.get(*pos).map(|t| t.as_str()) {
+        *pos += 1;
+        let right = parse_term(tokens, pos)?;
+        left = Expr::Binary(Box::new(left), op.chars().next().unwrap(), Box::new(right));
+    }
+    Ok(left)
+}
+
+fn parse_term(tokens: &[String], pos: &mut usize) -> Result<Expr, String> {
+    let token = tokens.get(*pos).ok_or("Missing operand")?;
+    *pos += 1;
+    match token.as_str() {
+        "+" => parse_term(tokens, pos),
+        "-" => Ok(Expr::Neg(Box::new(parse_term(tokens, pos)?))),
+        "(" => {
+            let expr = parse_sum(tokens, pos)?;
+            if tokens.get(*pos).map(|t| t.as_str()) != Some(")") {
+                return Err("Missing )".to_string());
+            }
+            *pos += 1;
+            Ok(expr)
+        }
+        "." | "$" => Ok(Expr::Here),
+        token if token.starts_with('\'') => Ok(Expr::Num(token.chars().nth(1).unwrap() as i32)),
+        token => {
+            let digits = token
+                .strip_prefix("0x")
+                .or_else(|| token.strip_prefix("0X"));
+            match digits {
+                Some(digits) => i32::from_str_radix(digits, 16)
+                    .map(Expr::Num)
+                    .map_err(|_| format!("Invalid number: {}", token)),
+                None if token.starts_with(|c: char| c.is_ascii_digit()) => {
+                    i32::from_str_radix(token, 16)
+                        .map(Expr::Num)
+                        .map_err(|_| format!("Invalid number: {}", token))
+                }
+                None if is_identifier(token) => Ok(Expr::Name(token.to_string())),
+                None => Err(format!("Unexpected {}", token)),
+            }
+        }
+    }
+}
+
+// The operation a mnemonic names, with the width string instructions carry in it
+fn mnemonic(name: &str) -> Option<(OperationType, Option<u8>)> {
+    use OperationType as O;
+    let operation_type = match name {
+        "MOV" => O::Mov,
+        "PUSH" => O::Push,
+        "POP" => O::Pop,
+        "XCHG" => O::Xchg,
+        "IN" => O::In,
+        "OUT" => O::Out,
+        "XLAT" => O::Xlat,
+        "LEA" => O::Lea,
+        "LDS" => O::Lds,
+        "LES" => O::Les,
+        "LAHF" => O::Lahf,
+        "SAHF" => O::Sahf,
+        "PUSHF" => O::Pushf,
+        "POPF" => O::Popf,
+        "PUSHA" => O::Pusha,
+        "POPA" => O::Popa,
+        "ADD" => O::Add,
+        "ADC" => O::Adc,
+        "INC" => O::Inc,
+        "AAA" => O::Aaa,
+        "DAA" => O::Daa,
+        "SUB" => O::Sub,
+        "SBB" => O::Sbb,
+        "DEC" => O::Dec,
+        "NEG" => O::Neg,
+        "CMP" => O::Cmp,
+        "AAS" => O::Aas,
+        "DAS" => O::Das,
+        "MUL" => O::Mul,
+        "IMUL" => O::Imul,
+        "AAM" => O::Aam,
+        "DIV" => O::Div,
+        "IDIV" => O::Idiv,
+        "AAD" => O::Aad,
+        "CBW" => O::Cbw,
+        "CWD" => O::Cwd,
+        "SALC" => O::Salc,
+        "NOT" => O::Not,
+        "SHL" | "SAL" => O::ShlSal,
+        "SHR" => O::Shr,
+        "SAR" => O::Sar,
+        "ROL" => O::Rol,
+        "ROR" => O::Ror,
+        "RCL" => O::Rcl,
+        "RCR" => O::Rcr,
+        "AND" => O::And,
+        "TEST" => O::Test,
+        "OR" => O::Or,
+        "XOR" => O::Xor,
+        "CALL" => O::Call,
+        "JMP" => O::Jmp,
+        "RET" | "RETF" => O::Ret,
+        "JE" | "JZ" => O::JeJz,
+        "JL" | "JNGE" => O::JlJnge,
+        "JLE" | "JNG" => O::JleJng,
+        "JB" | "JNAE" | "JC" => O::JbJnae,
+        "JBE" | "JNA" => O::JbeJna,
+        "JP" | "JPE" => O::JpJpe,
+        "JO" => O::Jo,
+        "JS" => O::Js,
+        "JNE" | "JNZ" => O::JneJnz,
+        "JNL" | "JGE" => O::JnlJge,
+        "JNLE" | "JG" => O::JnleJg,
+        "JNB" | "JAE" | "JNC" => O::JnbJae,
+        "JNBE" | "JA" => O::JnbeJa,
+        "JNP" | "JPO" => O::JnpJpo,
+        "JNO" => O::Jno,
+        "JNS" => O::Jns,
+        "LOOP" => O::Loop,
+        "LOOPZ" | "LOOPE" => O::LoopzLoope,
+        "LOOPNZ" | "LOOPNE" => O::LoopnzLoopne,
+        "JCXZ" => O::Jcxz,
+        "INT" => O::Int,
+        "INTO" => O::Into,
+        "IRET" => O::Iret,
+        "ENTER" => O::Enter,
+        "LEAVE" => O::Leave,
+        "BOUND" => O::Bound,
+        "CLC" => O::Clc,
+        "CMC" => O::Cmc,
+        "STC" => O::Stc,
+        "CLD" => O::Cld,
+        "STD" => O::Std,
+        "CLI" => O::Cli,
+        "STI" => O::Sti,
+        "HLT" => O::Hlt,
+        "WAIT" => O::Wait,
+        _ => {
+            let (string, w) = match name.split_at(name.len().saturating_sub(1)) {
+                (string, "B") => (string, 0),
+                (string, "W") => (string, 1),
+                _ => return None,
+            };
+            let operation_type = match string {
+                "MOVS" => O::Movs,
+                "CMPS" => O::Cmps,
+                "SCAS" => O::Scas,
+                "LODS" => O::Lods,
+                "STOS" => O::Stos,
+                "INS" => O::Ins,
+                "OUTS" => O::Outs,
+                _ => return None,
+            };
+            return Some((operation_type, Some(w)));
+        }
+    };
+    Some((operation_type, None))
+}
+
+// --- Encoding ---
+
+struct Output {
+    text: Vec<u8>,
+    data: Vec<u8>,
+    bss_size: usize,
+    labels: Vec<Symbol>,
+}
+
+struct Assembler {
+    cpu: Cpu,
+    // Text offset the code starts at
+    origin: i32,
+    // Names the source defines, which take precedence over hexadecimal numbers
+    defined: HashSet<String>,
+    symbols: HashMap<String, (Section, i32)>,
+    // Data size of the last pass, where bss symbols start
+    data_size: usize,
+    // Displacements (0) and immediates or jumps (1) of a statement that
+    // need their long form. Once long they stay long, so the passes settle.
+    long: HashSet<(usize, u8)>,
+    final_pass: bool,
+}
+
+impl Assembler {
+    fn pass(&mut self, statements: &[Statement]) -> Result<Output, String> {
+        let mut output = Output {
+            text: Vec::new(),
+            data: Vec::new(),
+            bss_size: 0,
+            labels: Vec::new(),
+        };
+        let mut section = Section::Text;
+        for (i, statement) in statements.iter().enumerate() {
+            let here = match section {
+                Section::Text => self.origin as usize + output.text.len(),
+                Section::Data => output.data.len(),
+                Section::Bss => output.bss_size,
+            } as i32;
+            let bytes = self
+                .statement(i, &statement.kind, section, here)
+                .map_err(|e| format!("line {}: {}", statement.line, e))?;
+            match &statement.kind {
+                Kind::Section(next) => section = *next,
+                Kind::Label(name) => {
+                    let sclass = match section {
+                        Section::Text => N_TEXT,
+                        Section::Data => N_DATA,
+                        Section::Bss => N_BSS,
+                    };
+                    let value = self.value(name).unwrap_or(0);
+                    output.labels.push(Symbol {
+                        name: name.clone(),
+                        value: value as u16 as u32,
+                        sclass: sclass | C_STAT,
+                    });
+                }
+                _ => {}
+            }
+            match section {
+                Section::Text => output.text.extend_from_slice(&bytes),
+                Section::Data => output.data.extend_from_slice(&bytes),
+                Section::Bss if bytes.iter().any(|&b| b != 0) => {
+                    return Err(format!("line {}: Initialized data in .bss", statement.line));
+                }
+                Section::Bss => output.bss_size += bytes.len(),
+            }
+        }
+        self.data_size = output.data.len();
+        Ok(output)
+    }
+
+    fn statement(
+        &mut self,
+        i: usize,
+        kind: &Kind,
+        section: Section,
+        here: i32,
+    ) -> Result<Vec<u8>, String> {
+        match kind {
+            Kind::Section(_) => Ok(Vec::new()),
+            Kind::Label(name) => {
+                if self.final_pass
+                    && self
+                        .symbols
+                        .get(name)
+                        .is_some_and(|s| *s != (section, here))
+                {
+                    return Err(format!("{} is defined twice", name));
+                }
+                self.symbols.insert(name.clone(), (section, here));
+                Ok(Vec::new())
+            }
+            Kind::Equ(name, expr) => {
+                if let Some(value) = self.eval(expr, here)? {
+                    self.symbols.insert(name.clone(), (Section::Text, value));
+                }
+                Ok(Vec::new())
+            }
+            Kind::Data(w, data) => {
+                let mut bytes = Vec::new();
+                for datum in data {
+                    match datum {
+                        Datum::Bytes(string) if *w == 0 => bytes.extend_from_slice(string),
+                        Datum::Bytes(_) => return Err("Strings need .byte or db".to_string()),
+                        Datum::Expr(expr) => {
+                            let value = self.eval(expr, here + bytes.len() as i32)?;
+                            let value = self.immediate(value.unwrap_or(0), *w)?;
+                            bytes.extend(&value.to_le_bytes()[..=*w as usize]);
+                        }
+                    }
+                }
+                Ok(bytes)
+            }
+            Kind::Space(expr) => {
+                let size = self.eval(expr, here)?.ok_or(".space needs a known size")?;
+                Ok(vec![0; size.max(0) as usize])
+            }
+            Kind::Align(expr) => {
+                let align = self.eval(expr, here)?.ok_or(".align needs a known size")?;
+                if align <= 0 {
+                    return Err("Invalid alignment".to_string());
+                }
+                let padding = (align - here % align) % align;
+                // Padding in code executes as NOPs
+                let fill = if section == Section::Text { 0x90 } else { 0 };
+                Ok(vec![fill; padding as usize])
+            }
+            Kind::Instruction {
+                prefixes,
+                mnemonic,
+                args,
+            } => {
+                if section != Section::Text {
+                    return Err("Instructions belong in .text".to_string());
+                }
+                let mut bytes = prefixes.clone();
+                if let Some(mnemonic) = mnemonic {
+                    let here = here + bytes.len() as i32;
+                    bytes.extend(self.instruction(i, mnemonic, args, here)?);
+                }
+                Ok(bytes)
+            }
+        }
+    }
+
+    fn value(&self, name: &str) -> Option<i32> {
+        let (section, value) = *self.symbols.get(name)?;
+        Some(match section {
+            Section::Bss => self.data_size as i32 + value,
+            _ => value,
+        })
+    }
+
+    // The value of an expression, None while a symbol is not yet known
+    fn eval(&self, expr: &Expr, here: i32) -> Result<Option<i32>, String> {
+        Ok(match expr {
+            Expr::Num(value) => Some(*value),
+            Expr::Here => Some(here),
+            Expr::Name(name) if self.defined.contains(name) => match self.value(name) {
+                Some(value) => Some(value),
+                None if self.final_pass => return Err(format!("{} has no value", name)),
+                None => None,
+            },
+            Expr::Name(name) => match i32::from_str_radix(name, 16) {
+                Ok(value) => Some(value),
+                Err(_) => return Err(format!("Undefined symbol: {}", name)),
+            },
+            Expr::Neg(expr) => self.eval(expr, here)?.map(|v| v.wrapping_neg()),
+            Expr::Binary(left, op, right) => {
+                let (left, right) = (self.eval(left, here)?, self.eval(right, here)?);
+                match (left, right) {
+                    (Some(left), Some(right)) => Some(match op {
+                        '+' => left.wrapping_add(right),
+                        '-' => left.wrapping_sub(right),
+                        '*' => left.wrapping_mul(right),
+                        _ if right == 0 => return Err("Division by zero".to_string()),
+                        _ => left / right,
+                    }),
+                    _ => None,
+                }
+            }
+        })
+    }
+
+    // Whether a value fits a sign-extended byte. Values that once needed a
+    // word take the long form for good.
+    fn fits_byte(&mut self, i: usize, slot: u8, value: Option<i32>) -> bool {
+        if self.long.contains(&(i, slot)) {
+            return false;
+        }
+        match value {
+            // Forward references start short and grow once they are known
+            None => true,
+            Some(value) if (-0x80..=0x7f).contains(&value) => true,
+            Some(_) => {
+                self.long.insert((i, slot));
+                false
+            }
+        }
+    }
+
+    // A byte or word value, checked to fit
+    fn immediate(&self, value: i32, w: u8) -> Result<u16, String> {
+        let range = if w == 0 {
+            -0x80..=0xff
+        } else {
+            -0x8000..=0xffff
+        };
+        if self.final_pass && !range.contains(&value) {
+            return Err(format!(
+                "{:x} does not fit a {}",
+                value,
+                ["byte", "word"][w as usize]
+            ));
+        }
+        Ok(match w {
+            0 => value as u8 as u16,
+            _ => value as u16,
+        })
+    }
+
+    // An operation in a form, with reg naming its member if the opcode is a group
+    fn operation(&self, operation_type: OperationType, form: Form) -> Operation {
+        let mut op = Operation::new();
+        op.operation_type = operation_type;
+        op.form = form;
+        if let Some((_, Some(reg))) = opcode::encoding(operation_type, form, self.cpu) {
+            op.reg = reg;
+        }
+        op
+    }
+
+    // The mod and rm fields with the displacement
+    fn mod_rm(
+        &mut self,
+        i: usize,
+        op: &mut Operation,
+        rm: &Operand,
+        here: i32,
+    ) -> Result<(), String> {
+        match rm {
+            Operand::Reg { reg, .. } => {
+                op.mod_rm = 0b11;
+                op.rm = *reg;
+            }
+            Operand::Mem { rm: None, disp } => {
+                let disp = self.eval(disp, here)?.unwrap_or(0);
+                op.mod_rm = 0b00;
+                op.rm = 0b110;
+                op.disp = self.immediate(disp, 1)?;
+            }
+            Operand::Mem { rm: Some(r), disp } => {
+                let value = self.eval(disp, here)?;
+                op.rm = *r;
+                // [BP] alone is encoded as [BP+0]
+                if value == Some(0) && *r != 0b110 && !self.long.contains(&(i, 0)) {
+                    op.mod_rm = 0b00;
+                } else if self.fits_byte(i, 0, value) {
+                    op.mod_rm = 0b01;
+                    op.disp = value.unwrap_or(0) as u8 as u16;
+                } else {
+                    op.mod_rm = 0b10;
+                    op.disp = self.immediate(value.unwrap_or(0), 1)?;
+                }
+            }
+            _ => return Err("Expected a register or memory operand".to_string()),
+        }
+        Ok(())
+    }
+
+    // Relative displacement from the end of an instruction of the given length
+    fn relative(&self, target: &Expr, here: i32, length: i32) -> Result<Option<i32>, String> {
+        Ok(self
+            .eval(target, here)?
+            .map(|target| target - (here + length)))
+    }
+
+    fn instruction(
+        &mut self,
+        i: usize,
+        name: &str,
+        args: &[Arg],
+        here: i32,
+    ) -> Result<Vec<u8>, String> {
+        use OperationType as O;
+        if name == "NOP" && args.is_empty() {
+            // XCHG AX, AX
+            let op = self.operation(O::Xchg, Form::RegAcc);
+            return Ok(op.encode(self.cpu)?.to_vec());
+        }
+        let (operation_type, string_w) =
+            mnemonic(name).ok_or_else(|| format!("Unknown instruction: {}", name))?;
+        let w = self.width(operation_type, args)?;
+        let operands: Vec<&Operand> = args.iter().map(|a| &a.operand).collect();
+        let invalid = || format!("Invalid operands for {}", name);
+
+        let op = match (operation_type, &operands[..]) {
+            (O::Ret, []) | (O::Ret, [Operand::Imm(_)]) => {
+                let form = if operands.is_empty() {
+                    Form::None
+                } else {
+                    Form::RetImm
+                };
+                let mut op = self.operation(O::Ret, form);
+                if let [Operand::Imm(expr)] = &operands[..] {
+                    let value = self.eval(expr, here)?.unwrap_or(0);
+                    op.disp = self.immediate(value, 1)?;
+                }
+                let mut bytes = op.encode(self.cpu)?.to_vec();
+                if name == "RETF" {
+                    // Bit 3 makes the return intersegment
+                    bytes[0] |= 0b1000;
+                }
+                return Ok(bytes);
+            }
+            (O::Aam | O::Aad, []) => {
+                let mut op = self.operation(operation_type, Form::Base);
+                op.data = 0x0a;
+                op
+            }
+            (_, []) => {
+                let mut op = self.operation(operation_type, Form::None);
+                op.w = string_w.unwrap_or(1);
+                op
+            }
+            (O::Mov, [Operand::Seg(seg), rm]) | (O::Mov, [rm, Operand::Seg(seg)]) => {
+                let form = if matches!(operands[0], Operand::Seg(_)) {
+                    Form::SegRm
+                } else {
+                    Form::RmSeg
+                };
+                let mut op = self.operation(O::Mov, form);
+                op.reg = *seg;
+                self.mod_rm(i, &mut op, rm, here)?;
+                op
+            }
+            (O::Mov, [Operand::Reg { reg: 0, .. }, Operand::Mem { rm: None, disp }])
+            | (O::Mov, [Operand::Mem { rm: None, disp }, Operand::Reg { reg: 0, .. }]) => {
+                // The accumulator and a direct address
+                let form = if matches!(operands[0], Operand::Reg { .. }) {
+                    Form::AccMem
+                } else {
+                    Form::MemAcc
+                };
+                let mut op = self.operation(O::Mov, form);
+                op.w = w;
+                let disp = self.eval(disp, here)?.unwrap_or(0);
+                op.disp = self.immediate(disp, 1)?;
+                op
+            }
+            (O::Mov, [Operand::Reg { reg, .. }, Operand::Imm(expr)]) => {
+                let mut op = self.operation(O::Mov, Form::RegImm);
+                op.w = w;
+                op.reg = *reg;
+                let value = self.eval(expr, here)?.unwrap_or(0);
+                op.data = self.immediate(value, w)?;
+                op
+            }
+            (
+                O::Add | O::Or | O::Adc | O::Sbb | O::And | O::Sub | O::Xor | O::Cmp | O::Test,
+                [Operand::Reg { reg: 0, .. }, Operand::Imm(expr)],
+            ) => {
+                let mut op = self.operation(operation_type, Form::AccImm);
+                op.w = w;
+                let value = self.eval(expr, here)?.unwrap_or(0);
+                op.data = self.immediate(value, w)?;
+                op
+            }
+            (
+                O::Add
+                | O::Or
+                | O::Adc
+                | O::Sbb
+                | O::And
+                | O::Sub
+                | O::Xor
+                | O::Cmp
+                | O::Test
+                | O::Mov,
+                [rm @ (Operand::Reg { .. } | Operand::Mem { .. }), Operand::Imm(expr)],
+            ) => {
+                let value = self.eval(expr, here)?;
+                // Only the ALU group sign-extends a byte immediate
+                let extends = !matches!(operation_type, O::Test | O::Mov);
+                let mut op = self.operation(operation_type, Form::RmImm);
+                op.w = w;
+                op.s = (extends && w == 1 && self.fits_byte(i, 1, value)) as u8;
+                self.mod_rm(i, &mut op, rm, here)?;
+                op.data = self.immediate(value.unwrap_or(0), w & !op.s)?;
+                op
+            }
+            (O::Xchg, [Operand::Reg { reg: 0, w: 1 }, Operand::Reg { reg, w: 1 }])
+            | (O::Xchg, [Operand::Reg { reg, w: 1 }, Operand::Reg { reg: 0, w: 1 }]) => {
+                let mut op = self.operation(O::Xchg, Form::RegAcc);
+                op.reg = *reg;
+                op
+            }
+            (
+                O::Mov
+                | O::Add
+                | O::Or
+                | O::Adc
+                | O::Sbb
+                | O::And
+                | O::Sub
+                | O::Xor
+                | O::Cmp
+                | O::Test
+                | O::Xchg,
+                [left, right],
+            ) => {
+                // The register goes in reg, the other operand in rm, with d
+                // set when the register is the destination. TEST and XCHG
+                // have no d bit and do not care about the order.
+                let (reg, rm, d) = match (left, right) {
+                    (
+                        rm @ (Operand::Reg { .. } | Operand::Mem { .. }),
+                        Operand::Reg { reg, .. },
+                    ) => (*reg, rm, 0),
+                    (Operand::Reg { reg, .. }, rm @ Operand::Mem { .. }) => (*reg, rm, 1),
+                    _ => return Err(invalid()),
+                };
+                let mut op = self.operation(operation_type, Form::RmReg);
+                op.w = w;
+                op.d = d;
+                op.reg = reg;
+                self.mod_rm(i, &mut op, rm, here)?;
+                op
+            }
+            (O::Inc | O::Dec | O::Push | O::Pop, [Operand::Reg { reg, w: 1 }]) => {
+                let mut op = self.operation(operation_type, Form::Reg);
+                op.reg = *reg;
+                op
+            }
+            (O::Push | O::Pop, [Operand::Seg(seg)]) => {
+                let mut op = self.operation(operation_type, Form::Seg);
+                op.reg = *seg;
+                // Only the 8086 has POP CS
+                return op
+                    .encode(self.cpu)
+                    .map(|raws| raws.to_vec())
+                    .map_err(|_| invalid());
+            }
+            (O::Push, [Operand::Imm(expr)]) => {
+                let value = self.eval(expr, here)?;
+                let mut op = self.operation(O::Push, Form::PushImm);
+                op.s = self.fits_byte(i, 1, value) as u8;
+                op.data = self.immediate(value.unwrap_or(0), 1 - op.s)?;
+                op
+            }
+            (
+                O::Inc
+                | O::Dec
+                | O::Push
+                | O::Pop
+                | O::Not
+                | O::Neg
+                | O::Mul
+                | O::Imul
+                | O::Div
+                | O::Idiv,
+                [rm @ (Operand::Reg { .. } | Operand::Mem { .. })],
+            ) => {
+                let mut op = self.operation(operation_type, Form::Rm);
+                op.w = w;
+                self.mod_rm(i, &mut op, rm, here)?;
+                op
+            }
+            (O::Imul, [Operand::Reg { reg, w: 1 }, Operand::Imm(expr)]) => {
+                let rm = operands[0].clone();
+                self.imul_immediate(i, *reg, &rm, expr, here)?
+            }
+            (O::Imul, [Operand::Reg { reg, w: 1 }, rm, Operand::Imm(expr)]) => {
+                let rm = (*rm).clone();
+                self.imul_immediate(i, *reg, &rm, expr, here)?
+            }
+            (O::ShlSal | O::Shr | O::Sar | O::Rol | O::Ror | O::Rcl | O::Rcr, [rm, count]) => {
+                let mut op = match count {
+                    // By CL
+                    Operand::Reg { reg: 1, w: 0 } => {
+                        let mut op = self.operation(operation_type, Form::Shift);
+                        op.v = 1;
+                        op
+                    }
+                    Operand::Imm(expr) => match self.eval(expr, here)? {
+                        Some(1) => self.operation(operation_type, Form::Shift),
+                        value => {
+                            let mut op = self.operation(operation_type, Form::ShiftImm);
+                            op.data = self.immediate(value.unwrap_or(0), 0)?;
+                            op
+                        }
+                    },
+                    _ => return Err(invalid()),
+                };
+                op.w = w;
+                self.mod_rm(i, &mut op, rm, here)?;
+                op
+            }
+            (O::In, [Operand::Reg { reg: 0, .. }, port])
+            | (O::Out, [port, Operand::Reg { reg: 0, .. }]) => {
+                let (form, port) = match port {
+                    Operand::Reg { reg: 2, w: 1 } => (
+                        if operation_type == O::In {
+                            Form::InDx
+                        } else {
+                            Form::OutDx
+                        },
+                        None,
+                    ),
+                    Operand::Imm(expr) => (
+                        if operation_type == O::In {
+                            Form::InPort
+                        } else {
+                            Form::OutPort
+                        },
+                        Some(self.eval(expr, here)?.unwrap_or(0)),
+                    ),
+                    _ => return Err(invalid()),
+                };
+                let mut op = self.operation(operation_type, form);
+                op.w = w;
+                if let Some(port) = port {
+                    op.port = self.immediate(port, 0)? as u8;
+                }
+                op
+            }
+            (O::Jmp | O::Call, [Operand::Far(seg, offset)]) => {
+                let mut op = self.operation(operation_type, Form::Far);
+                let offset = self.eval(offset, here)?.unwrap_or(0);
+                let seg = self.eval(seg, here)?.unwrap_or(0);
+                op.disp = self.immediate(offset, 1)?;
+                op.data = self.immediate(seg, 1)?;
+                op
+            }
+            (O::Jmp | O::Call, [rm @ (Operand::Reg { .. } | Operand::Mem { .. })]) => {
+                let mut op = self.operation(operation_type, Form::Rm);
+                // The next group member is the intersegment form
+                op.reg += args[0].far as u8;
+                self.mod_rm(i, &mut op, rm, here)?;
+                op
+            }
+            (O::Jmp, [Operand::Imm(target)]) => {
+                let short = self.relative(target, here, 2)?;
+                if args[0].short || self.fits_byte(i, 1, short) {
+                    let mut op = self.operation(O::Jmp, Form::Short);
+                    op.disp = self.rel8(short)? as u16;
+                    op
+                } else {
+                    let mut op = self.operation(O::Jmp, Form::Near);
+                    op.disp = self.relative(target, here, 3)?.unwrap_or(0) as u16;
+                    op
+                }
+            }
+            (O::Call, [Operand::Imm(target)]) => {
+                let mut op = self.operation(O::Call, Form::Near);
+                op.disp = self.relative(target, here, 3)?.unwrap_or(0) as u16;
+                op
+            }
+            (
+                O::JeJz
+                | O::JlJnge
+                | O::JleJng
+                | O::JbJnae
+                | O::JbeJna
+                | O::JpJpe
+                | O::Jo
+                | O::Js
+                | O::JneJnz
+                | O::JnlJge
+                | O::JnleJg
+                | O::JnbJae
+                | O::JnbeJa
+                | O::JnpJpo
+                | O::Jno
+                | O::Jns
+                | O::Loop
+                | O::LoopzLoope
+                | O::LoopnzLoopne
+                | O::Jcxz,
+                [Operand::Imm(target)],
+            ) => {
+                let mut op = self.operation(operation_type, Form::Rel8);
+                let rel = self.relative(target, here, 2)?;
+                op.disp = self.rel8(rel)? as u16;
+                op
+            }
+            (O::Int, [Operand::Imm(expr)]) => {
+                let value = self.eval(expr, here)?.unwrap_or(0);
+                if value == 3 {
+                    self.operation(O::Int, Form::Int3)
+                } else {
+                    let mut op = self.operation(O::Int, Form::Int);
+                    op.int_type = self.immediate(value, 0)? as u8;
+                    op
+                }
+            }
+            (
+                O::Lea | O::Lds | O::Les | O::Bound,
+                [Operand::Reg { reg, w: 1 }, rm @ Operand::Mem { .. }],
+            ) => {
+                let mut op = self.operation(operation_type, Form::RegMem);
+                op.reg = *reg;
+                self.mod_rm(i, &mut op, rm, here)?;
+                op
+            }
+            (O::Enter, [Operand::Imm(size), Operand::Imm(level)]) => {
+                let mut op = self.operation(O::Enter, Form::Enter);
+                let size = self.eval(size, here)?.unwrap_or(0);
+                let level = self.eval(level, here)?.unwrap_or(0);
+                op.data = self.immediate(size, 1)?;
+                op.disp = self.immediate(level, 0)?;
+                op
+            }
+            _ => return Err(invalid()),
+        };
+        Ok(op.encode(self.cpu)?.to_vec())
+    }
+
+    // IMUL reg, rm, imm with a sign-extended byte where it fits
+    fn imul_immediate(
+        &mut self,
+        i: usize,
+        reg: u8,
+        rm: &Operand,
+        expr: &Expr,
+        here: i32,
+    ) -> Result<Operation, String> {
+        let value = self.eval(expr, here)?;
+        let mut op = self.operation(OperationType::Imul, Form::ImulImm);
+        op.reg = reg;
+        op.s = self.fits_byte(i, 1, value) as u8;
+        self.mod_rm(i, &mut op, rm, here)?;
+        op.data = self.immediate(value.unwrap_or(0), 1 - op.s)?;
+        Ok(op)
+    }
+
+    fn rel8(&self, rel: Option<i32>) -> Result<u8, String> {
+        match rel {
+            Some(rel) if (-0x80..=0x7f).contains(&rel) => Ok(rel as u8),
+            _ if !self.final_pass => Ok(0),
+            _ => Err("Jump out of range".to_string()),
+        }
+    }
+
+    // Operand size from the registers and the Byte/Word keywords, a word if
+    // nothing says otherwise
+    fn width(&self, operation_type: OperationType, args: &[Arg]) -> Result<u8, String> {
+        use OperationType as O;
+        let mut width = None;
+        for arg in args {
+            let w = match (operation_type, &arg.operand) {
+                // Shifts count in CL and ports are addressed by DX
+                (
+                    O::ShlSal | O::Shr | O::Sar | O::Rol | O::Ror | O::Rcl | O::Rcr,
+                    Operand::Reg { reg: 1, w: 0 },
+                )
+                | (O::In | O::Out, Operand::Reg { reg: 2, w: 1 }) => None,
+                (_, Operand::Reg { w, .. }) => Some(*w),
+                _ => arg.w,
+            };
+            match (width, w) {
+                (Some(a), Some(b)) if a != b => {
+                    return Err("Operand sizes do not match".to_string());
+                }
+                (None, Some(_)) => width = w,
+                _ => {}
+            }
+        }
+        Ok(width.unwrap_or(1))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use test_case::test_case;
+
+    fn text(source: &str) -> Vec<u8> {
+        let executable = assemble(source, Cpu::I8086).unwrap();
+        let metadata = Metadata::from_bytes(&executable);
+        executable[32..32 + metadata.text_size].to_vec()
+    }
+
+    #[test_case("MOV AX, 1234", &[0xb8, 0x34, 0x12])]
+    #[test_case("ADD [BX+SI+4], AL", &[0x00, 0x40, 0x04])]
+    #[test_case("MOV Byte [0501], 02", &[0xc6, 0x06, 0x01, 0x05, 0x02])]
+    #[test_case("CMP CX, 2", &[0x83, 0xf9, 0x02])]
+    #[test_case("ADD AX, -1", &[0x05, 0xff, 0xff])]
+    #[test_case("MOV DX, [BP-2]", &[0x8b, 0x56, 0xfe])]
+    #[test_case("MOV AL, [BP]", &[0x8a, 0x46, 0x00])]
+    #[test_case("MOV AX, [0010]", &[0xa1, 0x10, 0x00])]
+    #[test_case("XCHG BX, AX", &[0x93])]
+    #[test_case("TEST AL, [BX]", &[0x84, 0x07])]
+    #[test_case("SHL Word [DI], CL", &[0xd3, 0x25])]
+    #[test_case("IN AL, DX", &[0xec])]
+    #[test_case("OUT 43, AL", &[0xe6, 0x43])]
+    #[test_case("ES: MOV AX, [BX]", &[0x26, 0x8b, 0x07])]
+    #[test_case("REP MOVSB", &[0xf3, 0xa4])]
+    #[test_case("PUSH ES", &[0x06])]
+    #[test_case("CALL Far [BX]", &[0xff, 0x1f])]
+    #[test_case("POP [BX]", &[0x8f, 0x07])]
+    #[test_case("JMP F000:E05B", &[0xea, 0x5b, 0xe0, 0x00, 0xf0])]
+    #[test_case("RETF 4", &[0xca, 0x04, 0x00])]
+    #[test_case("INT 20", &[0xcd, 0x20])]
+    #[test_case("INT 3", &[0xcc])]
+    #[test_case("AAM", &[0xd4, 0x0a])]
+    #[test_case("LEA SI, [BX+DI+100]", &[0x8d, 0xb1, 0x00, 0x01])]
+    fn test_instruction(source: &str, expected: &[u8]) {
+        assert_eq!(text(source), expected);
+    }
+
+    #[test]
+    fn test_labels_and_sections() {
+        let source = "
+            start:  MOV CX, count       ; loop counter
+                    MOV SI, message
+            again:  LOOP again
+                    JMP done
+                    JMP start
+            done:   HLT
+            .data
+            message: db \"hi\", 0a
+            .equ count, 3
+            .bss
+            buffer: .space 10
+            .text
+                    MOV [buffer], AX
+        ";
+        let executable = assemble(source, Cpu::I8086).unwrap();
+        let metadata = Metadata::from_bytes(&executable);
+        assert_eq!((metadata.data_size, metadata.bss_size), (3, 0x10));
+        let text = &executable[32..32 + metadata.text_size];
+        #[rustfmt::skip]
+        assert_eq!(text, [
+            0xb9, 0x03, 0x00,   // MOV CX, 3
+            0xbe, 0x00, 0x00,   // MOV SI, message
+            0xe2, 0xfe,         // LOOP again
+            0xeb, 0x02,         // JMP Short done
+            0xeb, 0xf4,         // JMP Short start
+            0xf4,               // HLT
+            0xa3, 0x03, 0x00,   // MOV [buffer], AX
+        ]);
+        let data = &executable[32 + metadata.text_size..][..3];
+        assert_eq!(data, b"hi\n");
+        // start, again, done, message, buffer
+        assert_eq!(metadata.syms, 5 * Symbol::SIZE);
+    }
+
+    #[test]
+    fn test_errors() {
+        assert_eq!(
+            assemble("JE done\nJE done\n.space 7e\ndone: HLT", Cpu::I8086),
+            Err("line 1: Jump out of range".to_string())
+        );
+        assert!(assemble("PUSHA", Cpu::I8086).is_err());
+        assert!(assemble("PUSHA", Cpu::I80186).is_ok());
+        assert!(assemble("MOV AL, BX", Cpu::I8086).is_err());
+    }
+}
@@ -0,0 +1,777 @@
+use std::{
+    cell::RefCell,
+    fs::{File, OpenOptions},
+    io::{Seek, SeekFrom, Write},
+    rc::Rc,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use crate::{
+    dump::Dump,
+    flag::Flag,
+    keyboard::{self, ALT, BREAK, CAPS_LOCK, CTRL, KBC_DATA_PORT, LEFT_SHIFT, RIGHT_SHIFT},
+    memory::{physical_address, MemoryBus, ADDRESS_MASK},
+    pic::PIC_COMMAND_PORT,
+    port::PortBus,
+    register::Register,
+    video::{self, Adapter, TextVideo},
+};
+
+pub const SECTOR_SIZE: usize = 512;
+pub const BOOT_SEGMENT: u16 = 0x0000;
+pub const BOOT_OFFSET: u16 = 0x7c00;
+// Every vector points at its own one byte IRET stub in the BIOS segment
+pub const STUB_SEGMENT: u16 = 0xf000;
+pub const STUB_OFFSET: u16 = 0xe000;
+const IRET: u8 = 0xcf;
+// IRQ0 handler, real code rather than a service because guests chain onto INT 1Ch
+const TIMER_OFFSET: u16 = 0xe100;
+#[rustfmt::skip]
+const TIMER_HANDLER: [u8; 25] = [
+    0x50,                   // PUSH AX
+    0x1e,                   // PUSH DS
+    0x31, 0xc0,             // XOR AX, AX
+    0x8e, 0xd8,             // MOV DS, AX
+    0xff, 0x06, 0x6c, 0x04, // INC [046c]
+    0x75, 0x04,             // JNE +4
+    0xff, 0x06, 0x6e, 0x04, // INC [046e]
+    0xcd, 0x1c,             // INT 1c
+    0xb0, 0x20,             // MOV AL, 20
+    0xe6, 0x20,             // OUT 20, AL (EOI)
+    0x1f,                   // POP DS
+    0x58,                   // POP AX
+    IRET,
+];
+
+const BOOT_SIGNATURE: [u8; 2] = [0x55, 0xaa];
+const CONVENTIONAL_MEMORY_KB: u16 = 640;
+// 1 floppy drive, 8087 present, 80x25 color
+const EQUIPMENT_LIST: u16 = 0x0023;
+// BIOS data area: shift state and the 16 key ring buffer, as offsets from 0040:0000
+const BDA: usize = 0x400;
+const SHIFT_FLAGS: usize = 0x17;
+const BUFFER_HEAD: usize = 0x1a;
+const BUFFER_TAIL: usize = 0x1c;
+const BUFFER_START: u16 = 0x1e;
+const BUFFER_END: u16 = 0x3e;
+const CAPS_LOCK_ON: u8 = 0x40;
+// The PIT runs at 1193180 Hz and overflows every 65536 counts
+const PIT_HZ: u128 = 1_193_180;
+const TICKS_PER_DAY: u128 = 0x1800b0;
+
+const STATUS_OK: u8 = 0x00;
+const STATUS_BAD_COMMAND: u8 = 0x01;
+const STATUS_WRITE_PROTECTED: u8 = 0x03;
+const STATUS_SECTOR_NOT_FOUND: u8 = 0x04;
+const STATUS_TIMEOUT: u8 = 0x80;
+
+#[derive(Debug, Clone, Copy)]
+pub struct Geometry {
+    pub cylinders: usize,
+    pub heads: usize,
+    pub sectors: usize,
+}
+
+// Standard floppy formats, keyed by image size
+const FLOPPY_GEOMETRIES: [(usize, Geometry); 8] = [
+    (
+        160 * 1024,
+        Geometry {
+            cylinders: 40,
+            heads: 1,
+            sectors: 8,
+        },
+    ),
+    (
+        180 * 1024,
+        Geometry {
+            cylinders: 40,
+            heads: 1,
+            sectors: 9,
+        },
+    ),
+    (
+        320 * 1024,
+        Geometry {
+            cylinders: 40,
+            heads: 2,
+            sectors: 8,
+        },
+    ),
+    (
+        360 * 1024,
+        Geometry {
+            cylinders: 40,
+            heads: 2,
+            sectors: 9,
+        },
+    ),
+    (
+        720 * 1024,
+        Geometry {
+            cylinders: 80,
+            heads: 2,
+            sectors: 9,
+        },
+    ),
+    (
+        1200 * 1024,
+        Geometry {
+            cylinders: 80,
+            heads: 2,
+            sectors: 15,
+        },
+    ),
+    (
+        1440 * 1024,
+        Geometry {
+            cylinders: 80,
+            heads: 2,
+            sectors: 18,
+        },
+    ),
+    (
+        2880 * 1024,
+        Geometry {
+            cylinders: 80,
+            heads: 2,
+            sectors: 36,
+        },
+    ),
+];
+
+pub struct Disk {
+    image: Vec<u8>,
+    file: Option<File>,
+    geometry: Geometry,
+    drive: u8,
+}
+
+impl Disk {
+    pub fn new(path: &str, image: Vec<u8>) -> Self {
+        // Sector writes go back to the image file when it is writable
+        let file = OpenOptions::new().write(true).open(path).ok();
+        let (geometry, drive) = match FLOPPY_GEOMETRIES
+            .iter()
+            .find(|(size, _)| *size == image.len())
+        {
+            Some((_, geometry)) => (*geometry, 0x00),
+            None => {
+                let heads = 16;
+                let sectors = 63;
+                let cylinders = image.len().div_ceil(heads * sectors * SECTOR_SIZE);
+                (
+                    Geometry {
+                        cylinders,
+                        heads,
+                        sectors,
+                    },
+                    0x80,
+                )
+            }
+        };
+        Disk {
+            image,
+            file,
+            geometry,
+            drive,
+        }
+    }
+
+    pub fn drive(&self) -> u8 {
+        self.drive
+    }
+
+    fn lba(&self, cylinder: usize, head: usize, sector: usize) -> Option<usize> {
+        let g = &self.geometry;
+        if sector == 0 || sector > g.sectors || head >= g.heads || cylinder >= g.cylinders {
+            return None;
+        }
+        Some((cylinder * g.heads + head) * g.sectors + sector - 1)
+    }
+
+    fn read(&self, lba: usize, count: usize) -> Option<&[u8]> {
+        let begin = lba * SECTOR_SIZE;
+        let end = begin + count * SECTOR_SIZE;
+        self.image.get(begin..end)
+    }
+
+    fn write(&mut self, lba: usize, data: &[u8]) -> u8 {
+        let begin = lba * SECTOR_SIZE;
+        let end = begin + data.len();
+        if end > self.image.len() {
+            return STATUS_SECTOR_NOT_FOUND;
+        }
+        let file = match self.file.as_mut() {
+            Some(file) => file,
+            None => return STATUS_WRITE_PROTECTED,
+        };
+        if file.seek(SeekFrom::Start(begin as u64)).is_err() || file.write_all(data).is_err() {
+            return STATUS_WRITE_PROTECTED;
+        }
+        self.image[begin..end].copy_from_slice(data);
+        STATUS_OK
+    }
+}
+
+// How the CPU goes on after a BIOS service
+pub enum Service {
+    // Return to the caller
+    Done,
+    // Wait for an interrupt with interrupts enabled, then run the service again
+    Wait,
+    // Nothing more can happen: stop the machine
+    Stop,
+}
+
+pub struct Bios {
+    disk: Disk,
+    disk_status: u8,
+    cursor: (u8, u8),
+    // Without a video adapter, text goes to stdout
+    video: Option<Rc<RefCell<TextVideo>>>,
+    tick_offset: i64,
+}
+
+fn ticks_since_midnight() -> i64 {
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_millis();
+    let ticks = now * PIT_HZ / 65_536_000;
+    (ticks % TICKS_PER_DAY) as i64
+}
+
+// Scan code in the high byte, character in the low byte
+fn key(scan: u8, flags: u8) -> u16 {
+    let shift = flags & 0x03 != 0;
+    let lower = keyboard::ascii(scan, false);
+    let shifted = if lower.is_ascii_lowercase() {
+        shift != (flags & CAPS_LOCK_ON != 0)
+    } else {
+        shift
+    };
+    let ascii = if flags & 0x08 != 0 {
+        0
+    } else if flags & 0x04 != 0 {
+        if lower.is_ascii_lowercase() {
+            lower & 0x1f
+        } else {
+            0
+        }
+    } else {
+        keyboard::ascii(scan, shifted)
+    };
+    u16::from_le_bytes([ascii, scan])
+}
+
+fn next_slot(offset: u16) -> u16 {
+    if offset + 2 >= BUFFER_END {
+        BUFFER_START
+    } else {
+        offset + 2
+    }
+}
+
+fn peek_key(memory: &dyn MemoryBus) -> Option<u16> {
+    let head = memory.read16(BDA + BUFFER_HEAD);
+    if head == memory.read16(BDA + BUFFER_TAIL) {
+        return None;
+    }
+    Some(memory.read16(BDA + head as usize))
+}
+
+fn pop_key(memory: &mut dyn MemoryBus) -> Option<u16> {
+    let key = peek_key(memory)?;
+    let head = memory.read16(BDA + BUFFER_HEAD);
+    memory.write16(BDA + BUFFER_HEAD, next_slot(head));
+    Some(key)
+}
+
+// Returns false when the buffer is full
+fn push_key(memory: &mut dyn MemoryBus, key: u16) -> bool {
+    let tail = memory.read16(BDA + BUFFER_TAIL);
+    let next = next_slot(tail);
+    if next == memory.read16(BDA + BUFFER_HEAD) {
+        return false;
+    }
+    memory.write16(BDA + tail as usize, key);
+    memory.write16(BDA + BUFFER_TAIL, next);
+    true
+}
+
+// Writes a character at the cursor like the BIOS teletype, scrolling at the bottom
+fn teletype(video: &mut TextVideo, c: u8) {
+    let (mut row, mut col) = video.cursor();
+    match c {
+        b'\r' => col = 0,
+        b'\n' => row += 1,
+        0x08 => col = col.saturating_sub(1),
+        0x07 => {}
+        _ => {
+            let attr = video.cell(row, col).1;
+            video.set_cell(row, col, c, attr);
+            col += 1;
+            if col >= video::COLUMNS {
+                col = 0;
+                row += 1;
+            }
+        }
+    }
+    if row >= video::ROWS {
+        let attr = video.cell(video::ROWS - 1, 0).1;
+        video.scroll(1, (0, 0, video::ROWS - 1, video::COLUMNS - 1), attr);
+        row = video::ROWS - 1;
+    }
+    video.set_cursor(row, col);
+}
+
+impl Bios {
+    pub fn new(disk: Disk) -> Self {
+        Bios {
+            disk,
+            disk_status: STATUS_OK,
+            cursor: (0, 0),
+            video: None,
+            tick_offset: 0,
+        }
+    }
+
+    pub fn attach_video(&mut self, video: Rc<RefCell<TextVideo>>) {
+        self.video = Some(video);
+    }
+
+    pub fn boot_drive(&self) -> u8 {
+        self.disk.drive()
+    }
+
+    pub fn load_boot_sector(&self, memory: &mut dyn MemoryBus) {
+        let sector = self
+            .disk
+            .read(0, 1)
+            .unwrap_or_else(|| panic!("Disk image is smaller than one sector"));
+        if sector[SECTOR_SIZE - 2..] != BOOT_SIGNATURE {
+            panic!("Boot sector signature 0x55aa not found");
+        }
+        let begin = physical_address(BOOT_SEGMENT, BOOT_OFFSET);
+        for (i, byte) in sector.iter().enumerate() {
+            memory.write8(begin + i, *byte);
+        }
+    }
+
+    pub fn install_vectors(&self, memory: &mut dyn MemoryBus) {
+        for vector in 0..=255u16 {
+            memory.write16(vector as usize * 4, STUB_OFFSET + vector);
+            memory.write16(vector as usize * 4 + 2, STUB_SEGMENT);
+            memory.write8(physical_address(STUB_SEGMENT, STUB_OFFSET + vector), IRET);
+        }
+        let timer = physical_address(STUB_SEGMENT, TIMER_OFFSET);
+        for (i, byte) in TIMER_HANDLER.iter().enumerate() {
+            memory.write8(timer + i, *byte);
+        }
+        memory.write16(0x08 * 4, TIMER_OFFSET);
+        memory.write16(BDA + BUFFER_HEAD, BUFFER_START);
+        memory.write16(BDA + BUFFER_TAIL, BUFFER_START);
+    }
+
+    // The vector whose stub is at addr, if a service runs there. Stubs of the
+    // other vectors just return
+    pub fn service_at(&self, addr: usize) -> Option<u8> {
+        let vector = addr.checked_sub(physical_address(STUB_SEGMENT, STUB_OFFSET))?;
+        match vector {
+            0x09 | 0x10..=0x13 | 0x15 | 0x16 | 0x18 | 0x19 | 0x1a => Some(vector as u8),
+            _ => None,
+        }
+    }
+
+    // Runs the BIOS service for INT int_type
+    pub fn interrupt(
+        &mut self,
+        int_type: u8,
+        reg: &mut Register,
+        flag: &mut Flag,
+        memory: &mut dyn MemoryBus,
+        ports: &mut dyn PortBus,
+        dump: &Dump,
+    ) -> Service {
+        dump.bios(int_type, reg.ah);
+        match int_type {
+            0x09 => self.keyboard_irq(memory, ports),
+            0x10 => self.video(reg, memory),
+            0x11 => reg.set_ax(self.equipment_list()),
+            0x12 => reg.set_ax(CONVENTIONAL_MEMORY_KB),
+            0x13 => self.disk(reg, flag, memory, dump),
+            0x15 => {
+                // No extended system services
+                reg.ah = 0x86;
+                flag.set_carry(true);
+            }
+            0x16 => return self.keyboard(reg, flag, memory),
+            0x18 => {
+                // No ROM BASIC to fall back to
+                return Service::Stop;
+            }
+            0x19 => {
+                self.load_boot_sector(memory);
+                reg.cs = BOOT_SEGMENT;
+                reg.ip = BOOT_OFFSET;
+                reg.dl = self.boot_drive();
+            }
+            0x1a => self.time(reg, flag),
+            _ => panic!("\nUnhandled BIOS interrupt: {:02x}h", int_type),
+        }
+        Service::Done
+    }
+
+    fn equipment_list(&self) -> u16 {
+        match &self.video {
+            Some(video) if video.borrow().adapter() == Adapter::Mda => EQUIPMENT_LIST | 0x0030,
+            _ => EQUIPMENT_LIST,
+        }
+    }
+
+    fn put_char(&mut self, c: u8) {
+        if let Some(video) = &self.video {
+            teletype(&mut video.borrow_mut(), c);
+            return;
+        }
+        let (row, col) = &mut self.cursor;
+        match c {
+            b'\r' => *col = 0,
+            b'\n' => *row = (*row + 1).min(24),
+            0x08 => *col = col.saturating_sub(1),
+            0x07 => {}
+            _ => {
+                *col += 1;
+                if *col >= 80 {
+                    *col = 0;
+                    *row = (*row + 1).min(24);
+                }
+            }
+        }
+        let mut stdout = std::io::stdout();
+        stdout
+            .write_all(&[c])
+            .and_then(|_| stdout.flush())
+            .expect("Failed to write to stdout");
+    }
+
+    fn video(&mut self, reg: &mut Register, memory: &dyn MemoryBus) {
+        if let Some(video) = self.video.clone() {
+            return self.text_mode(reg, memory, &mut video.borrow_mut());
+        }
+        match reg.ah {
+            0x00 => self.cursor = (0, 0),
+            0x02 => self.cursor = (reg.dh, reg.dl),
+            0x03 => {
+                reg.dh = self.cursor.0;
+                reg.dl = self.cursor.1;
+                reg.set_cx(0x0607);
+            }
+            0x08 => reg.set_ax(0x0720),
+            0x09 | 0x0a => {
+                for _ in 0..reg.get_cx() {
+                    self.put_char(reg.al);
+                }
+            }
+            0x0e => self.put_char(reg.al),
+            0x0f => {
+                reg.al = 0x03;
+                reg.ah = 80;
+                reg.bh = 0;
+            }
+            0x13 => {
+                // Bit 1 of AL: the string alternates characters and attributes
+                let step = if reg.al & 0b10 != 0 { 2 } else { 1 };
+                for i in 0..reg.get_cx() {
+                    let addr = physical_address(reg.es, reg.bp.wrapping_add(i * step));
+                    self.put_char(memory.read8(addr));
+                }
+            }
+            _ => {
+                // Cursor shape, scrolling, palette, ... have nothing to render
+            }
+        }
+    }
+
+    // INT 10h on a text adapter, page 0 only
+    fn text_mode(&mut self, reg: &mut Register, memory: &dyn MemoryBus, video: &mut TextVideo) {
+        let (row, col) = video.cursor();
+        let screen = (0, 0, video::ROWS - 1, video::COLUMNS - 1);
+        match reg.ah {
+            0x00 => {
+                video.scroll(0, screen, 0x07);
+                video.set_cursor(0, 0);
+            }
+            0x02 => video.set_cursor(
+                (reg.dh as usize).min(video::ROWS - 1),
+                (reg.dl as usize).min(video::COLUMNS - 1),
+            ),
+            0x03 => {
+                reg.dh = row as u8;
+                reg.dl = col as u8;
+                reg.set_cx(0x0607);
+            }
+            0x06 | 0x07 => {
+                let lines = reg.al as i32;
+                let window = (
+                    reg.ch as usize,
+                    reg.cl as usize,
+                    reg.dh as usize,
+                    reg.dl as usize,
+                );
+                let lines = if reg.ah == 0x06 { lines } else { -lines };
+                video.scroll(lines, window, reg.bh);
+            }
+            0x08 => {
+                let (ch, attr) = video.cell(row, col);
+                reg.set_ax(u16::from_le_bytes([ch, attr]));
+            }
+            0x09 | 0x0a => {
+                // Fills cells from the cursor on without moving it
+                let first = row * video::COLUMNS + col;
+                let last = (first + reg.get_cx() as usize).min(video::ROWS * video::COLUMNS);
+                for index in first..last {
+                    let (row, col) = (index / video::COLUMNS, index % video::COLUMNS);
+                    let attr = if reg.ah == 0x09 {
+                        reg.bl
+                    } else {
+                        video.cell(row, col).1
+                    };
+                    video.set_cell(row, col, reg.al, attr);
+                }
+            }
+            0x0e => teletype(video, reg.al),
+            0x0f => {
+                reg.al = video.adapter().mode();
+                reg.ah = video::COLUMNS as u8;
+                reg.bh = 0;
+            }
+            0x13 => {
+                let attributes = reg.al & 0b10 != 0;
+                let step = if attributes { 2 } else { 1 };
+                video.set_cursor(reg.dh as usize, reg.dl as usize);
+                for i in 0..reg.get_cx() {
+                    let addr = physical_address(reg.es, reg.bp.wrapping_add(i * step));
+                    let c = memory.read8(addr);
+                    let attr = if attributes {
+                        memory.read8(physical_address(reg.es, reg.bp.wrapping_add(i * step + 1)))
+                    } else {
+                        reg.bl
+                    };
+                    let (row, col) = video.cursor();
+                    teletype(video, c);
+                    if !matches!(c, b'\r' | b'\n' | 0x07 | 0x08) {
+                        video.set_cell(row, col, c, attr);
+                    }
+                }
+                // Bit 0 of AL: leave the cursor after the string
+                if reg.al & 0b01 == 0 {
+                    video.set_cursor(row, col);
+                }
+            }
+            _ => {
+                // Cursor shape, palette, ... are left to the guest
+            }
+        }
+    }
+
+    fn disk(
+        &mut self,
+        reg: &mut Register,
+        flag: &mut Flag,
+        memory: &mut dyn MemoryBus,
+        dump: &Dump,
+    ) {
+        let status = if reg.dl != self.disk.drive() && reg.ah != 0x00 {
+            STATUS_TIMEOUT
+        } else {
+            match reg.ah {
+                0x00 => STATUS_OK,
+                0x01 => self.disk_status,
+                0x02 | 0x03 => self.transfer(reg, memory, dump),
+                0x08 => {
+                    let g = self.disk.geometry;
+                    let max_cylinder = g.cylinders - 1;
+                    reg.bl = if self.disk.drive() < 0x80 { 0x04 } else { 0x00 };
+                    reg.ch = max_cylinder as u8;
+                    reg.cl = (g.sectors as u8 & 0x3f) | ((max_cylinder >> 2) as u8 & 0xc0);
+                    reg.dh = (g.heads - 1) as u8;
+                    reg.dl = 1;
+                    STATUS_OK
+                }
+                0x15 => {
+                    let status = if self.disk.drive() < 0x80 {
+                        0x01
+                    } else {
+                        let sectors = (self.disk.image.len() / SECTOR_SIZE) as u32;
+                        reg.set_cx((sectors >> 16) as u16);
+                        reg.set_dx(sectors as u16);
+                        0x03
+                    };
+                    reg.ah = status;
+                    flag.set_carry(false);
+                    return;
+                }
+                _ => STATUS_BAD_COMMAND,
+            }
+        };
+        self.disk_status = status;
+        reg.ah = status;
+        flag.set_carry(status != STATUS_OK);
+    }
+
+    fn transfer(&mut self, reg: &mut Register, memory: &mut dyn MemoryBus, dump: &Dump) -> u8 {
+        let is_write = reg.ah == 0x03;
+        let count = reg.al as usize;
+        let cylinder = reg.ch as usize | ((reg.cl as usize & 0xc0) << 2);
+        let sector = reg.cl as usize & 0x3f;
+        let head = reg.dh as usize;
+        let addr = physical_address(reg.es, reg.get_bx());
+
+        let lba = match self.disk.lba(cylinder, head, sector) {
+            Some(lba) => lba,
+            None => {
+                reg.al = 0;
+                return STATUS_SECTOR_NOT_FOUND;
+            }
+        };
+
+        let len = count * SECTOR_SIZE;
+        let status = if is_write {
+            let data: Vec<u8> = (0..len)
+                .map(|i| memory.read8((addr + i) & ADDRESS_MASK))
+                .collect();
+            self.disk.write(lba, &data)
+        } else {
+            match self.disk.read(lba, count) {
+                Some(data) => {
+                    for (i, byte) in data.iter().enumerate() {
+                        memory.write8((addr + i) & ADDRESS_MASK, *byte);
+                    }
+                    STATUS_OK
+                }
+                None => STATUS_SECTOR_NOT_FOUND,
+            }
+        };
+        dump.disk(is_write, lba, count, addr, status == STATUS_OK);
+        if status != STATUS_OK {
+            reg.al = 0;
+        }
+        status
+    }
+
+    // IRQ1: turns the scan code into a key in the buffer, tracking the shift keys
+    fn keyboard_irq(&mut self, memory: &mut dyn MemoryBus, ports: &mut dyn PortBus) {
+        let code = ports.read8(KBC_DATA_PORT);
+        let (scan, make) = (code & !BREAK, code & BREAK == 0);
+        let mut flags = memory.read8(BDA + SHIFT_FLAGS);
+        let modifier = match scan {
+            RIGHT_SHIFT => 0x01,
+            LEFT_SHIFT => 0x02,
+            CTRL => 0x04,
+            ALT => 0x08,
+            _ => 0,
+        };
+        if modifier != 0 {
+            flags = if make {
+                flags | modifier
+            } else {
+                flags & !modifier
+            };
+        } else if scan == CAPS_LOCK {
+            if make {
+                flags ^= CAPS_LOCK_ON;
+            }
+        } else if make {
+            // A full buffer drops the key, where a real BIOS would beep
+            push_key(memory, key(scan, flags));
+        }
+        memory.write8(BDA + SHIFT_FLAGS, flags);
+        ports.write8(PIC_COMMAND_PORT, 0x20);
+    }
+
+    fn keyboard(
+        &mut self,
+        reg: &mut Register,
+        flag: &mut Flag,
+        memory: &mut dyn MemoryBus,
+    ) -> Service {
+        match reg.ah {
+            0x00 | 0x10 => match pop_key(memory) {
+                Some(key) => reg.set_ax(key),
+                // Sleeps until IRQ1 fills the buffer
+                None => return Service::Wait,
+            },
+            0x01 | 0x11 => match peek_key(memory) {
+                Some(key) => {
+                    reg.set_ax(key);
+                    flag.set_zero(false);
+                }
+                None => flag.set_zero(true),
+            },
+            0x02 | 0x12 => reg.al = memory.read8(BDA + SHIFT_FLAGS),
+            0x05 => reg.al = if push_key(memory, reg.get_cx()) { 0 } else { 1 },
+            _ => {}
+        }
+        Service::Done
+    }
+
+    fn time(&mut self, reg: &mut Register, flag: &mut Flag) {
+        match reg.ah {
+            0x00 => {
+                let ticks = (ticks_since_midnight() + self.tick_offset) as u32;
+                reg.set_cx((ticks >> 16) as u16);
+                reg.set_dx(ticks as u16);
+                reg.al = 0;
+                flag.set_carry(false);
+            }
+            0x01 => {
+                let ticks = ((reg.get_cx() as i64) << 16) | reg.get_dx() as i64;
+                self.tick_offset = ticks - ticks_since_midnight();
+                flag.set_carry(false);
+            }
+            _ => flag.set_carry(true),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use test_case::test_case;
+
+    #[test_case(1440 * 1024, 0, 0, 1, Some(0) ; "first sector")]
+    #[test_case(1440 * 1024, 0, 1, 1, Some(18) ; "second head")]
+    #[test_case(1440 * 1024, 1, 0, 1, Some(36) ; "second cylinder")]
+    #[test_case(1440 * 1024, 0, 0, 0, None ; "sector is 1-based")]
+    #[test_case(360 * 1024, 0, 0, 10, None ; "beyond track")]
+    fn test_lba(size: usize, cylinder: usize, head: usize, sector: usize, expected: Option<usize>) {
+        let disk = Disk::new("", vec![0; size]);
+        assert_eq!(disk.lba(cylinder, head, sector), expected);
+    }
+
+    #[test_case(0x1e, 0x00, 0x1e61 ; "plain")]
+    #[test_case(0x1e, 0x02, 0x1e41 ; "shift")]
+    #[test_case(0x1e, 0x42, 0x1e61 ; "shift with caps lock")]
+    #[test_case(0x02, 0x40, 0x0231 ; "caps lock leaves digits")]
+    #[test_case(0x2e, 0x04, 0x2e03 ; "ctrl")]
+    #[test_case(0x48, 0x00, 0x4800 ; "cursor key")]
+    fn test_key(scan: u8, flags: u8, expected: u16) {
+        assert_eq!(key(scan, flags), expected);
+    }
+
+    #[test]
+    fn test_key_buffer_wraps() {
+        let mut memory = crate::memory::Memory::new(0x500);
+        memory.write16(BDA + BUFFER_HEAD, BUFFER_START);
+        memory.write16(BDA + BUFFER_TAIL, BUFFER_START);
+        // 16 slots hold 15 keys
+        assert_eq!((0..20).filter(|&k| push_key(&mut memory, k)).count(), 15);
+        assert_eq!(pop_key(&mut memory), Some(0));
+        assert!(push_key(&mut memory, 99));
+        let keys: Vec<u16> = std::iter::from_fn(|| pop_key(&mut memory)).collect();
+        assert_eq!(keys.last(), Some(&99));
+        assert_eq!(keys.len(), 15);
+    }
+}
@@ -0,0 +1,70 @@
+// Processor model, selected with --cpu. The 8088, 80188 and V20 are the 8-bit
+// bus versions of the 8086, 80186 and V30 and execute the same way, only slower.
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum Cpu {
+    #[default]
+    I8086,
+    I8088,
+    I80186,
+    I80188,
+    V20,
+    V30,
+}
+
+impl Cpu {
+    pub fn from_name(name: &str) -> Option<Cpu> {
+        match name.to_ascii_lowercase().as_str() {
+            "8086" | "86" => Some(Cpu::I8086),
+            "8088" | "88" => Some(Cpu::I8088),
+            "80186" | "186" => Some(Cpu::I80186),
+            "80188" | "188" => Some(Cpu::I80188),
+            "v20" => Some(Cpu::V20),
+            "v30" => Some(Cpu::V30),
+            _ => None,
+        }
+    }
+
+    fn is_8086_core(&self) -> bool {
+        matches!(self, Cpu::I8086 | Cpu::I8088)
+    }
+
+    fn is_nec(&self) -> bool {
+        matches!(self, Cpu::V20 | Cpu::V30)
+    }
+
+    // Each word is moved as two bytes, which costs four more clocks
+    pub fn has_byte_bus(&self) -> bool {
+        matches!(self, Cpu::I8088 | Cpu::I80188 | Cpu::V20)
+    }
+
+    // PUSHA/POPA, ENTER/LEAVE, BOUND, PUSH imm, IMUL imm, INS/OUTS and shifts by an immediate
+    pub fn has_186_instructions(&self) -> bool {
+        !self.is_8086_core()
+    }
+
+    // Later chips use only the low 5 bits of CL; the 8086 shifts up to 255 times
+    pub fn masks_shift_count(&self) -> bool {
+        !self.is_8086_core()
+    }
+
+    // 60-6F decode as 70-7F since the 8086 ignores bit 4 of a conditional jump
+    pub fn aliases_jumps(&self) -> bool {
+        self.is_8086_core()
+    }
+
+    // D6 sets AL from the carry flag; on the V20/V30 it is another XLAT
+    pub fn has_salc(&self) -> bool {
+        !self.is_nec()
+    }
+
+    // 0F pops CS on the 8086. The 80186 traps it as an invalid opcode and the
+    // V20/V30 use it to prefix their extended instructions.
+    pub fn pops_cs(&self) -> bool {
+        self.is_8086_core()
+    }
+
+    // The 80186 raises INT 6 for opcodes it does not define
+    pub fn traps_invalid_opcodes(&self) -> bool {
+        matches!(self, Cpu::I80186 | Cpu::I80188)
+    }
+}
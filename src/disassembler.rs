@@ -1,38 +1,136 @@
 use crate::{
+    cpu::Cpu,
     dump::Dump,
-    metadata::Metadata,
+    metadata::{Metadata, N_TEXT},
+    opcode::{self, Form},
     operation::{OperandType, Operation, OperationType},
 };
-use core::panic;
-use std::mem::swap;
+use std::{collections::BTreeMap, mem::swap};
 
-pub struct Disassembler {
-    text: Vec<u8>,
+// Zeros after the text, so that decoding the last bytes never reads past it
+const PADDING: usize = 8;
+
+// Unreached bytes listed per line
+const DATA_PER_LINE: usize = 6;
+
+pub struct Disassembler<'a> {
+    text: &'a [u8],
     dump: Dump,
     text_pos: usize,
+    cpu: Cpu,
 }
 
-pub fn disassemble(executable: &Vec<u8>, dump_enabled: bool) -> Vec<Operation> {
+pub fn disassemble(executable: &[u8], dump_enabled: bool, cpu: Cpu) -> Vec<Operation> {
     let metadata = Metadata::from_bytes(executable);
-    let text = executable
-        [metadata.hdr_len as usize..metadata.text_size + metadata.hdr_len as usize]
-        .to_vec();
-    let mut disassembler = Disassembler::new(text, &metadata, dump_enabled);
+    let text =
+        &executable[metadata.hdr_len as usize..metadata.text_size + metadata.hdr_len as usize];
+    let mut disassembler = Disassembler::new(text, dump_enabled, cpu);
     disassembler.disassemble_all()
 }
 
-impl Disassembler {
-    pub fn new(text: Vec<u8>, metadata: &Metadata, dump_enabled: bool) -> Self {
-        if text.len() != metadata.text_size {
-            panic!("Text segment size does not match metadata");
+// Follows control flow from the entry point, the text symbols and the targets
+// of direct jumps and calls. Bytes that no path reaches are listed as data,
+// so the listing never stops at bytes that do not decode.
+pub fn disassemble_recursive(executable: &[u8], dump_enabled: bool, cpu: Cpu) -> Vec<Operation> {
+    let metadata = Metadata::from_bytes(executable);
+    let end = metadata.data_offset().min(executable.len());
+    let text = &executable[metadata.text_offset().min(end)..end];
+    let mut symbols: Vec<_> = metadata
+        .symbols(executable)
+        .into_iter()
+        .filter(|symbol| symbol.section() == N_TEXT)
+        .collect();
+    symbols.sort_by_key(|symbol| symbol.value);
+    let mut roots = vec![metadata.entry];
+    roots.extend(symbols.iter().map(|symbol| symbol.value as usize));
+    let operations = trace(text, roots, cpu);
+
+    let dump = Dump::new(dump_enabled);
+    let is_label = |pos: usize| symbols.iter().any(|symbol| symbol.value as usize == pos);
+    let mut pos = 0;
+    while pos < text.len() {
+        for symbol in symbols.iter().filter(|symbol| symbol.value as usize == pos) {
+            dump.label(&symbol.name);
+        }
+        match operations.get(&pos) {
+            Some(op) => {
+                dump.operation(op, op.form);
+                if opcode::lookup(op.raws[0], cpu).undocumented {
+                    dump.undocumented();
+                }
+                pos = op.get_next_operation_pos();
+            }
+            None => {
+                let mut end = pos + 1;
+                while end < text.len()
+                    && end - pos < DATA_PER_LINE
+                    && !operations.contains_key(&end)
+                    && !is_label(end)
+                {
+                    end += 1;
+                }
+                dump.data(pos, &text[pos..end]);
+                pos = end;
+            }
+        }
+        dump.eol();
+    }
+    operations.into_values().collect()
+}
+
+// Decodes every instruction reachable from the roots. A path ends where the
+// bytes do not decode or would overlap an instruction found before.
+fn trace(text: &[u8], roots: Vec<usize>, cpu: Cpu) -> BTreeMap<usize, Operation> {
+    let mut padded = text.to_vec();
+    padded.resize(text.len() + PADDING, 0);
+    let mut disassembler = Disassembler::new(&padded, false, cpu);
+    let mut covered = vec![false; text.len()];
+    let mut operations = BTreeMap::new();
+    let mut pending = roots;
+    while let Some(pos) = pending.pop() {
+        if pos >= text.len() || covered[pos] {
+            continue;
+        }
+        let Some(op) = disassembler.decode_at(pos) else {
+            continue;
+        };
+        let end = op.get_next_operation_pos();
+        if end > text.len() || covered[pos..end].contains(&true) {
+            continue;
+        }
+        covered[pos..end].fill(true);
+        if op.falls_through() {
+            pending.push(end);
         }
+        pending.extend(op.target());
+        operations.insert(pos, op);
+    }
+    operations
+}
+
+impl<'a> Disassembler<'a> {
+    pub fn new(text: &'a [u8], dump_enabled: bool, cpu: Cpu) -> Self {
         Disassembler {
             text,
             dump: Dump::new(dump_enabled),
             text_pos: 0,
+            cpu,
         }
     }
 
+    fn immediate(&mut self, op: &mut Operation) -> u16 {
+        match (op.s, op.w) {
+            (0, 1) => self.word(op),
+            // Sign-extended to a word
+            (1, 1) => self.next_byte(op) as i8 as u16,
+            _ => self.next_byte(op) as u16,
+        }
+    }
+
+    fn word(&mut self, op: &mut Operation) -> u16 {
+        u16::from_le_bytes([self.next_byte(op), self.next_byte(op)])
+    }
+
     fn next_byte(&mut self, op: &mut Operation) -> u8 {
         let byte = self.text[self.text_pos];
         self.text_pos += 1;
@@ -44,14 +142,14 @@ impl Disassembler {
         match op.mod_rm {
             0b00 => {
                 if op.rm == 0b110 {
-                    op.disp = u16::from_le_bytes([self.next_byte(op), self.next_byte(op)]);
+                    op.disp = self.word(op);
                 }
             }
             0b01 => {
                 op.disp = self.next_byte(op) as u16;
             }
             0b10 => {
-                op.disp = u16::from_le_bytes([self.next_byte(op), self.next_byte(op)]);
+                op.disp = self.word(op);
             }
             0b11 => {
                 // No displacement
@@ -62,920 +160,253 @@ impl Disassembler {
         }
     }
 
-    fn next_operation(&mut self) -> Operation {
+    fn next_operation(&mut self) -> Result<Operation, String> {
         let mut op = Operation::new();
         op.pos = self.text_pos;
         let instruction = self.next_byte(&mut op);
 
         if self.text_pos >= self.text.len() && instruction == 0 {
             op.operation_type = OperationType::Undefined;
-            self.dump.none(&op);
-            return op;
+            self.dump.operation(&op, Form::None);
+            return Ok(op);
+        }
+
+        let entry = opcode::lookup(instruction, self.cpu);
+        if entry.has_mod_rm() {
+            let mod_reg_rm = self.next_byte(&mut op);
+            op.set_mod_reg_rm(mod_reg_rm);
+            self.disp(&mut op);
         }
+        let (operation_type, form) = match opcode::resolve(entry, op.reg) {
+            Some(resolved) => resolved,
+            // A group member the CPU does not define, its length is known
+            None if entry.has_mod_rm() => (OperationType::Undefined, Form::None),
+            None if self.cpu.traps_invalid_opcodes() => (OperationType::Undefined, Form::None),
+            None => return Err(format!("Unknown operation: {:02x}", instruction)),
+        };
+        op.operation_type = operation_type;
+        op.form = form;
+        let field =
+            |bit: Option<u8>, default: u8| bit.map_or(default, |bit| instruction >> bit & 1);
+        op.d = field(entry.bits.d, op.d);
+        op.w = field(entry.bits.w, op.w);
+        op.s = field(entry.bits.s, op.s);
+        op.v = field(entry.bits.v, op.v);
+        op.z = field(entry.bits.z, op.z);
+        self.operands(&mut op, instruction, form)?;
 
-        match instruction {
-            // --- Data Transfer ---
-            // Mov
-            0b1000_1000..=0b1000_1011 => {
-                // Register/Memory to/from Register
-                op.operation_type = OperationType::Mov;
-                let mod_reg_rm = self.next_byte(&mut op);
-                op.set_mod_reg_rm(mod_reg_rm);
-                self.disp(&mut op);
-                op.d = (instruction >> 1) & 1;
-                op.w = instruction & 1;
+        self.dump.operation(&op, form);
+        if entry.undocumented {
+            self.dump.undocumented();
+        }
+        Ok(op)
+    }
+
+    // Reads what follows the opcode and ModRM byte and names the operands
+    fn operands(&mut self, op: &mut Operation, instruction: u8, form: Form) -> Result<(), String> {
+        match form {
+            Form::None | Form::InDx | Form::OutDx | Form::Esc => {}
+            Form::RmReg => {
                 op.first = OperandType::EA;
                 op.second = OperandType::Reg;
                 if op.d == 1 {
                     swap(&mut op.second, &mut op.first);
                 }
-                self.dump.simple_calc1(&op);
-            }
-            0b1100_0110 | 0b1100_0111 => {
-                // Immediate to Register/Memory
-                op.operation_type = OperationType::Mov;
-                let mod_reg_rm = self.next_byte(&mut op);
-                op.set_mod_reg_rm(mod_reg_rm);
-                self.disp(&mut op);
-                op.w = instruction & 1;
-                if op.w == 1 {
-                    op.data =
-                        u16::from_le_bytes([self.next_byte(&mut op), self.next_byte(&mut op)]);
-                } else {
-                    op.data = self.next_byte(&mut op) as u16;
-                }
+            }
+            Form::RmImm => {
+                op.data = self.immediate(op);
                 op.first = OperandType::EA;
                 op.second = OperandType::Imm;
-                self.dump.simple_calc2(&op);
             }
-            0b1011_0000..=0b1011_1111 => {
-                // Immediate to Register
-                op.operation_type = OperationType::Mov;
-                op.w = instruction >> 3 & 1;
+            Form::AccImm => {
+                op.data = self.immediate(op);
+                op.first = OperandType::Reg;
+                op.second = OperandType::Imm;
+            }
+            Form::RegImm => {
                 op.reg = instruction & 0b111;
-                if op.w == 1 {
-                    op.data =
-                        u16::from_le_bytes([self.next_byte(&mut op), self.next_byte(&mut op)]);
-                } else {
-                    op.data = self.next_byte(&mut op) as u16;
-                }
+                op.data = self.immediate(op);
                 op.first = OperandType::Reg;
                 op.second = OperandType::Imm;
-                self.dump.simple_calc3(&op);
             }
-            0b1010_0000..=0b1010_0011 => {
-                // Memory to Accumulator
-                // Accumulator to Memory
-                op.operation_type = OperationType::Mov;
-                op.w = instruction & 1;
+            Form::AccMem | Form::MemAcc => {
                 op.rm = 0b110;
-                op.disp = u16::from_le_bytes([self.next_byte(&mut op), self.next_byte(&mut op)]);
+                op.disp = self.word(op);
                 op.first = OperandType::Reg;
                 op.second = OperandType::EA;
-                match (instruction >> 1) & 1 {
-                    0 => self.dump.mov4(&op),
-                    1 => {
-                        swap(&mut op.first, &mut op.second);
-                        self.dump.mov5(&op);
-                    }
-                    _ => panic!("Invalid operation"),
-                }
-            }
-            0b1000_1110 | 0b1000_1100 => {
-                // Register to Segment Register
-                // Segment Register to Register
-                op.operation_type = OperationType::Mov;
-                let mod_reg_rm = self.next_byte(&mut op);
-                op.set_mod_reg_rm(mod_reg_rm);
-                self.disp(&mut op);
-                op.first = OperandType::SegReg;
-                op.second = OperandType::Reg;
-                if op.raws[0] & 0b10 == 0 {
+                if form == Form::MemAcc {
                     swap(&mut op.first, &mut op.second);
                 }
+            }
+            Form::RmSeg | Form::SegRm => {
                 if op.reg & 0b100 != 0 {
-                    panic!("Invalid operation. reg must be 0b0xx in this operation");
+                    return Err(
+                        "Invalid operation. reg must be 0b0xx in this operation".to_string()
+                    );
                 }
-            }
-            // Push
-            0b0101_0000..=0b0101_0111 => {
-                // Register
-                op.operation_type = OperationType::Push;
-                op.reg = instruction & 0b111;
-                op.first = OperandType::Reg;
-                self.dump.stack2(&op);
-            }
-            0b0000_0110 | 0b0000_1110 | 0b0001_0110 | 0b0001_1110 => {
-                // Segment Register
-                op.operation_type = OperationType::Push;
-                op.reg = instruction >> 3 & 0b111;
                 op.first = OperandType::SegReg;
-                self.dump.stack3(&op);
-            }
-            // Pop
-            0b1000_1111 => {
-                // Register/Memory
-                op.operation_type = OperationType::Pop;
-                let mod_reg_rm = self.next_byte(&mut op);
-                op.set_mod_reg_rm(mod_reg_rm);
-                self.disp(&mut op);
-                if op.reg != 0b110 {
-                    panic!("Invalid operation. reg must be 0b110 in this operation");
+                op.second = OperandType::EA;
+                if form == Form::RmSeg {
+                    swap(&mut op.first, &mut op.second);
                 }
-                op.first = OperandType::EA;
-                self.dump.stack1(&op);
             }
-            0b0101_1000..=0b0101_1111 => {
-                // Register
-                op.operation_type = OperationType::Pop;
+            Form::Reg | Form::RegAcc => {
                 op.reg = instruction & 0b111;
                 op.first = OperandType::Reg;
-                self.dump.stack2(&op);
             }
-            0b0000_0111 | 0b0000_1111 | 0b0001_0111 | 0b0001_1111 => {
-                // Segment Register
-                op.operation_type = OperationType::Pop;
+            Form::Seg => {
                 op.reg = instruction >> 3 & 0b111;
                 op.first = OperandType::SegReg;
-                self.dump.stack3(&op);
-            }
-            // Xchg
-            0b1000_0110 | 0b1000_0111 => {
-                // Register/Memory with Register
-                op.operation_type = OperationType::Xchg;
-                let mod_reg_rm = self.next_byte(&mut op);
-                op.set_mod_reg_rm(mod_reg_rm);
-                self.disp(&mut op);
-                op.w = instruction & 1;
-                op.first = OperandType::EA;
-                op.second = OperandType::Reg;
-                self.dump.xchg1(&op);
             }
-            0b1001_0000..=0b1001_0111 => {
-                // Register with Accumulator
-                op.operation_type = OperationType::Xchg;
-                op.reg = instruction & 0b111;
-                op.first = OperandType::Reg;
-                self.dump.xchg2(&op);
-            }
-            // In
-            0b1110_0100 | 0b1110_0101 => {
-                // Fixed Port
-                op.operation_type = OperationType::In;
-                op.w = instruction & 1;
-                op.port = self.next_byte(&mut op);
-                self.dump.in1(&op);
-            }
-            0b1110_1100 | 0b1110_1101 => {
-                // Variable Port
-                op.operation_type = OperationType::In;
-                op.w = instruction & 1;
-                self.dump.in2(&op);
-            }
-            // Out
-            0b1110_0110 | 0b1110_0111 => {
-                // Fixed Port
-                op.operation_type = OperationType::Out;
-                op.w = instruction & 1;
-                op.port = self.next_byte(&mut op);
-            }
-            0b1110_1110 | 0b1110_1111 => {
-                // Variable Port
-                op.operation_type = OperationType::Out;
-                op.w = instruction & 1;
-            }
-            // Xlat
-            0b1101_0111 => {
-                op.operation_type = OperationType::Xlat;
-            }
-            // Lea
-            0b1000_1101 => {
-                op.operation_type = OperationType::Lea;
-                let mod_reg_rm = self.next_byte(&mut op);
-                op.set_mod_reg_rm(mod_reg_rm);
-                self.disp(&mut op);
-                self.dump.lea(&op);
-            }
-            // Lds
-            0b1100_0101 => {
-                op.operation_type = OperationType::Lds;
-                let mod_reg_rm = self.next_byte(&mut op);
-                op.set_mod_reg_rm(mod_reg_rm);
-                self.disp(&mut op);
-            }
-            // Les
-            0b1100_0100 => {
-                op.operation_type = OperationType::Les;
-                let mod_reg_rm = self.next_byte(&mut op);
-                op.set_mod_reg_rm(mod_reg_rm);
-                self.disp(&mut op);
-            }
-            // Lahf
-            0b1001_1111 => {
-                op.operation_type = OperationType::Lahf;
-            }
-            // Sahf
-            0b1001_1110 => {
-                op.operation_type = OperationType::Sahf;
-            }
-            // Pushf
-            0b1001_1100 => {
-                op.operation_type = OperationType::Pushf;
-            }
-            // Popf
-            0b1001_1101 => {
-                op.operation_type = OperationType::Popf;
-            }
-
-            // --- Arithmetic ---
-            // Add
-            0b0000_0000..=0b0000_0011 => {
-                // Register/Memory with Register to Either
-                op.operation_type = OperationType::Add;
-                let mod_reg_rm = self.next_byte(&mut op);
-                op.set_mod_reg_rm(mod_reg_rm);
-                self.disp(&mut op);
-                op.d = (instruction >> 1) & 1;
-                op.w = instruction & 1;
+            Form::Rm => {
                 op.first = OperandType::EA;
-                op.second = OperandType::Reg;
-                if op.d == 1 {
-                    swap(&mut op.second, &mut op.first);
-                }
-                self.dump.simple_calc1(&op);
-            }
-            0b0000_0100 | 0b0000_0101 => {
-                // Immediate to Accumulator
-                op.operation_type = OperationType::Add;
-                op.w = instruction & 1;
-                if op.w == 1 {
-                    op.data =
-                        u16::from_le_bytes([self.next_byte(&mut op), self.next_byte(&mut op)]);
-                } else {
-                    op.data = self.next_byte(&mut op) as u16;
-                }
-                op.first = OperandType::Reg;
-                op.second = OperandType::Imm;
-                self.dump.simple_calc3(&op);
-            }
-            // Adc
-            0b0001_0000..=0b0001_0011 => {
-                // Register/Memory with Register to Either
-                op.operation_type = OperationType::Adc;
-                let mod_reg_rm = self.next_byte(&mut op);
-                op.set_mod_reg_rm(mod_reg_rm);
-                self.disp(&mut op);
-                op.d = (instruction >> 1) & 1;
-                op.w = instruction & 1;
-                op.first = OperandType::EA;
-                op.second = OperandType::Reg;
-                if op.d == 1 {
-                    swap(&mut op.second, &mut op.first);
-                }
-                self.dump.simple_calc1(&op);
-            }
-            0b0001_0100 | 0b0001_0101 => {
-                // Immediate to Accumulator
-                op.operation_type = OperationType::Adc;
-                op.w = instruction & 1;
-                op.data = if op.w == 1 {
-                    u16::from_le_bytes([self.next_byte(&mut op), self.next_byte(&mut op)])
-                } else {
-                    self.next_byte(&mut op) as u16
-                };
-                op.first = OperandType::Reg;
-                op.second = OperandType::Imm;
-                self.dump.simple_calc3(&op);
-            }
-            // Inc
-            0b1111_1110 => {
-                // Register/Memory
-                op.operation_type = OperationType::Inc;
-                let mod_reg_rm = self.next_byte(&mut op);
-                op.set_mod_reg_rm(mod_reg_rm);
-                self.disp(&mut op);
-                op.w = instruction & 1;
-                op.first = OperandType::EA;
-                self.dump.inc_dec1(&op);
-            }
-            0b0100_0000..=0b0100_0111 => {
-                // Register
-                op.operation_type = OperationType::Inc;
-                op.reg = instruction & 0b111;
-                op.first = OperandType::Reg;
-                self.dump.inc_dec2(&op);
-            }
-            // Aaa
-            0b0011_0111 => {
-                op.operation_type = OperationType::Aaa;
-            }
-            // Baa
-            0b0010_0111 => {
-                op.operation_type = OperationType::Baa;
-            }
-            // Sub
-            0b0010_1000..=0b0010_1011 => {
-                // Register/Memory with Register to Either
-                op.operation_type = OperationType::Sub;
-                let mod_reg_rm = self.next_byte(&mut op);
-                op.set_mod_reg_rm(mod_reg_rm);
-                self.disp(&mut op);
-                op.d = (instruction >> 1) & 1;
-                op.w = instruction & 1;
-                op.first = OperandType::EA;
-                op.second = OperandType::Reg;
-                if op.d == 1 {
-                    swap(&mut op.second, &mut op.first);
-                }
-                self.dump.simple_calc1(&op);
-            }
-            0b0010_1100 | 0b0010_1101 => {
-                // Immediate from Accumulator
-                op.operation_type = OperationType::Sub;
-                op.w = instruction & 1;
-                op.data = if op.w == 1 {
-                    u16::from_le_bytes([self.next_byte(&mut op), self.next_byte(&mut op)])
-                } else {
-                    self.next_byte(&mut op) as u16
-                };
-                op.first = OperandType::Reg;
-                op.second = OperandType::Imm;
-                self.dump.simple_calc3(&op);
-            }
-            // Ssb
-            0b0001_1000..=0b0001_1011 => {
-                // Register/Memory with Register to Either
-                op.operation_type = OperationType::Sbb;
-                let mod_reg_rm = self.next_byte(&mut op);
-                op.set_mod_reg_rm(mod_reg_rm);
-                self.disp(&mut op);
-                op.d = (instruction >> 1) & 1;
-                op.w = instruction & 1;
-                op.first = OperandType::EA;
-                op.second = OperandType::Reg;
-                if op.d == 1 {
-                    swap(&mut op.second, &mut op.first);
-                }
-                self.dump.simple_calc1(&op);
-            }
-            0b0001_1100 | 0b0001_1101 => {
-                // データシートの誤植
-                // https://qiita.com/7shi/items/b3911948f9d97b05395e#%E4%BB%95%E6%A7%98%E6%9B%B8
-                // Immediate from Accumulator
-                op.operation_type = OperationType::Sbb;
-                op.w = instruction & 1;
-                op.data = if op.w == 1 {
-                    u16::from_le_bytes([self.next_byte(&mut op), self.next_byte(&mut op)])
-                } else {
-                    self.next_byte(&mut op) as u16
-                };
             }
-            // Dec
-            0b0100_1000..=0b0100_1111 => {
-                // Register
-                op.operation_type = OperationType::Dec;
-                op.reg = instruction & 0b111;
-                op.first = OperandType::Reg;
-                self.dump.inc_dec2(&op);
-            }
-            // Cmp
-            0b0011_1000..=0b0011_1011 => {
-                // Register/Memory and Register
-                op.operation_type = OperationType::Cmp;
-                let mod_reg_rm = self.next_byte(&mut op);
-                op.set_mod_reg_rm(mod_reg_rm);
-                self.disp(&mut op);
-                op.d = (instruction >> 1) & 1;
-                op.w = instruction & 1;
-                op.first = OperandType::EA;
-                op.second = OperandType::Reg;
-                if op.d == 1 {
-                    swap(&mut op.second, &mut op.first);
-                }
-                self.dump.simple_calc1(&op);
-            }
-            0b0011_1100 | 0b0011_1101 => {
-                // Immediate with Accumulator
-                op.operation_type = OperationType::Cmp;
-                op.w = instruction & 1;
-                op.data = if op.w == 1 {
-                    u16::from_le_bytes([self.next_byte(&mut op), self.next_byte(&mut op)])
-                } else {
-                    self.next_byte(&mut op) as u16
-                };
-                op.first = OperandType::Reg;
-                op.second = OperandType::Imm;
-                self.dump.simple_calc3(&op);
-            }
-            // Aas
-            0b0011_1111 => {
-                op.operation_type = OperationType::Aas;
-                self.dump.name(&op);
-            }
-            // Das
-            0b0010_1111 => {
-                op.operation_type = OperationType::Das;
-                self.dump.name(&op);
-            }
-            // Cbw
-            0b1001_1000 => {
-                op.operation_type = OperationType::Cbw;
-                self.dump.name(&op);
-            }
-            // Cwd
-            0b1001_1001 => {
-                op.operation_type = OperationType::Cwd;
-                self.dump.name(&op);
-            }
-
-            // --- Logic ---
-            // And
-            0b0010_0000..=0b0010_0011 => {
-                // Register/Memory and Register to Either
-                op.operation_type = OperationType::And;
-                let mod_reg_rm = self.next_byte(&mut op);
-                op.set_mod_reg_rm(mod_reg_rm);
-                self.disp(&mut op);
-                op.d = (instruction >> 1) & 1;
-                op.w = instruction & 1;
-                op.first = OperandType::EA;
-                op.second = OperandType::Reg;
-                if op.d == 1 {
-                    swap(&mut op.second, &mut op.first);
-                }
-                self.dump.bit_op1(&op);
-            }
-            0b0010_0100 | 0b0010_0101 => {
-                // Immediate with Accumulator
-                op.operation_type = OperationType::And;
-                op.w = instruction & 1;
-                op.data = if op.w == 1 {
-                    u16::from_le_bytes([self.next_byte(&mut op), self.next_byte(&mut op)])
-                } else {
-                    self.next_byte(&mut op) as u16
-                };
-                op.first = OperandType::Reg;
-                op.second = OperandType::Imm;
-                self.dump.bit_op3(&op);
-            }
-            // Test
-            0b1000_0100 | 0b1000_0101 => {
-                // Register/Memory and Register
-                op.operation_type = OperationType::Test;
-                let mod_reg_rm = self.next_byte(&mut op);
-                op.set_mod_reg_rm(mod_reg_rm);
-                self.disp(&mut op);
-                op.first = OperandType::EA;
-                op.second = OperandType::Reg;
-                if op.d == 1 {
-                    swap(&mut op.second, &mut op.first);
-                }
-                self.dump.bit_op1(&op);
-            }
-            0b1010_1000 | 0b1010_1001 => {
-                // Immediate Data and Accumulator
-                op.operation_type = OperationType::Test;
-                op.w = instruction & 1;
-                op.data = if op.w == 1 {
-                    u16::from_le_bytes([self.next_byte(&mut op), self.next_byte(&mut op)])
-                } else {
-                    self.next_byte(&mut op) as u16
-                };
-                op.first = OperandType::Reg;
-                op.second = OperandType::Imm;
-                self.dump.bit_op3(&op);
-            }
-            // Or
-            0b0000_1000..=0b0000_1011 => {
-                // Register/Memory and Register to Either
-                op.operation_type = OperationType::Or;
-                let mod_reg_rm = self.next_byte(&mut op);
-                op.set_mod_reg_rm(mod_reg_rm);
-                self.disp(&mut op);
-                op.d = (instruction >> 1) & 1;
-                op.w = instruction & 1;
-                op.first = OperandType::EA;
-                op.second = OperandType::Reg;
-                if op.d == 1 {
-                    swap(&mut op.second, &mut op.first);
-                }
-                self.dump.bit_op1(&op);
-            }
-            0b0000_1100 | 0b0000_1101 => {
-                // Immediate with Accumulator
-                op.operation_type = OperationType::Or;
-                op.w = instruction & 1;
-                op.data = if op.w == 1 {
-                    u16::from_le_bytes([self.next_byte(&mut op), self.next_byte(&mut op)])
-                } else {
-                    self.next_byte(&mut op) as u16
-                };
+            Form::RegMem => {
                 op.first = OperandType::Reg;
-                op.second = OperandType::Imm;
-                self.dump.bit_op3(&op);
-            }
-            // Xor
-            0b0011_0000..=0b0011_0011 => {
-                // Register/Memory and Register to Either
-                op.operation_type = OperationType::Xor;
-                let mod_reg_rm = self.next_byte(&mut op);
-                op.set_mod_reg_rm(mod_reg_rm);
-                self.disp(&mut op);
-                op.d = (instruction >> 1) & 1;
-                op.w = instruction & 1;
-                op.first = OperandType::EA;
-                op.second = OperandType::Reg;
-                if op.d == 1 {
-                    swap(&mut op.second, &mut op.first);
-                }
-                self.dump.bit_op1(&op);
-            }
-            0b0011_0100 | 0b0011_0101 => {
-                // Immediate with Accumulator
-                op.operation_type = OperationType::Xor;
-                op.w = instruction & 1;
-                op.data = if op.w == 1 {
-                    u16::from_le_bytes([self.next_byte(&mut op), self.next_byte(&mut op)])
-                } else {
-                    self.next_byte(&mut op) as u16
-                };
-                self.dump.bit_op3(&op);
+                op.second = OperandType::EA;
             }
-
-            // --- String Manipulation ---
-            // Rep
-            0b1111_0010 | 0b1111_0011 => {
-                op.operation_type = OperationType::Rep;
-                op.z = instruction & 1;
-                let next_op = self.next_byte(&mut op);
-                op.rep_operation_type = match next_op >> 1 & 0b111 {
-                    0b010 => OperationType::Movs,
-                    0b011 => OperationType::Cmps,
-                    0b111 => OperationType::Scas,
-                    0b110 => OperationType::Lods,
-                    0b101 => OperationType::Stos,
+            Form::InPort | Form::OutPort => {
+                op.port = self.next_byte(op);
+            }
+            Form::Rep => {
+                let next_op = self.next_byte(op);
+                op.rep_operation_type = match opcode::resolve(opcode::lookup(next_op, self.cpu), 0)
+                {
+                    Some((
+                        operation_type @ (OperationType::Movs
+                        | OperationType::Cmps
+                        | OperationType::Scas
+                        | OperationType::Lods
+                        | OperationType::Stos
+                        | OperationType::Ins
+                        | OperationType::Outs),
+                        _,
+                    )) => operation_type,
                     _ => {
-                        panic!("Invalid operation. {next_op:04x}");
+                        return Err(format!("Invalid operation. {next_op:04x}"));
                     }
                 };
                 op.w = next_op & 1;
-                self.dump.rep(&op);
-            }
-            // Movs/Cmps/Scas/Lods/Stos
-            0b1010_0100..=0b1010_1111 => {
-                op.w = instruction & 1;
-                op.operation_type = match instruction >> 1 & 0b111 {
-                    0b010 => OperationType::Movs,
-                    0b011 => OperationType::Cmps,
-                    0b111 => OperationType::Scas,
-                    0b110 => OperationType::Lods,
-                    0b101 => OperationType::Stos,
-                    _ => {
-                        panic!("Invalid operation. reg is invalid");
-                    }
-                };
-                self.dump.name(&op);
             }
-
-            // --- Control Transfer ---
-            // Call
-            0b1110_1000 => {
-                // Direct within Segment
-                op.operation_type = OperationType::Call;
-                op.disp = u16::from_le_bytes([self.next_byte(&mut op), self.next_byte(&mut op)]);
+            Form::Near => {
+                op.disp = self.word(op);
                 op.first = OperandType::Disp;
-                self.dump.call1(&op);
             }
-            0b1001_1010 => {
-                // Direct Intersegment
-                op.operation_type = OperationType::Call;
-                // offset-low offset-high
-                // seg-low seg-high
-                op.disp = u16::from_le_bytes([self.next_byte(&mut op), self.next_byte(&mut op)]);
-            }
-            // Jmp
-            0b1110_1001 => {
-                // Direct within Segment
-                op.operation_type = OperationType::Jmp;
-                op.disp = u16::from_le_bytes([self.next_byte(&mut op), self.next_byte(&mut op)]);
-                op.first = OperandType::Disp;
-                self.dump.jmp1(&op);
-            }
-            0b1110_1011 => {
-                // Direct within Segment-Short
-                op.operation_type = OperationType::Jmp;
-                op.disp = self.next_byte(&mut op) as u16;
+            Form::Short => {
+                op.disp = self.next_byte(op) as u16;
                 op.first = OperandType::Disp;
-                self.dump.jmp2(&op);
-            }
-            0b1110_1010 => {
-                // Direct Intersegment
-                op.operation_type = OperationType::Jmp;
-                op.disp = u16::from_le_bytes([self.next_byte(&mut op), self.next_byte(&mut op)]);
-            }
-            // Ret
-            0b1100_0011 | 0b1100_1011 => {
-                // Within Segment
-                // Intersegment
-                op.operation_type = OperationType::Ret;
-                self.dump.name(&op);
-            }
-            0b1100_0010 | 0b1100_1010 => {
-                // Within Segment Adding Immed to Sp
-                // Within Segment Adding Immediate to Sp
-                op.operation_type = OperationType::Ret;
-                op.disp = u16::from_le_bytes([self.next_byte(&mut op), self.next_byte(&mut op)]);
-                op.first = OperandType::Imm;
-                if instruction & 0b1000 == 1 {
-                    panic!("Not implemented yet");
-                } else {
-                    self.dump.ret2(&op);
-                }
-            }
-            // Jump
-            0b0111_0000..=0b0111_1111 => {
-                op.operation_type = match instruction & 0b1111 {
-                    0b0000 => OperationType::Jo,
-                    0b0001 => OperationType::Jno,
-                    0b0010 => OperationType::JbJnae,
-                    0b0011 => OperationType::JnbJae,
-                    0b0100 => OperationType::JeJz,
-                    0b0101 => OperationType::JneJnz,
-                    0b0110 => OperationType::JbeJna,
-                    0b0111 => OperationType::JnbeJa,
-                    0b1000 => OperationType::Js,
-                    0b1001 => OperationType::Jns,
-                    0b1010 => OperationType::JpJpe,
-                    0b1011 => OperationType::JnpJpo,
-                    0b1100 => OperationType::JlJnge,
-                    0b1101 => OperationType::JnlJge,
-                    0b1110 => OperationType::JleJng,
-                    0b1111 => OperationType::JnleJg,
-                    _ => {
-                        panic!("This code is not reachable");
-                    }
-                };
-                op.disp = self.next_byte(&mut op) as u16;
-                self.dump.jump(&op);
-            }
-            // Loop
-            0b1110_0000..=0b1110_0010 => {
-                op.disp = self.next_byte(&mut op) as u16;
-                match instruction & 0b11 {
-                    0b10 => {
-                        op.operation_type = OperationType::Loop;
-                        self.dump.loop1(&op);
-                    }
-                    0b01 => {
-                        op.operation_type = OperationType::LoopzLoope;
-                        panic!("Not implemented yet");
-                    }
-                    0b00 => {
-                        op.operation_type = OperationType::LoopnzLoopne;
-                        panic!("Not implemented yet");
-                    }
-                    _ => {
-                        panic!("This code is not reachable");
-                    }
-                };
-            }
-            // Jump
-            0b1110_0011 => {
-                // Jump on CX Zero
-                op.operation_type = OperationType::Jcxz;
-                op.disp = self.next_byte(&mut op) as u16;
-            }
-            // Int
-            0b1100_1101 => {
-                // Type Specified
-                op.operation_type = OperationType::Int;
-                op.int_type = self.next_byte(&mut op);
-                self.dump.int1(&op);
-            }
-            0b11001100 => {
-                // Type 3
-                op.operation_type = OperationType::Int;
-                self.dump.int2(&op);
-            }
-            // Into
-            0b1100_1110 => {
-                op.operation_type = OperationType::Into;
-            }
-            // Iret
-            0b1100_1111 => {
-                op.operation_type = OperationType::Iret;
-            }
-
-            // --- Processor Control ---
-            0b1111_1000 => {
-                op.operation_type = OperationType::Clc;
-                self.dump.name(&op);
-            }
-            0b1111_0101 => {
-                op.operation_type = OperationType::Cmc;
-                self.dump.name(&op);
-            }
-            0b1111_1001 => {
-                op.operation_type = OperationType::Stc;
-                self.dump.name(&op);
             }
-            0b1111_1100 => {
-                op.operation_type = OperationType::Cld;
-                self.dump.name(&op);
-            }
-            0b1111_1101 => {
-                op.operation_type = OperationType::Std;
-                self.dump.name(&op);
+            Form::Rel8 => {
+                op.disp = self.next_byte(op) as u16;
             }
-            0b1111_1010 => {
-                op.operation_type = OperationType::Cli;
-                self.dump.name(&op);
+            Form::Far => {
+                // offset-low offset-high
+                // seg-low seg-high
+                op.disp = self.word(op);
+                op.data = self.word(op);
+                op.first = OperandType::Far;
             }
-            0b1111_1011 => {
-                op.operation_type = OperationType::Sti;
-                self.dump.name(&op);
+            Form::RetImm => {
+                op.disp = self.word(op);
+                op.first = OperandType::Imm;
             }
-            0b1111_0100 => {
-                op.operation_type = OperationType::Hlt;
-                self.dump.name(&op);
+            Form::Int => {
+                op.int_type = self.next_byte(op);
             }
-            0b1001_1011 => {
-                op.operation_type = OperationType::Wait;
-                self.dump.name(&op);
+            Form::Int3 => {
+                op.int_type = 3;
             }
-            0b1101_1000..=0b1101_1111 => {
-                op.operation_type = OperationType::Esc;
-                op.reg = instruction & 0b111;
+            Form::Segment => {
+                op.reg = instruction >> 3 & 0b11;
             }
-            0b1111_0000 => {
-                op.operation_type = OperationType::Lock;
-                self.dump.name(&op);
+            Form::Base => {
+                let next = self.next_byte(op);
+                if next != 0b0000_1010 {
+                    return Err("Invalid operation".to_string());
+                }
+                op.data = next as u16;
             }
-
-            // --- Common ---
-            // Add/Adc/Sub/Ssb/Cmp/And
-            0b1000_0000..=0b1000_0011 => {
-                // Immediate to/with Register/Memory
-                let mod_reg_rm = self.next_byte(&mut op);
-                op.set_mod_reg_rm(mod_reg_rm);
-                self.disp(&mut op);
-                op.s = (instruction >> 1) & 1;
-                op.w = instruction & 1;
-                op.data = if op.s == 0 && op.w == 1 {
-                    u16::from_le_bytes([self.next_byte(&mut op), self.next_byte(&mut op)])
-                } else {
-                    self.next_byte(&mut op) as u16
-                };
-                op.operation_type = match op.reg {
-                    0b000 => OperationType::Add,
-                    0b010 => OperationType::Adc,
-                    0b101 => OperationType::Sub,
-                    0b011 => OperationType::Sbb,
-                    0b111 => OperationType::Cmp,
-                    0b100 => OperationType::And,
-                    0b001 => OperationType::Or,
-                    0b110 => OperationType::Xor,
-                    _ => {
-                        panic!("Invalid operation. reg is invalid");
-                    }
-                };
+            Form::Shift => {
                 op.first = OperandType::EA;
                 op.second = OperandType::Imm;
-                self.dump.simple_calc2(&op)
-            }
-            // Push/Inc/Dec/Call
-            0b1111_1111 => {
-                // Register/Memory
-                let mod_reg_rm = self.next_byte(&mut op);
-                op.set_mod_reg_rm(mod_reg_rm);
-                self.disp(&mut op);
-                op.operation_type = match op.reg {
-                    0b110 => OperationType::Push,
-                    0b000 => OperationType::Inc,
-                    0b001 => OperationType::Dec,
-                    0b010 | 0b011 => OperationType::Call,
-                    0b100 | 0b101 => OperationType::Jmp,
-                    _ => {
-                        panic!("Invalid operation. reg is invalid");
-                    }
-                };
-                op.first = OperandType::EA;
-                self.dump.stack1(&op);
-            }
-            // Neg/Mul/Imul/Div/Idiv/Not
-            0b1111_0110 | 0b1111_0111 => {
-                let mod_reg_rm = self.next_byte(&mut op);
-                op.set_mod_reg_rm(mod_reg_rm);
-                self.disp(&mut op);
-                op.w = instruction & 1;
-                op.operation_type = match op.reg {
-                    0b011 => OperationType::Neg,
-                    0b100 => OperationType::Mul,
-                    0b101 => OperationType::Imul,
-                    0b110 => OperationType::Div,
-                    0b111 => OperationType::Idiv,
-                    0b010 => OperationType::Not,
-                    0b000 => {
-                        // Immediate Data and Register/Memory
-                        op.data = if op.w == 1 {
-                            // 16-bit immediate
-                            u16::from_le_bytes([self.next_byte(&mut op), self.next_byte(&mut op)])
-                        } else {
-                            // 8-bit immediate
-                            self.next_byte(&mut op) as u16
-                        };
-                        OperationType::Test
-                    }
-                    _ => {
-                        panic!("Invalid operation. reg is invalid");
-                    }
-                };
-
-                op.first = OperandType::EA;
-                op.second = OperandType::Reg;
-                if op.w == 1 {
-                    swap(&mut op.second, &mut op.first);
-                }
-
-                if op.operation_type == OperationType::Neg {
-                    op.first = OperandType::EA;
-                    op.second = OperandType::None;
-                }
-
-                if op.operation_type == OperationType::Test {
-                    op.first = OperandType::EA;
-                    op.second = OperandType::Imm;
-                    self.dump.test2(&op);
-                } else {
-                    self.dump.complicate_calc(&op);
-                }
-            }
-            // Aam
-            0b1101_0100 => {
-                let next = self.next_byte(&mut op);
-                op.operation_type = match next {
-                    0b0000_1010 => OperationType::Aam,
-                    _ => {
-                        panic!("Invalid operation");
-                    }
-                }
-            }
-            // Aad
-            0b1101_0101 => {
-                let next = self.next_byte(&mut op);
-                op.operation_type = match next {
-                    0b0000_1010 => OperationType::Aad,
-                    _ => {
-                        panic!("Invalid operation");
-                    }
-                }
             }
-            // Shl/Sal/Shr/Sar/Rol/Ror/Rcl/Rcr
-            0b1101_0000..=0b1101_0011 => {
-                let mod_reg_rm = self.next_byte(&mut op);
-                op.set_mod_reg_rm(mod_reg_rm);
-                self.disp(&mut op);
-                op.v = instruction >> 1 & 1;
-                op.w = instruction & 1;
-                op.operation_type = match op.reg {
-                    0b100 => OperationType::ShlSal,
-                    0b101 => OperationType::Shr,
-                    0b111 => OperationType::Sar,
-                    0b000 => OperationType::Rol,
-                    0b001 => OperationType::Ror,
-                    0b010 => OperationType::Rcl,
-                    0b011 => OperationType::Rcr,
-                    _ => {
-                        panic!("Invalid operation. reg is invalid");
-                    }
-                };
+            Form::ShiftImm => {
+                op.data = self.next_byte(op) as u16;
                 op.first = OperandType::EA;
                 op.second = OperandType::Imm;
-                self.dump.shift_rotate(&op);
             }
-            _ => {
-                panic!("Unknown operation: {:02x}", instruction);
+            Form::PushImm => {
+                op.data = self.immediate(op);
+                op.first = OperandType::Imm;
+            }
+            Form::ImulImm => {
+                op.data = self.immediate(op);
+                op.first = OperandType::Reg;
+                op.second = OperandType::EA;
+            }
+            Form::Enter => {
+                // Frame size and nesting level
+                op.data = self.word(op);
+                op.disp = self.next_byte(op) as u16;
             }
         }
-        op
+        Ok(())
     }
 
     fn disassemble_all(&mut self) -> Vec<Operation> {
         let mut operations = Vec::new();
         while self.text_pos < self.text.len() {
-            let op = self.next_operation();
+            let op = self.next_operation().unwrap_or_else(|e| panic!("{}", e));
             operations.push(op);
             self.dump.eol();
         }
         operations
     }
 
+    // The instruction at pos, None where the bytes are not one
+    fn decode_at(&mut self, pos: usize) -> Option<Operation> {
+        self.text_pos = pos;
+        self.next_operation()
+            .ok()
+            .filter(|op| op.operation_type != OperationType::Undefined)
+    }
+
     pub fn next(&mut self, ip: u16) -> Option<Operation> {
         self.text_pos = ip as usize;
         if self.text_pos >= self.text.len() {
             return None;
         }
-        let op = self.next_operation();
+        let op = self.next_operation().unwrap_or_else(|e| panic!("{}", e));
         Some(op)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::assembler;
+
+    #[test]
+    fn test_disassemble_recursive() {
+        // A word table and bytes that do not decode between the instructions
+        let source = "
+            start:  CALL 11
+                    MOV BX, [9]
+                    JMP 14
+                    dw 1234, 5678
+                    db ff, ff, 'A', 'B'
+                    RET
+                    db 0f, ff
+                    HLT
+        ";
+        let mut executable = assembler::assemble(source, Cpu::I8086).unwrap();
+        let operations = disassemble_recursive(&executable, false, Cpu::I8086);
+        let positions: Vec<usize> = operations.iter().map(|op| op.pos).collect();
+        assert_eq!(positions, [0x00, 0x03, 0x07, 0x11, 0x14]);
+
+        // Without the symbol table the entry point leads to the same code
+        executable[28..32].fill(0);
+        let operations = disassemble_recursive(&executable, false, Cpu::I8086);
+        assert_eq!(operations.len(), 5);
+    }
+}
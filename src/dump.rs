@@ -1,7 +1,8 @@
 use core::panic;
 
 use crate::{
-    flag,
+    flag, fpu,
+    opcode::Form,
     operation::OperationType,
     register::{
         calc_relative_disp, effective_address, Register, Register16Bit, Register8Bit, RegisterType,
@@ -11,13 +12,15 @@ use crate::{
 
 use super::operation::Operation;
 
-fn dump_type(op_type: &OperationType, w: u8) {
+fn dump_type(out: &mut String, op_type: &OperationType, w: u8) {
     let type_str = match op_type {
         OperationType::Movs
         | OperationType::Cmps
         | OperationType::Scas
         | OperationType::Lods
-        | OperationType::Stos => {
+        | OperationType::Stos
+        | OperationType::Ins
+        | OperationType::Outs => {
             let str = op_type.to_string();
             if w == 0 {
                 format!("{str}B")
@@ -27,50 +30,53 @@ fn dump_type(op_type: &OperationType, w: u8) {
         }
         _ => format!("{op_type}"),
     };
-    print!("{type_str}");
+    out.push_str(&type_str);
 }
 
-fn dump_op_info(op: &Operation) {
+fn dump_op_bytes(op: &Operation) {
+    dump_bytes(op.pos, &op.raws);
+}
+
+fn dump_bytes(pos: usize, raws: &[u8]) {
     let mut bytes = String::new();
-    for byte in &op.raws {
+    for byte in raws {
         bytes.push_str(&format!("{:02x}", byte));
     }
     // Insert Tab
     for _ in bytes.len()..14 {
         bytes.push(' ');
     }
-    print!("{pos:04x}: {bytes}", pos = op.pos);
-    dump_type(&op.operation_type, op.w);
+    print!("{pos:04x}: {bytes}");
 }
 
-fn dump_comma() {
-    print!(", ");
+fn dump_comma(out: &mut String) {
+    out.push_str(", ");
 }
 
-fn dump_space() {
-    print!(" ");
+fn dump_space(out: &mut String) {
+    out.push(' ');
 }
 
-fn dump_reg(reg: u8, w: u8) {
+fn dump_reg(out: &mut String, reg: u8, w: u8) {
     let reg = RegisterType::new(reg, w);
-    print!("{reg}");
+    out.push_str(&format!("{reg}"));
 }
 
-fn dump_ea(op: &Operation) {
-    print!("{}", effective_address(op.rm, op.mod_rm, op.disp, op.w));
+fn dump_ea(out: &mut String, op: &Operation) {
+    out.push_str(&effective_address(op.rm, op.mod_rm, op.disp, op.w));
 }
 
-fn dump_immediate(op: &Operation) {
+fn dump_immediate(out: &mut String, op: &Operation) {
     match op.w {
-        0 => print!("{:x}", op.data),
+        0 => out.push_str(&format!("{:x}", op.data)),
         1 => match op.s {
-            0 => print!("{:04x}", op.data),
+            0 => out.push_str(&format!("{:04x}", op.data)),
             1 => {
                 let data = op.data as i8;
                 if data >= 0 {
-                    print!("{:x}", data)
+                    out.push_str(&format!("{:x}", data))
                 } else {
-                    print!("-{:x}", data.abs())
+                    out.push_str(&format!("-{:x}", data.unsigned_abs()))
                 }
             }
             _ => panic!("Invalid s"),
@@ -79,39 +85,187 @@ fn dump_immediate(op: &Operation) {
     }
 }
 
-fn dump_segment_register(seg_reg: u8) {
-    print!("{}", SegmentRegister::from_u8(seg_reg));
+fn dump_segment_register(out: &mut String, seg_reg: u8) {
+    out.push_str(&format!("{}", SegmentRegister::from_u8(seg_reg)));
 }
 
-fn dump_absolute_disp(disp: u16) {
-    print!("{:04x}", disp);
+fn dump_absolute_disp(out: &mut String, disp: u16) {
+    out.push_str(&format!("{:04x}", disp));
 }
 
-fn dump_relative_disp(op: &Operation, is_2byte_disp: bool) {
+fn dump_relative_disp(out: &mut String, op: &Operation, is_2byte_disp: bool) {
     let offset = op.get_next_operation_pos();
-    print!("{:04x}", calc_relative_disp(offset, op.disp, is_2byte_disp));
+    out.push_str(&format!(
+        "{:04x}",
+        calc_relative_disp(offset, op.disp, is_2byte_disp)
+    ));
 }
 
-fn dump_port(port: u8) {
-    print!("{:02x}", port);
+fn dump_port(out: &mut String, port: u8) {
+    out.push_str(&format!("{:02x}", port));
 }
 
-fn dump_byte() {
-    print!("Byte");
+fn dump_byte(out: &mut String) {
+    out.push_str("Byte");
 }
 
-fn dump_short() {
-    print!("Short");
+fn dump_short(out: &mut String) {
+    out.push_str("Short");
 }
 
-fn dump_count(v: u8) {
+fn dump_count(out: &mut String, v: u8) {
     match v {
-        0 => print!("1"),
-        1 => print!("{}", Register8Bit::CL),
+        0 => out.push('1'),
+        1 => out.push_str(&format!("{}", Register8Bit::CL)),
         _ => panic!("Invalid v"),
     }
 }
 
+// The text of an operation as the disassembly lists it, with its operands
+// laid out by its opcode table form
+pub fn format_operation(op: &Operation, form: Form) -> String {
+    let mut text = String::new();
+    let out = &mut text;
+    match form {
+        // Printed without the operation name
+        Form::Esc => return fpu::mnemonic(op),
+        Form::Segment => {
+            dump_segment_register(out, op.reg);
+            out.push(':');
+            return text;
+        }
+        Form::None | Form::Base => {
+            dump_type(out, &op.operation_type, op.w);
+            return text;
+        }
+        _ => {
+            dump_type(out, &op.operation_type, op.w);
+            dump_space(out);
+        }
+    }
+    match form {
+        Form::RmReg => match op.d {
+            0 => {
+                dump_ea(out, op);
+                dump_comma(out);
+                dump_reg(out, op.reg, op.w);
+            }
+            1 => {
+                dump_reg(out, op.reg, op.w);
+                dump_comma(out);
+                dump_ea(out, op);
+            }
+            _ => panic!("Invalid d"),
+        },
+        Form::RmImm => {
+            if op.mod_rm != 0b11 && op.w == 0 {
+                dump_byte(out);
+                dump_space(out);
+            }
+            dump_ea(out, op);
+            dump_comma(out);
+            dump_immediate(out, op);
+        }
+        Form::AccImm | Form::RegImm => {
+            dump_reg(out, op.reg, op.w);
+            dump_comma(out);
+            dump_immediate(out, op);
+        }
+        Form::AccMem => {
+            dump_reg(out, 0b000, op.w);
+            dump_comma(out);
+            dump_ea(out, op);
+        }
+        Form::MemAcc => {
+            dump_ea(out, op);
+            dump_comma(out);
+            dump_reg(out, 0b000, op.w);
+        }
+        Form::RmSeg => {
+            dump_ea(out, op);
+            dump_comma(out);
+            dump_segment_register(out, op.reg);
+        }
+        Form::SegRm => {
+            dump_segment_register(out, op.reg);
+            dump_comma(out);
+            dump_ea(out, op);
+        }
+        Form::Reg => dump_reg(out, op.reg, 1),
+        Form::RegAcc => {
+            dump_reg(out, op.reg, 1);
+            dump_comma(out);
+            out.push_str(&format!("{acc_reg}", acc_reg = Register16Bit::AX));
+        }
+        Form::Seg => dump_segment_register(out, op.reg),
+        Form::Rm => dump_ea(out, op),
+        Form::RegMem => {
+            dump_reg(out, op.reg, 1);
+            dump_comma(out);
+            dump_ea(out, op);
+        }
+        Form::InPort => {
+            dump_reg(out, 0b000, op.w);
+            dump_comma(out);
+            dump_port(out, op.port);
+        }
+        Form::InDx => {
+            dump_reg(out, 0b000, op.w);
+            dump_comma(out);
+            out.push_str(&format!("{dx_reg}", dx_reg = Register16Bit::DX));
+        }
+        Form::OutPort => {
+            dump_port(out, op.port);
+            dump_comma(out);
+            dump_reg(out, 0b000, op.w);
+        }
+        Form::OutDx => {
+            out.push_str(&format!("{dx_reg}", dx_reg = Register16Bit::DX));
+            dump_comma(out);
+            dump_reg(out, 0b000, op.w);
+        }
+        Form::Rep => dump_type(out, &op.rep_operation_type, op.w),
+        Form::Near => dump_relative_disp(out, op, true),
+        Form::Short => {
+            dump_short(out);
+            dump_space(out);
+            dump_relative_disp(out, op, false);
+        }
+        Form::Rel8 => dump_relative_disp(out, op, false),
+        // Direct Intersegment
+        Form::Far => out.push_str(&format!(
+            "{seg:04x}:{offset:04x}",
+            seg = op.data,
+            offset = op.disp
+        )),
+        Form::RetImm => dump_absolute_disp(out, op.disp),
+        Form::Int => out.push_str(&format!("{:02x}", op.int_type)),
+        Form::Int3 => out.push('3'),
+        Form::Shift => {
+            dump_ea(out, op);
+            dump_comma(out);
+            dump_count(out, op.v);
+        }
+        Form::ShiftImm => {
+            dump_ea(out, op);
+            dump_comma(out);
+            out.push_str(&format!("{:x}", op.data));
+        }
+        Form::PushImm => dump_immediate(out, op),
+        // Register times Register/Memory and Immediate
+        Form::ImulImm => {
+            dump_reg(out, op.reg, 1);
+            dump_comma(out);
+            dump_ea(out, op);
+            dump_comma(out);
+            dump_immediate(out, op);
+        }
+        Form::Enter => out.push_str(&format!("{:04x}, {:x}", op.data, op.disp)),
+        Form::Esc | Form::Segment | Form::None | Form::Base => unreachable!(),
+    }
+    text
+}
+
 pub struct Dump {
     pub enabled: bool,
 }
@@ -126,378 +280,43 @@ impl Dump {
     }
 
     // --- Dump Operation ---
-    pub fn name(&self, op: &Operation) {
-        if !self.is_enabled() {
-            return;
-        }
-        dump_op_info(op);
-    }
-
-    pub fn mov4(&self, op: &Operation) {
-        if !self.is_enabled() {
-            return;
-        }
-        // Memory to Accumulator
-        dump_op_info(op);
-        dump_space();
-        print!("{acc_reg}", acc_reg = RegisterType::new(0b000, op.w));
-        dump_comma();
-        dump_ea(op);
-    }
-
-    pub fn mov5(&self, op: &Operation) {
-        if !self.is_enabled() {
-            return;
-        }
-
-        // Accumulator to Memory
-        dump_op_info(op);
-        dump_space();
-        dump_ea(op);
-        dump_comma();
-        print!("{acc_reg}", acc_reg = RegisterType::new(0b000, op.w));
-    }
-
-    pub fn simple_calc1(&self, op: &Operation) {
-        if !self.is_enabled() {
-            return;
-        }
-
-        // Add, Sub, etc...
-        // Reg./Memory with Register to Either
-        dump_op_info(op);
-        dump_space();
-        match op.d {
-            0 => {
-                dump_ea(op);
-                dump_comma();
-                dump_reg(op.reg, op.w);
-            }
-            1 => {
-                dump_reg(op.reg, op.w);
-                dump_comma();
-                dump_ea(op);
-            }
-            _ => panic!("Invalid d"),
-        }
-    }
-
-    pub fn simple_calc2(&self, op: &Operation) {
-        if !self.is_enabled() {
-            return;
-        }
-
-        // Add, Sub, etc...
-        // Immediate to Register/Memory
-        dump_op_info(op);
-        dump_space();
-        if op.mod_rm != 0b11 && op.w == 0 {
-            dump_byte();
-            dump_space();
-        }
-        dump_ea(op);
-        dump_comma();
-        dump_immediate(op);
-    }
-
-    pub fn simple_calc3(&self, op: &Operation) {
-        if !self.is_enabled() {
-            return;
-        }
-
-        // Add, Sub, etc...
-        // Immediate to Accumulator
-        dump_op_info(op);
-        dump_space();
-        dump_reg(op.reg, op.w);
-        dump_comma();
-        dump_immediate(&op);
-    }
-
-    pub fn stack1(&self, op: &Operation) {
-        if !self.is_enabled() {
-            return;
-        }
-
-        // Register/Memory
-        dump_op_info(op);
-        dump_space();
-        dump_ea(op);
-    }
-
-    pub fn stack2(&self, op: &Operation) {
-        if !self.is_enabled() {
-            return;
-        }
-
-        // Register
-        dump_op_info(op);
-        dump_space();
-        dump_reg(op.reg, 1);
-    }
-
-    pub fn stack3(&self, op: &Operation) {
-        if !self.is_enabled() {
-            return;
-        }
-
-        // Segment Register
-        dump_op_info(op);
-        dump_space();
-        dump_segment_register(op.reg);
-    }
-
-    pub fn xchg1(&self, op: &Operation) {
-        if !self.is_enabled() {
-            return;
-        }
-
-        // Register/Memory with Register
-        dump_op_info(op);
-        dump_space();
-        dump_ea(op);
-        dump_comma();
-        dump_reg(op.reg, op.w);
-    }
-
-    pub fn xchg2(&self, op: &Operation) {
-        if !self.is_enabled() {
-            return;
-        }
-
-        // Register with Accumulator
-        dump_op_info(op);
-        dump_space();
-        dump_reg(op.reg, 1);
-        dump_comma();
-        print!("{acc_reg}", acc_reg = Register16Bit::AX);
-    }
-
-    pub fn in1(&self, op: &Operation) {
-        if !self.is_enabled() {
-            return;
-        }
-
-        dump_op_info(op);
-        dump_space();
-        dump_reg(0b000, op.w);
-        dump_comma();
-        dump_port(op.port);
-    }
-
-    pub fn in2(&self, op: &Operation) {
-        if !self.is_enabled() {
-            return;
-        }
-
-        dump_op_info(op);
-        dump_space();
-        dump_reg(0b000, op.w);
-        dump_comma();
-        print!("{dx_reg}", dx_reg = Register16Bit::DX);
-    }
-
-    pub fn lea(&self, op: &Operation) {
-        if !self.is_enabled() {
-            return;
-        }
-
-        dump_op_info(op);
-        dump_space();
-        dump_reg(op.reg, 1);
-        dump_comma();
-        dump_ea(op);
-    }
-
-    pub fn inc_dec1(&self, op: &Operation) {
-        if !self.is_enabled() {
-            return;
-        }
-
-        dump_op_info(op);
-        dump_space();
-        dump_ea(op);
-    }
-
-    pub fn inc_dec2(&self, op: &Operation) {
-        if !self.is_enabled() {
-            return;
-        }
-
-        dump_op_info(op);
-        dump_space();
-        dump_reg(op.reg, op.w);
-    }
-
-    pub fn complicate_calc(&self, op: &Operation) {
-        if !self.is_enabled() {
-            return;
-        }
-
-        dump_op_info(op);
-        dump_space();
-        dump_ea(op);
-    }
-
-    pub fn bit_op1(&self, op: &Operation) {
-        if !self.is_enabled() {
-            return;
-        }
-
-        dump_op_info(op);
-        dump_space();
-        match op.d {
-            0 => {
-                dump_ea(op);
-                dump_comma();
-                dump_reg(op.reg, op.w);
-            }
-            1 => {
-                dump_reg(op.reg, op.w);
-                dump_comma();
-                dump_ea(op);
-            }
-            _ => panic!("Invalid d"),
-        }
-    }
-
-    pub fn bit_op3(&self, op: &Operation) {
-        if !self.is_enabled() {
-            return;
-        }
-
-        dump_op_info(op);
-        dump_space();
-        dump_reg(op.reg, op.w);
-        dump_comma();
-        dump_immediate(op);
-    }
-
-    pub fn rep(&self, op: &Operation) {
-        if !self.is_enabled() {
-            return;
-        }
-
-        dump_op_info(op);
-        dump_space();
-        dump_type(&op.rep_operation_type, op.w);
-    }
-
-    pub fn shift_rotate(&self, op: &Operation) {
+    pub fn operation(&self, op: &Operation, form: Form) {
         if !self.is_enabled() {
             return;
         }
-
-        dump_op_info(op);
-        dump_space();
-        dump_ea(op);
-        dump_comma();
-        dump_count(op.v);
+        dump_op_bytes(op);
+        print!("{}", format_operation(op, form));
     }
 
-    pub fn test2(&self, op: &Operation) {
+    // Bytes in the text that no instruction covers
+    pub fn data(&self, pos: usize, bytes: &[u8]) {
         if !self.is_enabled() {
             return;
         }
-
-        dump_op_info(op);
-        dump_space();
-        if op.w == 0 && op.mod_rm != 0b11 {
-            dump_byte();
-            dump_space();
-        }
-        dump_ea(op);
-        dump_comma();
-        dump_immediate(op);
+        dump_bytes(pos, bytes);
+        let values: Vec<String> = bytes.iter().map(|byte| format!("{:02x}", byte)).collect();
+        print!("db {}", values.join(", "));
     }
 
-    pub fn call1(&self, op: &Operation) {
+    pub fn label(&self, name: &str) {
         if !self.is_enabled() {
             return;
         }
-
-        dump_op_info(op);
-        dump_space();
-        dump_relative_disp(op, true);
+        println!("{name}:");
     }
 
-    pub fn jmp1(&self, op: &Operation) {
+    pub fn clocks(&self, clocks: u64) {
         if !self.is_enabled() {
             return;
         }
-
-        dump_op_info(op);
-        dump_space();
-        dump_relative_disp(op, true);
+        print!(" ;{clocks} clocks");
     }
 
-    pub fn jmp2(&self, op: &Operation) {
+    pub fn undocumented(&self) {
         if !self.is_enabled() {
             return;
         }
-
-        dump_op_info(op);
-        dump_space();
-        dump_short();
-        dump_space();
-        dump_relative_disp(op, false);
-    }
-
-    pub fn ret2(&self, op: &Operation) {
-        if !self.is_enabled() {
-            return;
-        }
-
-        dump_op_info(op);
-        dump_space();
-        dump_absolute_disp(op.disp);
-    }
-
-    pub fn jump(&self, op: &Operation) {
-        if !self.is_enabled() {
-            return;
-        }
-
-        dump_op_info(op);
-        dump_space();
-        dump_relative_disp(op, false);
-    }
-
-    pub fn loop1(&self, op: &Operation) {
-        if !self.is_enabled() {
-            return;
-        }
-
-        dump_op_info(op);
-        dump_space();
-        dump_relative_disp(op, false);
-    }
-
-    pub fn int1(&self, op: &Operation) {
-        if !self.is_enabled() {
-            return;
-        }
-
-        dump_op_info(op);
-        dump_space();
-        print!("{:02x}", op.int_type);
-    }
-
-    pub fn int2(&self, op: &Operation) {
-        if !self.is_enabled() {
-            return;
-        }
-
-        dump_op_info(op);
-        dump_space();
-        print!("3");
-    }
-
-    pub fn none(&self, op: &Operation) {
-        if !self.is_enabled() {
-            return;
-        }
-        dump_op_info(op);
+        print!(" ;undocumented");
     }
 
     pub fn eol(&self) {
@@ -570,7 +389,61 @@ impl Dump {
         if !self.is_enabled() {
             return;
         }
-        print!("\n<ioctl(fd={}, req=0x{:04x}, addr=0x{:04x})>", fd, req, addr);
+        print!(
+            "\n<ioctl(fd={}, req=0x{:04x}, addr=0x{:04x})>",
+            fd, req, addr
+        );
+    }
+
+    pub fn port(&self, is_write: bool, port: u16, value: u16) {
+        if !self.is_enabled() {
+            return;
+        }
+        if is_write {
+            print!(
+                "\n<out(port=0x{:04x}, value=0x{:04x}): unmapped>",
+                port, value
+            );
+        } else {
+            print!("\n<in(port=0x{:04x}) => 0x{:04x}: unmapped>", port, value);
+        }
+    }
+
+    pub fn watch(&self, addr: usize, value: u8, is_write: bool) {
+        if !self.is_enabled() {
+            return;
+        }
+        let access = if is_write { "write" } else { "read" };
+        print!("\n<watch [{:05x}] {} 0x{:02x}>", addr, access, value);
+    }
+
+    pub fn hardware_interrupt(&self, vector: u8) {
+        if !self.is_enabled() {
+            return;
+        }
+        print!("\n<interrupt {:02x}h>", vector);
+    }
+
+    pub fn bios(&self, int_type: u8, ah: u8) {
+        if !self.is_enabled() {
+            return;
+        }
+        print!("\n<int{:02x}h(ah=0x{:02x})>", int_type, ah);
+    }
+
+    pub fn disk(&self, is_write: bool, lba: usize, count: usize, addr: usize, ok: bool) {
+        if !self.is_enabled() {
+            return;
+        }
+        let name = if is_write { "write" } else { "read" };
+        print!(
+            "\n<disk_{}(lba={}, count={}, addr=0x{:05x}) => {}>",
+            name,
+            lba,
+            count,
+            addr,
+            if ok { "0" } else { "EIO" }
+        );
     }
 
     pub fn brk(&self, addr: u16, ok: bool) {
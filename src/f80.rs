@@ -0,0 +1,682 @@
+use std::cmp::Ordering;
+
+// Exceptions in status word bit order
+pub const INVALID: u8 = 0x01;
+pub const DENORMAL: u8 = 0x02;
+pub const ZERO_DIVIDE: u8 = 0x04;
+pub const OVERFLOW: u8 = 0x08;
+pub const UNDERFLOW: u8 = 0x10;
+pub const PRECISION: u8 = 0x20;
+
+const BIAS: i32 = 16383;
+const MAX_EXPONENT: u16 = 0x7fff;
+const INTEGER_BIT: u64 = 1 << 63;
+const QUIET_BIT: u64 = 1 << 62;
+// A finite value is significand * 2^(exponent - EXPONENT_OFFSET)
+const EXPONENT_OFFSET: i32 = BIAS + 63;
+// Exponent of the lowest significand bit of a denormal
+const MIN_LSB: i32 = 1 - EXPONENT_OFFSET;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Rounding {
+    Nearest,
+    Down,
+    Up,
+    Zero,
+}
+
+// Rounding and precision control, and the exceptions raised so far
+pub struct Context {
+    pub rounding: Rounding,
+    // Significand bits kept by arithmetic: 24, 53 or 64
+    pub precision: u32,
+    pub flags: u8,
+}
+
+impl Context {
+    pub fn new(rounding: Rounding, precision: u32) -> Self {
+        Context {
+            rounding,
+            precision,
+            flags: 0,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Class {
+    Zero,
+    Denormal,
+    Normal,
+    Infinity,
+    Nan,
+}
+
+// Drops the low `shift` bits of `m`, rounding as the context says.
+// Returns the kept bits and whether anything was lost.
+fn shift_round(m: u128, shift: i32, sign: bool, rounding: Rounding) -> (u128, bool) {
+    if shift <= 0 {
+        return (m << -shift, false);
+    }
+    let (keep, rest, half) = if shift >= 128 {
+        (0, m, None)
+    } else {
+        (
+            m >> shift,
+            m & ((1 << shift) - 1),
+            Some(1u128 << (shift - 1)),
+        )
+    };
+    let inexact = rest != 0;
+    let up = match rounding {
+        Rounding::Nearest => half.is_some_and(|h| rest > h || rest == h && keep & 1 == 1),
+        Rounding::Up => inexact && !sign,
+        Rounding::Down => inexact && sign,
+        Rounding::Zero => false,
+    };
+    (keep + up as u128, inexact)
+}
+
+// Rounds m * 2^e to `p` significant bits, never below `min_lsb` for the lowest one.
+// Returns the kept bits (below 2^p) and the exponent of their lowest bit.
+fn round_bits(sign: bool, e: i32, m: u128, p: u32, min_lsb: i32, ctx: &mut Context) -> (u128, i32) {
+    let bits = 128 - m.leading_zeros() as i32;
+    let shift = (bits - p as i32).max(min_lsb - e);
+    let (mut keep, inexact) = shift_round(m, shift, sign, ctx.rounding);
+    let mut e = e + shift;
+    if keep == 1 << p {
+        keep >>= 1;
+        e += 1;
+    }
+    if inexact {
+        ctx.flags |= PRECISION;
+        if keep < 1 << (p - 1) {
+            ctx.flags |= UNDERFLOW;
+        }
+    }
+    (keep, e)
+}
+
+// Whether an overflowing result becomes infinity rather than the largest finite value
+fn overflows_to_infinity(sign: bool, rounding: Rounding) -> bool {
+    match rounding {
+        Rounding::Nearest => true,
+        Rounding::Up => !sign,
+        Rounding::Down => sign,
+        Rounding::Zero => false,
+    }
+}
+
+// 80-bit extended precision as stored by the 8087: sign, 15-bit biased exponent
+// and a 64-bit significand with an explicit integer bit
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct F80 {
+    pub sign: bool,
+    pub exponent: u16,
+    pub significand: u64,
+}
+
+impl F80 {
+    pub const ZERO: F80 = F80 {
+        sign: false,
+        exponent: 0,
+        significand: 0,
+    };
+    pub const ONE: F80 = F80 {
+        sign: false,
+        exponent: BIAS as u16,
+        significand: INTEGER_BIT,
+    };
+    pub const INFINITY: F80 = F80 {
+        sign: false,
+        exponent: MAX_EXPONENT,
+        significand: INTEGER_BIT,
+    };
+    // The quiet NaN produced by invalid operations
+    pub const INDEFINITE: F80 = F80 {
+        sign: true,
+        exponent: MAX_EXPONENT,
+        significand: INTEGER_BIT | QUIET_BIT,
+    };
+
+    pub fn new(sign: bool, exponent: u16, significand: u64) -> Self {
+        F80 {
+            sign,
+            exponent,
+            significand,
+        }
+    }
+
+    pub fn from_bytes(bytes: [u8; 10]) -> Self {
+        let significand = u64::from_le_bytes(bytes[..8].try_into().unwrap());
+        let top = u16::from_le_bytes([bytes[8], bytes[9]]);
+        F80::new(top & 0x8000 != 0, top & MAX_EXPONENT, significand)
+    }
+
+    pub fn to_bytes(self) -> [u8; 10] {
+        let mut bytes = [0; 10];
+        bytes[..8].copy_from_slice(&self.significand.to_le_bytes());
+        let top = (self.sign as u16) << 15 | self.exponent;
+        bytes[8..].copy_from_slice(&top.to_le_bytes());
+        bytes
+    }
+
+    pub fn class(&self) -> Class {
+        match self.exponent {
+            0 if self.significand == 0 => Class::Zero,
+            0 => Class::Denormal,
+            MAX_EXPONENT if self.significand << 1 == 0 => Class::Infinity,
+            MAX_EXPONENT => Class::Nan,
+            _ => Class::Normal,
+        }
+    }
+
+    pub fn is_nan(&self) -> bool {
+        self.class() == Class::Nan
+    }
+
+    fn is_finite_nonzero(&self) -> bool {
+        matches!(self.class(), Class::Normal | Class::Denormal) && self.significand != 0
+    }
+
+    pub fn negate(self) -> Self {
+        F80 {
+            sign: !self.sign,
+            ..self
+        }
+    }
+
+    pub fn abs(self) -> Self {
+        F80 {
+            sign: false,
+            ..self
+        }
+    }
+
+    fn zero(sign: bool) -> Self {
+        F80 { sign, ..F80::ZERO }
+    }
+
+    fn infinity(sign: bool) -> Self {
+        F80 {
+            sign,
+            ..F80::INFINITY
+        }
+    }
+
+    // Value as significand * 2^exponent
+    fn unpack(&self) -> (i32, u64) {
+        let exponent = (self.exponent as i32).max(1);
+        (exponent - EXPONENT_OFFSET, self.significand)
+    }
+
+    // Rounds sign * m * 2^e to the context precision
+    pub fn round(sign: bool, e: i32, m: u128, ctx: &mut Context) -> Self {
+        if m == 0 {
+            return F80::zero(sign);
+        }
+        let p = ctx.precision;
+        let (keep, e) = round_bits(sign, e, m, p, MIN_LSB + 64 - p as i32, ctx);
+        let significand = (keep as u64) << (64 - p);
+        if keep < 1 << (p - 1) {
+            return F80::new(sign, 0, significand);
+        }
+        let exponent = e - (64 - p as i32) + EXPONENT_OFFSET;
+        if exponent >= MAX_EXPONENT as i32 {
+            ctx.flags |= OVERFLOW | PRECISION;
+            if overflows_to_infinity(sign, ctx.rounding) {
+                return F80::infinity(sign);
+            }
+            let largest = (u64::MAX >> (64 - p)) << (64 - p);
+            return F80::new(sign, MAX_EXPONENT - 1, largest);
+        }
+        F80::new(sign, exponent as u16, significand)
+    }
+
+    // Exact for every value that fits, which covers all integers up to 64 bits
+    fn exact(sign: bool, e: i32, m: u128) -> Self {
+        F80::round(sign, e, m, &mut Context::new(Rounding::Nearest, 64))
+    }
+
+    pub fn from_i64(value: i64) -> Self {
+        F80::exact(value < 0, 0, value.unsigned_abs() as u128)
+    }
+
+    // Rounds to an integer; None when it does not fit in 64 bits
+    pub fn to_i64(self, ctx: &mut Context) -> Option<i64> {
+        match self.class() {
+            Class::Zero => return Some(0),
+            Class::Nan | Class::Infinity => return None,
+            _ => {}
+        }
+        let (e, m) = self.unpack();
+        if e > 0 {
+            return None;
+        }
+        let (keep, inexact) = shift_round(m as u128, -e, self.sign, ctx.rounding);
+        if inexact {
+            ctx.flags |= PRECISION;
+        }
+        let limit = if self.sign { 1 << 63 } else { (1 << 63) - 1 };
+        if keep > limit {
+            return None;
+        }
+        Some(if self.sign {
+            (keep as i64).wrapping_neg()
+        } else {
+            keep as i64
+        })
+    }
+
+    // IEEE single (8, 23) and double (11, 52) precision
+    fn from_ieee(bits: u64, exponent_bits: u32, mantissa: u32) -> Self {
+        let sign = bits >> (exponent_bits + mantissa) & 1 == 1;
+        let max = (1 << exponent_bits) - 1;
+        let exponent = (bits >> mantissa & max) as i32;
+        let fraction = bits & ((1 << mantissa) - 1);
+        let bias = (1 << (exponent_bits - 1)) - 1;
+        if exponent == max as i32 {
+            let payload = fraction << (63 - mantissa);
+            return F80::new(sign, MAX_EXPONENT, INTEGER_BIT | payload);
+        }
+        if exponent == 0 {
+            // Denormals of the short formats are normal in extended precision
+            return F80::exact(sign, 1 - bias - mantissa as i32, fraction as u128);
+        }
+        let m = fraction | 1 << mantissa;
+        F80::exact(sign, exponent - bias - mantissa as i32, m as u128)
+    }
+
+    fn to_ieee(self, exponent_bits: u32, mantissa: u32, ctx: &mut Context) -> u64 {
+        let max = (1u64 << exponent_bits) - 1;
+        let sign = (self.sign as u64) << (exponent_bits + mantissa);
+        let bias = (1 << (exponent_bits - 1)) - 1;
+        match self.class() {
+            Class::Zero => return sign,
+            Class::Infinity => return sign | max << mantissa,
+            Class::Nan => {
+                let payload = (self.significand << 1) >> (64 - mantissa);
+                return sign | max << mantissa | payload | 1 << (mantissa - 1);
+            }
+            _ => {}
+        }
+        let (e, m) = self.unpack();
+        let p = mantissa + 1;
+        let (keep, e) = round_bits(self.sign, e, m as u128, p, 1 - bias - mantissa as i32, ctx);
+        let keep = keep as u64;
+        if keep < 1 << mantissa {
+            // Denormal, or zero after rounding
+            return sign | keep;
+        }
+        let exponent = e + mantissa as i32 + bias;
+        if exponent >= max as i32 {
+            ctx.flags |= OVERFLOW | PRECISION;
+            if overflows_to_infinity(self.sign, ctx.rounding) {
+                return sign | max << mantissa;
+            }
+            return sign | (max - 1) << mantissa | ((1 << mantissa) - 1);
+        }
+        sign | (exponent as u64) << mantissa | keep & ((1 << mantissa) - 1)
+    }
+
+    pub fn from_f32_bits(bits: u32) -> Self {
+        F80::from_ieee(bits as u64, 8, 23)
+    }
+
+    pub fn from_f64_bits(bits: u64) -> Self {
+        F80::from_ieee(bits, 11, 52)
+    }
+
+    pub fn to_f32_bits(self, ctx: &mut Context) -> u32 {
+        self.to_ieee(8, 23, ctx) as u32
+    }
+
+    pub fn to_f64_bits(self, ctx: &mut Context) -> u64 {
+        self.to_ieee(11, 52, ctx)
+    }
+
+    pub fn from_f64(value: f64) -> Self {
+        F80::from_f64_bits(value.to_bits())
+    }
+
+    pub fn to_f64(self) -> f64 {
+        f64::from_bits(self.to_f64_bits(&mut Context::new(Rounding::Nearest, 64)))
+    }
+
+    // Flags denormal operands. NaN operands give a quiet NaN; signaling ones are invalid.
+    fn propagate_nan(a: F80, b: F80, ctx: &mut Context) -> Option<F80> {
+        if a.class() == Class::Denormal || b.class() == Class::Denormal {
+            ctx.flags |= DENORMAL;
+        }
+        let nan = [a, b].into_iter().find(|x| x.is_nan())?;
+        if [a, b]
+            .iter()
+            .any(|x| x.is_nan() && x.significand & QUIET_BIT == 0)
+        {
+            ctx.flags |= INVALID;
+        }
+        Some(F80 {
+            significand: nan.significand | QUIET_BIT,
+            ..nan
+        })
+    }
+
+    pub fn add(self, other: F80, ctx: &mut Context) -> F80 {
+        if let Some(nan) = F80::propagate_nan(self, other, ctx) {
+            return nan;
+        }
+        match (self.class(), other.class()) {
+            (Class::Infinity, Class::Infinity) if self.sign != other.sign => {
+                ctx.flags |= INVALID;
+                return F80::INDEFINITE;
+            }
+            (Class::Infinity, _) => return self,
+            (_, Class::Infinity) => return other,
+            (Class::Zero, Class::Zero) => {
+                let sign = if ctx.rounding == Rounding::Down {
+                    self.sign || other.sign
+                } else {
+                    self.sign && other.sign
+                };
+                return F80::zero(sign);
+            }
+            (Class::Zero, _) => {
+                return F80::round(other.sign, other.unpack().0, other.unpack().1 as u128, ctx)
+            }
+            (_, Class::Zero) => {
+                return F80::round(self.sign, self.unpack().0, self.unpack().1 as u128, ctx)
+            }
+            _ => {}
+        }
+        // 62 guard bits below the significand keep every bit an aligned subtraction needs
+        let (ea, ma) = self.unpack();
+        let (eb, mb) = other.unpack();
+        let (mut a, mut b) = (
+            (self.sign, ea - 62, (ma as u128) << 62),
+            (other.sign, eb - 62, (mb as u128) << 62),
+        );
+        if a.1 < b.1 {
+            std::mem::swap(&mut a, &mut b);
+        }
+        let shift = a.1 - b.1;
+        let aligned = if shift >= 127 {
+            (b.2 != 0) as u128
+        } else {
+            b.2 >> shift | (b.2 & ((1 << shift) - 1) != 0) as u128
+        };
+        let (sign, m) = if a.0 == b.0 {
+            (a.0, a.2 + aligned)
+        } else if a.2 >= aligned {
+            (a.0, a.2 - aligned)
+        } else {
+            (b.0, aligned - a.2)
+        };
+        if m == 0 {
+            return F80::zero(ctx.rounding == Rounding::Down);
+        }
+        F80::round(sign, a.1, m, ctx)
+    }
+
+    pub fn sub(self, other: F80, ctx: &mut Context) -> F80 {
+        if other.is_nan() {
+            return self.add(other, ctx);
+        }
+        self.add(other.negate(), ctx)
+    }
+
+    pub fn mul(self, other: F80, ctx: &mut Context) -> F80 {
+        if let Some(nan) = F80::propagate_nan(self, other, ctx) {
+            return nan;
+        }
+        let sign = self.sign != other.sign;
+        match (self.class(), other.class()) {
+            (Class::Infinity, Class::Zero) | (Class::Zero, Class::Infinity) => {
+                ctx.flags |= INVALID;
+                F80::INDEFINITE
+            }
+            (Class::Infinity, _) | (_, Class::Infinity) => F80::infinity(sign),
+            (Class::Zero, _) | (_, Class::Zero) => F80::zero(sign),
+            _ => {
+                let (ea, ma) = self.unpack();
+                let (eb, mb) = other.unpack();
+                F80::round(sign, ea + eb, ma as u128 * mb as u128, ctx)
+            }
+        }
+    }
+
+    pub fn div(self, other: F80, ctx: &mut Context) -> F80 {
+        if let Some(nan) = F80::propagate_nan(self, other, ctx) {
+            return nan;
+        }
+        let sign = self.sign != other.sign;
+        match (self.class(), other.class()) {
+            (Class::Infinity, Class::Infinity) | (Class::Zero, Class::Zero) => {
+                ctx.flags |= INVALID;
+                F80::INDEFINITE
+            }
+            (Class::Infinity, _) => F80::infinity(sign),
+            (_, Class::Infinity) | (Class::Zero, _) => F80::zero(sign),
+            (_, Class::Zero) => {
+                ctx.flags |= ZERO_DIVIDE;
+                F80::infinity(sign)
+            }
+            _ => {
+                let (ea, ma) = self.normalized();
+                let (eb, mb) = other.normalized();
+                let (ma, mb) = (ma as u128, mb as u128);
+                // 64 + 62 quotient bits, then whatever is left as a sticky bit
+                let high = (ma << 64) / mb;
+                let rest = (ma << 64) % mb;
+                let low = (rest << 62) / mb;
+                let sticky = (rest << 62) % mb != 0;
+                let m = high << 62 | low | sticky as u128;
+                F80::round(sign, ea - eb - 64 - 62, m, ctx)
+            }
+        }
+    }
+
+    pub fn sqrt(self, ctx: &mut Context) -> F80 {
+        match self.class() {
+            Class::Nan => return F80::propagate_nan(self, self, ctx).unwrap(),
+            Class::Zero => return self,
+            _ if self.sign => {
+                ctx.flags |= INVALID;
+                return F80::INDEFINITE;
+            }
+            Class::Infinity => return self,
+            _ => {}
+        }
+        let (e, m) = self.normalized();
+        // An even exponent halves exactly
+        let (e, m) = if e % 2 != 0 {
+            (e - 1, (m as u128) << 1)
+        } else {
+            (e, m as u128)
+        };
+        // Digit by digit over m * 2^66, two radicand bits per root bit
+        let mut rest: u128 = 0;
+        let mut root: u128 = 0;
+        for i in 0..66 {
+            let pair = if i <= 32 {
+                (m >> (64 - 2 * i)) & 0b11
+            } else {
+                0
+            };
+            rest = rest << 2 | pair;
+            let trial = root << 2 | 1;
+            root <<= 1;
+            if rest >= trial {
+                rest -= trial;
+                root |= 1;
+            }
+        }
+        let m = root << 1 | (rest != 0) as u128;
+        F80::round(false, (e - 66) / 2 - 1, m, ctx)
+    }
+
+    // Significand with the integer bit set, denormals included
+    fn normalized(&self) -> (i32, u64) {
+        let (e, m) = self.unpack();
+        let shift = m.leading_zeros() as i32;
+        (e - shift, m << shift)
+    }
+
+    pub fn compare(self, other: F80) -> Option<Ordering> {
+        if self.is_nan() || other.is_nan() {
+            return None;
+        }
+        let key = |x: &F80| match x.class() {
+            Class::Zero => (i32::MIN, 0),
+            Class::Infinity => (i32::MAX, 0),
+            _ => x.normalized(),
+        };
+        let zero = |x: &F80| x.class() == Class::Zero;
+        if zero(&self) && zero(&other) {
+            return Some(Ordering::Equal);
+        }
+        let magnitude = key(&self).cmp(&key(&other));
+        Some(
+            match (self.sign && !zero(&self), other.sign && !zero(&other)) {
+                (false, false) => magnitude,
+                (true, true) => magnitude.reverse(),
+                (false, true) => Ordering::Greater,
+                (true, false) => Ordering::Less,
+            },
+        )
+    }
+
+    // Rounds to an integral value in the current rounding mode
+    pub fn round_to_integer(self, ctx: &mut Context) -> F80 {
+        if !self.is_finite_nonzero() {
+            return self;
+        }
+        let (e, m) = self.unpack();
+        if e >= 0 {
+            return self;
+        }
+        let (keep, inexact) = shift_round(m as u128, -e, self.sign, ctx.rounding);
+        if inexact {
+            ctx.flags |= PRECISION;
+        }
+        F80::exact(self.sign, 0, keep)
+    }
+
+    // self * 2^n
+    pub fn scale(self, n: i32, ctx: &mut Context) -> F80 {
+        if !self.is_finite_nonzero() {
+            return self;
+        }
+        let (e, m) = self.unpack();
+        F80::round(self.sign, e.saturating_add(n), m as u128, ctx)
+    }
+
+    // Unbiased exponent and the significand scaled to [1, 2)
+    pub fn extract(self) -> (F80, F80) {
+        let (e, m) = self.normalized();
+        let exponent = F80::from_i64((e + 63) as i64);
+        (exponent, F80::new(self.sign, BIAS as u16, m))
+    }
+
+    // Partial remainder of self / other truncated towards zero. Returns the
+    // remainder, the low three quotient bits and whether the reduction is complete.
+    pub fn partial_remainder(self, other: F80, ctx: &mut Context) -> (F80, u8, bool) {
+        if let Some(nan) = F80::propagate_nan(self, other, ctx) {
+            return (nan, 0, true);
+        }
+        if self.class() == Class::Infinity || other.class() == Class::Zero {
+            ctx.flags |= INVALID;
+            return (F80::INDEFINITE, 0, true);
+        }
+        if self.class() == Class::Zero || other.class() == Class::Infinity {
+            return (self, 0, true);
+        }
+        let (ea, ma) = self.normalized();
+        let (eb, mb) = other.normalized();
+        let shift = ea - eb;
+        if shift < 0 {
+            return (self, 0, true);
+        }
+        let (ma, mb) = (ma as u128, mb as u128);
+        if shift < 64 {
+            let dividend = ma << shift;
+            let quotient = dividend / mb;
+            let rest = dividend % mb;
+            return (
+                F80::exact(self.sign, eb, rest),
+                quotient as u8 & 0b111,
+                true,
+            );
+        }
+        // Reduce the exponent difference by 32 to 63 per step, like the 8087
+        let n = 32 + shift % 32;
+        let rest = (ma << n) % mb;
+        (F80::exact(self.sign, ea - n, rest), 0, false)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use test_case::test_case;
+
+    fn ctx() -> Context {
+        Context::new(Rounding::Nearest, 64)
+    }
+
+    #[test_case(0.1, 0.2 ; "inexact")]
+    #[test_case(1e300, -1e300 ; "cancellation")]
+    #[test_case(3.5, 1e-310 ; "denormal")]
+    fn test_add_matches_double(a: f64, b: f64) {
+        let mut ctx = Context::new(Rounding::Nearest, 53);
+        let sum = F80::from_f64(a).add(F80::from_f64(b), &mut ctx);
+        assert_eq!(sum.to_f64(), a + b);
+    }
+
+    #[test]
+    fn test_divide_rounds_to_nearest() {
+        let mut ctx = ctx();
+        let third = F80::ONE.div(F80::from_i64(3), &mut ctx);
+        assert_eq!(third.significand, 0xaaaa_aaaa_aaaa_aaab);
+        assert_eq!(ctx.flags, PRECISION);
+        let mut ctx = Context::new(Rounding::Zero, 64);
+        let third = F80::ONE.div(F80::from_i64(3), &mut ctx);
+        assert_eq!(third.significand, 0xaaaa_aaaa_aaaa_aaaa);
+    }
+
+    #[test_case(2.0, std::f64::consts::SQRT_2 ; "two")]
+    #[test_case(0.25, 0.5 ; "exact")]
+    fn test_sqrt(value: f64, expected: f64) {
+        assert_eq!(F80::from_f64(value).sqrt(&mut ctx()).to_f64(), expected);
+    }
+
+    #[test_case(2.5, Rounding::Nearest, 2 ; "ties to even")]
+    #[test_case(-2.5, Rounding::Down, -3 ; "down")]
+    #[test_case(2.1, Rounding::Up, 3 ; "up")]
+    #[test_case(-2.9, Rounding::Zero, -2 ; "chop")]
+    fn test_to_i64(value: f64, rounding: Rounding, expected: i64) {
+        let mut ctx = Context::new(rounding, 64);
+        assert_eq!(F80::from_f64(value).to_i64(&mut ctx), Some(expected));
+    }
+
+    #[test]
+    fn test_special_values() {
+        let mut ctx = ctx();
+        let inf = F80::ONE.div(F80::ZERO, &mut ctx);
+        assert_eq!(inf, F80::INFINITY);
+        assert_eq!(ctx.flags, ZERO_DIVIDE);
+        assert_eq!(inf.sub(inf, &mut ctx), F80::INDEFINITE);
+        assert_eq!(F80::INDEFINITE.compare(F80::ONE), None);
+        assert_eq!(F80::from_i64(i64::MIN).to_i64(&mut ctx), Some(i64::MIN));
+        assert_eq!(F80::from_f64(1e19).to_i64(&mut ctx), None);
+    }
+
+    #[test]
+    fn test_partial_remainder() {
+        let mut ctx = ctx();
+        let (rest, quotient, complete) =
+            F80::from_i64(17).partial_remainder(F80::from_i64(5), &mut ctx);
+        assert_eq!(rest, F80::from_i64(2));
+        assert_eq!(quotient, 3);
+        assert!(complete);
+    }
+}
@@ -1,11 +1,93 @@
 use std::fmt::Display;
 
+// Value mask and sign bit of a byte (w = 0) or word (w = 1) operand
+pub fn width_mask(w: u8) -> (u16, u16) {
+    match w {
+        0 => (0xff, 0x80),
+        _ => (0xffff, 0x8000),
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Kind {
+    Add,
+    Sub,
+    Logic,
+    // INC and DEC keep the carry flag they started with
+    Inc,
+    Dec,
+}
+
+// The last ALU operation, from which its status flags are worked out on demand
+#[derive(Debug, Clone, Copy)]
+struct Lazy {
+    kind: Kind,
+    left: u16,
+    right: u16,
+    // Carry or borrow in, or the carry kept by INC and DEC
+    carry: bool,
+    result: u16,
+    w: u8,
+}
+
+impl Lazy {
+    fn carry(&self) -> bool {
+        let (mask, _) = width_mask(self.w);
+        match self.kind {
+            Kind::Add => self.left as u32 + self.right as u32 + self.carry as u32 > mask as u32,
+            Kind::Sub => (self.left as u32) < self.right as u32 + self.carry as u32,
+            Kind::Logic => false,
+            Kind::Inc | Kind::Dec => self.carry,
+        }
+    }
+
+    fn overflow(&self) -> bool {
+        let (_, sign_bit) = width_mask(self.w);
+        let (left, right, res) = (self.left, self.right, self.result);
+        match self.kind {
+            Kind::Add | Kind::Inc => (left ^ res) & (right ^ res) & sign_bit != 0,
+            Kind::Sub | Kind::Dec => (left ^ right) & (left ^ res) & sign_bit != 0,
+            Kind::Logic => false,
+        }
+    }
+
+    fn auxiliary(&self) -> bool {
+        match self.kind {
+            Kind::Logic => false,
+            _ => (self.left ^ self.right ^ self.result) & 0x10 != 0,
+        }
+    }
+
+    fn sign(&self) -> bool {
+        let (_, sign_bit) = width_mask(self.w);
+        self.result & sign_bit != 0
+    }
+
+    fn zero(&self) -> bool {
+        self.result == 0
+    }
+
+    // Parity only looks at the low byte
+    fn parity(&self) -> bool {
+        (self.result as u8).count_ones() % 2 == 0
+    }
+}
+
+// The status flags (carry, overflow, sign, zero, parity, auxiliary) of ADD,
+// SUB, the logic operations, INC and DEC are only worked out when read.
+// Anything else that changes one of them settles them first.
 #[derive(Debug)]
 pub struct Flag {
-    pub carry: bool,
-    pub overflow: bool,
-    pub sign: bool,
-    pub zero: bool,
+    carry: bool,
+    overflow: bool,
+    sign: bool,
+    zero: bool,
+    parity: bool,
+    auxiliary: bool,
+    lazy: Option<Lazy>,
+    pub direction: bool,
+    pub interrupt: bool,
+    pub trap: bool,
 }
 
 impl Flag {
@@ -15,23 +97,275 @@ impl Flag {
             overflow: false,
             sign: false,
             zero: false,
+            parity: false,
+            auxiliary: false,
+            lazy: None,
+            direction: false,
+            interrupt: false,
+            trap: false,
+        }
+    }
+
+    fn record(&mut self, kind: Kind, left: u16, right: u16, carry: bool, result: u16, w: u8) {
+        self.lazy = Some(Lazy {
+            kind,
+            left,
+            right,
+            carry,
+            result,
+            w,
+        });
+    }
+
+    // Writes the status flags of the last ALU operation into place
+    fn settle(&mut self) {
+        if let Some(lazy) = self.lazy.take() {
+            self.carry = lazy.carry();
+            self.overflow = lazy.overflow();
+            self.sign = lazy.sign();
+            self.zero = lazy.zero();
+            self.parity = lazy.parity();
+            self.auxiliary = lazy.auxiliary();
         }
     }
 
-    pub fn set_cosz(&mut self, carry: bool, overflow: bool, sign: bool, zero: bool) {
+    // --- ALU operations, returning the result ---
+    pub fn add(&mut self, left: u16, right: u16, carry: bool, w: u8) -> u16 {
+        let (mask, _) = width_mask(w);
+        let (left, right) = (left & mask, right & mask);
+        let result = left.wrapping_add(right).wrapping_add(carry as u16) & mask;
+        self.record(Kind::Add, left, right, carry, result, w);
+        result
+    }
+
+    pub fn sub(&mut self, left: u16, right: u16, borrow: bool, w: u8) -> u16 {
+        let (mask, _) = width_mask(w);
+        let (left, right) = (left & mask, right & mask);
+        let result = left.wrapping_sub(right).wrapping_sub(borrow as u16) & mask;
+        self.record(Kind::Sub, left, right, borrow, result, w);
+        result
+    }
+
+    pub fn logic(&mut self, result: u16, w: u8) -> u16 {
+        let (mask, _) = width_mask(w);
+        let result = result & mask;
+        self.record(Kind::Logic, 0, 0, false, result, w);
+        result
+    }
+
+    pub fn inc(&mut self, value: u16, w: u8) -> u16 {
+        let (mask, _) = width_mask(w);
+        let value = value & mask;
+        let result = value.wrapping_add(1) & mask;
+        let carry = self.carry();
+        self.record(Kind::Inc, value, 1, carry, result, w);
+        result
+    }
+
+    pub fn dec(&mut self, value: u16, w: u8) -> u16 {
+        let (mask, _) = width_mask(w);
+        let value = value & mask;
+        let result = value.wrapping_sub(1) & mask;
+        let carry = self.carry();
+        self.record(Kind::Dec, value, 1, carry, result, w);
+        result
+    }
+
+    // --- Status flags ---
+    pub fn carry(&self) -> bool {
+        self.lazy.map_or(self.carry, |lazy| lazy.carry())
+    }
+
+    pub fn overflow(&self) -> bool {
+        self.lazy.map_or(self.overflow, |lazy| lazy.overflow())
+    }
+
+    pub fn sign(&self) -> bool {
+        self.lazy.map_or(self.sign, |lazy| lazy.sign())
+    }
+
+    pub fn zero(&self) -> bool {
+        self.lazy.map_or(self.zero, |lazy| lazy.zero())
+    }
+
+    pub fn parity(&self) -> bool {
+        self.lazy.map_or(self.parity, |lazy| lazy.parity())
+    }
+
+    pub fn auxiliary(&self) -> bool {
+        self.lazy.map_or(self.auxiliary, |lazy| lazy.auxiliary())
+    }
+
+    pub fn set_carry(&mut self, carry: bool) {
+        self.settle();
         self.carry = carry;
+    }
+
+    pub fn set_overflow(&mut self, overflow: bool) {
+        self.settle();
         self.overflow = overflow;
-        self.sign = sign;
+    }
+
+    pub fn set_zero(&mut self, zero: bool) {
+        self.settle();
         self.zero = zero;
     }
+
+    pub fn set_auxiliary(&mut self, auxiliary: bool) {
+        self.settle();
+        self.auxiliary = auxiliary;
+    }
+
+    // Sign, zero and parity of a byte (w = 0) or word (w = 1) result
+    pub fn set_szp(&mut self, result: u16, w: u8) {
+        self.settle();
+        let (mask, sign_bit) = width_mask(w);
+        self.sign = result & sign_bit != 0;
+        self.zero = result & mask == 0;
+        // Parity only looks at the low byte
+        self.parity = (result as u8).count_ones() % 2 == 0;
+    }
+
+    pub fn to_u16(&self) -> u16 {
+        // Bits 12-15 always read as 1 on the 8086, bit 1 is reserved as 1
+        let mut flags = 0xf002;
+        for (set, bit) in [
+            (self.carry(), 0),
+            (self.parity(), 2),
+            (self.auxiliary(), 4),
+            (self.zero(), 6),
+            (self.sign(), 7),
+            (self.trap, 8),
+            (self.interrupt, 9),
+            (self.direction, 10),
+            (self.overflow(), 11),
+        ] {
+            if set {
+                flags |= 1 << bit;
+            }
+        }
+        flags
+    }
+
+    pub fn set_u16(&mut self, flags: u16) {
+        let bit = |n: u16| flags & (1 << n) != 0;
+        self.lazy = None;
+        self.carry = bit(0);
+        self.parity = bit(2);
+        self.auxiliary = bit(4);
+        self.zero = bit(6);
+        self.sign = bit(7);
+        self.trap = bit(8);
+        self.interrupt = bit(9);
+        self.direction = bit(10);
+        self.overflow = bit(11);
+    }
 }
 
 impl Display for Flag {
     fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
-        let c = if self.carry { 'C' } else { '-' };
-        let o = if self.overflow { 'O' } else { '-' };
-        let s = if self.sign { 'S' } else { '-' };
-        let z = if self.zero { 'Z' } else { '-' };
+        let c = if self.carry() { 'C' } else { '-' };
+        let o = if self.overflow() { 'O' } else { '-' };
+        let s = if self.sign() { 'S' } else { '-' };
+        let z = if self.zero() { 'Z' } else { '-' };
         write!(f, "{}{}{}{}", c, o, s, z)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use test_case::test_case;
+
+    // Status flags worked out independently, from the exact unsigned and
+    // signed results in wider integers: C, O, S, Z, P, A
+    fn widened(left: u16, right: u16, carry: bool, w: u8, sub: bool) -> [bool; 6] {
+        let bits = if w == 0 { 8 } else { 16 };
+        let unsigned = |value: u16| (value as i64) & ((1 << bits) - 1);
+        let signed = |value: u16| {
+            let value = unsigned(value);
+            if value >= 1 << (bits - 1) {
+                value - (1 << bits)
+            } else {
+                value
+            }
+        };
+        let nibble = |value: u16| (value & 0xf) as i64;
+        let combine = |a: i64, b: i64| {
+            if sub {
+                a - b - carry as i64
+            } else {
+                a + b + carry as i64
+            }
+        };
+        let exact = combine(unsigned(left), unsigned(right));
+        let exact_signed = combine(signed(left), signed(right));
+        let low_nibble = combine(nibble(left), nibble(right));
+        let res = exact.rem_euclid(1 << bits);
+        let parity = (0..8).filter(|bit| res & (1 << bit) != 0).count() % 2 == 0;
+        [
+            !(0..1 << bits).contains(&exact),
+            !(-(1 << (bits - 1))..1 << (bits - 1)).contains(&exact_signed),
+            res >= 1 << (bits - 1),
+            res == 0,
+            parity,
+            !(0..0x10).contains(&low_nibble),
+        ]
+    }
+
+    fn status(flag: &Flag) -> [bool; 6] {
+        [
+            flag.carry(),
+            flag.overflow(),
+            flag.sign(),
+            flag.zero(),
+            flag.parity(),
+            flag.auxiliary(),
+        ]
+    }
+
+    #[test_case(false ; "add")]
+    #[test_case(true ; "sub")]
+    fn test_lazy_matches_widened(sub: bool) {
+        let words = [0x0000, 0x0001, 0x000f, 0x0010, 0x7fff, 0x8000, 0x8001, 0xfffe, 0xffff];
+        let mut cases = Vec::new();
+        for left in 0..=0xff {
+            for right in 0..=0xff {
+                cases.push((left, right, 0));
+            }
+        }
+        for left in words {
+            for right in words {
+                cases.push((left, right, 1));
+            }
+        }
+        for (left, right, w) in cases {
+            for carry in [false, true] {
+                let mut flag = Flag::new();
+                if sub {
+                    flag.sub(left, right, carry, w);
+                } else {
+                    flag.add(left, right, carry, w);
+                }
+                let expected = widened(left, right, carry, w, sub);
+                assert_eq!(status(&flag), expected, "{left:04x} {right:04x} {carry} {w}");
+                // Settling keeps the same values
+                flag.settle();
+                assert_eq!(status(&flag), expected);
+            }
+        }
+    }
+
+    #[test]
+    fn test_inc_dec_keep_carry() {
+        let mut flag = Flag::new();
+        flag.set_carry(true);
+        assert_eq!(flag.inc(0x7fff, 1), 0x8000);
+        assert!(flag.carry() && flag.overflow() && flag.sign());
+        assert_eq!(flag.dec(0x00, 0), 0xff);
+        assert!(flag.carry() && !flag.overflow() && flag.auxiliary());
+        flag.logic(0, 1);
+        assert!(!flag.carry() && flag.zero() && flag.parity());
+        assert_eq!(flag.to_u16() & 0x0fff, 0x0046);
+    }
+}
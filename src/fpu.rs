@@ -0,0 +1,822 @@
+use crate::{
+    f80::{self, Class, Context, Rounding, F80},
+    memory::{MemoryBus, ADDRESS_MASK},
+    operation::Operation,
+    register::effective_address,
+};
+
+// Status word bits
+const EXCEPTIONS: u16 = 0x003f;
+const STACK_FAULT: u16 = 0x0040;
+const ERROR_SUMMARY: u16 = 0x0080;
+const C0: u16 = 0x0100;
+const C1: u16 = 0x0200;
+const C2: u16 = 0x0400;
+const C3: u16 = 0x4000;
+const CONDITION: u16 = C0 | C1 | C2 | C3;
+const TOP: u16 = 0x3800;
+
+// Control word after FINIT: exceptions masked, 64-bit precision, round to nearest
+const DEFAULT_CONTROL: u16 = 0x03ff;
+const INTERRUPT_MASK: u16 = 0x0080;
+
+// Tag word values
+const VALID: u16 = 0;
+const ZERO: u16 = 1;
+const SPECIAL: u16 = 2;
+const EMPTY: u16 = 3;
+
+// Real mode FSTENV image, and FSAVE with the eight registers after it
+const ENVIRONMENT_SIZE: usize = 14;
+
+const ARITHMETIC: [&str; 8] = [
+    "FADD", "FMUL", "FCOM", "FCOMP", "FSUB", "FSUBR", "FDIV", "FDIVR",
+];
+const INTEGER_ARITHMETIC: [&str; 8] = [
+    "FIADD", "FIMUL", "FICOM", "FICOMP", "FISUB", "FISUBR", "FIDIV", "FIDIVR",
+];
+// Register forms without operands, by escape, reg and rm. Empty names are undefined.
+const D9_E0: [&str; 8] = ["FCHS", "FABS", "", "", "FTST", "FXAM", "", ""];
+const D9_E8: [&str; 8] = [
+    "FLD1", "FLDL2T", "FLDL2E", "FLDPI", "FLDLG2", "FLDLN2", "FLDZ", "",
+];
+const D9_F0: [&str; 8] = [
+    "F2XM1", "FYL2X", "FPTAN", "FPATAN", "FXTRACT", "", "FDECSTP", "FINCSTP",
+];
+const D9_F8: [&str; 8] = ["FPREM", "FYL2XP1", "FSQRT", "", "FRNDINT", "FSCALE", "", ""];
+const DB_E0: [&str; 8] = ["FNENI", "FNDISI", "FNCLEX", "FNINIT", "", "", "", ""];
+
+// FLD1, FLDL2T, FLDL2E, FLDPI, FLDLG2, FLDLN2 and FLDZ, rounded to nearest
+const CONSTANTS: [F80; 7] = [
+    F80::ONE,
+    F80 {
+        sign: false,
+        exponent: 0x4000,
+        significand: 0xd49a_784b_cd1b_8afe,
+    },
+    F80 {
+        sign: false,
+        exponent: 0x3fff,
+        significand: 0xb8aa_3b29_5c17_f0bc,
+    },
+    F80 {
+        sign: false,
+        exponent: 0x4000,
+        significand: 0xc90f_daa2_2168_c235,
+    },
+    F80 {
+        sign: false,
+        exponent: 0x3ffd,
+        significand: 0x9a20_9a84_fbcf_f799,
+    },
+    F80 {
+        sign: false,
+        exponent: 0x3ffe,
+        significand: 0xb172_17f7_d1cf_79ac,
+    },
+    F80::ZERO,
+];
+
+// Reversed operations in the DC and DE register forms swap SUB/SUBR and DIV/DIVR
+fn reversed(reg: u8) -> u8 {
+    if reg >= 4 {
+        reg ^ 1
+    } else {
+        reg
+    }
+}
+
+fn named(table: &[&'static str; 8], rm: u8) -> Option<String> {
+    Some(table[rm as usize])
+        .filter(|name| !name.is_empty())
+        .map(str::to_string)
+}
+
+// Memory forms: mnemonic and operand size
+fn memory_name(escape: u8, reg: u8) -> Option<(&'static str, &'static str)> {
+    Some(match (escape, reg) {
+        (0, _) => (ARITHMETIC[reg as usize], "DWord"),
+        (1, 0) => ("FLD", "DWord"),
+        (1, 2) => ("FST", "DWord"),
+        (1, 3) => ("FSTP", "DWord"),
+        (1, 4) => ("FLDENV", ""),
+        (1, 5) => ("FLDCW", ""),
+        (1, 6) => ("FNSTENV", ""),
+        (1, 7) => ("FNSTCW", ""),
+        (2, _) => (INTEGER_ARITHMETIC[reg as usize], "DWord"),
+        (3, 0) => ("FILD", "DWord"),
+        (3, 2) => ("FIST", "DWord"),
+        (3, 3) => ("FISTP", "DWord"),
+        (3, 5) => ("FLD", "TByte"),
+        (3, 7) => ("FSTP", "TByte"),
+        (4, _) => (ARITHMETIC[reg as usize], "QWord"),
+        (5, 0) => ("FLD", "QWord"),
+        (5, 2) => ("FST", "QWord"),
+        (5, 3) => ("FSTP", "QWord"),
+        (5, 4) => ("FRSTOR", ""),
+        (5, 6) => ("FNSAVE", ""),
+        (5, 7) => ("FNSTSW", ""),
+        (6, _) => (INTEGER_ARITHMETIC[reg as usize], "Word"),
+        (7, 0) => ("FILD", "Word"),
+        (7, 2) => ("FIST", "Word"),
+        (7, 3) => ("FISTP", "Word"),
+        (7, 4) => ("FBLD", "TByte"),
+        (7, 5) => ("FILD", "QWord"),
+        (7, 6) => ("FBSTP", "TByte"),
+        (7, 7) => ("FISTP", "QWord"),
+        _ => return None,
+    })
+}
+
+fn register_name(escape: u8, reg: u8, i: u8) -> Option<String> {
+    let name = |reg: u8| ARITHMETIC[reg as usize];
+    Some(match (escape, reg) {
+        (0, 2 | 3) | (4, 2 | 3) => format!("{} ST({i})", name(reg)),
+        (0, _) => format!("{} ST, ST({i})", name(reg)),
+        (1, 0) => format!("FLD ST({i})"),
+        (1, 1) => format!("FXCH ST({i})"),
+        (1, 2) if i == 0 => "FNOP".to_string(),
+        (1, 4) => return named(&D9_E0, i),
+        (1, 5) => return named(&D9_E8, i),
+        (1, 6) => return named(&D9_F0, i),
+        (1, 7) => return named(&D9_F8, i),
+        (3, 4) => return named(&DB_E0, i),
+        (4, _) => format!("{} ST({i}), ST", name(reversed(reg))),
+        (5, 0) => format!("FFREE ST({i})"),
+        (5, 2) => format!("FST ST({i})"),
+        (5, 3) => format!("FSTP ST({i})"),
+        (6, 2) => format!("FCOMP ST({i})"),
+        (6, 3) if i == 1 => "FCOMPP".to_string(),
+        (6, 0 | 1 | 4..=7) => format!("{}P ST({i}), ST", name(reversed(reg))),
+        _ => return None,
+    })
+}
+
+// x87 assembly for an ESC instruction
+pub fn mnemonic(op: &Operation) -> String {
+    let escape = op.raws[0] & 0b111;
+    let opcode = escape << 3 | op.reg;
+    if op.mod_rm == 0b11 {
+        return register_name(escape, op.reg, op.rm)
+            .unwrap_or_else(|| format!("ESC {opcode:02x}, ST({})", op.rm));
+    }
+    let ea = effective_address(op.rm, op.mod_rm, op.disp, op.w);
+    match memory_name(escape, op.reg) {
+        Some((name, "")) => format!("{name} {ea}"),
+        Some((name, size)) => format!("{name} {size} {ea}"),
+        None => format!("ESC {opcode:02x}, {ea}"),
+    }
+}
+
+fn read_bytes<const N: usize>(memory: &dyn MemoryBus, addr: usize) -> [u8; N] {
+    std::array::from_fn(|i| memory.read8((addr + i) & ADDRESS_MASK))
+}
+
+fn write_bytes(memory: &mut dyn MemoryBus, addr: usize, bytes: &[u8]) {
+    for (i, byte) in bytes.iter().enumerate() {
+        memory.write8((addr + i) & ADDRESS_MASK, *byte);
+    }
+}
+
+fn tag_for(value: F80) -> u16 {
+    match value.class() {
+        Class::Zero => ZERO,
+        Class::Normal => VALID,
+        _ => SPECIAL,
+    }
+}
+
+// Intel 8087 numeric coprocessor. Exceptions always get the masked response;
+// an unmasked one only sets the error summary bit, as the PC routes the 8087
+// interrupt to NMI which is not wired up here.
+pub struct Fpu {
+    registers: [F80; 8],
+    control: u16,
+    // Status word without the stack top
+    status: u16,
+    top: u8,
+    tag: u16,
+    // Last non-control instruction and its operand, as 20-bit addresses
+    instruction: usize,
+    opcode: u16,
+    operand: usize,
+}
+
+impl Fpu {
+    pub fn new() -> Self {
+        let mut fpu = Fpu {
+            registers: [F80::ZERO; 8],
+            control: 0,
+            status: 0,
+            top: 0,
+            tag: 0,
+            instruction: 0,
+            opcode: 0,
+            operand: 0,
+        };
+        fpu.init();
+        fpu
+    }
+
+    fn init(&mut self) {
+        self.control = DEFAULT_CONTROL;
+        self.status = 0;
+        self.top = 0;
+        self.tag = 0xffff;
+        self.instruction = 0;
+        self.opcode = 0;
+        self.operand = 0;
+    }
+
+    pub fn status_word(&self) -> u16 {
+        self.status & !TOP | (self.top as u16) << 11
+    }
+
+    fn set_status_word(&mut self, value: u16) {
+        self.status = value & !TOP;
+        self.top = (value >> 11) as u8 & 0b111;
+    }
+
+    fn context(&self) -> Context {
+        let rounding = match self.control >> 10 & 0b11 {
+            0 => Rounding::Nearest,
+            1 => Rounding::Down,
+            2 => Rounding::Up,
+            _ => Rounding::Zero,
+        };
+        let precision = match self.control >> 8 & 0b11 {
+            0 => 24,
+            2 => 53,
+            _ => 64,
+        };
+        Context::new(rounding, precision)
+    }
+
+    fn raise(&mut self, flags: u8) {
+        self.status |= flags as u16;
+        if self.status & !self.control & EXCEPTIONS != 0 {
+            self.status |= ERROR_SUMMARY;
+        }
+    }
+
+    fn set_condition(&mut self, bits: u16) {
+        self.status = self.status & !CONDITION | bits;
+    }
+
+    // --- Register stack ---
+    fn physical(&self, i: u8) -> usize {
+        (self.top.wrapping_add(i) & 0b111) as usize
+    }
+
+    fn tag_of(&self, register: usize) -> u16 {
+        self.tag >> (register * 2) & 0b11
+    }
+
+    fn set_tag(&mut self, register: usize, tag: u16) {
+        self.tag = self.tag & !(0b11 << (register * 2)) | tag << (register * 2);
+    }
+
+    fn stack_fault(&mut self, overflow: bool, ctx: &mut Context) {
+        ctx.flags |= f80::INVALID;
+        self.status |= STACK_FAULT;
+        self.status = self.status & !C1 | if overflow { C1 } else { 0 };
+    }
+
+    fn st(&mut self, i: u8, ctx: &mut Context) -> F80 {
+        let register = self.physical(i);
+        if self.tag_of(register) == EMPTY {
+            self.stack_fault(false, ctx);
+            return F80::INDEFINITE;
+        }
+        self.registers[register]
+    }
+
+    fn set_st(&mut self, i: u8, value: F80) {
+        let register = self.physical(i);
+        self.registers[register] = value;
+        self.set_tag(register, tag_for(value));
+    }
+
+    fn push(&mut self, value: F80, ctx: &mut Context) {
+        self.top = self.top.wrapping_sub(1) & 0b111;
+        let value = if self.tag_of(self.physical(0)) != EMPTY {
+            self.stack_fault(true, ctx);
+            F80::INDEFINITE
+        } else {
+            value
+        };
+        self.set_st(0, value);
+    }
+
+    fn pop(&mut self) {
+        self.set_tag(self.physical(0), EMPTY);
+        self.top = (self.top + 1) & 0b111;
+    }
+
+    // --- Execution ---
+    // `ip` is the physical address of the instruction, `addr` that of its memory operand
+    pub fn execute(&mut self, op: &Operation, ip: usize, addr: usize, memory: &mut dyn MemoryBus) {
+        let escape = op.raws[0] & 0b111;
+        let mut ctx = self.context();
+        let control = if op.mod_rm == 0b11 {
+            self.register_form(escape, op.reg, op.rm, &mut ctx);
+            (escape, op.reg) == (3, 4)
+        } else {
+            self.memory_form(escape, op.reg, addr, memory, &mut ctx);
+            matches!((escape, op.reg), (1, 4..=7) | (5, 4..=7))
+        };
+        self.raise(ctx.flags);
+        // Control instructions leave the pointers for an exception handler to inspect
+        if !control {
+            self.instruction = ip;
+            self.opcode = (escape as u16) << 8 | op.raws[1] as u16;
+            self.operand = if op.mod_rm == 0b11 { 0 } else { addr };
+        }
+    }
+
+    fn operate(kind: u8, a: F80, b: F80, ctx: &mut Context) -> F80 {
+        match kind {
+            0 => a.add(b, ctx),
+            1 => a.mul(b, ctx),
+            4 => a.sub(b, ctx),
+            5 => b.sub(a, ctx),
+            6 => a.div(b, ctx),
+            7 => b.div(a, ctx),
+            _ => unreachable!("Not an arithmetic operation: {}", kind),
+        }
+    }
+
+    fn compare(&mut self, a: F80, b: F80, ctx: &mut Context) {
+        use std::cmp::Ordering;
+        let bits = match a.compare(b) {
+            Some(Ordering::Greater) => 0,
+            Some(Ordering::Less) => C0,
+            Some(Ordering::Equal) => C3,
+            None => {
+                ctx.flags |= f80::INVALID;
+                C3 | C2 | C0
+            }
+        };
+        self.set_condition(bits);
+    }
+
+    // ST = ST op value, or a comparison of ST with value
+    fn arithmetic(&mut self, kind: u8, value: F80, ctx: &mut Context) {
+        let st = self.st(0, ctx);
+        match kind {
+            2 => self.compare(st, value, ctx),
+            3 => {
+                self.compare(st, value, ctx);
+                self.pop();
+            }
+            _ => {
+                let result = Fpu::operate(kind, st, value, ctx);
+                self.set_st(0, result);
+            }
+        }
+    }
+
+    fn memory_form(
+        &mut self,
+        escape: u8,
+        reg: u8,
+        addr: usize,
+        memory: &mut dyn MemoryBus,
+        ctx: &mut Context,
+    ) {
+        match (escape, reg) {
+            (0, _) => {
+                let value = F80::from_f32_bits(u32::from_le_bytes(read_bytes(memory, addr)));
+                self.arithmetic(reg, value, ctx);
+            }
+            (2, _) => {
+                let value = i32::from_le_bytes(read_bytes(memory, addr));
+                self.arithmetic(reg, F80::from_i64(value as i64), ctx);
+            }
+            (4, _) => {
+                let value = F80::from_f64_bits(u64::from_le_bytes(read_bytes(memory, addr)));
+                self.arithmetic(reg, value, ctx);
+            }
+            (6, _) => {
+                let value = i16::from_le_bytes(read_bytes(memory, addr));
+                self.arithmetic(reg, F80::from_i64(value as i64), ctx);
+            }
+            (1, 0) => {
+                let value = F80::from_f32_bits(u32::from_le_bytes(read_bytes(memory, addr)));
+                self.push(value, ctx);
+            }
+            (1, 2 | 3) => {
+                let value = self.st(0, ctx).to_f32_bits(ctx);
+                write_bytes(memory, addr, &value.to_le_bytes());
+                if reg == 3 {
+                    self.pop();
+                }
+            }
+            (1, 4) => self.load_environment(memory, addr),
+            (1, 5) => self.control = u16::from_le_bytes(read_bytes(memory, addr)),
+            (1, 6) => self.store_environment(memory, addr),
+            (1, 7) => write_bytes(memory, addr, &self.control.to_le_bytes()),
+            (3, 0) => {
+                let value = i32::from_le_bytes(read_bytes(memory, addr));
+                self.push(F80::from_i64(value as i64), ctx);
+            }
+            (3, 2 | 3) => self.store_integer(memory, addr, 4, reg == 3, ctx),
+            (3, 5) => self.push(F80::from_bytes(read_bytes(memory, addr)), ctx),
+            (3, 7) => {
+                let value = self.st(0, ctx);
+                write_bytes(memory, addr, &value.to_bytes());
+                self.pop();
+            }
+            (5, 0) => {
+                let value = F80::from_f64_bits(u64::from_le_bytes(read_bytes(memory, addr)));
+                self.push(value, ctx);
+            }
+            (5, 2 | 3) => {
+                let value = self.st(0, ctx).to_f64_bits(ctx);
+                write_bytes(memory, addr, &value.to_le_bytes());
+                if reg == 3 {
+                    self.pop();
+                }
+            }
+            (5, 4) => {
+                self.load_environment(memory, addr);
+                for i in 0..8 {
+                    let value =
+                        F80::from_bytes(read_bytes(memory, addr + ENVIRONMENT_SIZE + i * 10));
+                    let register = self.physical(i as u8);
+                    self.registers[register] = value;
+                }
+            }
+            (5, 6) => {
+                self.store_environment(memory, addr);
+                for i in 0..8 {
+                    let value = self.registers[self.physical(i as u8)];
+                    write_bytes(memory, addr + ENVIRONMENT_SIZE + i * 10, &value.to_bytes());
+                }
+                self.init();
+            }
+            (5, 7) => write_bytes(memory, addr, &self.status_word().to_le_bytes()),
+            (7, 0) => {
+                let value = i16::from_le_bytes(read_bytes(memory, addr));
+                self.push(F80::from_i64(value as i64), ctx);
+            }
+            (7, 2 | 3) => self.store_integer(memory, addr, 2, reg == 3, ctx),
+            (7, 4) => {
+                let value = from_bcd(read_bytes(memory, addr));
+                self.push(value, ctx);
+            }
+            (7, 5) => {
+                let value = i64::from_le_bytes(read_bytes(memory, addr));
+                self.push(F80::from_i64(value), ctx);
+            }
+            (7, 6) => {
+                let value = self.st(0, ctx);
+                write_bytes(memory, addr, &to_bcd(value, ctx));
+                self.pop();
+            }
+            (7, 7) => self.store_integer(memory, addr, 8, true, ctx),
+            _ => {
+                // Undefined on the 8087
+            }
+        }
+    }
+
+    // FIST and FISTP; out of range values store the integer indefinite
+    fn store_integer(
+        &mut self,
+        memory: &mut dyn MemoryBus,
+        addr: usize,
+        size: usize,
+        pop: bool,
+        ctx: &mut Context,
+    ) {
+        let limit = 1i128 << (size * 8 - 1);
+        let value = self
+            .st(0, ctx)
+            .to_i64(ctx)
+            .filter(|n| (-limit..limit).contains(&(*n as i128)));
+        let bytes = match value {
+            Some(n) => n.to_le_bytes(),
+            None => {
+                ctx.flags |= f80::INVALID;
+                (limit as u64).to_le_bytes()
+            }
+        };
+        write_bytes(memory, addr, &bytes[..size]);
+        if pop {
+            self.pop();
+        }
+    }
+
+    fn store_environment(&self, memory: &mut dyn MemoryBus, addr: usize) {
+        let words = [
+            self.control,
+            self.status_word(),
+            self.tag,
+            self.instruction as u16,
+            ((self.instruction >> 16) as u16) << 12 | self.opcode & 0x07ff,
+            self.operand as u16,
+            ((self.operand >> 16) as u16) << 12,
+        ];
+        for (i, word) in words.iter().enumerate() {
+            write_bytes(memory, addr + i * 2, &word.to_le_bytes());
+        }
+    }
+
+    fn load_environment(&mut self, memory: &dyn MemoryBus, addr: usize) {
+        let words: [u16; 7] =
+            std::array::from_fn(|i| u16::from_le_bytes(read_bytes(memory, addr + i * 2)));
+        self.control = words[0];
+        self.set_status_word(words[1]);
+        self.tag = words[2];
+        self.instruction = words[3] as usize | ((words[4] >> 12) as usize) << 16;
+        self.opcode = words[4] & 0x07ff;
+        self.operand = words[5] as usize | ((words[6] >> 12) as usize) << 16;
+    }
+
+    fn register_form(&mut self, escape: u8, reg: u8, i: u8, ctx: &mut Context) {
+        match (escape, reg) {
+            (0, _) => {
+                let value = self.st(i, ctx);
+                self.arithmetic(reg, value, ctx);
+            }
+            (4, 2 | 3) | (6, 2) => {
+                let (a, b) = (self.st(0, ctx), self.st(i, ctx));
+                self.compare(a, b, ctx);
+                if reg == 3 || escape == 6 {
+                    self.pop();
+                }
+            }
+            (6, 3) if i == 1 => {
+                let (a, b) = (self.st(0, ctx), self.st(1, ctx));
+                self.compare(a, b, ctx);
+                self.pop();
+                self.pop();
+            }
+            (4, _) | (6, 0 | 1 | 4..=7) => {
+                let (a, b) = (self.st(i, ctx), self.st(0, ctx));
+                let result = Fpu::operate(reversed(reg), a, b, ctx);
+                self.set_st(i, result);
+                if escape == 6 {
+                    self.pop();
+                }
+            }
+            (1, 0) => {
+                let value = self.st(i, ctx);
+                self.push(value, ctx);
+            }
+            (1, 1) => {
+                let (a, b) = (self.st(0, ctx), self.st(i, ctx));
+                self.set_st(0, b);
+                self.set_st(i, a);
+            }
+            (1, 4) => self.sign_group(i, ctx),
+            (1, 5) if i < 7 => self.push(CONSTANTS[i as usize], ctx),
+            (1, 6) => self.transcendental_group(i, ctx),
+            (1, 7) => self.arithmetic_group(i, ctx),
+            (3, 4) => match i {
+                0 => self.control &= !INTERRUPT_MASK,
+                1 => self.control |= INTERRUPT_MASK,
+                2 => self.status &= !(EXCEPTIONS | STACK_FAULT | ERROR_SUMMARY | 0x8000),
+                3 => self.init(),
+                _ => {}
+            },
+            (5, 0) => self.set_tag(self.physical(i), EMPTY),
+            (5, 2 | 3) => {
+                let value = self.st(0, ctx);
+                self.set_st(i, value);
+                if reg == 3 {
+                    self.pop();
+                }
+            }
+            _ => {
+                // FNOP, and encodings the 8087 does not define
+            }
+        }
+    }
+
+    // FCHS, FABS, FTST and FXAM
+    fn sign_group(&mut self, i: u8, ctx: &mut Context) {
+        match i {
+            0 => {
+                let value = self.st(0, ctx);
+                self.set_st(0, value.negate());
+            }
+            1 => {
+                let value = self.st(0, ctx);
+                self.set_st(0, value.abs());
+            }
+            4 => {
+                let value = self.st(0, ctx);
+                self.compare(value, F80::ZERO, ctx);
+            }
+            5 => {
+                let value = self.registers[self.physical(0)];
+                let class = if self.tag_of(self.physical(0)) == EMPTY {
+                    C3 | C0
+                } else {
+                    match value.class() {
+                        Class::Nan => C0,
+                        Class::Normal => C2,
+                        Class::Infinity => C2 | C0,
+                        Class::Zero => C3,
+                        Class::Denormal => C3 | C2,
+                    }
+                };
+                let sign = if value.sign { C1 } else { 0 };
+                self.set_condition(class | sign);
+            }
+            _ => {}
+        }
+    }
+
+    // F2XM1, FYL2X, FPTAN, FPATAN, FXTRACT, FDECSTP and FINCSTP. The
+    // transcendentals are computed in double precision and always inexact.
+    fn transcendental_group(&mut self, i: u8, ctx: &mut Context) {
+        match i {
+            0 => {
+                let x = self.st(0, ctx).to_f64();
+                ctx.flags |= f80::PRECISION;
+                self.set_st(0, F80::from_f64((x * std::f64::consts::LN_2).exp_m1()));
+            }
+            1 | 3 => {
+                let (x, y) = (self.st(0, ctx).to_f64(), self.st(1, ctx).to_f64());
+                ctx.flags |= f80::PRECISION;
+                let result = if i == 1 { y * x.log2() } else { y.atan2(x) };
+                self.set_st(1, F80::from_f64(result));
+                self.pop();
+            }
+            2 => {
+                let x = self.st(0, ctx).to_f64();
+                ctx.flags |= f80::PRECISION;
+                self.set_st(0, F80::from_f64(x.tan()));
+                self.push(F80::ONE, ctx);
+            }
+            4 => {
+                let value = self.st(0, ctx);
+                let (exponent, significand) = value.extract();
+                self.set_st(0, exponent);
+                self.push(significand, ctx);
+            }
+            6 => self.top = self.top.wrapping_sub(1) & 0b111,
+            7 => self.top = (self.top + 1) & 0b111,
+            _ => {}
+        }
+    }
+
+    // FPREM, FYL2XP1, FSQRT, FRNDINT and FSCALE
+    fn arithmetic_group(&mut self, i: u8, ctx: &mut Context) {
+        match i {
+            0 => {
+                let (a, b) = (self.st(0, ctx), self.st(1, ctx));
+                let (rest, quotient, complete) = a.partial_remainder(b, ctx);
+                self.set_st(0, rest);
+                let quotient = quotient as u16;
+                let bits = if complete {
+                    (quotient & 4) << 6 | (quotient & 2) << 13 | (quotient & 1) << 9
+                } else {
+                    C2
+                };
+                self.set_condition(bits);
+            }
+            1 => {
+                let (x, y) = (self.st(0, ctx).to_f64(), self.st(1, ctx).to_f64());
+                ctx.flags |= f80::PRECISION;
+                let result = y * x.ln_1p() / std::f64::consts::LN_2;
+                self.set_st(1, F80::from_f64(result));
+                self.pop();
+            }
+            2 => {
+                let value = self.st(0, ctx);
+                self.set_st(0, value.sqrt(ctx));
+            }
+            4 => {
+                let value = self.st(0, ctx);
+                self.set_st(0, value.round_to_integer(ctx));
+            }
+            5 => {
+                let (value, scale) = (self.st(0, ctx), self.st(1, ctx));
+                let mut chop = Context::new(Rounding::Zero, 64);
+                let n = match scale.to_i64(&mut chop) {
+                    Some(n) => n.clamp(i32::MIN as i64, i32::MAX as i64) as i32,
+                    None if scale.sign => i32::MIN,
+                    None => i32::MAX,
+                };
+                self.set_st(0, value.scale(n, ctx));
+            }
+            _ => {}
+        }
+    }
+}
+
+// 18 packed BCD digits and a sign byte
+fn from_bcd(bytes: [u8; 10]) -> F80 {
+    let value = bytes[..9].iter().rev().fold(0i64, |n, byte| {
+        n * 100 + (byte >> 4) as i64 * 10 + (byte & 0x0f) as i64
+    });
+    let value = F80::from_i64(value);
+    if bytes[9] & 0x80 != 0 {
+        value.negate()
+    } else {
+        value
+    }
+}
+
+fn to_bcd(value: F80, ctx: &mut Context) -> [u8; 10] {
+    let mut bytes = [0; 10];
+    match value
+        .to_i64(ctx)
+        .filter(|n| n.unsigned_abs() < 10u64.pow(18))
+    {
+        Some(n) => {
+            let mut digits = n.unsigned_abs();
+            for byte in bytes[..9].iter_mut() {
+                *byte = (digits % 10) as u8 | ((digits / 10 % 10) as u8) << 4;
+                digits /= 100;
+            }
+            if value.sign {
+                bytes[9] = 0x80;
+            }
+        }
+        None => {
+            ctx.flags |= f80::INVALID;
+            bytes[7] = 0xc0;
+            bytes[8] = 0xff;
+            bytes[9] = 0xff;
+        }
+    }
+    bytes
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{memory::Memory, operation::Raws};
+    use test_case::test_case;
+
+    fn esc(bytes: &[u8]) -> Operation {
+        let mut op = Operation::new();
+        op.raws = Raws::from(bytes);
+        op.set_mod_reg_rm(bytes[1]);
+        if bytes.len() > 2 {
+            op.disp = u16::from_le_bytes([bytes[2], bytes[3]]);
+        }
+        op
+    }
+
+    #[test_case(&[0xd8, 0xc1], "FADD ST, ST(1)" ; "fadd register")]
+    #[test_case(&[0xdc, 0xe9], "FSUB ST(1), ST" ; "reversed fsub")]
+    #[test_case(&[0xde, 0xf9], "FDIVP ST(1), ST" ; "fdivp")]
+    #[test_case(&[0xde, 0xd9], "FCOMPP" ; "fcompp")]
+    #[test_case(&[0xd9, 0xeb], "FLDPI" ; "constant")]
+    #[test_case(&[0xdb, 0xe3], "FNINIT" ; "fninit")]
+    #[test_case(&[0xdd, 0x06, 0x34, 0x12], "FLD QWord [1234]" ; "fld m64")]
+    #[test_case(&[0xdf, 0x3e, 0x00, 0x02], "FISTP QWord [0200]" ; "fistp m64")]
+    #[test_case(&[0xdd, 0x3e, 0x00, 0x02], "FNSTSW [0200]" ; "fnstsw")]
+    #[test_case(&[0xd9, 0x0e, 0x00, 0x02], "ESC 09, [0200]" ; "undefined")]
+    fn test_mnemonic(bytes: &[u8], expected: &str) {
+        assert_eq!(mnemonic(&esc(bytes)), expected);
+    }
+
+    #[test]
+    fn test_integer_arithmetic() {
+        let mut fpu = Fpu::new();
+        let mut memory = Memory::new(0x1000);
+        memory.load(0x200, &7i16.to_le_bytes());
+        memory.load(0x210, &1.5f64.to_le_bytes());
+        // FILD [200]; FMUL QWord [210]; FISTP Word [220]
+        fpu.execute(&esc(&[0xdf, 0x06, 0x00, 0x02]), 0, 0x200, &mut memory);
+        fpu.execute(&esc(&[0xdc, 0x0e, 0x10, 0x02]), 0, 0x210, &mut memory);
+        fpu.execute(&esc(&[0xdf, 0x1e, 0x20, 0x02]), 0, 0x220, &mut memory);
+        // 10.5 rounds to even
+        assert_eq!(memory.read16(0x220), 10);
+        assert_eq!(fpu.status_word(), f80::PRECISION as u16);
+        assert_eq!(fpu.tag, 0xffff);
+    }
+
+    #[test]
+    fn test_stack_underflow_and_compare() {
+        let mut fpu = Fpu::new();
+        let mut memory = Memory::new(0x100);
+        // FCOM ST(1) on an empty stack
+        fpu.execute(&esc(&[0xd8, 0xd1]), 0, 0, &mut memory);
+        assert_eq!(
+            fpu.status_word() & (STACK_FAULT | CONDITION),
+            STACK_FAULT | C3 | C2 | C0
+        );
+        // FLD1; FLDZ; FCOM ST(1): 0 < 1
+        fpu.execute(&esc(&[0xdb, 0xe3]), 0, 0, &mut memory);
+        fpu.execute(&esc(&[0xd9, 0xe8]), 0, 0, &mut memory);
+        fpu.execute(&esc(&[0xd9, 0xee]), 0, 0, &mut memory);
+        fpu.execute(&esc(&[0xd8, 0xd1]), 0, 0, &mut memory);
+        assert_eq!(fpu.status_word(), 6 << 11 | C0);
+    }
+
+    #[test]
+    fn test_bcd_round_trip() {
+        let mut ctx = Context::new(Rounding::Nearest, 64);
+        let bytes = to_bcd(F80::from_i64(-1234567), &mut ctx);
+        assert_eq!(bytes, [0x67, 0x45, 0x23, 0x01, 0, 0, 0, 0, 0, 0x80]);
+        assert_eq!(from_bcd(bytes), F80::from_i64(-1234567));
+    }
+}
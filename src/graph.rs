@@ -0,0 +1,343 @@
+use crate::{
+    cpu::Cpu,
+    disassembler,
+    dump::format_operation,
+    metadata::{Metadata, N_TEXT},
+    operation::{Operation, OperationType},
+};
+use std::collections::{BTreeMap, BTreeSet};
+
+// The graphs exported in Graphviz DOT
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Graph {
+    // One control-flow graph per function
+    Cfg,
+    // Which function calls which
+    Calls,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Edge {
+    Fallthrough,
+    // A conditional jump or loop taken, and not taken
+    Taken,
+    NotTaken,
+    Jump,
+    Call,
+}
+
+struct Block<'a> {
+    operations: Vec<&'a Operation>,
+    edges: Vec<(Edge, usize)>,
+}
+
+// Basic blocks and functions of the code that recursive descent reaches
+struct Program<'a> {
+    blocks: BTreeMap<usize, Block<'a>>,
+    // Function entries and the blocks of each
+    functions: BTreeMap<usize, BTreeSet<usize>>,
+    names: BTreeMap<usize, String>,
+}
+
+impl Graph {
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "cfg" => Some(Graph::Cfg),
+            "calls" => Some(Graph::Calls),
+            _ => None,
+        }
+    }
+}
+
+// The graph of the executable's text in DOT, labeled with its text symbols
+pub fn export(executable: &[u8], graph: Graph, cpu: Cpu) -> String {
+    let operations = disassembler::disassemble_recursive(executable, false, cpu);
+    let metadata = Metadata::from_bytes(executable);
+    let mut symbols: Vec<_> = metadata
+        .symbols(executable)
+        .into_iter()
+        .filter(|symbol| symbol.section() == N_TEXT)
+        .map(|symbol| (symbol.value as usize, symbol.name))
+        .collect();
+    symbols.sort();
+    let program = Program::new(&operations, metadata.entry, &symbols);
+    match graph {
+        Graph::Cfg => program.cfg(),
+        Graph::Calls => program.call_graph(),
+    }
+}
+
+impl<'a> Program<'a> {
+    fn new(operations: &'a [Operation], entry: usize, symbols: &[(usize, String)]) -> Self {
+        let at: BTreeMap<usize, &Operation> = operations.iter().map(|op| (op.pos, op)).collect();
+        let mut entries = BTreeSet::from([entry]);
+        entries.retain(|pos| at.contains_key(pos));
+
+        // Blocks start at the roots, at jump targets and after control transfers
+        let mut leaders: BTreeSet<usize> = symbols.iter().map(|(value, _)| *value).collect();
+        leaders.retain(|pos| at.contains_key(pos));
+        leaders.extend(&entries);
+        for op in operations {
+            if let Some(target) = op.target().filter(|target| at.contains_key(target)) {
+                leaders.insert(target);
+                if op.operation_type == OperationType::Call {
+                    entries.insert(target);
+                }
+            }
+            if ends_block(op) {
+                leaders.insert(op.get_next_operation_pos());
+            }
+        }
+
+        let mut blocks = BTreeMap::new();
+        let mut current: Option<(usize, Block)> = None;
+        for op in operations {
+            let continues = current.as_ref().is_some_and(|(_, block)| {
+                let last = block.operations.last().unwrap();
+                last.get_next_operation_pos() == op.pos && !ends_block(last)
+            });
+            if !continues || leaders.contains(&op.pos) {
+                if let Some((start, block)) = current.take() {
+                    blocks.insert(start, block);
+                }
+                let block = Block {
+                    operations: Vec::new(),
+                    edges: Vec::new(),
+                };
+                current = Some((op.pos, block));
+            }
+            current.as_mut().unwrap().1.operations.push(op);
+        }
+        if let Some((start, block)) = current {
+            blocks.insert(start, block);
+        }
+
+        for block in blocks.values_mut() {
+            let last = *block.operations.last().unwrap();
+            let next = last.get_next_operation_pos();
+            let target = last.target().filter(|target| at.contains_key(target));
+            let edges = &mut block.edges;
+            match (last.operation_type, target) {
+                (OperationType::Call, Some(target)) => edges.push((Edge::Call, target)),
+                (OperationType::Jmp, Some(target)) => edges.push((Edge::Jump, target)),
+                (_, Some(target)) => edges.push((Edge::Taken, target)),
+                _ => {}
+            }
+            if last.falls_through() && at.contains_key(&next) {
+                let edge = match (last.operation_type, target) {
+                    (OperationType::Call, _) | (_, None) => Edge::Fallthrough,
+                    _ => Edge::NotTaken,
+                };
+                edges.push((edge, next));
+            }
+        }
+
+        // Symbols that no function reaches start functions of their own,
+        // the others are labels inside one
+        for (value, _) in symbols {
+            if at.contains_key(value) && !entries.contains(value) {
+                let reached = entries
+                    .iter()
+                    .any(|&entry| function_blocks(&blocks, &entries, entry).contains(value));
+                if !reached {
+                    entries.insert(*value);
+                }
+            }
+        }
+        let functions = entries
+            .iter()
+            .map(|&entry| (entry, function_blocks(&blocks, &entries, entry)))
+            .collect();
+
+        let mut names: BTreeMap<usize, String> = BTreeMap::new();
+        for (value, name) in symbols {
+            names.entry(*value).or_insert(name.clone());
+        }
+        Program {
+            blocks,
+            functions,
+            names,
+        }
+    }
+
+    fn name(&self, pos: usize) -> String {
+        match self.names.get(&pos) {
+            Some(name) => name.clone(),
+            None => format!("sub_{:04x}", pos),
+        }
+    }
+
+    fn cfg(&self) -> String {
+        let mut dot = String::new();
+        for (&entry, reached) in &self.functions {
+            dot.push_str(&format!("digraph \"{}\" {{\n", escape(&self.name(entry))));
+            dot.push_str("    node [shape=box, fontname=monospace];\n");
+            let mut callees = BTreeSet::new();
+            for &start in reached {
+                let block = &self.blocks[&start];
+                let mut label = String::new();
+                if let Some(name) = self.names.get(&start) {
+                    label.push_str(&format!("{}:\\l", escape(name)));
+                }
+                for op in &block.operations {
+                    let text = format_operation(op, op.form);
+                    label.push_str(&format!("{:04x}: {}\\l", op.pos, escape(&text)));
+                }
+                dot.push_str(&format!("    b{:04x} [label=\"{}\"];\n", start, label));
+                for &(edge, to) in &block.edges {
+                    let attributes = match edge {
+                        Edge::Fallthrough => "",
+                        Edge::Taken => " [label=T, color=darkgreen]",
+                        Edge::NotTaken => " [label=F, color=red]",
+                        Edge::Jump => " [color=blue]",
+                        Edge::Call => " [style=dashed]",
+                    };
+                    // Other functions are drawn as their names, their bodies
+                    // have their own graphs
+                    let node = if edge == Edge::Call
+                        || (to != entry && self.functions.contains_key(&to))
+                    {
+                        callees.insert(to);
+                        format!("f{:04x}", to)
+                    } else {
+                        format!("b{:04x}", to)
+                    };
+                    dot.push_str(&format!("    b{:04x} -> {}{};\n", start, node, attributes));
+                }
+            }
+            for callee in callees {
+                dot.push_str(&format!(
+                    "    f{:04x} [label=\"{}\", shape=ellipse];\n",
+                    callee,
+                    escape(&self.name(callee))
+                ));
+            }
+            dot.push_str("}\n");
+        }
+        dot
+    }
+
+    fn call_graph(&self) -> String {
+        let mut dot = String::from("digraph calls {\n");
+        dot.push_str("    node [shape=ellipse, fontname=monospace];\n");
+        for &entry in self.functions.keys() {
+            dot.push_str(&format!(
+                "    f{:04x} [label=\"{}\"];\n",
+                entry,
+                escape(&self.name(entry))
+            ));
+        }
+        for (&entry, reached) in &self.functions {
+            let callees: BTreeSet<usize> = reached
+                .iter()
+                .flat_map(|start| &self.blocks[start].edges)
+                .filter(|(edge, _)| *edge == Edge::Call)
+                .map(|&(_, to)| to)
+                .collect();
+            for callee in callees {
+                dot.push_str(&format!("    f{:04x} -> f{:04x};\n", entry, callee));
+            }
+        }
+        dot.push_str("}\n");
+        dot
+    }
+}
+
+// The blocks reached from a function entry without following calls or
+// running into another function
+fn function_blocks(
+    blocks: &BTreeMap<usize, Block>,
+    entries: &BTreeSet<usize>,
+    entry: usize,
+) -> BTreeSet<usize> {
+    let mut reached = BTreeSet::new();
+    let mut pending = vec![entry];
+    while let Some(start) = pending.pop() {
+        if !reached.insert(start) {
+            continue;
+        }
+        for &(edge, to) in &blocks[&start].edges {
+            if edge != Edge::Call && !entries.contains(&to) {
+                pending.push(to);
+            }
+        }
+    }
+    reached
+}
+
+// Whether the operation is the last of its block: jumps, calls and returns
+fn ends_block(op: &Operation) -> bool {
+    op.target().is_some() || !op.falls_through() || op.operation_type == OperationType::Call
+}
+
+fn escape(text: &str) -> String {
+    text.replace('\\', "\\\\").replace('"', "\\\"")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::assembler;
+
+    fn dot(graph: Graph) -> String {
+        let source = "
+            start:  MOV CX, 3
+            again:  CALL count
+                    LOOP again
+                    CMP AX, 0
+                    JZ done
+                    CALL 14
+            done:   HLT
+            count:  INC AX
+                    RET
+                    NOP
+                    JMP count
+        ";
+        let executable = assembler::assemble(source, Cpu::I8086).unwrap();
+        export(&executable, graph, Cpu::I8086)
+    }
+
+    #[test]
+    fn test_cfg() {
+        let dot = dot(Graph::Cfg);
+        let graphs: Vec<&str> = dot.split_inclusive("}\n").collect();
+        assert_eq!(graphs.len(), 3);
+        // again and done are labels inside start
+        let start = graphs[0];
+        assert!(start.starts_with("digraph \"start\" {\n"));
+        #[rustfmt::skip]
+        let lines = [
+            "b0003 [label=\"again:\\l0003: CALL 0011\\l\"];",
+            "b0008 [label=\"0008: CMP AX, 0000\\l000b: JE 0010\\l\"];",
+            "b0000 -> b0003;",
+            "b0003 -> f0011 [style=dashed];",
+            "b0003 -> b0006;",
+            "b0006 -> b0003 [label=T, color=darkgreen];",
+            "b0006 -> b0008 [label=F, color=red];",
+            "b0008 -> b0010 [label=T, color=darkgreen];",
+            "b0008 -> b000d [label=F, color=red];",
+            "b000d -> f0014 [style=dashed];",
+            "b000d -> b0010;",
+            // HLT runs into count when an interrupt returns
+            "b0010 -> f0011;",
+            "f0014 [label=\"sub_0014\", shape=ellipse];",
+        ];
+        for line in lines {
+            assert!(start.contains(line), "{} missing from\n{}", line, start);
+        }
+        assert!(graphs[1].starts_with("digraph \"count\" {\n"));
+        assert!(graphs[2].contains("b0014 -> f0011 [color=blue];"));
+    }
+
+    #[test]
+    fn test_call_graph() {
+        let dot = dot(Graph::Calls);
+        assert!(dot.contains("f0000 [label=\"start\"];"));
+        assert!(dot.contains("f0011 [label=\"count\"];"));
+        assert!(dot.contains("f0014 [label=\"sub_0014\"];"));
+        assert!(dot.contains("f0000 -> f0011;"));
+        assert!(dot.contains("f0000 -> f0014;"));
+        // A jump to another function is not a call
+        assert!(!dot.contains("f0014 -> f0011;"));
+    }
+}
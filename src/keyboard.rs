@@ -0,0 +1,499 @@
+use std::{
+    collections::VecDeque,
+    io::{stdin, BufReader, IsTerminal, Read},
+    process::{Command, Stdio},
+    sync::mpsc::{self, Receiver, TryRecvError},
+};
+
+use crate::{machine::CLOCK_HZ, port::PortBus};
+
+pub const KBC_DATA_PORT: u16 = 0x60;
+pub const KBC_STATUS_PORT: u16 = 0x64;
+pub const KEYBOARD_IRQ: u8 = 1;
+
+// Status register bits
+const STATUS_OUTPUT_FULL: u8 = 0x01;
+const STATUS_SYSTEM: u8 = 0x04;
+const STATUS_COMMAND: u8 = 0x08;
+const STATUS_UNLOCKED: u8 = 0x10;
+
+// Command byte bits
+const COMMAND_INTERRUPT: u8 = 0x01;
+const COMMAND_SYSTEM: u8 = 0x04;
+const COMMAND_DISABLED: u8 = 0x10;
+const COMMAND_TRANSLATE: u8 = 0x40;
+
+const ACK: u8 = 0xfa;
+const SELF_TEST_PASSED: u8 = 0xaa;
+
+// Set 1 scan codes
+pub const RIGHT_SHIFT: u8 = 0x36;
+pub const LEFT_SHIFT: u8 = 0x2a;
+pub const CTRL: u8 = 0x1d;
+pub const ALT: u8 = 0x38;
+pub const CAPS_LOCK: u8 = 0x3a;
+pub const BREAK: u8 = 0x80;
+const ENTER: u8 = 0x1c;
+const BACKSPACE: u8 = 0x0e;
+const ESC: u8 = 0x01;
+
+// Ctrl-] on the host terminal stops the machine, as in telnet
+const QUIT: u8 = 0x1d;
+
+// US layout: first scan code of each row, unshifted and shifted characters
+const KEY_ROWS: [(u8, &[u8], &[u8]); 4] = [
+    (0x02, b"1234567890-=", b"!@#$%^&*()_+"),
+    (0x10, b"qwertyuiop[]", b"QWERTYUIOP{}"),
+    (0x1e, b"asdfghjkl;'`", b"ASDFGHJKL:\"~"),
+    (0x2b, b"\\zxcvbnm,./", b"|ZXCVBNM<>?"),
+];
+
+// Keys without a character, by name in key scripts
+const NAMED_KEYS: [(&str, u8); 14] = [
+    ("enter", ENTER),
+    ("esc", ESC),
+    ("tab", 0x0f),
+    ("backspace", BACKSPACE),
+    ("space", 0x39),
+    ("up", 0x48),
+    ("down", 0x50),
+    ("left", 0x4b),
+    ("right", 0x4d),
+    ("home", 0x47),
+    ("end", 0x4f),
+    ("pgup", 0x49),
+    ("pgdn", 0x51),
+    ("del", 0x53),
+];
+
+pub fn scan_code(ascii: u8) -> u8 {
+    match ascii {
+        0x1b => ESC,
+        0x08 | 0x7f => BACKSPACE,
+        b'\t' => 0x0f,
+        b'\r' => ENTER,
+        b' ' => 0x39,
+        _ => KEY_ROWS
+            .iter()
+            .find_map(|(first, normal, shifted)| {
+                normal
+                    .iter()
+                    .chain(shifted.iter())
+                    .position(|c| *c == ascii)
+                    .map(|i| first + (i % normal.len()) as u8)
+            })
+            .unwrap_or(0),
+    }
+}
+
+// The character a key produces, 0 for keys without one
+pub fn ascii(scan: u8, shifted: bool) -> u8 {
+    match scan {
+        ESC => 0x1b,
+        BACKSPACE => 0x08,
+        0x0f => b'\t',
+        ENTER => b'\r',
+        0x39 => b' ',
+        0x37 => b'*',
+        _ => KEY_ROWS
+            .iter()
+            .find_map(|(first, normal, shifted_row)| {
+                let row = if shifted { shifted_row } else { normal };
+                row.get(scan.checked_sub(*first)? as usize).copied()
+            })
+            .unwrap_or(0),
+    }
+}
+
+// Make and break codes for a key, wrapped in the modifier it needs
+fn strokes(scan: u8, modifier: Option<u8>) -> Vec<u8> {
+    match modifier {
+        Some(m) => vec![m, scan, scan | BREAK, m | BREAK],
+        None => vec![scan, scan | BREAK],
+    }
+}
+
+// Host character to key strokes
+fn type_char(c: u8) -> Vec<u8> {
+    match c {
+        b'\n' => strokes(ENTER, None),
+        // Ctrl-A .. Ctrl-Z, except those with keys of their own
+        0x01..=0x1a if !matches!(c, 0x08 | 0x09 | 0x0d) => strokes(scan_code(c + 0x60), Some(CTRL)),
+        _ => {
+            let scan = scan_code(c);
+            if scan == 0 {
+                return Vec::new();
+            }
+            let shifted = c.is_ascii_uppercase()
+                || KEY_ROWS.iter().any(|(_, _, shifted)| shifted.contains(&c));
+            strokes(scan, shifted.then_some(LEFT_SHIFT))
+        }
+    }
+}
+
+// Puts the host terminal in raw mode and restores it when dropped
+pub struct RawMode(String);
+
+impl RawMode {
+    pub fn enter() -> Option<Self> {
+        if !stdin().is_terminal() {
+            return None;
+        }
+        let saved = Command::new("stty")
+            .arg("-g")
+            .stdin(Stdio::inherit())
+            .output()
+            .ok()?;
+        // Output processing stays on so a bare newline still returns the carriage
+        Command::new("stty")
+            .args(["-icanon", "-echo", "-isig", "-ixon", "-icrnl", "min", "1"])
+            .status()
+            .ok()?;
+        Some(RawMode(
+            String::from_utf8_lossy(&saved.stdout).trim().to_string(),
+        ))
+    }
+}
+
+impl Drop for RawMode {
+    fn drop(&mut self) {
+        let _ = Command::new("stty").arg(&self.0).status();
+    }
+}
+
+// Where key presses come from
+pub enum KeySource {
+    // Host terminal, characters translated to key strokes
+    Stdin {
+        input: Receiver<u8>,
+        // Held until the machine is dropped, which restores the terminal
+        _raw: Option<RawMode>,
+    },
+    // Scan codes and the CPU clock at which they are typed
+    Script(VecDeque<(u64, Vec<u8>)>),
+}
+
+impl KeySource {
+    pub fn stdin() -> Self {
+        let raw = RawMode::enter();
+        let (tx, rx) = mpsc::channel();
+        std::thread::spawn(move || {
+            for byte in BufReader::new(stdin()).bytes() {
+                match byte {
+                    Ok(byte) if tx.send(byte).is_ok() => {}
+                    _ => break,
+                }
+            }
+        });
+        KeySource::Stdin {
+            input: rx,
+            _raw: raw,
+        }
+    }
+
+    // One event per line: a delay in emulated milliseconds after the previous
+    // event, then the keys to type. `<enter>`, `<f1>`, `<ctrl-c>`, `<lt>`, ...
+    // name keys without a character of their own. Lines starting with `#` are comments.
+    pub fn script(text: &str) -> Result<Self, String> {
+        let mut events = VecDeque::new();
+        let mut ms = 0;
+        for (number, line) in text.lines().enumerate() {
+            if line.trim().is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let error = |message: &str| format!("line {}: {}", number + 1, message);
+            let (delay, keys) = line.split_once(' ').unwrap_or((line, ""));
+            ms += delay
+                .parse::<u64>()
+                .map_err(|_| error("expected a delay in milliseconds"))?;
+            let codes = parse_keys(keys).map_err(|e| error(&e))?;
+            events.push_back((ms * CLOCK_HZ / 1000, codes));
+        }
+        Ok(KeySource::Script(events))
+    }
+}
+
+fn parse_keys(keys: &str) -> Result<Vec<u8>, String> {
+    let mut codes = Vec::new();
+    let mut rest = keys;
+    while let Some(c) = rest.chars().next() {
+        if c != '<' {
+            if !c.is_ascii() {
+                return Err(format!("no key for {:?}", c));
+            }
+            codes.extend(type_char(c as u8));
+            rest = &rest[1..];
+            continue;
+        }
+        let end = rest.find('>').ok_or("unterminated <key>")?;
+        let name = &rest[1..end];
+        rest = &rest[end + 1..];
+        if name == "lt" {
+            codes.extend(type_char(b'<'));
+        } else if let Some(letter) = name.strip_prefix("ctrl-") {
+            match letter.as_bytes() {
+                [c] if c.is_ascii_lowercase() => codes.extend(strokes(scan_code(*c), Some(CTRL))),
+                _ => return Err(format!("unknown key <{}>", name)),
+            }
+        } else if let Some(n) = name.strip_prefix('f').and_then(|n| n.parse::<u8>().ok()) {
+            if !(1..=10).contains(&n) {
+                return Err(format!("unknown key <{}>", name));
+            }
+            codes.extend(strokes(0x3a + n, None));
+        } else {
+            let scan = NAMED_KEYS
+                .iter()
+                .find(|(key, _)| *key == name)
+                .map(|(_, scan)| *scan)
+                .ok_or(format!("unknown key <{}>", name))?;
+            codes.extend(strokes(scan, None));
+        }
+    }
+    Ok(codes)
+}
+
+// Intel 8042 keyboard controller with the keyboard behind it, delivering set 1 scan codes
+pub struct Kbc {
+    source: Option<KeySource>,
+    // Scan codes typed but not yet read
+    keys: VecDeque<u8>,
+    // Answers to commands go out ahead of keys
+    replies: VecDeque<u8>,
+    output: Option<u8>,
+    command_byte: u8,
+    // Controller command waiting for its data byte on port 0x60
+    pending: Option<u8>,
+    // Keyboard command waiting for its argument (set LEDs, typematic rate)
+    argument: bool,
+    // Whether the last write went to the command port
+    last_command: bool,
+    irq_line: bool,
+    quit: bool,
+}
+
+impl Kbc {
+    pub fn new() -> Self {
+        Kbc {
+            source: None,
+            keys: VecDeque::new(),
+            replies: VecDeque::new(),
+            output: None,
+            command_byte: COMMAND_INTERRUPT | COMMAND_SYSTEM | COMMAND_TRANSLATE,
+            pending: None,
+            argument: false,
+            last_command: false,
+            irq_line: false,
+            quit: false,
+        }
+    }
+
+    pub fn set_source(&mut self, source: KeySource) {
+        self.source = Some(source);
+    }
+
+    // The user asked to stop the machine from the host terminal
+    pub fn quit_requested(&self) -> bool {
+        self.quit
+    }
+
+    // Whether an idle guest could still be woken by a key
+    pub fn may_interrupt(&self) -> bool {
+        self.command_byte & COMMAND_INTERRUPT != 0
+            && (self.output.is_some() || !self.keys.is_empty() || self.source.is_some())
+    }
+
+    // Pulls in host input, returns true when IRQ1 rises
+    pub fn poll(&mut self, clock: u64) -> bool {
+        let line = self.output.is_some() && self.command_byte & COMMAND_INTERRUPT != 0;
+        let rising = line && !self.irq_line;
+        self.irq_line = line;
+        self.receive(clock);
+        // Like the UART, the next byte arrives a poll later so the line drops in between
+        if self.output.is_none() {
+            self.output = self.replies.pop_front();
+        }
+        if self.output.is_none() && self.command_byte & COMMAND_DISABLED == 0 {
+            self.output = self.keys.pop_front();
+        }
+        rising
+    }
+
+    fn receive(&mut self, clock: u64) {
+        match &mut self.source {
+            Some(KeySource::Stdin { input, .. }) => {
+                let mut bytes = Vec::new();
+                loop {
+                    match input.try_recv() {
+                        Ok(byte) => bytes.push(byte),
+                        Err(TryRecvError::Empty) => break,
+                        Err(TryRecvError::Disconnected) => {
+                            self.source = None;
+                            break;
+                        }
+                    }
+                }
+                self.type_bytes(&bytes);
+            }
+            Some(KeySource::Script(events)) => {
+                while events.front().is_some_and(|(due, _)| *due <= clock) {
+                    let (_, codes) = events.pop_front().unwrap();
+                    self.keys.extend(codes);
+                }
+                if events.is_empty() {
+                    self.source = None;
+                }
+            }
+            None => {}
+        }
+    }
+
+    fn type_bytes(&mut self, bytes: &[u8]) {
+        let mut i = 0;
+        while i < bytes.len() {
+            // Terminal escape sequences for the cursor keys
+            let arrow = match bytes[i..] {
+                [0x1b, b'[', b'A', ..] => Some(0x48),
+                [0x1b, b'[', b'B', ..] => Some(0x50),
+                [0x1b, b'[', b'C', ..] => Some(0x4d),
+                [0x1b, b'[', b'D', ..] => Some(0x4b),
+                _ => None,
+            };
+            if let Some(scan) = arrow {
+                self.keys.extend(strokes(scan, None));
+                i += 3;
+                continue;
+            }
+            if bytes[i] == QUIT {
+                self.quit = true;
+            } else {
+                self.keys.extend(type_char(bytes[i]));
+            }
+            i += 1;
+        }
+    }
+
+    fn controller_command(&mut self, command: u8) {
+        match command {
+            0x20 => self.replies.push_back(self.command_byte),
+            0x60 => self.pending = Some(command),
+            0xaa => self.replies.push_back(0x55),
+            0xab => self.replies.push_back(0x00),
+            0xad => self.command_byte |= COMMAND_DISABLED,
+            0xae => self.command_byte &= !COMMAND_DISABLED,
+            _ => {
+                // Output port, A20 and reset pulses are not wired to anything
+            }
+        }
+    }
+
+    fn keyboard_command(&mut self, command: u8) {
+        if self.argument {
+            self.argument = false;
+            self.replies.push_back(ACK);
+            return;
+        }
+        match command {
+            // Set LEDs and set typematic rate take one argument
+            0xed | 0xf3 => {
+                self.argument = true;
+                self.replies.push_back(ACK);
+            }
+            0xee => self.replies.push_back(0xee),
+            0xff => {
+                self.keys.clear();
+                self.replies.extend([ACK, SELF_TEST_PASSED]);
+            }
+            _ => self.replies.push_back(ACK),
+        }
+    }
+}
+
+impl PortBus for Kbc {
+    fn read8(&mut self, port: u16) -> u8 {
+        match port {
+            KBC_DATA_PORT => self.output.take().unwrap_or(0),
+            _ => {
+                let full = if self.output.is_some() {
+                    STATUS_OUTPUT_FULL
+                } else {
+                    0
+                };
+                let command = if self.last_command { STATUS_COMMAND } else { 0 };
+                let system = if self.command_byte & COMMAND_SYSTEM != 0 {
+                    STATUS_SYSTEM
+                } else {
+                    0
+                };
+                STATUS_UNLOCKED | command | system | full
+            }
+        }
+    }
+
+    fn write8(&mut self, port: u16, value: u8) {
+        self.last_command = port == KBC_STATUS_PORT;
+        match port {
+            KBC_DATA_PORT => match self.pending.take() {
+                Some(_) => self.command_byte = value,
+                None => self.keyboard_command(value),
+            },
+            _ => {
+                self.pending = None;
+                self.controller_command(value);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use test_case::test_case;
+
+    #[test_case(b'a', 0x1e ; "lower")]
+    #[test_case(b'A', 0x1e ; "upper")]
+    #[test_case(b'1', 0x02 ; "digit")]
+    #[test_case(b'?', 0x35 ; "shifted punctuation")]
+    #[test_case(b'\r', 0x1c ; "enter")]
+    fn test_scan_code(c: u8, expected: u8) {
+        assert_eq!(scan_code(c), expected);
+        assert_eq!(ascii(expected, c.is_ascii_uppercase() || c == b'?'), c);
+    }
+
+    #[test]
+    fn test_script_timing() {
+        let mut kbc = Kbc::new();
+        kbc.set_source(KeySource::script("# boot first\n10 A<enter>").unwrap());
+        let due = 10 * CLOCK_HZ / 1000;
+        kbc.poll(due - 1);
+        assert_eq!(kbc.read8(KBC_STATUS_PORT) & STATUS_OUTPUT_FULL, 0);
+        let mut codes = Vec::new();
+        for clock in due..due + 20 {
+            if kbc.poll(clock) {
+                codes.push(kbc.read8(KBC_DATA_PORT));
+            }
+        }
+        assert_eq!(codes, [0x2a, 0x1e, 0x9e, 0xaa, 0x1c, 0x9c]);
+        assert!(!kbc.may_interrupt());
+    }
+
+    #[test]
+    fn test_controller_commands() {
+        let mut kbc = Kbc::new();
+        kbc.write8(KBC_STATUS_PORT, 0xaa);
+        kbc.poll(0);
+        assert_eq!(kbc.read8(KBC_DATA_PORT), 0x55);
+        kbc.write8(KBC_STATUS_PORT, 0x60);
+        kbc.write8(KBC_DATA_PORT, 0x00);
+        kbc.write8(KBC_DATA_PORT, 0xff);
+        // Interrupts are off now, the reset replies are still there to poll for
+        assert!(!kbc.poll(0));
+        assert!(!kbc.poll(0));
+        assert_eq!(kbc.read8(KBC_DATA_PORT), ACK);
+    }
+
+    #[test]
+    fn test_unknown_script_key() {
+        assert!(KeySource::script("0 <f11>").is_err());
+        assert!(KeySource::script("x abc").is_err());
+    }
+}
@@ -1,89 +1,528 @@
+use std::{
+    cell::RefCell,
+    collections::{HashMap, HashSet},
+    rc::Rc,
+};
+
 use crate::{
-    disassembler,
+    bios::{self, Bios, Disk, Service},
+    cpu::Cpu,
+    disassembler::Disassembler,
     dump::Dump,
-    flag::Flag,
+    flag::{width_mask, Flag},
+    fpu::Fpu,
+    keyboard::{self, Kbc, KeySource},
+    memory::{physical_address, Memory, MemoryBus, ADDRESS_MASK, MEMORY_SIZE},
     message::{Message, MESSAGE_SIZE},
     metadata::{self},
-    operation::{OperandType, Operation, OperationType},
-    register::{self, Register, RegisterType},
+    opcode,
+    operation::{OperandType, Operation, OperationType, MAX_LENGTH},
+    pic::{self, Pic},
+    pit::{self, Pit},
+    port::{self, DebugConsole, PortBus, PortMap},
+    register::{self, Register, RegisterType, SegmentRegister},
+    speaker::{self, Speaker},
+    throttle::Throttle,
+    timing::{self, Execution},
+    uart::{self, Backend, Uart},
+    video::{Adapter, Display, TextVideo},
 };
 
+enum Platform {
+    // MINIX a.out process: separate I&D, INT is a system call
+    Minix(metadata::Metadata),
+    // Bare PC: code is fetched from memory at CS:IP, INT vectors through the IVT
+    BareMetal,
+}
+
 pub struct Machine {
     stop: bool,
-    memory: Vec<u8>,
+    memory: Memory,
     register: Register,
-    metadata: metadata::Metadata,
+    platform: Platform,
     flag: Flag,
     dump: Dump,
     text: Vec<u8>,
+    // Decoded operations by CS:IP
+    decoded: HashMap<(u16, u16), Operation>,
+    engine: Engine,
+    blocks: HashMap<(u16, u16), Rc<Block>>,
+    // Blocks that modify their own code, left to the interpreter
+    interpreted: HashSet<(u16, u16)>,
+    segment_override: Option<SegmentRegister>,
+    bios: Option<Bios>,
+    ports: PortMap,
+    // Interrupt lines, sampled between instructions
+    nmi: bool,
+    intr: Option<u8>,
+    // Set by instructions after which the CPU holds off interrupts for one more instruction
+    interrupt_shadow: bool,
+    halted: bool,
+    board: Board,
+    // Serial ports and the IRQ line each one drives
+    serial: Vec<(Rc<RefCell<Uart>>, u8)>,
+    video: Option<(Rc<RefCell<TextVideo>>, Display)>,
+    fpu: Fpu,
+    cpu: Cpu,
+    // CPU clocks since reset
+    clock: u64,
+    clock_hz: u64,
+    // Time in 4.77 MHz clocks, which the devices run on whatever the CPU clock
+    time: u64,
+    throttle: Throttle,
+    // Whether the last conditional transfer was taken, for its timing
+    taken: bool,
+    instructions: u64,
 }
 
-fn read_16(memory: &[u8], addr: usize) -> u16 {
-    if addr + 1 >= memory.len() {
-        panic!("Memory access out of bounds at address {}", addr + 1);
-    }
-    u16::from_le_bytes([memory[addr], memory[addr + 1]])
+// The CPU clock of the IBM PC and XT
+pub const CLOCK_HZ: u64 = 4_772_727;
+
+// Devices on the motherboard, shared between the machine and the port bus
+struct Board {
+    pic: Rc<RefCell<Pic>>,
+    pit: Rc<RefCell<Pit>>,
+    speaker: Rc<RefCell<Speaker>>,
+    keyboard: Rc<RefCell<Kbc>>,
 }
 
-fn write_16(memory: &mut [u8], addr: usize, value: u16) {
-    if addr + 1 >= memory.len() {
-        panic!("Memory access out of bounds at address {}", addr + 1);
+impl Board {
+    fn new() -> Self {
+        let pit = Rc::new(RefCell::new(Pit::new()));
+        Board {
+            pic: Rc::new(RefCell::new(Pic::new())),
+            speaker: Rc::new(RefCell::new(Speaker::new(pit.clone()))),
+            pit,
+            keyboard: Rc::new(RefCell::new(Kbc::new())),
+        }
     }
-    memory[addr..addr + 2].copy_from_slice(&value.to_le_bytes());
-}
 
-fn carry_lsh_u8(value: u8, count: u8) -> bool {
-    if count == 0 {
-        return false;
+    fn ports(&self, debug: bool) -> PortMap {
+        let mut ports = PortMap::new(debug);
+        ports.attach(
+            pic::PIC_COMMAND_PORT..=pic::PIC_DATA_PORT,
+            Box::new(self.pic.clone()),
+        );
+        ports.attach(
+            pit::PIT_BASE_PORT..=pit::PIT_CONTROL_PORT,
+            Box::new(self.pit.clone()),
+        );
+        ports.attach(
+            keyboard::KBC_DATA_PORT..=keyboard::KBC_DATA_PORT,
+            Box::new(self.keyboard.clone()),
+        );
+        ports.attach(
+            speaker::SYSTEM_CONTROL_PORT..=speaker::SYSTEM_CONTROL_PORT,
+            Box::new(self.speaker.clone()),
+        );
+        ports.attach(
+            keyboard::KBC_STATUS_PORT..=keyboard::KBC_STATUS_PORT,
+            Box::new(self.keyboard.clone()),
+        );
+        ports.attach(
+            port::DEBUG_CONSOLE_PORT..=port::DEBUG_CONSOLE_PORT,
+            Box::new(DebugConsole),
+        );
+        ports
     }
-    let res = (value << (count - 1)) >> 7;
-    res & 1 == 1
 }
 
-fn carry_lsh_u16(value: u16, count: u16) -> bool {
-    if count == 0 {
-        return false;
+// While halted, time passes in steps of a typical 8088 instruction
+const CLOCKS_PER_HALT_STEP: u64 = 12;
+// The PIT runs at a quarter of the 4.77 MHz clock
+const CLOCKS_PER_PIT_TICK: u64 = 4;
+
+// How decoded operations are run, selected with --engine
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum Engine {
+    // Decodes and dispatches every instruction
+    #[default]
+    Interpreter,
+    // Runs translated basic blocks of pre-bound handlers
+    Threaded,
+}
+
+impl Engine {
+    pub fn from_name(name: &str) -> Option<Engine> {
+        match name {
+            "interpreter" => Some(Engine::Interpreter),
+            "threaded" => Some(Engine::Threaded),
+            _ => None,
+        }
     }
-    let res = (value << (count - 1)) >> 15;
-    res & 1 == 1
 }
 
-fn carry_rsh_u16(value: u16, count: u16) -> bool {
-    if count == 0 {
-        return false;
+// Longest straight-line run translated into one block
+const MAX_BLOCK_LENGTH: usize = 64;
+
+// A basic block: the operations of a straight-line run with their handlers
+type Block = Vec<(Handler, Operation)>;
+
+// Operations after which the next instruction is not necessarily the next in memory
+fn ends_block(operation_type: OperationType) -> bool {
+    matches!(
+        operation_type,
+        OperationType::Jmp
+            | OperationType::Call
+            | OperationType::Ret
+            | OperationType::Iret
+            | OperationType::Int
+            | OperationType::Into
+            | OperationType::JeJz
+            | OperationType::JlJnge
+            | OperationType::JleJng
+            | OperationType::JbJnae
+            | OperationType::JbeJna
+            | OperationType::JpJpe
+            | OperationType::Jo
+            | OperationType::Js
+            | OperationType::JneJnz
+            | OperationType::JnlJge
+            | OperationType::JnleJg
+            | OperationType::JnbJae
+            | OperationType::JnbeJa
+            | OperationType::JnpJpo
+            | OperationType::Jno
+            | OperationType::Jns
+            | OperationType::Loop
+            | OperationType::LoopzLoope
+            | OperationType::LoopnzLoopne
+            | OperationType::Jcxz
+            | OperationType::Bound
+            | OperationType::Hlt
+            | OperationType::Undefined
+    )
+}
+
+// Executes one kind of operation. The interpreter looks it up for every
+// instruction and the threaded engine once per translated block.
+type Handler = fn(&mut Machine, &Operation);
+
+fn handler(operation_type: OperationType) -> Handler {
+    match operation_type {
+        OperationType::Mov => |m, op| m.mov(op),
+        OperationType::Add => |m, op| m.add(op),
+        OperationType::Sub => |m, op| m.sub(op),
+        OperationType::Int => |m, op| m.int(op),
+        OperationType::Push => |m, op| m.push(op),
+        OperationType::Pop => |m, op| m.pop(op),
+        OperationType::Call => |m, op| m.call(op),
+        OperationType::Jmp => |m, op| m.jmp(op),
+        OperationType::Lea => |m, op| m.lea(op),
+        OperationType::Ret => |m, op| m.ret(op),
+        OperationType::Or => |m, op| m.or(op),
+        OperationType::JeJz => |m, op| m.jump_if(op, m.flag.zero()),
+        OperationType::Cmp => |m, op| m.cmp(op),
+        OperationType::JnlJge => |m, op| m.jump_if(op, m.flag.sign() == m.flag.overflow()),
+        OperationType::Xor => |m, op| m.xor(op),
+        OperationType::JnbJae => |m, op| m.jump_if(op, !m.flag.carry()),
+        OperationType::Test => |m, op| m.test(op),
+        OperationType::JneJnz => |m, op| m.jump_if(op, !m.flag.zero()),
+        OperationType::Dec => |m, op| m.dec(op),
+        OperationType::JlJnge => |m, op| m.jump_if(op, m.flag.sign() != m.flag.overflow()),
+        OperationType::Cbw => |m, _| m.cbw(),
+        OperationType::Inc => |m, op| m.inc(op),
+        OperationType::And => |m, op| m.and(op),
+        OperationType::JbJnae => |m, op| m.jump_if(op, m.flag.carry()),
+        OperationType::JleJng => {
+            |m, op| m.jump_if(op, m.flag.zero() || m.flag.sign() != m.flag.overflow())
+        }
+        OperationType::JnbeJa => |m, op| m.jump_if(op, !m.flag.carry() && !m.flag.zero()),
+        OperationType::JnleJg => {
+            |m, op| m.jump_if(op, !m.flag.zero() && m.flag.sign() == m.flag.overflow())
+        }
+        OperationType::Cwd => |m, _| m.cwd(),
+        OperationType::Salc => |m, _| m.register.al = if m.flag.carry() { 0xff } else { 0 },
+        OperationType::Div => |m, op| m.div(op),
+        OperationType::Xchg => |m, op| m.xchg(op),
+        OperationType::Neg => |m, op| m.neg(op),
+        OperationType::JbeJna => |m, op| m.jump_if(op, m.flag.carry() || m.flag.zero()),
+        OperationType::Jo => |m, op| m.jump_if(op, m.flag.overflow()),
+        OperationType::Jno => |m, op| m.jump_if(op, !m.flag.overflow()),
+        OperationType::Js => |m, op| m.jump_if(op, m.flag.sign()),
+        OperationType::Jns => |m, op| m.jump_if(op, !m.flag.sign()),
+        OperationType::Loop => |m, op| m.loop_cx(op, true),
+        OperationType::LoopzLoope => |m, op| m.loop_cx(op, m.flag.zero()),
+        OperationType::LoopnzLoopne => |m, op| m.loop_cx(op, !m.flag.zero()),
+        OperationType::Jcxz => |m, op| m.jump_if(op, m.register.get_cx() == 0),
+        OperationType::JpJpe => |m, op| m.jump_if(op, m.flag.parity()),
+        OperationType::JnpJpo => |m, op| m.jump_if(op, !m.flag.parity()),
+        OperationType::ShlSal
+        | OperationType::Shr
+        | OperationType::Sar
+        | OperationType::Rol
+        | OperationType::Ror
+        | OperationType::Rcl
+        | OperationType::Rcr => |m, op| m.shift_rotate(op),
+        OperationType::Mul => |m, op| m.mul(op),
+        OperationType::Imul => |m, op| m.imul(op),
+        OperationType::Idiv => |m, op| m.idiv(op),
+        OperationType::Adc => |m, op| m.adc(op),
+        OperationType::Sbb => |m, op| m.sbb(op),
+        OperationType::Not => |m, op| m.not(op),
+        OperationType::Aaa => |m, _| m.aaa(),
+        OperationType::Aas => |m, _| m.aas(),
+        OperationType::Daa => |m, _| m.daa(),
+        OperationType::Das => |m, _| m.das(),
+        OperationType::Aam => |m, op| m.aam(op),
+        OperationType::Aad => |m, op| m.aad(op),
+        OperationType::Xlat => |m, _| m.xlat(),
+        OperationType::Lds => |m, op| m.load_far_pointer(op, SegmentRegister::DS),
+        OperationType::Les => |m, op| m.load_far_pointer(op, SegmentRegister::ES),
+        OperationType::Lahf => |m, _| m.lahf(),
+        OperationType::Sahf => |m, _| m.sahf(),
+        OperationType::Pushf => |m, _| m.stack_push_u16(m.flag.to_u16()),
+        OperationType::Popf => |m, _| m.popf(),
+        OperationType::Into => |m, _| {
+            m.taken = m.flag.overflow();
+            if m.flag.overflow() {
+                m.interrupt(4);
+            }
+        },
+        OperationType::Iret => |m, _| m.iret(),
+        OperationType::In => |m, op| m.port_in(op),
+        OperationType::Out => |m, op| m.port_out(op),
+        OperationType::Esc => |m, op| m.esc(op),
+        // The 8087 finishes each instruction at once, and there is a single bus master
+        OperationType::Wait | OperationType::Lock => |_, _| {},
+        OperationType::Movs
+        | OperationType::Cmps
+        | OperationType::Scas
+        | OperationType::Lods
+        | OperationType::Stos
+        | OperationType::Ins
+        | OperationType::Outs => |m, op| m.string_step(op.operation_type, op.w),
+        OperationType::Pusha => |m, _| m.pusha(),
+        OperationType::Popa => |m, _| m.popa(),
+        OperationType::Enter => |m, op| m.enter(op),
+        OperationType::Leave => |m, _| m.leave(),
+        OperationType::Bound => |m, op| m.bound(op),
+        OperationType::Rep => |m, op| m.rep(op),
+        OperationType::Clc => |m, _| m.flag.set_carry(false),
+        OperationType::Stc => |m, _| m.flag.set_carry(true),
+        OperationType::Cmc => |m, _| m.flag.set_carry(!m.flag.carry()),
+        OperationType::Cld => |m, _| m.flag.direction = false,
+        OperationType::Std => |m, _| m.flag.direction = true,
+        OperationType::Cli => |m, _| m.flag.interrupt = false,
+        OperationType::Sti => |m, _| {
+            m.flag.interrupt = true;
+            m.interrupt_shadow = true;
+        },
+        OperationType::Hlt => |m, _| m.halted = true,
+        OperationType::Segment => |m, op| {
+            m.segment_override = Some(SegmentRegister::from_u8(op.reg));
+        },
+        OperationType::Undefined => |m, op| m.invalid_opcode(op),
     }
-    let res = (value >> (count - 1)) & 1;
-    res == 1
 }
 
 impl Machine {
-    pub fn new(executable: &Vec<u8>, args: &[String], envs: &[String], debug: bool) -> Self {
+    pub fn new(executable: &[u8], args: &[String], envs: &[String], debug: bool) -> Self {
         let metadata = metadata::Metadata::from_bytes(executable);
 
         let text_begin = metadata.hdr_len as usize;
         let text = executable[text_begin..text_begin + metadata.text_size].to_vec();
 
-        let mut memory = vec![0; metadata.total];
+        let mut memory = Memory::new(metadata.total);
         let data_begin = metadata.hdr_len as usize + metadata.text_size;
-        memory[0..metadata.data_size]
-            .copy_from_slice(&executable[data_begin..data_begin + metadata.data_size]);
+        memory.load(0, &executable[data_begin..data_begin + metadata.data_size]);
 
         let args_frame = Self::create_args_frame(args, envs, metadata.total);
         let frame_base = metadata.total - args_frame.len();
-        memory[frame_base..metadata.total].copy_from_slice(&args_frame);
+        memory.load(frame_base, &args_frame);
 
         let mut register = Register::new();
         register.sp = frame_base as u16;
 
+        let board = Board::new();
+
         Machine {
             stop: false,
             memory,
             register,
-            metadata,
+            platform: Platform::Minix(metadata),
             flag: Flag::new(),
             dump: Dump::new(debug),
             text,
+            decoded: HashMap::new(),
+            engine: Engine::default(),
+            blocks: HashMap::new(),
+            interpreted: HashSet::new(),
+            segment_override: None,
+            bios: None,
+            ports: PortMap::new(debug),
+            nmi: false,
+            intr: None,
+            interrupt_shadow: false,
+            halted: false,
+            board,
+            serial: Vec::new(),
+            video: None,
+            fpu: Fpu::new(),
+            cpu: Cpu::default(),
+            clock: 0,
+            clock_hz: CLOCK_HZ,
+            time: 0,
+            throttle: Throttle::new(CLOCK_HZ, false),
+            taken: false,
+            instructions: 0,
+        }
+    }
+
+    pub fn boot(disk: Disk, debug: bool) -> Self {
+        let mut memory = Memory::new(MEMORY_SIZE);
+        let bios = Bios::new(disk);
+        bios.install_vectors(&mut memory);
+        bios.load_boot_sector(&mut memory);
+
+        let mut register = Register::new();
+        register.cs = bios::BOOT_SEGMENT;
+        register.ip = bios::BOOT_OFFSET;
+        register.sp = bios::BOOT_OFFSET;
+        register.dl = bios.boot_drive();
+
+        let mut flag = Flag::new();
+        flag.interrupt = true;
+
+        // The BIOS leaves the PIC programmed but the timer idle
+        let board = Board::new();
+        board.pic.borrow_mut().init_pc();
+
+        Machine {
+            stop: false,
+            memory,
+            register,
+            platform: Platform::BareMetal,
+            flag,
+            dump: Dump::new(debug),
+            text: Vec::new(),
+            decoded: HashMap::new(),
+            engine: Engine::default(),
+            blocks: HashMap::new(),
+            interpreted: HashSet::new(),
+            segment_override: None,
+            bios: Some(bios),
+            ports: board.ports(debug),
+            nmi: false,
+            intr: None,
+            interrupt_shadow: false,
+            halted: false,
+            board,
+            serial: Vec::new(),
+            video: None,
+            fpu: Fpu::new(),
+            cpu: Cpu::default(),
+            clock: 0,
+            clock_hz: CLOCK_HZ,
+            time: 0,
+            throttle: Throttle::new(CLOCK_HZ, false),
+            taken: false,
+            instructions: 0,
+        }
+    }
+
+    pub fn with_rom(rom: &[u8], debug: bool) -> Self {
+        if rom.is_empty() || rom.len() > 0x10000 {
+            panic!(
+                "BIOS ROM must be between 1 byte and 64 KiB, got {}",
+                rom.len()
+            );
+        }
+        // The ROM ends at the top of the address space, so an 8 KiB image starts at F000:E000
+        let mut memory = Memory::new(MEMORY_SIZE);
+        memory.map_rom(MEMORY_SIZE - rom.len(), rom);
+
+        // Reset vector
+        let mut register = Register::new();
+        register.cs = 0xffff;
+        register.ip = 0x0000;
+
+        let board = Board::new();
+
+        Machine {
+            stop: false,
+            memory,
+            register,
+            platform: Platform::BareMetal,
+            flag: Flag::new(),
+            dump: Dump::new(debug),
+            text: Vec::new(),
+            decoded: HashMap::new(),
+            engine: Engine::default(),
+            blocks: HashMap::new(),
+            interpreted: HashSet::new(),
+            segment_override: None,
+            bios: None,
+            ports: board.ports(debug),
+            nmi: false,
+            intr: None,
+            interrupt_shadow: false,
+            halted: false,
+            board,
+            serial: Vec::new(),
+            video: None,
+            fpu: Fpu::new(),
+            cpu: Cpu::default(),
+            clock: 0,
+            clock_hz: CLOCK_HZ,
+            time: 0,
+            throttle: Throttle::new(CLOCK_HZ, false),
+            taken: false,
+            instructions: 0,
+        }
+    }
+
+    pub fn set_cpu(&mut self, cpu: Cpu) {
+        self.cpu = cpu;
+    }
+
+    pub fn set_engine(&mut self, engine: Engine) {
+        self.engine = engine;
+    }
+
+    // Runs the CPU at hz and holds it to that speed in real time
+    pub fn throttle(&mut self, hz: u64) {
+        self.clock_hz = hz;
+        self.throttle = Throttle::new(hz, true);
+    }
+
+    // Logs every access to a physical address, whether or not tracing is on
+    pub fn watch(&mut self, addr: usize) {
+        let dump = Dump::new(true);
+        self.memory.add_hook(Box::new(move |access| {
+            if access.addr == addr {
+                dump.watch(access.addr, access.value, access.is_write);
+            }
+        }));
+    }
+
+    // Connects COM1 (0) or COM2 (1) to a host backend
+    pub fn attach_serial(&mut self, com: usize, backend: Backend) {
+        let (base, irq) = uart::COM_PORTS[com];
+        let uart = Rc::new(RefCell::new(Uart::new(base, backend)));
+        self.ports.attach(base..=base + 7, Box::new(uart.clone()));
+        self.serial.push((uart, irq));
+    }
+
+    // Maps a text mode adapter, shown live on the terminal and/or saved when the machine stops
+    pub fn attach_video(&mut self, adapter: Adapter, live: bool, screen_dump: Option<String>) {
+        let video = Rc::new(RefCell::new(TextVideo::new(adapter)));
+        self.memory
+            .map_mmio(adapter.memory(), Box::new(video.clone()));
+        self.ports.attach(adapter.ports(), Box::new(video.clone()));
+        if let Some(bios) = &mut self.bios {
+            bios.attach_video(video.clone());
         }
+        self.video = Some((video, Display::new(live, screen_dump)));
+    }
+
+    // Records the PC speaker to a WAV file, written when the machine stops
+    pub fn capture_speaker(&mut self, path: &str) {
+        self.board.speaker.borrow_mut().capture(path);
+    }
+
+    pub fn attach_keyboard(&mut self, source: KeySource) {
+        self.board.keyboard.borrow_mut().set_source(source);
     }
 
     fn create_args_frame(args: &[String], envs: &[String], total_memory: usize) -> Vec<u8> {
@@ -170,16 +609,20 @@ impl Machine {
                 let offset = op.get_next_operation_pos();
                 register::calc_relative_disp(offset, op.disp, true)
             }
-            0b0111_0000..=0b0111_1111 | 0b1110_0000..=0b1110_0010 | 0b1110_1011 => {
+            0b0110_0000..=0b0110_1111 if self.cpu.aliases_jumps() => {
+                let offset = op.get_next_operation_pos();
+                register::calc_relative_disp(offset, op.disp, false)
+            }
+            0b0111_0000..=0b0111_1111 | 0b1110_0000..=0b1110_0011 | 0b1110_1011 => {
                 let offset = op.get_next_operation_pos();
                 register::calc_relative_disp(offset, op.disp, false)
             }
             _ => {
                 let base = match op.rm {
-                    0b000 => self.register.get_bx() + self.register.si,
-                    0b001 => self.register.get_bx() + self.register.di,
-                    0b010 => self.register.bp + self.register.si,
-                    0b011 => self.register.bp + self.register.di,
+                    0b000 => self.register.get_bx().wrapping_add(self.register.si),
+                    0b001 => self.register.get_bx().wrapping_add(self.register.di),
+                    0b010 => self.register.bp.wrapping_add(self.register.si),
+                    0b011 => self.register.bp.wrapping_add(self.register.di),
                     0b100 => self.register.si,
                     0b101 => self.register.di,
                     0b110 => self.register.bp,
@@ -194,29 +637,30 @@ impl Machine {
                             base
                         }
                     }
-                    0b01 => {
-                        let disp_signed = op.disp as i8;
-                        if disp_signed >= 0 {
-                            base + disp_signed as u16
-                        } else {
-                            base - disp_signed.abs() as u16
-                        }
-                    }
-                    0b10 => {
-                        let disp_signed = op.disp as i16;
-                        if disp_signed >= 0 {
-                            base + disp_signed as u16
-                        } else {
-                            base - disp_signed.abs() as u16
-                        }
-                    }
+                    0b01 => base.wrapping_add(op.disp as i8 as u16),
+                    0b10 => base.wrapping_add(op.disp),
                     0b11 => self.register.get(RegisterType::new(op.rm, op.w)),
                     _ => unreachable!(),
                 }
             }
         }
-        .try_into()
-        .unwrap_or_else(|_| panic!("Effective address calculation overflowed"))
+        .into()
+    }
+
+    fn segment(&self, default: SegmentRegister) -> u16 {
+        let seg = self.segment_override.unwrap_or(default);
+        self.register.get(RegisterType::Segment(seg))
+    }
+
+    fn data_address(&self, op: &Operation) -> usize {
+        // BP based addressing defaults to the stack segment
+        let default = match (op.mod_rm, op.rm) {
+            (0b00, 0b110) => SegmentRegister::DS,
+            (_, 0b010 | 0b011 | 0b110) => SegmentRegister::SS,
+            _ => SegmentRegister::DS,
+        };
+        let offset = self.calc_effective_address(op) as u16;
+        physical_address(self.segment(default), offset)
     }
 
     fn read_operand(&self, op: &Operation, operand: OperandType) -> u16 {
@@ -227,21 +671,14 @@ impl Machine {
                     return self.register.get(RegisterType::new(op.rm, op.w));
                 }
 
-                let addr = self.calc_effective_address(op);
-                if addr + 1 >= self.memory.len() {
-                    panic!("Memory access out of bounds at address {}", addr + 1);
-                }
-                let value = u16::from_le_bytes([self.memory[addr], self.memory[addr + 1]]);
+                let addr = self.data_address(op);
+                let value = self.read_memory(addr, op.w);
                 self.dump.address_value(addr, value);
                 value
             }
-            OperandType::SegReg => match op.reg {
-                0b00 => self.register.es,
-                0b01 => self.register.cs,
-                0b10 => self.register.ss,
-                0b11 => self.register.ds,
-                _ => panic!("Invalid segment register"),
-            },
+            OperandType::SegReg => self
+                .register
+                .get(RegisterType::Segment(SegmentRegister::from_u8(op.reg))),
             OperandType::Imm => op.data,
             _ => unreachable!("Invalid operand type: {:?}", operand),
         }
@@ -257,32 +694,38 @@ impl Machine {
                     return;
                 }
 
-                let addr = self.calc_effective_address(op);
-                let prev = u16::from_le_bytes([
-                    self.memory[addr],
-                    if op.w == 0 { 0 } else { self.memory[addr + 1] },
-                ]);
-                self.dump.address_value_change(addr, prev, value);
-                if addr >= self.memory.len() {
-                    panic!("Memory access out of bounds at address {}", addr);
+                let addr = self.data_address(op);
+                if self.dump.enabled {
+                    // Only read back for the trace, MMIO reads may have side effects
+                    let prev = self.read_memory(addr, op.w);
+                    self.dump.address_value_change(addr, prev, value);
                 }
-                if op.w == 0 {
-                    self.memory[addr] = value as u8;
-                } else {
-                    if addr + 1 >= self.memory.len() {
-                        panic!("Memory access out of bounds at address {}", addr + 1);
-                    }
-                    self.memory[addr..addr + 2].copy_from_slice(&value.to_le_bytes());
+                self.write_memory(addr, value, op.w);
+            }
+            OperandType::SegReg => {
+                let seg = SegmentRegister::from_u8(op.reg);
+                // Loading SS holds off interrupts until SP has been loaded too
+                if seg == SegmentRegister::SS {
+                    self.interrupt_shadow = true;
                 }
+                self.register.set(RegisterType::Segment(seg), value);
             }
             _ => unreachable!(),
         }
     }
 
-    fn get_data_segment(&self) -> &[u8] {
-        let begin = 0;
-        let end = begin + self.metadata.total;
-        &self.memory[begin..end]
+    fn read_memory(&self, addr: usize, w: u8) -> u16 {
+        match w {
+            0 => self.memory.read8(addr) as u16,
+            _ => self.memory.read16(addr),
+        }
+    }
+
+    fn write_memory(&mut self, addr: usize, value: u16, w: u8) {
+        match w {
+            0 => self.memory.write8(addr, value as u8),
+            _ => self.memory.write16(addr, value),
+        }
     }
 
     fn mov(&mut self, op: &Operation) {
@@ -293,170 +736,416 @@ impl Machine {
     fn add(&mut self, op: &Operation) {
         let left = self.read_operand(op, op.first);
         let right = self.read_operand(op, op.second);
-        let result = match op.s << 1 | op.w {
-            0b00 => {
-                let res = left + right;
-                let res_u8 = left as u8 + right as u8;
-                self.flag
-                    .set_cosz(res >= 0x100, res >= 0x100, false, res == 0);
-                res_u8 as u16
-            }
-            0b01 => {
-                let res = left as u16 as i32 + right as u16 as i32;
-                let res_u16 = left + right;
-                self.flag.set_cosz(
-                    res > 0x10000,
-                    res != res_u16 as i32,
-                    (res_u16 as i16) < 0,
-                    res_u16 == 0,
-                );
-                res_u16 as u16
-            }
-            0b11 => {
-                let res = left as i16 as i32 + right as i16 as i32;
-                let res_i16 = left as i16 + right as i16;
-                self.flag.set_cosz(
-                    res > 0x10000,
-                    res != res_i16 as i32,
-                    res_i16 < 0,
-                    res_i16 == 0,
-                );
-                res_i16 as u16
-            }
-            _ => unreachable!("Invalid operand width"),
-        };
+        let result = self.flag.add(left, right, false, op.w);
+        self.write_operand(op, op.first, result);
+    }
+
+    fn adc(&mut self, op: &Operation) {
+        let left = self.read_operand(op, op.first);
+        let right = self.read_operand(op, op.second);
+        let result = self.flag.add(left, right, self.flag.carry(), op.w);
         self.write_operand(op, op.first, result);
     }
 
     fn sub(&mut self, op: &Operation) {
         let left = self.read_operand(op, op.first);
         let right = self.read_operand(op, op.second);
-        let result: u16 = match op.s << 1 | op.w {
-            0b00 => {
-                let res_u8 = left as u8 - right as u8;
-                let res = res_u8 as i32;
-                self.flag.zero = res_u8 == 0;
-                self.flag.sign = false;
-                self.flag.overflow = res as i32 != res;
-                self.flag.carry = left < right;
-                res as u16
-            }
-            0b01 => {
-                let res_i16: i16 = left as i16 - right as i16;
-                let res = res_i16 as i32;
-                self.flag.zero = res == 0;
-                self.flag.sign = res < 0;
-                self.flag.overflow = res != res_i16 as i32;
-                self.flag.carry = left < right;
-                res_i16 as u16
-            }
-            0b11 => {
-                let res_i16: i16 = left as i16 - right as i16;
-                let res = (left as i16 - (right as u8 as i16)) as i32;
-                self.flag.zero = res_i16 == 0;
-                self.flag.sign = res_i16 < 0;
-                self.flag.overflow = res != res_i16 as i32;
-                self.flag.carry = left < right as u8 as u16;
-                res_i16 as u16
-            }
-            _ => unreachable!("Invalid operand width"),
-        };
+        let result = self.flag.sub(left, right, false, op.w);
         self.write_operand(op, op.first, result);
     }
 
+    fn sbb(&mut self, op: &Operation) {
+        let left = self.read_operand(op, op.first);
+        let right = self.read_operand(op, op.second);
+        let result = self.flag.sub(left, right, self.flag.carry(), op.w);
+        self.write_operand(op, op.first, result);
+    }
+
+    fn mul(&mut self, op: &Operation) {
+        let right = self.read_operand(op, op.first);
+        let high = match op.w {
+            0 => {
+                let res = self.register.al as u16 * (right as u8 as u16);
+                self.register.set_ax(res);
+                res > 0xff
+            }
+            1 => {
+                let res = self.register.get_ax() as u32 * right as u32;
+                self.register.set_ax(res as u16);
+                self.register.set_dx((res >> 16) as u16);
+                res > 0xffff
+            }
+            _ => unreachable!("Invalid w"),
+        };
+        self.flag.set_carry(high);
+        self.flag.set_overflow(high);
+    }
+
+    fn imul(&mut self, op: &Operation) {
+        if op.first == OperandType::Reg {
+            // Register times Register/Memory and Immediate
+            let left = self.read_operand(op, op.second) as i16 as i32;
+            let res = left * op.data as i16 as i32;
+            self.register.set(op.get_register(), res as u16);
+            self.flag.set_carry(res != res as i16 as i32);
+            self.flag.set_overflow(self.flag.carry());
+            return;
+        }
+        let right = self.read_operand(op, op.first);
+        let high = match op.w {
+            0 => {
+                let res = self.register.al as i8 as i16 * right as u8 as i8 as i16;
+                self.register.set_ax(res as u16);
+                res != res as i8 as i16
+            }
+            1 => {
+                let res = self.register.get_ax() as i16 as i32 * right as i16 as i32;
+                self.register.set_ax(res as u16);
+                self.register.set_dx((res >> 16) as u16);
+                res != res as i16 as i32
+            }
+            _ => unreachable!("Invalid w"),
+        };
+        self.flag.set_carry(high);
+        self.flag.set_overflow(high);
+    }
+
     fn div(&mut self, op: &Operation) {
-        let left = self.read_operand(op, op.second);
-        let numerator = self.register.get_ax() as u32 | ((self.register.get_dx() as u32) << 16);
+        let divisor = self.read_operand(op, op.first);
+        match op.w {
+            0 => {
+                let divisor = divisor as u8 as u16;
+                let numerator = self.register.get_ax();
+                if divisor == 0 || numerator / divisor > 0xff {
+                    self.divide_error();
+                    return;
+                }
+                self.register.al = (numerator / divisor) as u8;
+                self.register.ah = (numerator % divisor) as u8;
+            }
+            1 => {
+                let divisor = divisor as u32;
+                let numerator =
+                    self.register.get_ax() as u32 | ((self.register.get_dx() as u32) << 16);
+                if divisor == 0 || numerator / divisor > 0xffff {
+                    self.divide_error();
+                    return;
+                }
+                self.register.set_ax((numerator / divisor) as u16);
+                self.register.set_dx((numerator % divisor) as u16);
+            }
+            _ => unreachable!("Invalid w"),
+        }
+    }
+
+    fn idiv(&mut self, op: &Operation) {
+        let divisor = self.read_operand(op, op.first);
+        // The 8086 rejects the most negative quotient as well
         match op.w {
             0 => {
-                let quot_i32 = numerator as i32 / left as i32;
-                let quot_u8 = (quot_i32 & 0xff) as u8;
-                let rem = numerator % left as u32;
-                self.register.al = quot_u8;
-                self.register.ah = (rem & 0xff) as u8;
+                let divisor = divisor as u8 as i8 as i32;
+                let numerator = self.register.get_ax() as i16 as i32;
+                if divisor == 0 || !(-0x7f..=0x7f).contains(&(numerator / divisor)) {
+                    self.divide_error();
+                    return;
+                }
+                self.register.al = (numerator / divisor) as u8;
+                self.register.ah = (numerator % divisor) as u8;
             }
             1 => {
-                let quot_i32 = numerator as i32 / left as i32;
-                let quot_u16 = (quot_i32 & 0xffff) as u16;
-                let rem = numerator % left as u32;
-                self.register.set_ax(quot_u16);
-                self.register.set_dx((rem & 0xffff) as u16);
+                let divisor = divisor as i16 as i64;
+                let numerator = (self.register.get_ax() as u32
+                    | ((self.register.get_dx() as u32) << 16))
+                    as i32 as i64;
+                if divisor == 0 || !(-0x7fff..=0x7fff).contains(&(numerator / divisor)) {
+                    self.divide_error();
+                    return;
+                }
+                self.register.set_ax((numerator / divisor) as u16);
+                self.register.set_dx((numerator % divisor) as u16);
             }
             _ => unreachable!("Invalid w"),
         }
     }
 
+    fn divide_error(&mut self) {
+        match self.platform {
+            Platform::Minix(_) => panic!("\nDivide error"),
+            Platform::BareMetal => self.interrupt(0),
+        }
+    }
+
+    fn invalid_opcode(&mut self, op: &Operation) {
+        match self.platform {
+            Platform::BareMetal if self.cpu.traps_invalid_opcodes() => {
+                // The return address is the faulting instruction
+                self.register.ip = op.pos as u16;
+                self.interrupt(6);
+            }
+            _ => panic!("\nUndefined operation: {:02x}", op.raws[0]),
+        }
+    }
+
     fn neg(&mut self, op: &Operation) {
         let value = self.read_operand(op, op.first);
-        if op.w == 1 {
-            let val_i32 = value as i32;
-            let res_i16 = -val_i32 as i16;
-            self.flag
-                .set_cosz(value != 0, false, res_i16 < 0, res_i16 == 0);
-            self.write_operand(op, op.first, res_i16 as u16);
-        } else {
-            let val_u8 = value as u8;
-            let res_i8 = -(val_u8 as i8);
-            self.flag
-                .set_cosz(value != 0, false, res_i8 < 0, res_i8 == 0);
-            self.write_operand(op, op.first, res_i8 as u16);
-        }
+        let result = self.flag.sub(0, value, false, op.w);
+        self.write_operand(op, op.first, result);
+    }
+
+    fn not(&mut self, op: &Operation) {
+        let value = self.read_operand(op, op.first);
+        self.write_operand(op, op.first, !value);
     }
 
     fn inc(&mut self, op: &Operation) {
-        let value = self.read_operand(op, op.first) as i16;
-        let result = value as i32 + 1;
-
-        if op.w == 0 {
-            let res_u8 = (result & 0xff) as u8;
-            self.flag
-                .set_cosz(self.flag.carry, res_u8 as i32 != result, false, res_u8 == 0);
-            self.write_operand(op, op.first, res_u8 as u16);
-        } else {
-            let res_i16 = (result & 0xffff) as i16;
-            self.flag.set_cosz(
-                self.flag.carry,
-                result != res_i16 as i32,
-                res_i16 < 0,
-                res_i16 == 0,
-            );
-            self.write_operand(op, op.first, res_i16 as u16);
-        }
+        let value = self.read_operand(op, op.first);
+        let result = self.flag.inc(value, op.w);
+        self.write_operand(op, op.first, result);
     }
 
     fn dec(&mut self, op: &Operation) {
         let value = self.read_operand(op, op.first);
-        let res = (value as i16 - 1) as i32;
+        let result = self.flag.dec(value, op.w);
+        self.write_operand(op, op.first, result);
+    }
 
-        if op.w == 0 {
-            let res_u8 = (res & 0xff) as u8;
-            self.flag
-                .set_cosz(self.flag.carry, res_u8 as i32 != res, false, res_u8 == 0);
-            self.write_operand(op, op.first, res_u8 as u16);
+    fn aaa(&mut self) {
+        if self.register.al & 0x0f > 9 || self.flag.auxiliary() {
+            self.register.al = self.register.al.wrapping_add(6);
+            self.register.ah = self.register.ah.wrapping_add(1);
+            self.flag.set_auxiliary(true);
+            self.flag.set_carry(true);
         } else {
-            let res_i16 = res as i16;
-            self.flag.set_cosz(
-                self.flag.carry,
-                res != res_i16 as i32,
-                res < 0,
-                res_i16 == 0,
-            );
-            self.write_operand(op, op.first, res_i16 as u16);
+            self.flag.set_auxiliary(false);
+            self.flag.set_carry(false);
+        }
+        self.register.al &= 0x0f;
+    }
+
+    fn aas(&mut self) {
+        if self.register.al & 0x0f > 9 || self.flag.auxiliary() {
+            self.register.al = self.register.al.wrapping_sub(6);
+            self.register.ah = self.register.ah.wrapping_sub(1);
+            self.flag.set_auxiliary(true);
+            self.flag.set_carry(true);
+        } else {
+            self.flag.set_auxiliary(false);
+            self.flag.set_carry(false);
+        }
+        self.register.al &= 0x0f;
+    }
+
+    fn daa(&mut self) {
+        let al = self.register.al;
+        let carry = self.flag.carry();
+        self.flag.set_auxiliary(al & 0x0f > 9 || self.flag.auxiliary());
+        if self.flag.auxiliary() {
+            self.register.al = self.register.al.wrapping_add(0x06);
+        }
+        self.flag.set_carry(al > 0x99 || carry);
+        if self.flag.carry() {
+            self.register.al = self.register.al.wrapping_add(0x60);
+        }
+        self.flag.set_szp(self.register.al as u16, 0);
+    }
+
+    fn das(&mut self) {
+        let al = self.register.al;
+        let carry = self.flag.carry();
+        self.flag.set_auxiliary(al & 0x0f > 9 || self.flag.auxiliary());
+        if self.flag.auxiliary() {
+            self.register.al = self.register.al.wrapping_sub(0x06);
+        }
+        self.flag.set_carry(al > 0x99 || carry);
+        if self.flag.carry() {
+            self.register.al = self.register.al.wrapping_sub(0x60);
+        }
+        self.flag.set_szp(self.register.al as u16, 0);
+    }
+
+    fn aam(&mut self, op: &Operation) {
+        let base = op.data as u8;
+        if base == 0 {
+            self.divide_error();
+            return;
+        }
+        self.register.ah = self.register.al / base;
+        self.register.al %= base;
+        self.flag.set_szp(self.register.al as u16, 0);
+    }
+
+    fn aad(&mut self, op: &Operation) {
+        let base = op.data as u8;
+        let al = self
+            .register
+            .al
+            .wrapping_add(self.register.ah.wrapping_mul(base));
+        self.register.al = al;
+        self.register.ah = 0;
+        self.flag.set_szp(al as u16, 0);
+    }
+
+    fn int(&mut self, op: &Operation) {
+        match self.platform {
+            Platform::Minix(_) => self.syscall(),
+            Platform::BareMetal => self.interrupt(op.int_type),
+        }
+    }
+
+    fn interrupt(&mut self, int_type: u8) {
+        // Vector through the interrupt vector table at 0000:0000
+        self.stack_push_u16(self.flag.to_u16());
+        self.flag.interrupt = false;
+        self.flag.trap = false;
+        self.stack_push_u16(self.register.cs);
+        self.stack_push_u16(self.register.ip);
+        let addr = int_type as usize * 4;
+        self.register.ip = self.memory.read16(addr);
+        self.register.cs = self.memory.read16(addr + 2);
+    }
+
+    fn iret(&mut self) {
+        self.register.ip = self.stack_pop_u16();
+        self.register.cs = self.stack_pop_u16();
+        let flags = self.stack_pop_u16();
+        self.flag.set_u16(flags);
+    }
+
+    // Runs the BIOS service when execution reaches its stub, before the stub's IRET
+    fn bios_trap(&mut self) {
+        let addr = physical_address(self.register.cs, self.register.ip);
+        let Some(bios) = self.bios.as_mut() else {
+            return;
+        };
+        let Some(int_type) = bios.service_at(addr) else {
+            return;
+        };
+        let (cs, ip) = (self.register.cs, self.register.ip);
+        let service = bios.interrupt(
+            int_type,
+            &mut self.register,
+            &mut self.flag,
+            &mut self.memory,
+            &mut self.ports,
+            &self.dump,
+        );
+        self.dump.eol();
+        match service {
+            Service::Done => {}
+            Service::Wait => {
+                // STI; HLT at the stub, which traps again once an interrupt returns there
+                self.flag.interrupt = true;
+                self.halted = true;
+                return;
+            }
+            Service::Stop => self.stop = true,
+        }
+        if (cs, ip) != (self.register.cs, self.register.ip) {
+            // The service transferred control (bootstrap) and never returns
+            return;
+        }
+        // Hand the status flags back to the caller through the saved FLAGS.
+        // IF, TF and DF are the caller's own.
+        let saved = physical_address(self.register.ss, self.register.sp.wrapping_add(4));
+        let control = 0x0700;
+        let flags = self.memory.read16(saved) & control | self.flag.to_u16() & !control;
+        self.memory.write16(saved, flags);
+    }
+
+    // Maskable interrupt request, held until the CPU accepts it with IF set
+    #[cfg(test)]
+    pub(crate) fn raise_interrupt(&mut self, vector: u8) {
+        self.intr = Some(vector);
+    }
+
+    // Non-maskable interrupt, taken through vector 2 regardless of IF
+    #[cfg(test)]
+    pub(crate) fn raise_nmi(&mut self) {
+        self.nmi = true;
+    }
+
+    // Whether a halted or spinning CPU can still be woken by an interrupt
+    fn can_wake(&self) -> bool {
+        if self.nmi {
+            return true;
+        }
+        let pic = self.board.pic.borrow();
+        let timer = self.board.pit.borrow().is_counting(0) && !pic.is_masked(0);
+        let serial = self
+            .serial
+            .iter()
+            .any(|(uart, irq)| !pic.is_masked(*irq) && uart.borrow().may_interrupt());
+        let keyboard =
+            !pic.is_masked(keyboard::KEYBOARD_IRQ) && self.board.keyboard.borrow().may_interrupt();
+        self.flag.interrupt
+            && (self.intr.is_some() || pic.has_interrupt() || timer || serial || keyboard)
+    }
+
+    // Runs the devices clocked alongside the CPU
+    fn advance(&mut self, clocks: u64) {
+        self.clock += clocks;
+        self.throttle.advance(self.clock);
+        let before = self.time;
+        self.time = (self.clock as u128 * CLOCK_HZ as u128 / self.clock_hz as u128) as u64;
+        let ticks = self.time / CLOCKS_PER_PIT_TICK - before / CLOCKS_PER_PIT_TICK;
+        let mut pic = self.board.pic.borrow_mut();
+        if self.board.pit.borrow_mut().tick(ticks) {
+            pic.raise_irq(0);
+        }
+        self.board.speaker.borrow_mut().tick(ticks);
+        for (uart, irq) in &self.serial {
+            if uart.borrow_mut().poll() {
+                pic.raise_irq(*irq);
+            }
+        }
+        let mut keyboard = self.board.keyboard.borrow_mut();
+        if keyboard.poll(self.time) {
+            pic.raise_irq(keyboard::KEYBOARD_IRQ);
+        }
+        if keyboard.quit_requested() {
+            self.stop = true;
+        }
+        if let Some((video, display)) = &mut self.video {
+            display.tick(&mut video.borrow_mut(), self.time - before);
         }
     }
 
-    fn int(&mut self) {
+    fn service_interrupts(&mut self) {
+        // A prefix and the instruction it modifies are never split
+        if self.segment_override.is_some() || self.interrupt_shadow {
+            self.interrupt_shadow = false;
+            return;
+        }
+        let vector = if self.nmi {
+            self.nmi = false;
+            2
+        } else if !self.flag.interrupt {
+            return;
+        } else if let Some(vector) = self.intr.take() {
+            vector
+        } else if let Some(vector) = self.board.pic.borrow_mut().acknowledge() {
+            vector
+        } else {
+            return;
+        };
+        self.halted = false;
+        self.dump.hardware_interrupt(vector);
+        self.dump.eol();
+        self.interrupt(vector);
+    }
+
+    fn syscall(&mut self) {
         let bx = self.register.get_bx() as usize;
-        let msg = Message::load(self.get_data_segment(), bx);
+        let msg = Message::load(&self.memory, bx);
         match msg.message_type {
             1 => {
                 // exit
-                let detail = msg.load_detail1(self.get_data_segment());
+                let detail = msg.load_detail1(&self.memory);
                 self.exit(detail.m1i1());
             }
             4 => {
                 // write
-                let detail = msg.load_detail1(self.get_data_segment());
+                let detail = msg.load_detail1(&self.memory);
                 let fd = detail.m1i1();
                 let addr = detail.m1p1();
                 let len = detail.m1i2();
@@ -464,34 +1153,34 @@ impl Machine {
 
                 // Write errno and return value to memory
                 // res
-                write_16(&mut self.memory, bx, 0);
+                self.memory.write16(bx, 0);
                 // errno
-                write_16(&mut self.memory, bx + 2, len);
+                self.memory.write16(bx + 2, len);
 
                 self.register.set_ax(0);
             }
             17 => {
                 // brk
-                let addr = read_16(self.get_data_segment(), bx + MESSAGE_SIZE + 6);
+                let addr = self.memory.read16(bx + MESSAGE_SIZE + 6);
                 let res = self.brk(addr);
                 self.register.set_ax(0);
-                write_16(&mut self.memory, bx, 0);
+                self.memory.write16(bx, 0);
                 if res {
-                    write_16(&mut self.memory, bx + 2, 0);
+                    self.memory.write16(bx + 2, 0);
                 } else {
-                    write_16(&mut self.memory, bx + 2, 12); // EINVAL
+                    self.memory.write16(bx + 2, 12); // EINVAL
                 }
             }
             54 => {
                 // ioctl
-                let fd = read_16(self.get_data_segment(), bx + MESSAGE_SIZE);
-                let req = read_16(self.get_data_segment(), bx + MESSAGE_SIZE + 4);
-                let addr = read_16(self.get_data_segment(), bx + MESSAGE_SIZE + 14);
+                let fd = self.memory.read16(bx + MESSAGE_SIZE);
+                let req = self.memory.read16(bx + MESSAGE_SIZE + 4);
+                let addr = self.memory.read16(bx + MESSAGE_SIZE + 14);
                 self.ioctl(fd, req, addr);
                 self.register.set_ax(0);
-                write_16(&mut self.memory, bx, 0);
-                let errno = -22 as i16;
-                write_16(&mut self.memory, bx + 2, errno as u16);
+                self.memory.write16(bx, 0);
+                let errno: i16 = -22;
+                self.memory.write16(bx + 2, errno as u16);
             }
             _ => {
                 panic!("\nUnhandled interrupt type: {}", msg.message_type);
@@ -500,7 +1189,17 @@ impl Machine {
     }
 
     fn push(&mut self, op: &Operation) {
-        let value = self.read_operand(op, op.first);
+        let pushes_sp = match op.first {
+            OperandType::Reg => op.reg == 0b100,
+            OperandType::EA => op.mod_rm == 0b11 && op.rm == 0b100,
+            _ => false,
+        };
+        // Up to the 80186 and V30, PUSH SP stores SP as it is after the decrement
+        let value = if pushes_sp {
+            self.register.sp.wrapping_sub(2)
+        } else {
+            self.read_operand(op, op.first)
+        };
         self.stack_push_u16(value);
     }
 
@@ -509,37 +1208,138 @@ impl Machine {
         self.write_operand(op, op.first, res);
     }
 
-    fn call(&mut self, op: &Operation) {
-        let addr = self.calc_effective_address(op);
-        if addr >= self.memory.len() {
+    fn pusha(&mut self) {
+        let sp = self.register.sp;
+        for value in [
+            self.register.get_ax(),
+            self.register.get_cx(),
+            self.register.get_dx(),
+            self.register.get_bx(),
+            sp,
+            self.register.bp,
+            self.register.si,
+            self.register.di,
+        ] {
+            self.stack_push_u16(value);
+        }
+    }
+
+    fn popa(&mut self) {
+        self.register.di = self.stack_pop_u16();
+        self.register.si = self.stack_pop_u16();
+        self.register.bp = self.stack_pop_u16();
+        // The saved SP is discarded
+        self.stack_pop_u16();
+        let bx = self.stack_pop_u16();
+        self.register.set_bx(bx);
+        let dx = self.stack_pop_u16();
+        self.register.set_dx(dx);
+        let cx = self.stack_pop_u16();
+        self.register.set_cx(cx);
+        let ax = self.stack_pop_u16();
+        self.register.set_ax(ax);
+    }
+
+    fn enter(&mut self, op: &Operation) {
+        self.stack_push_u16(self.register.bp);
+        let frame = self.register.sp;
+        let level = op.disp & 0x1f;
+        if level > 0 {
+            // Copy the enclosing frame pointers for nested procedures
+            for _ in 1..level {
+                self.register.bp = self.register.bp.wrapping_sub(2);
+                let value = self
+                    .memory
+                    .read16(physical_address(self.register.ss, self.register.bp));
+                self.stack_push_u16(value);
+            }
+            self.stack_push_u16(frame);
+        }
+        self.register.bp = frame;
+        self.register.sp = self.register.sp.wrapping_sub(op.data);
+    }
+
+    fn leave(&mut self) {
+        self.register.sp = self.register.bp;
+        self.register.bp = self.stack_pop_u16();
+    }
+
+    fn bound(&mut self, op: &Operation) {
+        let addr = self.data_address(op);
+        let lower = self.memory.read16(addr) as i16;
+        let upper = self.memory.read16((addr + 2) & ADDRESS_MASK) as i16;
+        self.dump.address_value(addr, lower as u16);
+        let index = self.register.get(op.get_register()) as i16;
+        if index < lower || index > upper {
+            // The return address is the BOUND instruction itself
+            self.register.ip = op.pos as u16;
+            match self.platform {
+                Platform::Minix(_) => panic!("\nBound range exceeded"),
+                Platform::BareMetal => self.interrupt(5),
+            }
+        }
+    }
+
+    fn near_target(&self, op: &Operation) -> usize {
+        let addr = match op.first {
+            OperandType::EA => self.read_operand(op, op.first) as usize,
+            _ => self.calc_effective_address(op),
+        };
+        if addr >= self.memory.size() {
             panic!("Memory access out of bounds at address {}", addr);
         }
-        let return_addr = self.register.ip as u16;
+        addr
+    }
+
+    fn far_target(&self, op: &Operation) -> (u16, u16) {
+        match op.first {
+            // Direct Intersegment
+            OperandType::Far => (op.data, op.disp),
+            // Indirect Intersegment
+            _ => {
+                let addr = self.data_address(op);
+                let offset = self.memory.read16(addr);
+                let segment = self.memory.read16((addr + 2) & ADDRESS_MASK);
+                self.dump.address_value(addr, offset);
+                (segment, offset)
+            }
+        }
+    }
+
+    fn call(&mut self, op: &Operation) {
+        if op.is_far() {
+            let (segment, offset) = self.far_target(op);
+            self.stack_push_u16(self.register.cs);
+            self.stack_push_u16(self.register.ip);
+            self.register.cs = segment;
+            self.register.ip = offset;
+            return;
+        }
+        let addr = self.near_target(op);
+        let return_addr = self.register.ip;
         self.stack_push_u16(return_addr);
         self.register.ip = addr as u16;
     }
 
     fn lea(&mut self, op: &Operation) {
-        let addr = self.calc_effective_address(op);
-        let val = match op.w {
-            0 => self.memory[addr] as u16,
-            1 => {
-                if addr + 1 >= self.memory.len() {
-                    panic!("Memory access out of bounds at address {}", addr + 1);
-                }
-                u16::from_le_bytes([self.memory[addr], self.memory[addr + 1]])
-            }
-            _ => unreachable!("Invalid operand width"),
-        };
-        self.dump.address_value(addr, val);
-        self.register.set(op.get_register(), addr as u16);
+        let offset = self.calc_effective_address(op);
+        if self.dump.enabled {
+            // LEA does not access memory, the value is only read for the trace
+            let addr = self.data_address(op);
+            let val = self.read_memory(addr, op.w);
+            self.dump.address_value(addr, val);
+        }
+        self.register.set(op.get_register(), offset as u16);
     }
 
     fn ret(&mut self, op: &Operation) {
         let return_addr = self.stack_pop_u16();
-        if return_addr as usize >= self.memory.len() {
+        if return_addr as usize >= self.memory.size() {
             panic!("Memory access out of bounds at address {}", return_addr);
         }
+        if op.is_far() {
+            self.register.cs = self.stack_pop_u16();
+        }
         match op.first {
             OperandType::Imm => {
                 // Within Segment Adding Immediate to Sp
@@ -558,382 +1358,541 @@ impl Machine {
     fn and(&mut self, op: &Operation) {
         let left = self.read_operand(op, op.first);
         let right = self.read_operand(op, op.second);
-        let result = match op.w {
-            0 => (left as u8 & right as u8) as u16,
-            1 => left & right,
-            _ => unreachable!("Invalid operand width"),
-        };
-        self.flag
-            .set_cosz(false, false, (result as i16) < 0, result == 0);
+        let result = self.flag.logic(left & right, op.w);
         self.write_operand(op, op.first, result);
     }
 
     fn or(&mut self, op: &Operation) {
         let left = self.read_operand(op, op.first);
         let right = self.read_operand(op, op.second);
-        let result = match op.w {
-            0 => (left as u8 | right as u8) as u16,
-            1 => left | right,
-            _ => unreachable!("Invalid operand width"),
-        };
-        self.flag.set_cosz(false, false, false, result == 0);
+        let result = self.flag.logic(left | right, op.w);
         self.write_operand(op, op.first, result);
     }
 
     fn xor(&mut self, op: &Operation) {
         let left = self.read_operand(op, op.first);
         let right = self.read_operand(op, op.second);
-        let result = match op.w {
-            0 => (left as u8 ^ right as u8) as u16,
-            1 => left ^ right,
-            _ => unreachable!("Invalid operand width"),
-        };
-        self.flag.set_cosz(false, false, false, result == 0);
+        let result = self.flag.logic(left ^ right, op.w);
         self.write_operand(op, op.first, result);
     }
 
     fn cmp(&mut self, op: &Operation) {
         let left = self.read_operand(op, op.first);
         let right = self.read_operand(op, op.second);
-        match op.s << 1 | op.w {
-            0b00 => {
-                let res = (left & 0xff) as i32 - (right & 0xff) as i32;
-                self.flag
-                    .set_cosz(left < right, res > 0xff, res < 0, res == 0);
-            }
-            0b01 => {
-                let res_u16 = (left as i16 - right as i16) as u16;
-                let res = left as i32 - right as i32;
-                self.flag
-                    .set_cosz(left < right, res > 0xffff, res < 0, res_u16 == 0);
-            }
-            0b11 => {
-                if op.second == OperandType::Imm {
-                    let res_i16 = left as i16 - right as i8 as i16;
-                    let res = left as i16 as i32 - right as i8 as i32;
-                    self.flag
-                        .set_cosz(left < right, res > 0xffff, res < 0, res_i16 == 0);
-                } else {
-                    let res_i16 = left as i16 - right as i16;
-                    let res = left as i16 as i32 - right as i16 as i32;
-                    self.flag
-                        .set_cosz(left < right, res > 0xffff, res < 0, res_i16 == 0);
-                }
-            }
-            _ => unreachable!("Invalid operand width"),
-        };
+        self.flag.sub(left, right, false, op.w);
     }
 
-    fn shl(&mut self, op: &Operation) {
-        let left = self.read_operand(op, op.first);
-        match op.v << 1 | op.w {
-            0b00 => {
-                let left = left as u8;
-                let res = (left << 1) as i32;
-                let res_i8 = res as i8;
-                let carry = carry_lsh_u8(left, 1);
-                let overflow = ((res_i8 >> 7) & 1 == 1) != carry;
-                self.flag.set_cosz(carry, overflow, res_i8 < 0, res_i8 == 0);
-                self.write_operand(op, op.first, res_i8 as u16);
-            }
-            0b01 => {
-                let res = (left << 1) as i32;
-                let res_i16 = res as i16;
-                let carry = carry_lsh_u16(left, 1 as u16);
-                let overflow = ((res_i16 >> 15) & 1 == 1) != carry;
-                self.flag
-                    .set_cosz(carry, overflow, res_i16 < 0, res_i16 == 0);
-                self.write_operand(op, op.first, res_i16 as u16);
-            }
-            0b10 => {
-                let cl = self.register.cl;
-                if cl == 0 {
-                    // Do nothing if CL is 0
+    fn shift_rotate(&mut self, op: &Operation) {
+        let count = match (op.raws[0] & 0b1111_1110, op.v) {
+            // Shift/Rotate by Immediate
+            (0b1100_0000, _) => op.data as u8,
+            (_, 0) => 1,
+            _ => self.register.cl,
+        };
+        let count = if self.cpu.masks_shift_count() {
+            count & 0x1f
+        } else {
+            count
+        };
+        if count == 0 {
+            return;
+        }
+        let (mask, sign_bit) = width_mask(op.w);
+        let mut value = self.read_operand(op, op.first) & mask;
+        let original = value;
+        let mut carry = self.flag.carry();
+        for _ in 0..count {
+            value = match op.operation_type {
+                OperationType::ShlSal => {
+                    carry = value & sign_bit != 0;
+                    value << 1
                 }
-                let left = left as u8;
-                let num_shift = cl & 0x1f;
-                let res = (left << num_shift) as i32;
-                let res_i8 = res as i8;
-                let carry = carry_lsh_u8(left, 1);
-                let overflow = ((res_i8 >> 7) & 1 == 1) != carry;
-                self.flag.set_cosz(carry, overflow, res_i8 < 0, res_i8 == 0);
-                self.write_operand(op, op.first, res_i8 as u16);
-            }
-            0b11 => {
-                let cl = self.register.cl;
-                if cl == 0 {
-                    // Do nothing if CL is 0
+                OperationType::Shr => {
+                    carry = value & 1 != 0;
+                    value >> 1
                 }
-                let num_shift = cl & 0x1f;
-                let res = (left << num_shift) as i32;
-                let res_i16 = res as i16;
-                let carry = carry_lsh_u16(left, num_shift as u16);
-                let overflow = ((res_i16 >> 15) & 1 == 1) != carry;
-                self.flag
-                    .set_cosz(carry, overflow, res_i16 < 0, res_i16 == 0);
-                self.write_operand(op, op.first, res_i16 as u16);
-            }
-            _ => unreachable!("Invalid operation"),
-        };
-    }
-
-    fn sar(&mut self, op: &Operation) {
-        let left = self.read_operand(op, op.first);
-        match op.v << 1 | op.w {
-            0b00 => {
-                let left = left as u8;
-                let res = (left as i8 >> 1) as i32;
-                let res_i8 = res as i8;
-                let carry = carry_rsh_u16(left as u16, 1);
-                self.flag.set_cosz(carry, false, res_i8 < 0, res_i8 == 0);
-                self.write_operand(op, op.first, res_i8 as u16);
-            }
-            0b01 => {
-                let res = (left as i16 >> 1) as i32;
-                let res_i16 = res as i16;
-                let carry = carry_rsh_u16(left, 1);
-                self.flag.set_cosz(carry, false, res_i16 < 0, res_i16 == 0);
-                self.write_operand(op, op.first, res_i16 as u16);
-            }
-            0b10 => {
-                let cl = self.register.cl;
-                if cl == 0 {
-                    // Do nothing if CL is 0
-                    return;
+                OperationType::Sar => {
+                    carry = value & 1 != 0;
+                    (value >> 1) | (value & sign_bit)
                 }
-                let num_shift = cl & 0x1f;
-                let res = (left as i16 >> num_shift) as i32;
-                let res_i16 = res as i16;
-                let carry = carry_rsh_u16(left, num_shift as u16);
-                self.flag.set_cosz(carry, false, res_i16 < 0, res_i16 == 0);
-                self.write_operand(op, op.first, res_i16 as u16);
-            }
-            0b11 => {
-                let cl = self.register.cl;
-                if cl == 0 {
-                    // Do nothing if CL is 0
-                    return;
+                OperationType::Rol => {
+                    carry = value & sign_bit != 0;
+                    (value << 1) | carry as u16
+                }
+                OperationType::Ror => {
+                    carry = value & 1 != 0;
+                    (value >> 1) | if carry { sign_bit } else { 0 }
                 }
-                let num_shift = cl & 0x1f;
-                let res = (left >> num_shift) as i32;
-                let res_i16 = res as i16;
-                let carry = carry_rsh_u16(left, num_shift as u16);
-                self.flag.set_cosz(carry, false, res_i16 < 0, res_i16 == 0);
-                self.write_operand(op, op.first, res_i16 as u16);
-            }
-            _ => unreachable!("Invalid operation"),
+                OperationType::Rcl => {
+                    let out = value & sign_bit != 0;
+                    let res = (value << 1) | carry as u16;
+                    carry = out;
+                    res
+                }
+                OperationType::Rcr => {
+                    let out = value & 1 != 0;
+                    let res = (value >> 1) | if carry { sign_bit } else { 0 };
+                    carry = out;
+                    res
+                }
+                _ => unreachable!("Invalid shift operation: {:?}", op.operation_type),
+            } & mask;
+        }
+        self.flag.set_carry(carry);
+        let overflow = match op.operation_type {
+            OperationType::ShlSal | OperationType::Rol | OperationType::Rcl => {
+                (value & sign_bit != 0) != carry
+            }
+            OperationType::Shr => original & sign_bit != 0,
+            OperationType::Sar => false,
+            _ => (value ^ (value << 1)) & sign_bit != 0,
         };
+        self.flag.set_overflow(overflow);
+        if matches!(
+            op.operation_type,
+            OperationType::ShlSal | OperationType::Shr | OperationType::Sar
+        ) {
+            self.flag.set_szp(value, op.w);
+        }
+        self.write_operand(op, op.first, value);
     }
 
     fn jmp(&mut self, op: &Operation) {
-        let addr = self.calc_effective_address(op);
-        if addr >= self.memory.len() {
-            panic!("Memory access out of bounds at address {}", addr);
+        if op.is_far() {
+            let (segment, offset) = self.far_target(op);
+            self.register.cs = segment;
+            self.register.ip = offset;
+            return;
+        }
+        let addr = self.near_target(op);
+        if matches!(self.platform, Platform::BareMetal) && addr == op.pos && !self.can_wake() {
+            // Only an interrupt could leave a jump to itself
+            self.stop = true;
         }
         self.register.ip = addr as u16;
     }
 
-    fn je(&mut self, op: &Operation) {
-        if self.flag.zero {
+    fn jump_if(&mut self, op: &Operation, condition: bool) {
+        self.taken = condition;
+        if condition {
             let addr = self.calc_effective_address(op);
-            if addr >= self.memory.len() {
+            if addr >= self.memory.size() {
                 panic!("Memory access out of bounds at address {}", addr);
             }
             self.register.ip = addr as u16;
         }
     }
 
-    fn jnl(&mut self, op: &Operation) {
-        if !self.flag.sign {
-            let addr = self.calc_effective_address(op);
-            if addr >= self.memory.len() {
-                panic!("Memory access out of bounds at address {}", addr);
-            }
-            self.register.ip = addr as u16;
+    fn loop_cx(&mut self, op: &Operation, condition: bool) {
+        let cx = self.register.get_cx().wrapping_sub(1);
+        self.register.set_cx(cx);
+        self.jump_if(op, cx != 0 && condition);
+    }
+
+    fn test(&mut self, op: &Operation) {
+        let left = self.read_operand(op, op.first);
+        let right = self.read_operand(op, op.second);
+        self.flag.logic(left & right, op.w);
+    }
+
+    fn cbw(&mut self) {
+        let al = self.register.al as i8;
+        let ah = if al < 0 { 0xff } else { 0x00 };
+        self.register.ah = ah as u8;
+    }
+
+    fn cwd(&mut self) {
+        let ax = self.register.get_ax() as i16;
+        let dx = if ax < 0 { 0xffff } else { 0x0000 };
+        self.register.set_dx(dx);
+    }
+
+    fn xchg(&mut self, op: &Operation) {
+        let left = self.read_operand(op, op.first);
+
+        if op.second == OperandType::None {
+            // XCHG with AX
+            let right = self.register.get_ax();
+            self.register.set_ax(left);
+            self.write_operand(op, op.first, right);
+        } else {
+            let right = self.read_operand(op, op.second);
+            self.write_operand(op, op.first, right);
+            self.write_operand(op, op.second, left);
         }
     }
 
-    fn jnb(&mut self, op: &Operation) {
-        if !self.flag.carry {
-            let addr = self.calc_effective_address(op);
-            if addr >= self.memory.len() {
-                panic!("Memory access out of bounds at address {}", addr);
+    fn string_step(&mut self, op_type: OperationType, w: u8) {
+        let size = if w == 0 { 1 } else { 2 };
+        let delta = if self.flag.direction {
+            0u16.wrapping_sub(size)
+        } else {
+            size
+        };
+        let src = physical_address(self.segment(SegmentRegister::DS), self.register.si);
+        let dst = physical_address(self.register.es, self.register.di);
+        let acc = RegisterType::new(0b000, w);
+        match op_type {
+            OperationType::Movs => {
+                let value = self.read_memory(src, w);
+                self.write_memory(dst, value, w);
+                self.register.si = self.register.si.wrapping_add(delta);
+                self.register.di = self.register.di.wrapping_add(delta);
             }
-            self.register.ip = addr as u16;
+            OperationType::Cmps => {
+                let left = self.read_memory(src, w);
+                let right = self.read_memory(dst, w);
+                self.flag.sub(left, right, false, w);
+                self.register.si = self.register.si.wrapping_add(delta);
+                self.register.di = self.register.di.wrapping_add(delta);
+            }
+            OperationType::Scas => {
+                let right = self.read_memory(dst, w);
+                self.flag.sub(self.register.get(acc), right, false, w);
+                self.register.di = self.register.di.wrapping_add(delta);
+            }
+            OperationType::Lods => {
+                let value = self.read_memory(src, w);
+                self.register.set(acc, value);
+                self.register.si = self.register.si.wrapping_add(delta);
+            }
+            OperationType::Stos => {
+                self.write_memory(dst, self.register.get(acc), w);
+                self.register.di = self.register.di.wrapping_add(delta);
+            }
+            OperationType::Ins => {
+                let port = self.register.get_dx();
+                let value = match w {
+                    0 => self.ports.read8(port) as u16,
+                    _ => self.ports.read16(port),
+                };
+                self.write_memory(dst, value, w);
+                self.register.di = self.register.di.wrapping_add(delta);
+            }
+            OperationType::Outs => {
+                let port = self.register.get_dx();
+                let value = self.read_memory(src, w);
+                match w {
+                    0 => self.ports.write8(port, value as u8),
+                    _ => self.ports.write16(port, value),
+                }
+                self.register.si = self.register.si.wrapping_add(delta);
+            }
+            _ => unreachable!("Invalid string operation: {:?}", op_type),
         }
     }
 
-    fn jne(&mut self, op: &Operation) {
-        if !self.flag.zero {
-            let addr = self.calc_effective_address(op);
-            if addr >= self.memory.len() {
-                panic!("Memory access out of bounds at address {}", addr);
+    fn rep(&mut self, op: &Operation) {
+        while self.register.get_cx() != 0 {
+            self.string_step(op.rep_operation_type, op.w);
+            self.register.set_cx(self.register.get_cx() - 1);
+            let compares = matches!(
+                op.rep_operation_type,
+                OperationType::Cmps | OperationType::Scas
+            );
+            // REPZ stops on a mismatch, REPNZ on a match
+            if compares && self.flag.zero() != (op.z == 1) {
+                break;
             }
-            self.register.ip = addr as u16;
         }
     }
 
-    fn jl(&mut self, op: &Operation) {
-        if self.flag.sign {
-            let addr = self.calc_effective_address(op);
-            if addr >= self.memory.len() {
-                panic!("Memory access out of bounds at address {}", addr);
-            }
-            self.register.ip = addr as u16;
+    fn xlat(&mut self) {
+        let offset = self.register.get_bx().wrapping_add(self.register.al as u16);
+        let addr = physical_address(self.segment(SegmentRegister::DS), offset);
+        self.register.al = self.memory.read8(addr);
+    }
+
+    fn load_far_pointer(&mut self, op: &Operation, segment: SegmentRegister) {
+        let addr = self.data_address(op);
+        let offset = self.memory.read16(addr);
+        let value = self.memory.read16((addr + 2) & ADDRESS_MASK);
+        self.dump.address_value(addr, offset);
+        self.register.set(op.get_register(), offset);
+        self.register.set(RegisterType::Segment(segment), value);
+    }
+
+    fn lahf(&mut self) {
+        self.register.ah = self.flag.to_u16() as u8;
+    }
+
+    fn sahf(&mut self) {
+        let flags = (self.flag.to_u16() & 0xff00) | self.register.ah as u16;
+        self.flag.set_u16(flags);
+    }
+
+    fn popf(&mut self) {
+        let flags = self.stack_pop_u16();
+        self.flag.set_u16(flags);
+    }
+
+    fn port(&self, op: &Operation) -> u16 {
+        // Bit 3 selects the variable port form, which addresses through DX
+        match op.raws[0] & 0b1000 {
+            0 => op.port as u16,
+            _ => self.register.get_dx(),
         }
     }
 
-    fn jb(&mut self, op: &Operation) {
-        if self.flag.carry {
-            let addr = self.calc_effective_address(op);
-            if addr >= self.memory.len() {
-                panic!("Memory access out of bounds at address {}", addr);
+    fn port_in(&mut self, op: &Operation) {
+        let port = self.port(op);
+        match op.w {
+            0 => self.register.al = self.ports.read8(port),
+            _ => {
+                let value = self.ports.read16(port);
+                self.register.set_ax(value);
             }
-            self.register.ip = addr as u16;
         }
     }
 
-    fn jbe(&mut self, op: &Operation) {
-        if self.flag.carry || self.flag.zero {
-            let addr = self.calc_effective_address(op);
-            if addr >= self.memory.len() {
-                panic!("Memory access out of bounds at address {}", addr);
-            }
-            self.register.ip = addr as u16;
+    fn esc(&mut self, op: &Operation) {
+        let addr = if op.mod_rm == 0b11 {
+            0
+        } else {
+            self.data_address(op)
+        };
+        let ip = physical_address(self.register.cs, op.pos as u16);
+        self.fpu.execute(op, ip, addr, &mut self.memory);
+    }
+
+    fn port_out(&mut self, op: &Operation) {
+        let port = self.port(op);
+        match op.w {
+            0 => self.ports.write8(port, self.register.al),
+            _ => self.ports.write16(port, self.register.get_ax()),
         }
     }
 
-    fn jle(&mut self, op: &Operation) {
-        if self.flag.sign || self.flag.zero {
-            let addr = self.calc_effective_address(op);
-            if addr >= self.memory.len() {
-                panic!("Memory access out of bounds at address {}", addr);
+    // Drops decoded operations and blocks once their code has been overwritten
+    fn invalidate_code(&mut self) -> bool {
+        if !self.memory.take_code_written() {
+            return false;
+        }
+        self.decoded.clear();
+        self.blocks.clear();
+        true
+    }
+
+    fn fetch(&mut self, ip: u16) -> Option<Operation> {
+        self.invalidate_code();
+        let key = (self.register.cs, ip);
+        let op = match self.decoded.get(&key) {
+            Some(op) => *op,
+            None => {
+                let op = self.decode(ip)?;
+                self.decoded.insert(key, op);
+                op
             }
-            self.register.ip = addr as u16;
+        };
+        // The trace prints each instruction as it runs, cached or not
+        self.dump.operation(&op, op.form);
+        if opcode::lookup(op.raws[0], self.cpu).undocumented {
+            self.dump.undocumented();
         }
+        Some(op)
     }
 
-    fn jnbe(&mut self, op: &Operation) {
-        if !self.flag.carry && !self.flag.zero {
-            let addr = self.calc_effective_address(op);
-            if addr >= self.memory.len() {
-                panic!("Memory access out of bounds at address {}", addr);
+    // Decodes the instruction at CS:IP from a window of its longest length.
+    // Bare metal code wraps at the end of the segment and of memory; MINIX
+    // text ends where it ends, so an instruction cut short by it is none.
+    fn decode(&mut self, ip: u16) -> Option<Operation> {
+        let mut window = [0; MAX_LENGTH];
+        match self.platform {
+            Platform::Minix(_) => {
+                let text = self.text.get(ip as usize..)?;
+                let len = text.len().min(MAX_LENGTH);
+                window[..len].copy_from_slice(&text[..len]);
+            }
+            Platform::BareMetal => {
+                for (i, byte) in window.iter_mut().enumerate() {
+                    let addr = physical_address(self.register.cs, ip.wrapping_add(i as u16));
+                    *byte = self.memory.bytes(addr..addr + 1)[0];
+                }
+            }
+        }
+        let mut op = Disassembler::new(&window, false, self.cpu).next(0)?;
+        op.pos = ip as usize;
+        // Only the 80186 traps what it does not define, the 8086 stops there
+        if op.operation_type == OperationType::Undefined && !self.cpu.traps_invalid_opcodes() {
+            return None;
+        }
+        match self.platform {
+            Platform::Minix(_) if op.get_next_operation_pos() > self.text.len() => return None,
+            Platform::Minix(_) => {}
+            Platform::BareMetal => {
+                for i in 0..op.raws.len() {
+                    let addr = physical_address(self.register.cs, ip.wrapping_add(i as u16));
+                    self.memory.mark_code(addr..addr + 1);
+                }
             }
-            self.register.ip = addr as u16;
         }
+        Some(op)
     }
 
-    fn jnle(&mut self, op: &Operation) {
-        if !self.flag.sign && !self.flag.zero {
-            let addr = self.calc_effective_address(op);
-            if addr >= self.memory.len() {
-                panic!("Memory access out of bounds at address {}", addr);
+    // Decodes the straight-line run at CS:IP up to its first control transfer
+    fn translate(&mut self) -> Option<Rc<Block>> {
+        let mut block = Vec::new();
+        let mut ip = self.register.ip;
+        while block.len() < MAX_BLOCK_LENGTH {
+            let Some(op) = self.fetch(ip) else {
+                break;
+            };
+            block.push((handler(op.operation_type), op));
+            if ends_block(op.operation_type) {
+                break;
             }
-            self.register.ip = addr as u16;
+            ip = op.get_next_operation_pos() as u16;
+        }
+        if block.is_empty() {
+            return None;
         }
+        let block = Rc::new(block);
+        let key = (self.register.cs, self.register.ip);
+        self.blocks.insert(key, block.clone());
+        Some(block)
     }
 
-    fn test(&mut self, op: &Operation) {
-        let left = self.read_operand(op, op.first);
-        let right = self.read_operand(op, op.second);
-        let result = match op.w {
-            0 => (left as u8 & right as u8) as u16,
-            1 => left & right,
-            _ => unreachable!("Invalid operand width"),
+    // Runs the block at CS:IP, sampling interrupts between its instructions as
+    // the interpreter does. Leaves it early when control goes elsewhere, and for
+    // good when the block writes to code, which the interpreter then runs instead.
+    fn run_block(&mut self) -> bool {
+        self.invalidate_code();
+        let key = (self.register.cs, self.register.ip);
+        let block = match self.blocks.get(&key) {
+            Some(block) => block.clone(),
+            None => match self.translate() {
+                Some(block) => block,
+                None => return false,
+            },
         };
-        self.flag
-            .set_cosz(false, false, (result as i16) < 0, result == 0);
+        for (i, (handler, op)) in block.iter().enumerate() {
+            if i > 0 {
+                if self.stop || self.halted {
+                    break;
+                }
+                self.service_interrupts();
+                if self.register.ip as usize != op.pos {
+                    break;
+                }
+            }
+            self.step(op, *handler);
+            if self.invalidate_code() {
+                self.interpreted.insert(key);
+                break;
+            }
+            if self.register.cs != key.0 || self.register.ip as usize != op.get_next_operation_pos()
+            {
+                break;
+            }
+        }
+        true
     }
 
-    fn cbw(&mut self) {
-        let al = self.register.al as i8;
-        let ah = if al < 0 { 0xff } else { 0x00 };
-        self.register.ah = ah as u8;
+    // Executes one decoded operation and accounts for its clocks
+    fn step(&mut self, op: &Operation, handler: Handler) {
+        self.register.ip = op.get_next_operation_pos() as u16;
+        let cx = self.register.get_cx();
+        let segment_override = self.segment_override.is_some();
+        // TF as the instruction starts, so that the POPF or IRET setting it
+        // runs one more instruction before the first trap
+        let trap = self.flag.trap && matches!(self.platform, Platform::BareMetal);
+        handler(self, op);
+        if op.operation_type != OperationType::Segment {
+            self.segment_override = None;
+        }
+        let count = match op.operation_type {
+            OperationType::Rep => cx.wrapping_sub(self.register.get_cx()),
+            _ => cx,
+        };
+        let execution = Execution {
+            count,
+            taken: self.taken,
+            segment_override,
+        };
+        let clocks = timing::clocks(op, self.cpu, &execution);
+        self.dump.clocks(clocks);
+        self.advance(clocks);
+        self.instructions += 1;
+        self.dump.eol();
+        // Single step through vector 1, except between a prefix or MOV SS and
+        // the instruction after it, and out of INT or a taken INTO, which
+        // clear TF themselves
+        let interrupted = match op.operation_type {
+            OperationType::Int => true,
+            OperationType::Into => !self.flag.trap,
+            _ => false,
+        };
+        if trap && self.segment_override.is_none() && !self.interrupt_shadow && !interrupted {
+            self.interrupt(1);
+        }
     }
 
-    fn cwd(&mut self) {
-        let ax = self.register.get_ax() as i16;
-        let dx = if ax < 0 { 0xffff } else { 0x0000 };
-        self.register.set_dx(dx);
+    // CPU clocks since reset
+    pub fn clocks(&self) -> u64 {
+        self.clock
     }
 
-    fn xchg(&mut self, op: &Operation) {
-        let left = self.read_operand(op, op.first);
+    // Instructions executed since reset
+    pub fn instructions(&self) -> u64 {
+        self.instructions
+    }
 
-        if op.second == OperandType::None {
-            // XCHG with AX
-            let right = self.register.get_ax();
-            self.register.set_ax(left);
-            self.write_operand(op, op.first, right);
-            return;
-        } else {
-            let right = self.read_operand(op, op.second);
-            dbg!(left, right);
-            self.write_operand(op, op.first, right);
-            self.write_operand(op, op.second, left);
-        }
+    pub fn effective_mhz(&self) -> f64 {
+        self.throttle.effective_mhz(self.clock)
     }
 
     pub fn run(&mut self) {
-        let mut disassembler =
-            disassembler::Disassembler::new(self.text.clone(), &self.metadata, self.dump.enabled);
         self.dump.labels();
+        self.throttle.start();
 
         loop {
-            if self.stop || self.register.ip > self.metadata.text_size as u16 {
+            if self.stop {
+                break;
+            }
+            self.service_interrupts();
+            if self.halted {
+                if !self.can_wake() {
+                    break;
+                }
+                self.advance(CLOCKS_PER_HALT_STEP);
+                continue;
+            }
+            self.bios_trap();
+            if self.stop {
+                break;
+            }
+            if self.halted {
+                // A BIOS service is waiting for an interrupt
+                continue;
+            }
+            if matches!(self.platform, Platform::Minix(_))
+                && self.register.ip as usize > self.text.len()
+            {
                 break;
             }
+            // The trace goes through the interpreter, which prints every instruction
+            if self.engine == Engine::Threaded
+                && !self.dump.enabled
+                && !self
+                    .interpreted
+                    .contains(&(self.register.cs, self.register.ip))
+            {
+                if !self.run_block() {
+                    break;
+                }
+                continue;
+            }
             self.dump.state(&self.register, &self.flag);
-            let op = match disassembler.next(self.register.ip) {
+            let op = match self.fetch(self.register.ip) {
                 Some(op) => op,
                 None => break,
             };
-            self.register.ip = op.get_next_operation_pos() as u16;
-            match op.operation_type {
-                OperationType::Mov => self.mov(&op),
-                OperationType::Add => self.add(&op),
-                OperationType::Sub => self.sub(&op),
-                OperationType::Int => self.int(),
-                OperationType::Push => self.push(&op),
-                OperationType::Pop => self.pop(&op),
-                OperationType::Call => self.call(&op),
-                OperationType::Jmp => self.jmp(&op),
-                OperationType::Lea => self.lea(&op),
-                OperationType::Ret => self.ret(&op),
-                OperationType::Or => self.or(&op),
-                OperationType::JeJz => self.je(&op),
-                OperationType::Cmp => self.cmp(&op),
-                OperationType::JnlJge => self.jnl(&op),
-                OperationType::Xor => self.xor(&op),
-                OperationType::JnbJae => self.jnb(&op),
-                OperationType::Test => self.test(&op),
-                OperationType::JneJnz => self.jne(&op),
-                OperationType::Dec => self.dec(&op),
-                OperationType::JlJnge => self.jl(&op),
-                OperationType::Cbw => self.cbw(),
-                OperationType::Inc => self.inc(&op),
-                OperationType::And => self.and(&op),
-                OperationType::JbJnae => self.jb(&op),
-                OperationType::JleJng => self.jle(&op),
-                OperationType::JnbeJa => self.jnbe(&op),
-                OperationType::ShlSal => self.shl(&op),
-                OperationType::Sar => self.sar(&op),
-                OperationType::JnleJg => self.jnle(&op),
-                OperationType::Cwd => self.cwd(),
-                OperationType::Div => self.div(&op),
-                OperationType::Xchg => self.xchg(&op),
-                OperationType::Neg => self.neg(&op),
-                OperationType::JbeJna => self.jbe(&op),
-                OperationType::Undefined => {
-                    panic!("\nUndefined operation: {:?}", op.operation_type);
-                }
-                _ => {
-                    println!("\nUnknown operation: {:?}", op.operation_type);
-                    self.stop = true;
-                }
-            }
-            self.dump.eol();
+            self.step(&op, handler(op.operation_type));
         }
+        if let Some((video, display)) = &mut self.video {
+            display.finish(&mut video.borrow_mut());
+        }
+        self.board.speaker.borrow().finish();
     }
 
     fn exit(&mut self, status: u16) {
@@ -942,13 +1901,13 @@ impl Machine {
     }
 
     fn write(&self, fd: u16, addr: usize, len: u16) {
-        if addr >= self.metadata.total {
+        if addr >= self.memory.size() {
             panic!("Memory access out of bounds at address {}", addr);
         }
-        let data = self.get_data_segment();
-        let begin = addr;
-        let end = begin + len as usize;
-        let str = String::from_utf8(data[begin..end].to_vec())
+        let data = (addr..addr + len as usize)
+            .map(|addr| self.memory.read8(addr))
+            .collect();
+        let str = String::from_utf8(data)
             .unwrap_or_else(|_| panic!("Failed to convert memory to string at address {}", addr));
         self.dump.write(fd, addr, len);
         print!("{}", str);
@@ -959,32 +1918,32 @@ impl Machine {
     }
 
     fn brk(&mut self, addr: u16) -> bool {
-        let ok = !(addr < self.metadata.data_size as u16
-            || addr >= ((self.register.sp & !0x3ff) - 0x400));
+        let data_size = match &self.platform {
+            Platform::Minix(metadata) => metadata.data_size,
+            Platform::BareMetal => unreachable!("brk is a MINIX system call"),
+        };
+        let ok = !(addr < data_size as u16 || addr >= ((self.register.sp & !0x3ff) - 0x400));
         self.dump.brk(addr, ok);
         ok
     }
 
     fn stack_push_u16(&mut self, value: u16) {
-        let value = value.to_le_bytes();
-        let sp = self.register.sp as usize;
-        let sp_new = sp
-            .checked_sub(value.len())
-            .unwrap_or_else(|| panic!("Stack overflow: SP is too low to push value"));
-        self.memory[sp_new..sp].copy_from_slice(&value);
-        self.register.sp = sp_new as u16;
+        let sp = self.register.sp;
+        let sp_new = match self.platform {
+            Platform::Minix(_) => sp
+                .checked_sub(2)
+                .unwrap_or_else(|| panic!("Stack overflow: SP is too low to push value")),
+            // SP wraps around within the stack segment
+            Platform::BareMetal => sp.wrapping_sub(2),
+        };
+        let addr = physical_address(self.register.ss, sp_new);
+        self.write_memory(addr, value, 1);
+        self.register.sp = sp_new;
     }
     fn stack_pop_u16(&mut self) -> u16 {
-        let sp = self.register.sp as usize;
-        let sp_new = sp
-            .checked_add(2)
-            .unwrap_or_else(|| panic!("Stack overflow: SP is too high to pop value"));
-        let value = u16::from_le_bytes(
-            self.memory[sp..sp_new]
-                .try_into()
-                .expect("Failed to read value from stack"),
-        );
-        self.register.sp = sp_new as u16;
+        let addr = physical_address(self.register.ss, self.register.sp);
+        let value = self.memory.read16(addr);
+        self.register.sp = self.register.sp.wrapping_add(2);
         value
     }
 
@@ -1006,3 +1965,310 @@ impl Machine {
     //     value
     // }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::assembler;
+    use test_case::test_case;
+
+    // 256 byte ROM at F000:FF00 whose reset vector jumps to its start
+    fn rom(code: &[u8]) -> Vec<u8> {
+        let mut rom = vec![0xf4; 0x100];
+        rom[..code.len()].copy_from_slice(code);
+        rom[0xf0..0xf5].copy_from_slice(&[0xea, 0x00, 0xff, 0x00, 0xf0]);
+        rom
+    }
+
+    #[test_case(0xfb, false, 0x1234; "Maskable with IF set")]
+    #[test_case(0xfa, false, 0x0000; "Maskable with IF clear")]
+    #[test_case(0xfa, true, 0x1234; "NMI with IF clear")]
+    fn test_external_interrupt(sti_or_cli: u8, nmi: bool, bx: u16) {
+        // STI or CLI; NOP; HLT, and the handler MOV BX, 1234; IRET at F000:FF10
+        let mut code = vec![sti_or_cli, 0x90, 0xf4];
+        code.resize(0x10, 0x90);
+        code.extend_from_slice(&[0xbb, 0x34, 0x12, 0xcf]);
+
+        let mut machine = Machine::with_rom(&rom(&code), false);
+        machine.register.sp = 0x400;
+        let vector = if nmi { 2 } else { 0x20 };
+        machine.memory.write16(vector * 4, 0xff10);
+        machine.memory.write16(vector * 4 + 2, 0xf000);
+        if nmi {
+            machine.raise_nmi();
+        } else {
+            machine.raise_interrupt(0x20);
+        }
+        machine.run();
+        assert_eq!(machine.register.get_bx(), bx);
+    }
+
+    #[test_case(Engine::Interpreter ; "interpreter")]
+    #[test_case(Engine::Threaded ; "threaded")]
+    fn test_single_step(engine: Engine) {
+        #[rustfmt::skip]
+        let mut code = vec![
+            0x9c, 0x58,             // PUSHF; POP AX
+            0x0d, 0x00, 0x01,       // OR AX, 0100
+            0x50, 0x9d,             // PUSH AX; POPF
+            0x90, 0x90,             // NOP; NOP
+            0x25, 0xff, 0xfe,       // AND AX, FEFF
+            0x50, 0x9d,             // PUSH AX; POPF
+            0x90, 0xf4,             // NOP; HLT
+        ];
+        code.resize(0x20, 0x90);
+        // INC BX; IRET at F000:FF20
+        code.extend_from_slice(&[0x43, 0xcf]);
+
+        let mut machine = Machine::with_rom(&rom(&code), false);
+        machine.set_engine(engine);
+        machine.register.sp = 0x400;
+        machine.memory.write16(4, 0xff20);
+        machine.memory.write16(6, 0xf000);
+        machine.run();
+        // After the two NOPs, AND, PUSH and the POPF that clears TF, but not
+        // inside the handler
+        assert_eq!(machine.register.get_bx(), 5);
+    }
+
+    #[test]
+    fn test_timer_interrupt() {
+        #[rustfmt::skip]
+        let mut code = vec![
+            0xb0, 0x13, 0xe6, 0x20, // MOV AL, 13; OUT 20, AL (ICW1)
+            0xb0, 0x08, 0xe6, 0x21, // MOV AL, 08; OUT 21, AL (ICW2)
+            0xb0, 0x09, 0xe6, 0x21, // MOV AL, 09; OUT 21, AL (ICW4)
+            0xb0, 0x00, 0xe6, 0x21, // MOV AL, 00; OUT 21, AL (OCW1)
+            0xb0, 0x34, 0xe6, 0x43, // MOV AL, 34; OUT 43, AL (channel 0, mode 2)
+            0xb0, 0x64, 0xe6, 0x40, // MOV AL, 64; OUT 40, AL
+            0xb0, 0x00, 0xe6, 0x40, // MOV AL, 00; OUT 40, AL
+            0xfb, 0xf4,             // STI; HLT
+            0xfa, 0xf4,             // CLI; HLT
+        ];
+        code.resize(0x30, 0x90);
+        // MOV BX, 1234; MOV AL, 20; OUT 20, AL; IRET
+        code.extend_from_slice(&[0xbb, 0x34, 0x12, 0xb0, 0x20, 0xe6, 0x20, 0xcf]);
+
+        let mut machine = Machine::with_rom(&rom(&code), false);
+        machine.register.sp = 0x400;
+        machine.memory.write16(0x08 * 4, 0xff30);
+        machine.memory.write16(0x08 * 4 + 2, 0xf000);
+        machine.run();
+        assert_eq!(machine.register.get_bx(), 0x1234);
+        // The handler acknowledged the interrupt
+        assert!(!machine.board.pic.borrow().has_interrupt());
+    }
+
+    #[test_case(Cpu::I8088, 0x0000, 0xff ; "8088 shifts by the full count")]
+    #[test_case(Cpu::I80186, 0x0002, 0xff ; "80186 masks the count")]
+    #[test_case(Cpu::V20, 0x0002, 0x00 ; "V20 decodes D6 as XLAT")]
+    fn test_cpu_quirks(cpu: Cpu, bx: u16, al: u8) {
+        #[rustfmt::skip]
+        let code = [
+            0xb8, 0x01, 0x00, // MOV AX, 0001
+            0xb1, 0x21,       // MOV CL, 21
+            0xd3, 0xe0,       // SHL AX, CL
+            0x89, 0xc3,       // MOV BX, AX
+            0x54,             // PUSH SP
+            0x5a,             // POP DX
+            0xf9,             // STC
+            0xd6,             // SALC
+            0xf4,             // HLT
+        ];
+        let mut machine = Machine::with_rom(&rom(&code), false);
+        machine.set_cpu(cpu);
+        machine.register.sp = 0x400;
+        machine.run();
+        assert_eq!(machine.register.get_bx(), bx);
+        assert_eq!(machine.register.get_dx(), 0x3fe);
+        assert_eq!(machine.register.al, al);
+    }
+
+    #[test_case(Cpu::I8086, 0x0000 ; "8086 jump alias")]
+    #[test_case(Cpu::I80186, 0x1234 ; "80186 invalid opcode")]
+    fn test_opcode_64(cpu: Cpu, bx: u16) {
+        // XOR AX, AX; JZ +2 (64 02); MOV BL, 1; HLT, and an INT 6 handler MOV BX, 1234; HLT
+        let mut code = vec![0x31, 0xc0, 0x64, 0x02, 0xb3, 0x01, 0xf4];
+        code.resize(0x10, 0x90);
+        code.extend_from_slice(&[0xbb, 0x34, 0x12, 0xf4]);
+        let mut machine = Machine::with_rom(&rom(&code), false);
+        machine.set_cpu(cpu);
+        machine.register.sp = 0x400;
+        machine.memory.write16(6 * 4, 0xff10);
+        machine.memory.write16(6 * 4 + 2, 0xf000);
+        machine.run();
+        assert_eq!(machine.register.get_bx(), bx);
+    }
+
+    #[test_case(Cpu::I8086, 0x0000 ; "8086 stops")]
+    #[test_case(Cpu::I80186, 0x1234 ; "80186 invalid opcode")]
+    fn test_undefined_group_member(cpu: Cpu, bx: u16) {
+        // FF /7 [BX], and an INT 6 handler MOV BX, 1234; HLT
+        let mut code = vec![0xff, 0x3f, 0xf4];
+        code.resize(0x10, 0x90);
+        code.extend_from_slice(&[0xbb, 0x34, 0x12, 0xf4]);
+        let mut machine = Machine::with_rom(&rom(&code), false);
+        machine.set_cpu(cpu);
+        machine.register.sp = 0x400;
+        machine.memory.write16(6 * 4, 0xff10);
+        machine.memory.write16(6 * 4 + 2, 0xf000);
+        machine.run();
+        assert_eq!(machine.register.get_bx(), bx);
+    }
+
+    #[test]
+    fn test_undocumented_opcodes() {
+        #[rustfmt::skip]
+        let mut code = vec![
+            0xb0, 0x01,                   // MOV AL, 01
+            0x82, 0xc0, 0x05,             // ADD AL, 05
+            0xf1, 0x90,                   // LOCK NOP
+            0xe8, 0x06, 0x00,             // CALL FF10
+            0x9a, 0x18, 0xff, 0x00, 0xf0, // CALL F000:FF18
+            0xf4,                         // HLT
+            0xbb, 0x34, 0x12,             // MOV BX, 1234
+            0xc1,                         // RET
+        ];
+        code.resize(0x18, 0x90);
+        #[rustfmt::skip]
+        code.extend_from_slice(&[
+            0xb9, 0x78, 0x56, // MOV CX, 5678
+            0xc9,             // RETF
+        ]);
+        let mut machine = Machine::with_rom(&rom(&code), false);
+        machine.register.sp = 0x400;
+        machine.run();
+        assert_eq!(machine.register.al, 0x06);
+        assert_eq!(machine.register.get_bx(), 0x1234);
+        assert_eq!(machine.register.get_cx(), 0x5678);
+        assert_eq!(machine.register.sp, 0x400);
+    }
+
+    #[test_case(Engine::Interpreter ; "interpreter")]
+    #[test_case(Engine::Threaded ; "threaded")]
+    fn test_self_modifying_code(engine: Engine) {
+        #[rustfmt::skip]
+        let code = [
+            0xbb, 0x01, 0x00,             // 0500: MOV BX, 0001
+            0xc6, 0x06, 0x01, 0x05, 0x02, // MOV Byte [0501], 02
+            0x41,                         // INC CX
+            0x83, 0xf9, 0x02,             // CMP CX, 2
+            0x75, 0xf2,                   // JNZ 0500
+            0xf4,                         // HLT
+        ];
+        // JMP 0000:0500
+        let mut machine = Machine::with_rom(&rom(&[0xea, 0x00, 0x05, 0x00, 0x00]), false);
+        machine.set_engine(engine);
+        machine.memory.load(0x500, &code);
+        machine.run();
+        // The second pass runs the patched immediate
+        assert_eq!(machine.register.get_bx(), 0x0002);
+        assert_eq!(machine.register.get_cx(), 0x0002);
+        assert_eq!(machine.instructions(), 13);
+        // Nothing cached still holds the bytes from before the write
+        let cached = machine
+            .decoded
+            .iter()
+            .map(|(&key, op)| (key, op))
+            .chain(machine.blocks.iter().flat_map(|(&(cs, _), block)| {
+                block.iter().map(move |(_, op)| ((cs, op.pos as u16), op))
+            }));
+        for ((cs, ip), op) in cached {
+            let addr = physical_address(cs, ip);
+            assert_eq!(*op.raws, *machine.memory.bytes(addr..addr + op.raws.len()));
+        }
+    }
+
+    #[test]
+    fn test_186_instructions() {
+        #[rustfmt::skip]
+        let code = [
+            0xb8, 0x03, 0x00,       // MOV AX, 0003
+            0x6b, 0xd8, 0x07,       // IMUL BX, AX, 7
+            0xc1, 0xe3, 0x02,       // SHL BX, 2
+            0x6a, 0xfe,             // PUSH -2
+            0x59,                   // POP CX
+            0x60,                   // PUSHA
+            0x31, 0xc0,             // XOR AX, AX
+            0x61,                   // POPA
+            0xc8, 0x10, 0x00, 0x01, // ENTER 0010, 1
+            0x89, 0xea,             // MOV DX, BP
+            0xc9,                   // LEAVE
+            0xf4,                   // HLT
+        ];
+        let mut machine = Machine::with_rom(&rom(&code), false);
+        machine.set_cpu(Cpu::I80186);
+        machine.register.sp = 0x400;
+        machine.run();
+        assert_eq!(machine.register.get_ax(), 3);
+        assert_eq!(machine.register.get_bx(), 84);
+        assert_eq!(machine.register.get_cx(), 0xfffe);
+        assert_eq!(machine.register.get_dx(), 0x3fe);
+        assert_eq!(machine.register.sp, 0x400);
+    }
+
+    #[test]
+    fn test_assembled_program() {
+        let source = "
+                    MOV SI, table
+                    MOV CX, count
+                    XOR AX, AX
+            next:   ADD AL, [SI]
+                    INC SI
+                    LOOP next
+                    MOV [sum], AX
+                    HLT
+            .data
+            table:  db 1, 2, 3, 4
+            .equ count, . - table
+            .bss
+            sum:    .space 2
+        ";
+        let executable = assembler::assemble(source, Cpu::I8086).unwrap();
+        let mut machine = Machine::new(&executable, &["a.out".to_string()], &[], false);
+        machine.run();
+        assert_eq!(machine.register.get_ax(), 10);
+        assert_eq!(machine.register.get_cx(), 0);
+        assert_eq!(machine.memory.read16(4), 10);
+    }
+
+    // MOV AX, 1234 straddling the end of the segment, or of memory with CS=FFFF
+    #[test_case(0x1000, 0xfffe ; "segment")]
+    #[test_case(0xffff, 0x000e ; "memory")]
+    fn test_fetch_wraps(cs: u16, ip: u16) {
+        let [ip_low, ip_high] = ip.to_le_bytes();
+        let [cs_low, cs_high] = cs.to_le_bytes();
+        let mut rom = rom(&[0xea, ip_low, ip_high, cs_low, cs_high]);
+        let mut ram = Vec::new();
+        for (i, byte) in [0xb8, 0x34, 0x12, 0xf4].into_iter().enumerate() {
+            let addr = physical_address(cs, ip.wrapping_add(i as u16));
+            match addr.checked_sub(0xf_ff00) {
+                Some(offset) => rom[offset] = byte,
+                None => ram.push((addr, byte)),
+            }
+        }
+        let mut machine = Machine::with_rom(&rom, false);
+        for (addr, byte) in ram {
+            machine.memory.write8(addr, byte);
+        }
+        machine.run();
+        assert_eq!(machine.register.get_ax(), 0x1234);
+    }
+
+    #[test]
+    fn test_text_cut_short() {
+        // MOV AX, imm16 without its immediate at the end of the text
+        let executable = assembler::assemble("MOV AX, 1\ndb b8, 34", Cpu::I8086).unwrap();
+        let mut machine = Machine::new(&executable, &["a.out".to_string()], &[], false);
+        machine.run();
+        assert_eq!(machine.register.get_ax(), 1);
+    }
+
+    #[test]
+    fn test_empty_data_segment() {
+        let executable = assembler::assemble("MOV AX, 1\nHLT", Cpu::I8086).unwrap();
+        let mut machine = Machine::new(&executable, &["a.out".to_string()], &[], false);
+        machine.run();
+        assert_eq!(machine.register.get_ax(), 1);
+    }
+}
@@ -1,14 +1,32 @@
 use std::io::Read;
 
 mod args;
+mod assembler;
+mod bios;
+mod cpu;
 mod disassembler;
 mod dump;
+mod f80;
 mod flag;
+mod fpu;
+mod graph;
+mod keyboard;
 mod machine;
+mod memory;
 mod message;
 mod metadata;
+mod opcode;
 mod operation;
+mod patch;
+mod pic;
+mod pit;
+mod port;
 mod register;
+mod speaker;
+mod throttle;
+mod timing;
+mod uart;
+mod video;
 
 fn main() {
     let config = match args::parse_args() {
@@ -19,6 +37,11 @@ fn main() {
         }
     };
 
+    if config.mode == args::AppMode::Assemble {
+        assemble(&config);
+        return;
+    }
+
     let mut stream = match std::fs::File::open(&config.target) {
         Ok(file) => std::io::BufReader::new(file),
         Err(e) => {
@@ -32,14 +55,146 @@ fn main() {
         .read_to_end(&mut executable)
         .expect("Failed to read executable file");
 
-    match config.mode {
-        args::AppMode::Disassemble => {
-            disassembler::disassemble(&executable, true);
+    if config.mode == args::AppMode::Patch {
+        patch(&config, &executable);
+        return;
+    }
+
+    if config.mode == args::AppMode::Disassemble {
+        if let Some(graph) = config.graph {
+            print!("{}", graph::export(&executable, graph, config.cpu));
+        } else if config.recursive {
+            disassembler::disassemble_recursive(&executable, true, config.cpu);
+        } else {
+            disassembler::disassemble(&executable, true, config.cpu);
         }
-        args::AppMode::Execute => {
-            let mut machine =
-                machine::Machine::new(&executable, &config.argv, &config.envs, config.debug);
+        return;
+    }
+
+    if config.bench {
+        // Runs the program once on each engine, so its side effects happen
+        // twice: only for programs that do not write files or devices
+        for engine in [machine::Engine::Interpreter, machine::Engine::Threaded] {
+            let mut machine = build(&config, &executable);
+            machine.set_engine(engine);
+            let start = std::time::Instant::now();
             machine.run();
+            let seconds = start.elapsed().as_secs_f64();
+            eprintln!(
+                "{:?}: {} instructions in {:.3} s, {:.0} instructions/s",
+                engine,
+                machine.instructions(),
+                seconds,
+                machine.instructions() as f64 / seconds
+            );
+        }
+        return;
+    }
+
+    let mut machine = build(&config, &executable);
+    machine.set_engine(config.engine);
+    machine.run();
+    if config.clocks || config.speed.is_some() {
+        eprintln!(
+            "{} clocks, {:.2} MHz effective",
+            machine.clocks(),
+            machine.effective_mhz()
+        );
+    }
+}
+
+// Creates the machine for the selected mode with its devices attached
+fn build(config: &args::ArgsConfig, executable: &[u8]) -> machine::Machine {
+    let mut machine = match config.mode {
+        args::AppMode::Disassemble | args::AppMode::Assemble | args::AppMode::Patch => {
+            unreachable!()
+        }
+        args::AppMode::Execute => {
+            machine::Machine::new(executable, &config.argv, &config.envs, config.debug)
+        }
+        args::AppMode::Boot => {
+            let disk = bios::Disk::new(&config.target, executable.to_vec());
+            machine::Machine::boot(disk, config.debug)
+        }
+        args::AppMode::Rom => machine::Machine::with_rom(executable, config.debug),
+    };
+    machine.set_cpu(config.cpu);
+    if let Some(hz) = config.speed {
+        machine.throttle(hz);
+    }
+    if config.video.is_some() || config.screen_dump.is_some() {
+        if config.mode == args::AppMode::Execute {
+            eprintln!("--video and --screen-dump need --boot or --bios");
+            std::process::exit(1);
+        }
+        let adapter = config.video.unwrap_or(video::Adapter::Cga);
+        machine.attach_video(adapter, config.video.is_some(), config.screen_dump.clone());
+    }
+    if let Some(path) = &config.speaker {
+        if config.mode == args::AppMode::Execute {
+            eprintln!("--speaker needs --boot or --bios");
+            std::process::exit(1);
+        }
+        machine.capture_speaker(path);
+    }
+    for &addr in &config.watch {
+        machine.watch(addr);
+    }
+    if config.mode != args::AppMode::Execute {
+        let source = match &config.keys {
+            Some(path) => {
+                let script = std::fs::read_to_string(path)
+                    .map_err(|e| e.to_string())
+                    .and_then(|text| keyboard::KeySource::script(&text));
+                Some(script.unwrap_or_else(|e| {
+                    eprintln!("Failed to load key script {}: {}", path, e);
+                    std::process::exit(1);
+                }))
+            }
+            // A serial console on stdio gets the terminal instead
+            None if config.serial.iter().all(|(_, spec)| spec != "stdio") => {
+                Some(keyboard::KeySource::stdin())
+            }
+            None => None,
+        };
+        if let Some(source) = source {
+            machine.attach_keyboard(source);
         }
     }
+    for (com, spec) in &config.serial {
+        let backend = uart::Backend::open(spec).unwrap_or_else(|e| {
+            eprintln!("Failed to open serial backend: {}", e);
+            std::process::exit(1);
+        });
+        machine.attach_serial(*com, backend);
+    }
+    machine
+}
+
+// Assembles the target source into the executable named by -o
+fn assemble(config: &args::ArgsConfig) {
+    let source = std::fs::read_to_string(&config.target).unwrap_or_else(|e| {
+        eprintln!("Failed to read source file: {}", e);
+        std::process::exit(1);
+    });
+    let executable = assembler::assemble(&source, config.cpu).unwrap_or_else(|e| {
+        eprintln!("{}: {}", config.target, e);
+        std::process::exit(1);
+    });
+    if let Err(e) = std::fs::write(&config.output, executable) {
+        eprintln!("Failed to write {}: {}", config.output, e);
+        std::process::exit(1);
+    }
+}
+
+// Writes a copy of the target executable with the edits applied
+fn patch(config: &args::ArgsConfig, executable: &[u8]) {
+    let patched = patch::patch(executable, &config.edits, config.cpu).unwrap_or_else(|e| {
+        eprintln!("{}: {}", config.target, e);
+        std::process::exit(1);
+    });
+    if let Err(e) = std::fs::write(&config.output, patched) {
+        eprintln!("Failed to write {}: {}", config.output, e);
+        std::process::exit(1);
+    }
 }
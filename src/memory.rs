@@ -0,0 +1,267 @@
+use std::{cell::RefCell, ops::Range, rc::Rc};
+
+// 20 address lines
+pub const MEMORY_SIZE: usize = 0x10_0000;
+pub const ADDRESS_MASK: usize = MEMORY_SIZE - 1;
+
+// Segment and offset to a physical address, wrapping around at 1 MiB
+pub fn physical_address(segment: u16, offset: u16) -> usize {
+    (((segment as usize) << 4) + offset as usize) & ADDRESS_MASK
+}
+
+// Physical memory as seen by the CPU. Word accesses are little endian.
+pub trait MemoryBus {
+    fn read8(&self, addr: usize) -> u8;
+
+    fn write8(&mut self, addr: usize, value: u8);
+
+    // A word at the very top of memory wraps around to address 0
+    fn read16(&self, addr: usize) -> u16 {
+        u16::from_le_bytes([self.read8(addr), self.read8((addr + 1) & ADDRESS_MASK)])
+    }
+
+    fn write16(&mut self, addr: usize, value: u16) {
+        let [low, high] = value.to_le_bytes();
+        self.write8(addr, low);
+        self.write8((addr + 1) & ADDRESS_MASK, high);
+    }
+}
+
+// A device decoding a window of the address space, addressed by offset into its region
+pub trait Mmio {
+    fn read8(&self, offset: usize) -> u8;
+
+    fn write8(&mut self, offset: usize, value: u8);
+}
+
+// Lets a device that also decodes ports be mapped into memory
+impl<T: Mmio> Mmio for Rc<RefCell<T>> {
+    fn read8(&self, offset: usize) -> u8 {
+        self.borrow().read8(offset)
+    }
+
+    fn write8(&mut self, offset: usize, value: u8) {
+        self.borrow_mut().write8(offset, value)
+    }
+}
+
+enum Region {
+    Rom,
+    Mmio(Box<dyn Mmio>),
+}
+
+// Called for every byte read or written, after MMIO reads and before writes land
+pub type Hook = Box<dyn Fn(&Access)>;
+
+#[derive(Debug)]
+pub struct Access {
+    pub addr: usize,
+    pub value: u8,
+    pub is_write: bool,
+}
+
+// RAM by default, with ROM and MMIO regions mapped on top
+pub struct Memory {
+    ram: Vec<u8>,
+    regions: Vec<(Range<usize>, Region)>,
+    hooks: Vec<Hook>,
+    // Bytes of instructions the CPU has cached, and whether one was written since
+    code: Vec<bool>,
+    code_written: bool,
+}
+
+impl Memory {
+    pub fn new(size: usize) -> Self {
+        Memory {
+            ram: vec![0; size],
+            regions: Vec::new(),
+            hooks: Vec::new(),
+            code: vec![false; size],
+            code_written: false,
+        }
+    }
+
+    pub fn size(&self) -> usize {
+        self.ram.len()
+    }
+
+    // Copies an image into the backing store, ignoring ROM protection
+    pub fn load(&mut self, addr: usize, data: &[u8]) {
+        if data.is_empty() {
+            return;
+        }
+        self.check(addr + data.len() - 1);
+        self.ram[addr..addr + data.len()].copy_from_slice(data);
+        if self.code[addr..addr + data.len()].contains(&true) {
+            self.code_written = true;
+        }
+    }
+
+    pub fn map_rom(&mut self, addr: usize, data: &[u8]) {
+        self.load(addr, data);
+        self.map(addr..addr + data.len(), Region::Rom);
+    }
+
+    pub fn map_mmio(&mut self, range: Range<usize>, device: Box<dyn Mmio>) {
+        self.map(range, Region::Mmio(device));
+    }
+
+    pub fn add_hook(&mut self, hook: Hook) {
+        self.hooks.push(hook);
+    }
+
+    // Backing store contents, used to decode instructions without going through devices
+    pub fn bytes(&self, range: Range<usize>) -> &[u8] {
+        let end = range.end.min(self.ram.len());
+        &self.ram[range.start..end]
+    }
+
+    // Marks decoded instruction bytes, so that writes to them can be detected
+    pub fn mark_code(&mut self, range: Range<usize>) {
+        let end = range.end.min(self.code.len());
+        let start = range.start.min(end);
+        self.code[start..end].fill(true);
+    }
+
+    // Whether marked code was written since the last call. Clears the marks if so.
+    pub fn take_code_written(&mut self) -> bool {
+        if !self.code_written {
+            return false;
+        }
+        self.code_written = false;
+        self.code.fill(false);
+        true
+    }
+
+    fn map(&mut self, range: Range<usize>, region: Region) {
+        self.check(range.end - 1);
+        if self
+            .regions
+            .iter()
+            .any(|(mapped, _)| mapped.start < range.end && range.start < mapped.end)
+        {
+            panic!(
+                "Memory region {:05x}-{:05x} overlaps an existing mapping",
+                range.start,
+                range.end - 1
+            );
+        }
+        self.regions.push((range, region));
+    }
+
+    fn region(&self, addr: usize) -> Option<(usize, &Region)> {
+        self.regions
+            .iter()
+            .find(|(range, _)| range.contains(&addr))
+            .map(|(range, region)| (addr - range.start, region))
+    }
+
+    fn check(&self, addr: usize) {
+        if addr >= self.ram.len() {
+            panic!("Memory access out of bounds at address {}", addr);
+        }
+    }
+
+    fn notify(&self, addr: usize, value: u8, is_write: bool) {
+        if self.hooks.is_empty() {
+            return;
+        }
+        let access = Access {
+            addr,
+            value,
+            is_write,
+        };
+        for hook in &self.hooks {
+            hook(&access);
+        }
+    }
+}
+
+impl MemoryBus for Memory {
+    fn read8(&self, addr: usize) -> u8 {
+        self.check(addr);
+        let value = match self.region(addr) {
+            Some((offset, Region::Mmio(device))) => device.read8(offset),
+            _ => self.ram[addr],
+        };
+        self.notify(addr, value, false);
+        value
+    }
+
+    fn write8(&mut self, addr: usize, value: u8) {
+        self.check(addr);
+        self.notify(addr, value, true);
+        let index = self
+            .regions
+            .iter()
+            .position(|(range, _)| range.contains(&addr));
+        match index.map(|i| &mut self.regions[i]) {
+            // Writes to ROM are silently dropped, as on real hardware
+            Some((_, Region::Rom)) => {}
+            Some((range, Region::Mmio(device))) => device.write8(addr - range.start, value),
+            None => {
+                self.ram[addr] = value;
+                if self.code[addr] {
+                    self.code_written = true;
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::{cell::Cell, rc::Rc};
+
+    struct Latch(u8);
+
+    impl Mmio for Latch {
+        fn read8(&self, _offset: usize) -> u8 {
+            self.0
+        }
+
+        fn write8(&mut self, offset: usize, value: u8) {
+            self.0 = value.wrapping_add(offset as u8);
+        }
+    }
+
+    #[test]
+    fn test_regions() {
+        let mut memory = Memory::new(0x100);
+        memory.map_rom(0xf0, &[0xaa; 0x10]);
+        memory.map_mmio(0x80..0x90, Box::new(Latch(0)));
+
+        memory.write16(0x10, 0x1234);
+        memory.write8(0xf4, 0x55);
+        memory.write8(0x82, 0x40);
+
+        assert_eq!(memory.read16(0x10), 0x1234);
+        assert_eq!(memory.read8(0xf4), 0xaa);
+        assert_eq!(memory.read8(0x8f), 0x42);
+    }
+
+    #[test]
+    fn test_hooks() {
+        let mut memory = Memory::new(0x10);
+        let writes = Rc::new(Cell::new(0));
+        let counter = writes.clone();
+        memory.add_hook(Box::new(move |access| {
+            if access.is_write {
+                counter.set(counter.get() + 1);
+            }
+        }));
+        memory.write16(0, 0xbeef);
+        memory.read8(0);
+        assert_eq!(writes.get(), 2);
+    }
+
+    #[test]
+    fn test_word_wraps_around() {
+        let mut memory = Memory::new(ADDRESS_MASK + 1);
+        memory.write16(ADDRESS_MASK, 0x1234);
+        assert_eq!(memory.read8(ADDRESS_MASK), 0x34);
+        assert_eq!(memory.read8(0), 0x12);
+        assert_eq!(memory.read16(ADDRESS_MASK), 0x1234);
+    }
+}
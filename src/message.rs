@@ -1,3 +1,5 @@
+use crate::memory::MemoryBus;
+
 #[allow(unused)]
 #[derive(Debug)]
 pub struct Message {
@@ -14,9 +16,9 @@ pub struct Detail1 {
 pub const MESSAGE_SIZE: usize = 2 * 2;
 
 impl Message {
-    pub fn load(data: &[u8], offset: usize) -> Self {
-        let source = u16::from_le_bytes([data[offset], data[offset + 1]]);
-        let message_type = u16::from_le_bytes([data[offset + 2], data[offset + 3]]);
+    pub fn load(memory: &dyn MemoryBus, offset: usize) -> Self {
+        let source = memory.read16(offset);
+        let message_type = memory.read16(offset + 2);
         Message {
             offset,
             source,
@@ -24,14 +26,8 @@ impl Message {
         }
     }
 
-    pub fn load_detail1(&self, data: &[u8]) -> Detail1 {
-        let mut detail = [0u16; 6];
-        for i in 0..6 {
-            detail[i] = u16::from_le_bytes([
-                data[self.offset + MESSAGE_SIZE + i * 2],
-                data[self.offset + MESSAGE_SIZE + i * 2 + 1],
-            ]);
-        }
+    pub fn load_detail1(&self, memory: &dyn MemoryBus) -> Detail1 {
+        let detail = std::array::from_fn(|i| memory.read16(self.offset + MESSAGE_SIZE + i * 2));
         Detail1 { detail }
     }
 }
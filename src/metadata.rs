@@ -1,3 +1,8 @@
+// Separate instruction and data spaces
+const A_SEP: u8 = 0x20;
+const A_I8086: u8 = 0x04;
+const HEADER_LENGTH: u8 = 32;
+
 #[allow(unused)]
 #[derive(Debug)]
 pub struct Metadata {
@@ -16,7 +21,7 @@ pub struct Metadata {
 }
 
 impl Metadata {
-    pub fn from_bytes(executable: &Vec<u8>) -> Self {
+    pub fn from_bytes(executable: &[u8]) -> Self {
         if executable.len() < 32 {
             panic!("File too short to contain metadata");
         }
@@ -36,4 +41,119 @@ impl Metadata {
             syms: u32::from_le_bytes([data[28], data[29], data[30], data[31]]) as usize,
         }
     }
+
+    // A header for separate instruction and data spaces, as the MINIX linker writes it
+    pub fn new(
+        text_size: usize,
+        data_size: usize,
+        bss_size: usize,
+        total: usize,
+        syms: usize,
+    ) -> Self {
+        Metadata {
+            magic: [0x01, 0x03],
+            flags: A_SEP,
+            cpu: A_I8086,
+            hdr_len: HEADER_LENGTH,
+            unused: 0,
+            version: 0,
+            text_size,
+            data_size,
+            bss_size,
+            entry: 0,
+            total,
+            syms,
+        }
+    }
+
+    // Where the text, data and symbol table start in the file
+    pub fn text_offset(&self) -> usize {
+        self.hdr_len as usize
+    }
+
+    pub fn data_offset(&self) -> usize {
+        self.text_offset() + self.text_size
+    }
+
+    pub fn symbols_offset(&self) -> usize {
+        self.data_offset() + self.data_size
+    }
+
+    pub fn symbols(&self, executable: &[u8]) -> Vec<Symbol> {
+        let start = self.symbols_offset().min(executable.len());
+        let end = (start + self.syms).min(executable.len());
+        executable[start..end]
+            .chunks_exact(Symbol::SIZE)
+            .map(Symbol::from_bytes)
+            .collect()
+    }
+
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = vec![
+            self.magic[0],
+            self.magic[1],
+            self.flags,
+            self.cpu,
+            self.hdr_len,
+            self.unused,
+        ];
+        bytes.extend_from_slice(&self.version.to_le_bytes());
+        for field in [
+            self.text_size,
+            self.data_size,
+            self.bss_size,
+            self.entry,
+            self.total,
+            self.syms,
+        ] {
+            bytes.extend_from_slice(&(field as u32).to_le_bytes());
+        }
+        bytes
+    }
+}
+
+// Section of a symbol, in the low bits of its storage class
+pub const N_TEXT: u8 = 0o2;
+pub const N_DATA: u8 = 0o3;
+pub const N_BSS: u8 = 0o4;
+pub const N_SECT: u8 = 0o7;
+// A symbol local to its file
+pub const C_STAT: u8 = 0o30;
+
+// An entry of the symbol table after the data segment (struct nlist)
+pub struct Symbol {
+    pub name: String,
+    pub value: u32,
+    pub sclass: u8,
+}
+
+impl Symbol {
+    pub const SIZE: usize = 16;
+
+    pub fn from_bytes(bytes: &[u8]) -> Self {
+        let name = bytes[..8]
+            .iter()
+            .take_while(|&&b| b != 0)
+            .map(|&b| b as char);
+        Symbol {
+            name: name.collect(),
+            value: u32::from_le_bytes([bytes[8], bytes[9], bytes[10], bytes[11]]),
+            sclass: bytes[12],
+        }
+    }
+
+    pub fn section(&self) -> u8 {
+        self.sclass & N_SECT
+    }
+
+    pub fn to_bytes(&self) -> [u8; Self::SIZE] {
+        let mut bytes = [0; Self::SIZE];
+        // Names are cut to 8 bytes and padded with NULs
+        for (i, byte) in self.name.bytes().take(8).enumerate() {
+            bytes[i] = byte;
+        }
+        bytes[8..12].copy_from_slice(&self.value.to_le_bytes());
+        bytes[12] = self.sclass;
+        bytes
+    }
 }
@@ -0,0 +1,718 @@
+use crate::{cpu::Cpu, operation::OperationType};
+use Form as F;
+use OperationType as O;
+
+// How the bytes after an opcode are laid out, which operands they name and
+// how the disassembly prints them
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Form {
+    // No operands
+    None,
+    // Register/Memory and Register, the register first if d is set
+    RmReg,
+    // Register/Memory and Immediate
+    RmImm,
+    // Accumulator and Immediate
+    AccImm,
+    // Register in the opcode and Immediate
+    RegImm,
+    // Accumulator and Direct Memory
+    AccMem,
+    MemAcc,
+    // Register/Memory and Segment Register
+    RmSeg,
+    SegRm,
+    // Register in the opcode
+    Reg,
+    // Register in the opcode and Accumulator
+    RegAcc,
+    // Segment Register in the opcode
+    Seg,
+    // Register/Memory
+    Rm,
+    // Register and Memory
+    RegMem,
+    // Fixed and variable ports
+    InPort,
+    InDx,
+    OutPort,
+    OutDx,
+    // String instruction in the next byte
+    Rep,
+    // Direct within Segment
+    Near,
+    // Direct within Segment-Short, printed as such
+    Short,
+    // Conditional jumps and loops
+    Rel8,
+    // Direct Intersegment
+    Far,
+    // Return adding Immediate to SP
+    RetImm,
+    // Type Specified, Type 3
+    Int,
+    Int3,
+    // Coprocessor instruction
+    Esc,
+    // Segment Override Prefix
+    Segment,
+    // AAM and AAD with their base 10
+    Base,
+    // Shift/Rotate by 1 or CL, by Immediate
+    Shift,
+    ShiftImm,
+    // 80186 PUSH, IMUL and ENTER immediates
+    PushImm,
+    ImulImm,
+    Enter,
+}
+
+impl Form {
+    pub fn has_mod_rm(self) -> bool {
+        matches!(
+            self,
+            Form::RmReg
+                | Form::RmImm
+                | Form::RmSeg
+                | Form::SegRm
+                | Form::Rm
+                | Form::RegMem
+                | Form::Esc
+                | Form::Shift
+                | Form::ShiftImm
+                | Form::ImulImm
+        )
+    }
+}
+
+// Operations picked by the reg field of the ModRM byte
+pub type Group = [Option<(OperationType, Form)>; 8];
+
+#[derive(Debug, Clone, Copy)]
+pub enum Instruction {
+    Undefined,
+    Op(OperationType, Form),
+    Group(&'static Group),
+}
+
+// Opcode bits holding the direction, width, sign extension, count and
+// zero flags, for the opcodes that have them
+#[derive(Debug, Clone, Copy)]
+pub struct Bits {
+    pub d: Option<u8>,
+    pub w: Option<u8>,
+    pub s: Option<u8>,
+    pub v: Option<u8>,
+    pub z: Option<u8>,
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct Entry {
+    pub instruction: Instruction,
+    pub bits: Bits,
+    // Missing from Intel's tables, but executed by the chip
+    pub undocumented: bool,
+}
+
+impl Entry {
+    const fn new(instruction: Instruction) -> Self {
+        Entry {
+            instruction,
+            bits: Bits {
+                d: None,
+                w: None,
+                s: None,
+                v: None,
+                z: None,
+            },
+            undocumented: false,
+        }
+    }
+
+    // Operand forms that start with a ModRM byte, groups included
+    pub fn has_mod_rm(&self) -> bool {
+        match self.instruction {
+            Instruction::Undefined => false,
+            Instruction::Op(_, form) => form.has_mod_rm(),
+            Instruction::Group(_) => true,
+        }
+    }
+
+    const fn d(mut self, bit: u8) -> Self {
+        self.bits.d = Some(bit);
+        self
+    }
+
+    const fn w(mut self, bit: u8) -> Self {
+        self.bits.w = Some(bit);
+        self
+    }
+
+    const fn s(mut self, bit: u8) -> Self {
+        self.bits.s = Some(bit);
+        self
+    }
+
+    const fn v(mut self, bit: u8) -> Self {
+        self.bits.v = Some(bit);
+        self
+    }
+
+    const fn z(mut self, bit: u8) -> Self {
+        self.bits.z = Some(bit);
+        self
+    }
+
+    const fn undocumented(mut self) -> Self {
+        self.undocumented = true;
+        self
+    }
+}
+
+const fn op(operation_type: OperationType, form: Form) -> Entry {
+    Entry::new(Instruction::Op(operation_type, form))
+}
+
+const fn group(group: &'static Group) -> Entry {
+    Entry::new(Instruction::Group(group))
+}
+
+const UNDEFINED: Entry = Entry::new(Instruction::Undefined);
+
+// Add/Or/Adc/Sbb/And/Sub/Xor/Cmp
+static IMMEDIATE: Group = [
+    Some((O::Add, F::RmImm)),
+    Some((O::Or, F::RmImm)),
+    Some((O::Adc, F::RmImm)),
+    Some((O::Sbb, F::RmImm)),
+    Some((O::And, F::RmImm)),
+    Some((O::Sub, F::RmImm)),
+    Some((O::Xor, F::RmImm)),
+    Some((O::Cmp, F::RmImm)),
+];
+
+const fn shifts(form: Form) -> Group {
+    [
+        Some((O::Rol, form)),
+        Some((O::Ror, form)),
+        Some((O::Rcl, form)),
+        Some((O::Rcr, form)),
+        Some((O::ShlSal, form)),
+        Some((O::Shr, form)),
+        None,
+        Some((O::Sar, form)),
+    ]
+}
+
+static SHIFT: Group = shifts(F::Shift);
+static SHIFT_IMMEDIATE: Group = shifts(F::ShiftImm);
+
+// Test/Not/Neg/Mul/Imul/Div/Idiv
+static UNARY: Group = [
+    Some((O::Test, F::RmImm)),
+    None,
+    Some((O::Not, F::Rm)),
+    Some((O::Neg, F::Rm)),
+    Some((O::Mul, F::Rm)),
+    Some((O::Imul, F::Rm)),
+    Some((O::Div, F::Rm)),
+    Some((O::Idiv, F::Rm)),
+];
+
+static INC_DEC: Group = [
+    Some((O::Inc, F::Rm)),
+    Some((O::Dec, F::Rm)),
+    None,
+    None,
+    None,
+    None,
+    None,
+    None,
+];
+
+// Inc/Dec/Call/Jmp/Push
+static INDIRECT: Group = [
+    Some((O::Inc, F::Rm)),
+    Some((O::Dec, F::Rm)),
+    Some((O::Call, F::Rm)),
+    Some((O::Call, F::Rm)),
+    Some((O::Jmp, F::Rm)),
+    Some((O::Jmp, F::Rm)),
+    Some((O::Push, F::Rm)),
+    None,
+];
+
+static POP: Group = [
+    Some((O::Pop, F::Rm)),
+    None,
+    None,
+    None,
+    None,
+    None,
+    None,
+    None,
+];
+
+// The 8086, its undocumented encodings included
+static OPCODES: [Entry; 256] = [
+    // 00
+    op(O::Add, F::RmReg).d(1).w(0),
+    op(O::Add, F::RmReg).d(1).w(0),
+    op(O::Add, F::RmReg).d(1).w(0),
+    op(O::Add, F::RmReg).d(1).w(0),
+    op(O::Add, F::AccImm).w(0),
+    op(O::Add, F::AccImm).w(0),
+    op(O::Push, F::Seg),
+    op(O::Pop, F::Seg),
+    op(O::Or, F::RmReg).d(1).w(0),
+    op(O::Or, F::RmReg).d(1).w(0),
+    op(O::Or, F::RmReg).d(1).w(0),
+    op(O::Or, F::RmReg).d(1).w(0),
+    op(O::Or, F::AccImm).w(0),
+    op(O::Or, F::AccImm).w(0),
+    op(O::Push, F::Seg),
+    op(O::Pop, F::Seg).undocumented(),
+    // 10
+    op(O::Adc, F::RmReg).d(1).w(0),
+    op(O::Adc, F::RmReg).d(1).w(0),
+    op(O::Adc, F::RmReg).d(1).w(0),
+    op(O::Adc, F::RmReg).d(1).w(0),
+    op(O::Adc, F::AccImm).w(0),
+    op(O::Adc, F::AccImm).w(0),
+    op(O::Push, F::Seg),
+    op(O::Pop, F::Seg),
+    op(O::Sbb, F::RmReg).d(1).w(0),
+    op(O::Sbb, F::RmReg).d(1).w(0),
+    op(O::Sbb, F::RmReg).d(1).w(0),
+    op(O::Sbb, F::RmReg).d(1).w(0),
+    // データシートの誤植
+    // https://qiita.com/7shi/items/b3911948f9d97b05395e#%E4%BB%95%E6%A7%98%E6%9B%B8
+    op(O::Sbb, F::AccImm).w(0),
+    op(O::Sbb, F::AccImm).w(0),
+    op(O::Push, F::Seg),
+    op(O::Pop, F::Seg),
+    // 20
+    op(O::And, F::RmReg).d(1).w(0),
+    op(O::And, F::RmReg).d(1).w(0),
+    op(O::And, F::RmReg).d(1).w(0),
+    op(O::And, F::RmReg).d(1).w(0),
+    op(O::And, F::AccImm).w(0),
+    op(O::And, F::AccImm).w(0),
+    op(O::Segment, F::Segment),
+    op(O::Daa, F::None),
+    op(O::Sub, F::RmReg).d(1).w(0),
+    op(O::Sub, F::RmReg).d(1).w(0),
+    op(O::Sub, F::RmReg).d(1).w(0),
+    op(O::Sub, F::RmReg).d(1).w(0),
+    op(O::Sub, F::AccImm).w(0),
+    op(O::Sub, F::AccImm).w(0),
+    op(O::Segment, F::Segment),
+    op(O::Das, F::None),
+    // 30
+    op(O::Xor, F::RmReg).d(1).w(0),
+    op(O::Xor, F::RmReg).d(1).w(0),
+    op(O::Xor, F::RmReg).d(1).w(0),
+    op(O::Xor, F::RmReg).d(1).w(0),
+    op(O::Xor, F::AccImm).w(0),
+    op(O::Xor, F::AccImm).w(0),
+    op(O::Segment, F::Segment),
+    op(O::Aaa, F::None),
+    op(O::Cmp, F::RmReg).d(1).w(0),
+    op(O::Cmp, F::RmReg).d(1).w(0),
+    op(O::Cmp, F::RmReg).d(1).w(0),
+    op(O::Cmp, F::RmReg).d(1).w(0),
+    op(O::Cmp, F::AccImm).w(0),
+    op(O::Cmp, F::AccImm).w(0),
+    op(O::Segment, F::Segment),
+    op(O::Aas, F::None),
+    // 40
+    op(O::Inc, F::Reg),
+    op(O::Inc, F::Reg),
+    op(O::Inc, F::Reg),
+    op(O::Inc, F::Reg),
+    op(O::Inc, F::Reg),
+    op(O::Inc, F::Reg),
+    op(O::Inc, F::Reg),
+    op(O::Inc, F::Reg),
+    op(O::Dec, F::Reg),
+    op(O::Dec, F::Reg),
+    op(O::Dec, F::Reg),
+    op(O::Dec, F::Reg),
+    op(O::Dec, F::Reg),
+    op(O::Dec, F::Reg),
+    op(O::Dec, F::Reg),
+    op(O::Dec, F::Reg),
+    // 50
+    op(O::Push, F::Reg),
+    op(O::Push, F::Reg),
+    op(O::Push, F::Reg),
+    op(O::Push, F::Reg),
+    op(O::Push, F::Reg),
+    op(O::Push, F::Reg),
+    op(O::Push, F::Reg),
+    op(O::Push, F::Reg),
+    op(O::Pop, F::Reg),
+    op(O::Pop, F::Reg),
+    op(O::Pop, F::Reg),
+    op(O::Pop, F::Reg),
+    op(O::Pop, F::Reg),
+    op(O::Pop, F::Reg),
+    op(O::Pop, F::Reg),
+    op(O::Pop, F::Reg),
+    // 60: the 8086 ignores bit 4, so these are the 70-7F jumps
+    op(O::Jo, F::Rel8).undocumented(),
+    op(O::Jno, F::Rel8).undocumented(),
+    op(O::JbJnae, F::Rel8).undocumented(),
+    op(O::JnbJae, F::Rel8).undocumented(),
+    op(O::JeJz, F::Rel8).undocumented(),
+    op(O::JneJnz, F::Rel8).undocumented(),
+    op(O::JbeJna, F::Rel8).undocumented(),
+    op(O::JnbeJa, F::Rel8).undocumented(),
+    op(O::Js, F::Rel8).undocumented(),
+    op(O::Jns, F::Rel8).undocumented(),
+    op(O::JpJpe, F::Rel8).undocumented(),
+    op(O::JnpJpo, F::Rel8).undocumented(),
+    op(O::JlJnge, F::Rel8).undocumented(),
+    op(O::JnlJge, F::Rel8).undocumented(),
+    op(O::JleJng, F::Rel8).undocumented(),
+    op(O::JnleJg, F::Rel8).undocumented(),
+    // 70
+    op(O::Jo, F::Rel8),
+    op(O::Jno, F::Rel8),
+    op(O::JbJnae, F::Rel8),
+    op(O::JnbJae, F::Rel8),
+    op(O::JeJz, F::Rel8),
+    op(O::JneJnz, F::Rel8),
+    op(O::JbeJna, F::Rel8),
+    op(O::JnbeJa, F::Rel8),
+    op(O::Js, F::Rel8),
+    op(O::Jns, F::Rel8),
+    op(O::JpJpe, F::Rel8),
+    op(O::JnpJpo, F::Rel8),
+    op(O::JlJnge, F::Rel8),
+    op(O::JnlJge, F::Rel8),
+    op(O::JleJng, F::Rel8),
+    op(O::JnleJg, F::Rel8),
+    // 80
+    group(&IMMEDIATE).s(1).w(0),
+    group(&IMMEDIATE).s(1).w(0),
+    group(&IMMEDIATE).s(1).w(0).undocumented(),
+    group(&IMMEDIATE).s(1).w(0),
+    op(O::Test, F::RmReg).w(0),
+    op(O::Test, F::RmReg).w(0),
+    op(O::Xchg, F::RmReg).w(0),
+    op(O::Xchg, F::RmReg).w(0),
+    op(O::Mov, F::RmReg).d(1).w(0),
+    op(O::Mov, F::RmReg).d(1).w(0),
+    op(O::Mov, F::RmReg).d(1).w(0),
+    op(O::Mov, F::RmReg).d(1).w(0),
+    op(O::Mov, F::RmSeg),
+    op(O::Lea, F::RegMem),
+    op(O::Mov, F::SegRm),
+    group(&POP),
+    // 90
+    op(O::Xchg, F::RegAcc),
+    op(O::Xchg, F::RegAcc),
+    op(O::Xchg, F::RegAcc),
+    op(O::Xchg, F::RegAcc),
+    op(O::Xchg, F::RegAcc),
+    op(O::Xchg, F::RegAcc),
+    op(O::Xchg, F::RegAcc),
+    op(O::Xchg, F::RegAcc),
+    op(O::Cbw, F::None),
+    op(O::Cwd, F::None),
+    op(O::Call, F::Far),
+    op(O::Wait, F::None),
+    op(O::Pushf, F::None),
+    op(O::Popf, F::None),
+    op(O::Sahf, F::None),
+    op(O::Lahf, F::None),
+    // A0
+    op(O::Mov, F::AccMem).w(0),
+    op(O::Mov, F::AccMem).w(0),
+    op(O::Mov, F::MemAcc).w(0),
+    op(O::Mov, F::MemAcc).w(0),
+    op(O::Movs, F::None).w(0),
+    op(O::Movs, F::None).w(0),
+    op(O::Cmps, F::None).w(0),
+    op(O::Cmps, F::None).w(0),
+    op(O::Test, F::AccImm).w(0),
+    op(O::Test, F::AccImm).w(0),
+    op(O::Stos, F::None).w(0),
+    op(O::Stos, F::None).w(0),
+    op(O::Lods, F::None).w(0),
+    op(O::Lods, F::None).w(0),
+    op(O::Scas, F::None).w(0),
+    op(O::Scas, F::None).w(0),
+    // B0
+    op(O::Mov, F::RegImm).w(3),
+    op(O::Mov, F::RegImm).w(3),
+    op(O::Mov, F::RegImm).w(3),
+    op(O::Mov, F::RegImm).w(3),
+    op(O::Mov, F::RegImm).w(3),
+    op(O::Mov, F::RegImm).w(3),
+    op(O::Mov, F::RegImm).w(3),
+    op(O::Mov, F::RegImm).w(3),
+    op(O::Mov, F::RegImm).w(3),
+    op(O::Mov, F::RegImm).w(3),
+    op(O::Mov, F::RegImm).w(3),
+    op(O::Mov, F::RegImm).w(3),
+    op(O::Mov, F::RegImm).w(3),
+    op(O::Mov, F::RegImm).w(3),
+    op(O::Mov, F::RegImm).w(3),
+    op(O::Mov, F::RegImm).w(3),
+    // C0: the 8086 ignores bit 1 of the returns
+    op(O::Ret, F::RetImm).undocumented(),
+    op(O::Ret, F::None).undocumented(),
+    op(O::Ret, F::RetImm),
+    op(O::Ret, F::None),
+    op(O::Les, F::RegMem),
+    op(O::Lds, F::RegMem),
+    op(O::Mov, F::RmImm).w(0),
+    op(O::Mov, F::RmImm).w(0),
+    op(O::Ret, F::RetImm).undocumented(),
+    op(O::Ret, F::None).undocumented(),
+    op(O::Ret, F::RetImm),
+    op(O::Ret, F::None),
+    op(O::Int, F::Int3),
+    op(O::Int, F::Int),
+    op(O::Into, F::None),
+    op(O::Iret, F::None),
+    // D0
+    group(&SHIFT).v(1).w(0),
+    group(&SHIFT).v(1).w(0),
+    group(&SHIFT).v(1).w(0),
+    group(&SHIFT).v(1).w(0),
+    op(O::Aam, F::Base),
+    op(O::Aad, F::Base),
+    op(O::Salc, F::None).undocumented(),
+    op(O::Xlat, F::None),
+    op(O::Esc, F::Esc),
+    op(O::Esc, F::Esc),
+    op(O::Esc, F::Esc),
+    op(O::Esc, F::Esc),
+    op(O::Esc, F::Esc),
+    op(O::Esc, F::Esc),
+    op(O::Esc, F::Esc),
+    op(O::Esc, F::Esc),
+    // E0
+    op(O::LoopnzLoopne, F::Rel8),
+    op(O::LoopzLoope, F::Rel8),
+    op(O::Loop, F::Rel8),
+    op(O::Jcxz, F::Rel8),
+    op(O::In, F::InPort).w(0),
+    op(O::In, F::InPort).w(0),
+    op(O::Out, F::OutPort).w(0),
+    op(O::Out, F::OutPort).w(0),
+    op(O::Call, F::Near),
+    op(O::Jmp, F::Near),
+    op(O::Jmp, F::Far),
+    op(O::Jmp, F::Short),
+    op(O::In, F::InDx).w(0),
+    op(O::In, F::InDx).w(0),
+    op(O::Out, F::OutDx).w(0),
+    op(O::Out, F::OutDx).w(0),
+    // F0
+    op(O::Lock, F::None),
+    op(O::Lock, F::None).undocumented(),
+    op(O::Rep, F::Rep).z(0),
+    op(O::Rep, F::Rep).z(0),
+    op(O::Hlt, F::None),
+    op(O::Cmc, F::None),
+    group(&UNARY).w(0),
+    group(&UNARY).w(0),
+    op(O::Clc, F::None),
+    op(O::Stc, F::None),
+    op(O::Cli, F::None),
+    op(O::Sti, F::None),
+    op(O::Cld, F::None),
+    op(O::Std, F::None),
+    group(&INC_DEC).w(0),
+    group(&INDIRECT).w(0),
+];
+
+// 60-6F on the 80186 and NEC models, in place of the jump aliases
+static OPCODES_186: [Entry; 16] = [
+    op(O::Pusha, F::None),
+    op(O::Popa, F::None),
+    op(O::Bound, F::RegMem),
+    UNDEFINED,
+    UNDEFINED,
+    UNDEFINED,
+    UNDEFINED,
+    UNDEFINED,
+    op(O::Push, F::PushImm).s(1),
+    op(O::Imul, F::ImulImm).s(1),
+    op(O::Push, F::PushImm).s(1),
+    op(O::Imul, F::ImulImm).s(1),
+    op(O::Ins, F::None).w(0),
+    op(O::Ins, F::None).w(0),
+    op(O::Outs, F::None).w(0),
+    op(O::Outs, F::None).w(0),
+];
+
+// C0, C1, C8 and C9 on the 80186 and NEC models, in place of the RET aliases
+static SHIFT_IMMEDIATE_BYTE: Entry = group(&SHIFT_IMMEDIATE).w(0);
+static ENTER: Entry = op(O::Enter, F::Enter);
+static LEAVE: Entry = op(O::Leave, F::None);
+
+// The NEC models run D6 as XLAT
+static XLAT_ALIAS: Entry = op(O::Xlat, F::None).undocumented();
+
+// The entry the given CPU decodes an opcode with
+pub fn lookup(opcode: u8, cpu: Cpu) -> &'static Entry {
+    match opcode {
+        0x0f if !cpu.pops_cs() => &UNDEFINED,
+        0x60..=0x6f if !cpu.aliases_jumps() => &OPCODES_186[opcode as usize & 0x0f],
+        0xc0 | 0xc1 if cpu.has_186_instructions() => &SHIFT_IMMEDIATE_BYTE,
+        0xc8 if cpu.has_186_instructions() => &ENTER,
+        0xc9 if cpu.has_186_instructions() => &LEAVE,
+        0xd6 if !cpu.has_salc() => &XLAT_ALIAS,
+        _ => &OPCODES[opcode as usize],
+    }
+}
+
+// The operation and operand form, resolving groups by the reg field
+pub fn resolve(entry: &Entry, reg: u8) -> Option<(OperationType, Form)> {
+    match entry.instruction {
+        Instruction::Undefined => None,
+        Instruction::Op(operation_type, form) => Some((operation_type, form)),
+        Instruction::Group(group) => group[reg as usize],
+    }
+}
+
+// The lowest opcode, and the reg field of a group member, that encodes an
+// operation in a form. Documented encodings are preferred.
+pub fn encoding(operation_type: OperationType, form: Form, cpu: Cpu) -> Option<(u8, Option<u8>)> {
+    for undocumented in [false, true] {
+        for opcode in 0..=0xff {
+            let entry = lookup(opcode, cpu);
+            if entry.undocumented != undocumented {
+                continue;
+            }
+            match entry.instruction {
+                Instruction::Op(t, f) if t == operation_type && f == form => {
+                    return Some((opcode, None));
+                }
+                Instruction::Group(group) => {
+                    let member = Some((operation_type, form));
+                    if let Some(reg) = group.iter().position(|m| *m == member) {
+                        return Some((opcode, Some(reg as u8)));
+                    }
+                }
+                _ => {}
+            }
+        }
+    }
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::disassembler::Disassembler;
+    use test_case::test_case;
+
+    #[test]
+    fn test_coverage() {
+        // Every opcode decodes on the 8086 except the ones it lacks entirely
+        let undefined: Vec<u8> = (0..=0xff)
+            .filter(|&opcode| {
+                matches!(
+                    lookup(opcode, Cpu::I8086).instruction,
+                    Instruction::Undefined
+                )
+            })
+            .collect();
+        assert!(undefined.is_empty());
+        // The 80186 drops POP CS and the jump aliases without an instruction
+        let undefined: Vec<u8> = (0..=0xff)
+            .filter(|&opcode| {
+                matches!(
+                    lookup(opcode, Cpu::I80186).instruction,
+                    Instruction::Undefined
+                )
+            })
+            .collect();
+        assert_eq!(undefined, [0x0f, 0x63, 0x64, 0x65, 0x66, 0x67]);
+    }
+
+    // Every reg field of every group opcode decodes, to its member or to an
+    // undefined operation
+    #[test_case(Cpu::I8086 ; "8086")]
+    #[test_case(Cpu::I80186 ; "80186")]
+    #[test_case(Cpu::V20 ; "v20")]
+    fn test_group_coverage(cpu: Cpu) {
+        let mut undefined = Vec::new();
+        for opcode in 0..=0xff {
+            let Instruction::Group(group) = lookup(opcode, cpu).instruction else {
+                continue;
+            };
+            for reg in 0..8 {
+                // [BX], without a displacement
+                let mut text = vec![opcode, reg << 3 | 0b111];
+                text.resize(8, 0);
+                let op = Disassembler::new(&text, false, cpu).next(0).unwrap();
+                match group[reg as usize] {
+                    Some((operation_type, _)) => assert_eq!(op.operation_type, operation_type),
+                    None => {
+                        assert_eq!(op.operation_type, O::Undefined);
+                        assert_eq!(*op.raws, text[..2]);
+                        undefined.push((opcode, reg));
+                    }
+                }
+            }
+        }
+        let mut expected: Vec<(u8, u8)> = (1..8).map(|reg| (0x8f, reg)).collect();
+        if cpu.has_186_instructions() {
+            expected.extend([(0xc0, 6), (0xc1, 6)]);
+        }
+        expected.extend((0xd0..=0xd3).map(|opcode| (opcode, 6)));
+        expected.extend([(0xf6, 1), (0xf7, 1)]);
+        expected.extend((2..8).map(|reg| (0xfe, reg)));
+        expected.push((0xff, 7));
+        assert_eq!(undefined, expected);
+    }
+
+    // Group opcodes from the Intel manual, each with its ModRM reg field
+    #[test_case(&[0x80, 0x07, 0x12], O::Add ; "80 /0 add")]
+    #[test_case(&[0x80, 0x3f, 0x12], O::Cmp ; "80 /7 cmp")]
+    #[test_case(&[0x81, 0x0f, 0x34, 0x12], O::Or ; "81 /1 or")]
+    #[test_case(&[0x81, 0x2f, 0x34, 0x12], O::Sub ; "81 /5 sub")]
+    #[test_case(&[0x83, 0x17, 0xff], O::Adc ; "83 /2 adc")]
+    #[test_case(&[0x83, 0x1f, 0x01], O::Sbb ; "83 /3 sbb")]
+    #[test_case(&[0x83, 0x27, 0x01], O::And ; "83 /4 and")]
+    #[test_case(&[0x83, 0x37, 0x01], O::Xor ; "83 /6 xor")]
+    #[test_case(&[0x8f, 0x07], O::Pop ; "8f /0 pop")]
+    #[test_case(&[0xd0, 0x07], O::Rol ; "d0 /0 rol")]
+    #[test_case(&[0xd1, 0x0f], O::Ror ; "d1 /1 ror")]
+    #[test_case(&[0xd2, 0x17], O::Rcl ; "d2 /2 rcl")]
+    #[test_case(&[0xd3, 0x1f], O::Rcr ; "d3 /3 rcr")]
+    #[test_case(&[0xd0, 0x27], O::ShlSal ; "d0 /4 shl")]
+    #[test_case(&[0xd1, 0x2f], O::Shr ; "d1 /5 shr")]
+    #[test_case(&[0xd3, 0x3f], O::Sar ; "d3 /7 sar")]
+    #[test_case(&[0xf6, 0x07, 0x12], O::Test ; "f6 /0 test")]
+    #[test_case(&[0xf7, 0x07, 0x34, 0x12], O::Test ; "f7 /0 test")]
+    #[test_case(&[0xf6, 0x17], O::Not ; "f6 /2 not")]
+    #[test_case(&[0xf7, 0x1f], O::Neg ; "f7 /3 neg")]
+    #[test_case(&[0xf6, 0x27], O::Mul ; "f6 /4 mul")]
+    #[test_case(&[0xf7, 0x2f], O::Imul ; "f7 /5 imul")]
+    #[test_case(&[0xf6, 0x37], O::Div ; "f6 /6 div")]
+    #[test_case(&[0xf7, 0x3f], O::Idiv ; "f7 /7 idiv")]
+    #[test_case(&[0xfe, 0x07], O::Inc ; "fe /0 inc")]
+    #[test_case(&[0xfe, 0x0f], O::Dec ; "fe /1 dec")]
+    #[test_case(&[0xff, 0x07], O::Inc ; "ff /0 inc")]
+    #[test_case(&[0xff, 0x0f], O::Dec ; "ff /1 dec")]
+    #[test_case(&[0xff, 0x17], O::Call ; "ff /2 call")]
+    #[test_case(&[0xff, 0x1f], O::Call ; "ff /3 call far")]
+    #[test_case(&[0xff, 0x27], O::Jmp ; "ff /4 jmp")]
+    #[test_case(&[0xff, 0x2f], O::Jmp ; "ff /5 jmp far")]
+    #[test_case(&[0xff, 0x37], O::Push ; "ff /6 push")]
+    fn test_group_encodings(bytes: &[u8], operation_type: OperationType) {
+        let mut text = bytes.to_vec();
+        text.resize(8, 0);
+        let op = Disassembler::new(&text, false, Cpu::I8086).next(0).unwrap();
+        assert_eq!(op.operation_type, operation_type);
+        assert_eq!(*op.raws, *bytes);
+    }
+}
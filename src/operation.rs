@@ -1,6 +1,13 @@
-use std::fmt::{Debug, Display};
+use std::{
+    fmt::{Debug, Display},
+    ops::Deref,
+};
 
-use crate::register::RegisterType;
+use crate::{
+    cpu::Cpu,
+    opcode::{self, Form},
+    register::{calc_relative_disp, RegisterType},
+};
 
 #[derive(PartialEq, Debug, Clone, Copy)]
 pub enum OperationType {
@@ -20,11 +27,13 @@ pub enum OperationType {
     Sahf,
     Pushf,
     Popf,
+    Pusha,
+    Popa,
     Add,
     Adc,
     Inc,
     Aaa,
-    Baa,
+    Daa,
     Sub,
     Sbb,
     Dec,
@@ -41,6 +50,7 @@ pub enum OperationType {
     Aad,
     Cbw,
     Cwd,
+    Salc,
     // Logic
     Not,
     ShlSal,
@@ -61,6 +71,8 @@ pub enum OperationType {
     Scas,
     Lods,
     Stos,
+    Ins,
+    Outs,
     // Control Transfer
     Call,
     Jmp,
@@ -88,6 +100,9 @@ pub enum OperationType {
     Int,
     Into,
     Iret,
+    Enter,
+    Leave,
+    Bound,
     // Processor Control
     Clc,
     Cmc,
@@ -100,6 +115,7 @@ pub enum OperationType {
     Wait,
     Esc,
     Lock,
+    Segment,
 }
 
 impl Display for OperationType {
@@ -120,11 +136,13 @@ impl Display for OperationType {
             OperationType::Sahf => "SAHF",
             OperationType::Pushf => "PUSHF",
             OperationType::Popf => "POPF",
+            OperationType::Pusha => "PUSHA",
+            OperationType::Popa => "POPA",
             OperationType::Add => "ADD",
             OperationType::Adc => "ADC",
             OperationType::Inc => "INC",
             OperationType::Aaa => "AAA",
-            OperationType::Baa => "BAA",
+            OperationType::Daa => "DAA",
             OperationType::Sub => "SUB",
             OperationType::Sbb => "SBB",
             OperationType::Dec => "DEC",
@@ -140,6 +158,7 @@ impl Display for OperationType {
             OperationType::Aad => "AAD",
             OperationType::Cbw => "CBW",
             OperationType::Cwd => "CWD",
+            OperationType::Salc => "SALC",
             OperationType::Not => "NOT",
             OperationType::ShlSal => "SHL",
             OperationType::Shr => "SHR",
@@ -158,6 +177,8 @@ impl Display for OperationType {
             OperationType::Scas => "SCAS",
             OperationType::Lods => "LODS",
             OperationType::Stos => "STOS",
+            OperationType::Ins => "INS",
+            OperationType::Outs => "OUTS",
             OperationType::Call => "CALL",
             OperationType::Jmp => "JMP",
             OperationType::Ret => "RET",
@@ -184,6 +205,9 @@ impl Display for OperationType {
             OperationType::Int => "INT",
             OperationType::Into => "INTO",
             OperationType::Iret => "IRET",
+            OperationType::Enter => "ENTER",
+            OperationType::Leave => "LEAVE",
+            OperationType::Bound => "BOUND",
             OperationType::Clc => "CLC",
             OperationType::Cmc => "CMC",
             OperationType::Stc => "STC",
@@ -195,6 +219,7 @@ impl Display for OperationType {
             OperationType::Wait => "WAIT",
             OperationType::Esc => "ESC",
             OperationType::Lock => "LOCK",
+            OperationType::Segment => "SEG",
         };
         write!(f, "{}", name)
     }
@@ -208,13 +233,63 @@ pub enum OperandType {
     Imm,
     EA,
     Disp,
+    Far,
 }
 
-#[derive(Debug, Clone)]
+// The longest 8086 instruction without prefixes: opcode, ModRM, disp16 and imm16
+pub const MAX_LENGTH: usize = 6;
+
+// Instruction bytes, kept inline so that decoding does not allocate
+#[derive(Clone, Copy, Default, PartialEq)]
+pub struct Raws {
+    bytes: [u8; MAX_LENGTH],
+    len: u8,
+}
+
+impl Raws {
+    pub fn push(&mut self, byte: u8) {
+        self.bytes[self.len as usize] = byte;
+        self.len += 1;
+    }
+
+    fn push_word(&mut self, word: u16) {
+        for byte in word.to_le_bytes() {
+            self.push(byte);
+        }
+    }
+}
+
+impl Deref for Raws {
+    type Target = [u8];
+
+    fn deref(&self) -> &[u8] {
+        &self.bytes[..self.len as usize]
+    }
+}
+
+impl From<&[u8]> for Raws {
+    fn from(bytes: &[u8]) -> Self {
+        let mut raws = Raws::default();
+        for &byte in bytes {
+            raws.push(byte);
+        }
+        raws
+    }
+}
+
+impl Debug for Raws {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{:02x?}", self.deref())
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
 pub struct Operation {
     pub pos: usize,
     pub operation_type: OperationType,
-    pub raws: Vec<u8>,
+    // How the operands are laid out in the bytes
+    pub form: Form,
+    pub raws: Raws,
     pub d: u8,
     pub w: u8,
     pub s: u8,
@@ -237,7 +312,8 @@ impl Operation {
         Operation {
             pos: 0,
             operation_type: OperationType::Undefined,
-            raws: Vec::new(),
+            form: Form::None,
+            raws: Raws::default(),
             d: 0,
             w: 1,
             s: 0,
@@ -269,14 +345,231 @@ impl Operation {
     pub fn get_register(&self) -> RegisterType {
         RegisterType::new(self.reg, self.w)
     }
+
+    pub fn is_far(&self) -> bool {
+        match self.raws[0] {
+            // Call/Jmp Direct Intersegment, Ret Intersegment
+            0b1001_1010 | 0b1110_1010 | 0b1100_1010 | 0b1100_1011 => true,
+            // Ret Intersegment aliases on the 8086
+            0b1100_1000 | 0b1100_1001 => self.operation_type == OperationType::Ret,
+            // Call/Jmp Indirect Intersegment
+            0b1111_1111 => self.reg == 0b011 || self.reg == 0b101,
+            _ => false,
+        }
+    }
+
+    // The destination of a direct jump, call or loop within the segment
+    pub fn target(&self) -> Option<usize> {
+        let next = self.get_next_operation_pos() as u16 as usize;
+        match self.form {
+            Form::Near => Some(calc_relative_disp(next, self.disp, true) as usize),
+            Form::Short | Form::Rel8 => Some(calc_relative_disp(next, self.disp, false) as usize),
+            _ => None,
+        }
+    }
+
+    // Whether execution can go on with the next instruction
+    pub fn falls_through(&self) -> bool {
+        !matches!(
+            self.operation_type,
+            OperationType::Jmp | OperationType::Ret | OperationType::Iret
+        )
+    }
+
+    // The bytes of the operation: the lowest documented opcode of its form
+    // with the d/w/s/v/z bits set, the reg field that names a group member,
+    // and the displacement size mod gives. Intersegment returns and ESC
+    // opcodes are only told apart by their opcode, so those bits come from raws.
+    pub fn encode(&self, cpu: Cpu) -> Result<Raws, String> {
+        let no_form = || format!("{} has no such form on this CPU", self.operation_type);
+        let (base, group) =
+            opcode::encoding(self.operation_type, self.form, cpu).ok_or_else(no_form)?;
+        let bits = opcode::lookup(base, cpu).bits;
+        let field = |bit: Option<u8>, value: u8| bit.map_or(0, |bit| (value & 1) << bit);
+        let mut opcode = base
+            | field(bits.d, self.d)
+            | field(bits.w, self.w)
+            // Sign extension only means something for a word
+            | field(bits.s, self.s & self.w)
+            | field(bits.v, self.v)
+            | field(bits.z, self.z);
+        match self.form {
+            Form::Reg | Form::RegAcc | Form::RegImm => opcode |= self.reg,
+            Form::Seg | Form::Segment => opcode |= self.reg << 3,
+            Form::Esc => opcode |= self.raws[0] & 0b111,
+            _ if self.operation_type == OperationType::Ret
+                && !self.raws.is_empty()
+                && self.is_far() =>
+            {
+                opcode |= 0b1000
+            }
+            _ => {}
+        }
+        // What the bytes decode to, with a group member told by reg
+        let member = if group.is_some() { self.reg } else { 0 };
+        if opcode::resolve(opcode::lookup(opcode, cpu), member)
+            != Some((self.operation_type, self.form))
+        {
+            return Err(no_form());
+        }
+
+        let mut raws = Raws::default();
+        raws.push(opcode);
+        if self.form == Form::Rep {
+            let (string, _) =
+                opcode::encoding(self.rep_operation_type, Form::None, cpu).ok_or_else(no_form)?;
+            raws.push(string | self.w);
+        }
+        if self.form.has_mod_rm() {
+            self.encode_mod_rm(&mut raws);
+        }
+        match self.form {
+            Form::RmImm | Form::AccImm | Form::RegImm | Form::PushImm | Form::ImulImm => {
+                match (self.s, self.w) {
+                    (0, 1) => raws.push_word(self.data),
+                    _ => raws.push(self.data as u8),
+                }
+            }
+            Form::AccMem | Form::MemAcc | Form::Near | Form::RetImm => raws.push_word(self.disp),
+            Form::Short | Form::Rel8 => raws.push(self.disp as u8),
+            Form::Far => {
+                raws.push_word(self.disp);
+                raws.push_word(self.data);
+            }
+            Form::InPort | Form::OutPort => raws.push(self.port),
+            Form::Int => raws.push(self.int_type),
+            Form::Base | Form::ShiftImm => raws.push(self.data as u8),
+            Form::Enter => {
+                raws.push_word(self.data);
+                raws.push(self.disp as u8);
+            }
+            _ => {}
+        }
+        Ok(raws)
+    }
+
+    fn encode_mod_rm(&self, raws: &mut Raws) {
+        raws.push(self.mod_rm << 6 | self.reg << 3 | self.rm);
+        match self.mod_rm {
+            0b00 if self.rm == 0b110 => raws.push_word(self.disp),
+            0b01 => raws.push(self.disp as u8),
+            0b10 => raws.push_word(self.disp),
+            _ => {}
+        }
+    }
 }
 
 impl Display for Operation {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         let mut raws = String::new();
-        for byte in &self.raws {
+        for byte in self.raws.iter() {
             raws.push_str(&format!("{:02x}", byte));
         }
         write!(f, "{:04}: {raws}\t{}", self.pos, self.operation_type)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::disassembler::Disassembler;
+    use test_case::test_case;
+
+    fn decode(bytes: &[u8], cpu: Cpu) -> Operation {
+        Disassembler::new(bytes, false, cpu).next(0).unwrap()
+    }
+
+    // What an operation does, leaving out how it was encoded
+    fn meaning(op: &Operation) -> (Operation, bool) {
+        let far = op.is_far();
+        let mut op = *op;
+        if op.w == 0 {
+            op.s = 0;
+        }
+        op.raws = Raws::default();
+        (op, far)
+    }
+
+    #[test_case(&[0x8b, 0x86, 0x7f, 0x00], &[0x8b, 0x86, 0x7f, 0x00] ; "word displacement that fits a byte")]
+    #[test_case(&[0x8b, 0x47, 0x00], &[0x8b, 0x47, 0x00] ; "zero displacement")]
+    #[test_case(&[0x60, 0x05], &[0x70, 0x05] ; "jump alias")]
+    #[test_case(&[0xc9], &[0xcb] ; "intersegment return alias")]
+    #[test_case(&[0x82, 0x3f, 0x12], &[0x80, 0x3f, 0x12] ; "82 /7 cmp alias")]
+    #[test_case(&[0xd2, 0x27], &[0xd2, 0x27] ; "d2 /4 shl")]
+    fn test_encode(bytes: &[u8], expected: &[u8]) {
+        let raws = decode(bytes, Cpu::I8086).encode(Cpu::I8086).unwrap();
+        assert_eq!(&*raws, expected);
+    }
+
+    #[test]
+    fn test_encode_errors() {
+        // POP CS is gone on the 80186
+        let pop_cs = decode(&[0x0f], Cpu::I8086);
+        assert!(pop_cs.encode(Cpu::I80186).is_err());
+        // A group member the opcode does not have
+        let mut push = decode(&[0xff, 0x37], Cpu::I8086);
+        push.reg = 0b111;
+        assert!(push.encode(Cpu::I8086).is_err());
+    }
+
+    #[test]
+    fn test_encode_round_trip() {
+        // Zero, and displacements of either sign after the ModRM byte
+        let tails = [
+            [0x00, 0x00, 0x00, 0x00],
+            [0x7f, 0x00, 0xff, 0x80],
+            [0x80, 0xff, 0x34, 0x12],
+        ];
+        for cpu in [Cpu::I8086, Cpu::I80186, Cpu::V20] {
+            let string = |byte: u8| {
+                matches!(
+                    opcode::resolve(opcode::lookup(byte, cpu), 0),
+                    Some((
+                        OperationType::Movs
+                            | OperationType::Cmps
+                            | OperationType::Scas
+                            | OperationType::Lods
+                            | OperationType::Stos
+                            | OperationType::Ins
+                            | OperationType::Outs,
+                        Form::None
+                    ))
+                )
+            };
+            for instruction in 0..=0xff {
+                let entry = opcode::lookup(instruction, cpu);
+                for second in 0..=0xff {
+                    // Skip the bytes the decoder rejects
+                    let (operation_type, form) = match opcode::resolve(entry, second >> 3 & 0b111) {
+                        Some(resolved) => resolved,
+                        None => continue,
+                    };
+                    if (form == Form::Rep && !string(second))
+                        || (form == Form::Base && second != 0x0a)
+                        || (matches!(form, Form::RmSeg | Form::SegRm) && second & 0b10_0000 != 0)
+                        || operation_type == OperationType::Undefined
+                    {
+                        continue;
+                    }
+                    for tail in tails {
+                        let mut bytes = vec![instruction, second];
+                        bytes.extend_from_slice(&tail);
+                        let op = decode(&bytes, cpu);
+                        let raws = op.encode(cpu).unwrap();
+                        let canonical = decode(&raws, cpu);
+                        assert_eq!(canonical.raws, raws, "{:02x?} on {:?}", bytes, cpu);
+                        assert_eq!(
+                            meaning(&canonical),
+                            meaning(&op),
+                            "{:02x?} on {:?}",
+                            bytes,
+                            cpu
+                        );
+                        // Canonical bytes decode to an operation that encodes to them again
+                        assert_eq!(decode(&canonical.encode(cpu).unwrap(), cpu), canonical);
+                    }
+                }
+            }
+        }
+    }
+}
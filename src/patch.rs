@@ -0,0 +1,153 @@
+use crate::{
+    assembler,
+    cpu::Cpu,
+    disassembler::Disassembler,
+    metadata::{Metadata, Symbol, N_DATA, N_TEXT},
+};
+
+// XCHG AX, AX
+const NOP: u8 = 0x90;
+
+// A change to an executable at a symbol or hexadecimal offset
+pub enum Edit {
+    // Instructions that replace the ones starting at a text offset, with
+    // lines separated by `|`
+    Text(String, String),
+    // Hexadecimal bytes written over the data
+    Data(String, String),
+}
+
+// Applies edits to a copy of an executable. The header and symbol table are
+// kept as they are, since the text and data keep their sizes.
+pub fn patch(executable: &[u8], edits: &[Edit], cpu: Cpu) -> Result<Vec<u8>, String> {
+    let metadata = Metadata::from_bytes(executable);
+    if executable.len() < metadata.symbols_offset() {
+        return Err("File is shorter than its header says".to_string());
+    }
+    let symbols = metadata.symbols(executable);
+    let mut patched = executable.to_vec();
+    for edit in edits {
+        match edit {
+            Edit::Text(at, source) => {
+                let offset = offset(at, &symbols, N_TEXT)?;
+                let text = &mut patched[metadata.text_offset()..metadata.data_offset()];
+                let code =
+                    assembler::assemble_at(&source.replace('|', "\n"), offset, &symbols, cpu)
+                        .map_err(|e| format!("{}: {}", at, e))?;
+                let end = instruction_end(text, offset, code.len(), cpu)
+                    .ok_or_else(|| format!("{}: Runs past the end of the text", at))?;
+                text[offset..offset + code.len()].copy_from_slice(&code);
+                // What is left of the last replaced instruction
+                text[offset + code.len()..end].fill(NOP);
+            }
+            Edit::Data(at, bytes) => {
+                let offset = offset(at, &symbols, N_DATA)?;
+                let bytes = parse_bytes(bytes)?;
+                let data = &mut patched[metadata.data_offset()..metadata.symbols_offset()];
+                if offset + bytes.len() > data.len() {
+                    return Err(format!("{}: Runs past the end of the data", at));
+                }
+                data[offset..offset + bytes.len()].copy_from_slice(&bytes);
+            }
+        }
+    }
+    Ok(patched)
+}
+
+// An offset given by a symbol of the section or in hexadecimal
+fn offset(at: &str, symbols: &[Symbol], section: u8) -> Result<usize, String> {
+    if let Some(symbol) = symbols.iter().find(|symbol| symbol.name == at) {
+        if symbol.section() != section {
+            return Err(format!("{} is in another section", at));
+        }
+        return Ok(symbol.value as usize);
+    }
+    let digits = at.trim_start_matches("0x");
+    usize::from_str_radix(digits, 16).map_err(|_| format!("Unknown symbol or offset: {}", at))
+}
+
+// The end of the instructions that the first length bytes from offset
+// reach into, None if they run past the text
+fn instruction_end(text: &[u8], offset: usize, length: usize, cpu: Cpu) -> Option<usize> {
+    let mut disassembler = Disassembler::new(text, false, cpu);
+    let mut end = offset;
+    while end < offset + length {
+        if end > u16::MAX as usize {
+            return None;
+        }
+        end = disassembler.next(end as u16)?.get_next_operation_pos();
+    }
+    Some(end)
+}
+
+fn parse_bytes(text: &str) -> Result<Vec<u8>, String> {
+    text.split(|c: char| c.is_whitespace() || c == ',')
+        .filter(|byte| !byte.is_empty())
+        .map(|byte| u8::from_str_radix(byte, 16).map_err(|_| format!("Invalid byte: {}", byte)))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn executable() -> Vec<u8> {
+        let source = "
+            start:  CALL stub
+                    HLT
+            stub:   MOV AX, 1234
+                    MOV BX, 5678
+                    RET
+            .data
+            table:  db 1, 2, 3, 4
+        ";
+        assembler::assemble(source, Cpu::I8086).unwrap()
+    }
+
+    #[test]
+    fn test_patch_text() {
+        let executable = executable();
+        let edits = [
+            Edit::Text("start".to_string(), "RET".to_string()),
+            Edit::Text("stub".to_string(), "JMP start".to_string()),
+        ];
+        let patched = patch(&executable, &edits, Cpu::I8086).unwrap();
+        let metadata = Metadata::from_bytes(&patched);
+        let text = &patched[metadata.text_offset()..metadata.data_offset()];
+        #[rustfmt::skip]
+        assert_eq!(text, [
+            0xc3,               // RET
+            0x90, 0x90,         // NOPs for the rest of CALL stub
+            0xf4,               // HLT
+            0xeb, 0xfa,         // JMP Short start
+            0x90,               // NOP for the rest of MOV AX, 1234
+            0xbb, 0x78, 0x56,   // MOV BX, 5678
+            0xc3,               // RET
+        ]);
+        // Only the text changed
+        assert_eq!(patched.len(), executable.len());
+        assert_eq!(
+            patched[metadata.data_offset()..],
+            executable[metadata.data_offset()..]
+        );
+    }
+
+    #[test]
+    fn test_patch_data() {
+        let executable = executable();
+        let edits = [
+            Edit::Data("table".to_string(), "ff fe".to_string()),
+            Edit::Data("3".to_string(), "7f".to_string()),
+        ];
+        let patched = patch(&executable, &edits, Cpu::I8086).unwrap();
+        let metadata = Metadata::from_bytes(&patched);
+        assert_eq!(
+            patched[metadata.data_offset()..metadata.symbols_offset()],
+            [0xff, 0xfe, 0x03, 0x7f]
+        );
+        let edits = [Edit::Data("3".to_string(), "01 02".to_string())];
+        assert!(patch(&executable, &edits, Cpu::I8086).is_err());
+        let edits = [Edit::Data("stub".to_string(), "01".to_string())];
+        assert!(patch(&executable, &edits, Cpu::I8086).is_err());
+    }
+}
@@ -0,0 +1,192 @@
+use crate::port::PortBus;
+
+pub const PIC_COMMAND_PORT: u16 = 0x20;
+pub const PIC_DATA_PORT: u16 = 0x21;
+
+// Initialization command words, in the order the PIC expects them
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Init {
+    Ready,
+    Icw2,
+    Icw3,
+    Icw4,
+}
+
+// Intel 8259A programmable interrupt controller, single master, fixed priority (IR0 highest)
+pub struct Pic {
+    irr: u8,
+    isr: u8,
+    imr: u8,
+    vector_base: u8,
+    init: Init,
+    single: bool,
+    needs_icw4: bool,
+    auto_eoi: bool,
+    read_isr: bool,
+}
+
+impl Pic {
+    pub fn new() -> Self {
+        Pic {
+            irr: 0,
+            isr: 0,
+            // Everything masked until the guest programs the PIC
+            imr: 0xff,
+            vector_base: 0x08,
+            init: Init::Ready,
+            single: true,
+            needs_icw4: false,
+            auto_eoi: false,
+            read_isr: false,
+        }
+    }
+
+    // Programs the PIC the way the PC BIOS leaves it: IRQ0-7 on vectors 08h-0Fh
+    pub fn init_pc(&mut self) {
+        self.write8(PIC_COMMAND_PORT, 0x13);
+        self.write8(PIC_DATA_PORT, 0x08);
+        self.write8(PIC_DATA_PORT, 0x09);
+        self.write8(PIC_DATA_PORT, 0x00);
+    }
+
+    pub fn raise_irq(&mut self, irq: u8) {
+        self.irr |= 1 << irq;
+    }
+
+    pub fn is_masked(&self, irq: u8) -> bool {
+        self.imr & (1 << irq) != 0
+    }
+
+    // The highest priority request that is unmasked and not blocked by one in service
+    fn highest_request(&self) -> Option<u8> {
+        let requests = self.irr & !self.imr;
+        let irq = (0..8).find(|irq| requests & (1 << irq) != 0)?;
+        let in_service = (0..8).find(|irq| self.isr & (1 << irq) != 0);
+        match in_service {
+            Some(served) if served <= irq => None,
+            _ => Some(irq),
+        }
+    }
+
+    pub fn has_interrupt(&self) -> bool {
+        self.init == Init::Ready && self.highest_request().is_some()
+    }
+
+    // INTA cycle: moves the request into service and returns its vector
+    pub fn acknowledge(&mut self) -> Option<u8> {
+        if self.init != Init::Ready {
+            return None;
+        }
+        let irq = self.highest_request()?;
+        self.irr &= !(1 << irq);
+        if !self.auto_eoi {
+            self.isr |= 1 << irq;
+        }
+        Some(self.vector_base | irq)
+    }
+
+    fn command(&mut self, value: u8) {
+        if value & 0x10 != 0 {
+            // ICW1
+            self.single = value & 0x02 != 0;
+            self.needs_icw4 = value & 0x01 != 0;
+            self.init = Init::Icw2;
+            self.imr = 0;
+            self.isr = 0;
+            self.irr = 0;
+            self.auto_eoi = false;
+            self.read_isr = false;
+        } else if value & 0x08 != 0 {
+            // OCW3, only the register read select is meaningful here
+            if value & 0x02 != 0 {
+                self.read_isr = value & 0x01 != 0;
+            }
+        } else {
+            // OCW2
+            match value & 0xe0 {
+                // Non-specific EOI
+                0x20 => {
+                    if let Some(irq) = (0..8).find(|irq| self.isr & (1 << irq) != 0) {
+                        self.isr &= !(1 << irq);
+                    }
+                }
+                // Specific EOI
+                0x60 => self.isr &= !(1 << (value & 0x07)),
+                _ => {
+                    // Priority rotation is not modelled
+                }
+            }
+        }
+    }
+
+    fn data(&mut self, value: u8) {
+        self.init = match self.init {
+            Init::Ready => {
+                // OCW1
+                self.imr = value;
+                Init::Ready
+            }
+            Init::Icw2 => {
+                self.vector_base = value & 0xf8;
+                match (self.single, self.needs_icw4) {
+                    (false, _) => Init::Icw3,
+                    (true, true) => Init::Icw4,
+                    (true, false) => Init::Ready,
+                }
+            }
+            // Cascading is not modelled, a PC/XT has a single PIC
+            Init::Icw3 if self.needs_icw4 => Init::Icw4,
+            Init::Icw3 => Init::Ready,
+            Init::Icw4 => {
+                self.auto_eoi = value & 0x02 != 0;
+                Init::Ready
+            }
+        };
+    }
+}
+
+impl PortBus for Pic {
+    fn read8(&mut self, port: u16) -> u8 {
+        match port {
+            PIC_COMMAND_PORT if self.read_isr => self.isr,
+            PIC_COMMAND_PORT => self.irr,
+            _ => self.imr,
+        }
+    }
+
+    fn write8(&mut self, port: u16, value: u8) {
+        match port {
+            PIC_COMMAND_PORT => self.command(value),
+            _ => self.data(value),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_priority_and_eoi() {
+        let mut pic = Pic::new();
+        pic.init_pc();
+        pic.raise_irq(4);
+        pic.raise_irq(1);
+        assert_eq!(pic.acknowledge(), Some(0x09));
+        // IRQ 4 waits until IRQ 1 is done
+        assert_eq!(pic.acknowledge(), None);
+        pic.write8(PIC_COMMAND_PORT, 0x20);
+        assert_eq!(pic.acknowledge(), Some(0x0c));
+    }
+
+    #[test]
+    fn test_mask() {
+        let mut pic = Pic::new();
+        pic.init_pc();
+        pic.write8(PIC_DATA_PORT, 0x01);
+        pic.raise_irq(0);
+        assert!(!pic.has_interrupt());
+        pic.write8(PIC_DATA_PORT, 0x00);
+        assert_eq!(pic.acknowledge(), Some(0x08));
+    }
+}
@@ -0,0 +1,287 @@
+use crate::port::PortBus;
+
+pub const PIT_BASE_PORT: u16 = 0x40;
+pub const PIT_CONTROL_PORT: u16 = 0x43;
+
+// Access modes selected by bits 5-4 of the control word
+const ACCESS_LATCH: u8 = 0b00;
+const ACCESS_LOW: u8 = 0b01;
+const ACCESS_HIGH: u8 = 0b10;
+
+struct Channel {
+    mode: u8,
+    access: u8,
+    // A reload of 0 counts 65536
+    reload: u16,
+    count: u32,
+    counting: bool,
+    // Counting is held while the gate input is low
+    gate: bool,
+    output: bool,
+    latch: Option<u16>,
+    read_high: bool,
+    write_high: bool,
+    write_low: u8,
+}
+
+impl Channel {
+    fn new(gate: bool) -> Self {
+        Channel {
+            mode: 0,
+            access: ACCESS_LOW | ACCESS_HIGH,
+            reload: 0,
+            count: 0,
+            counting: false,
+            gate,
+            output: false,
+            latch: None,
+            read_high: false,
+            write_high: false,
+            write_low: 0,
+        }
+    }
+
+    fn control(&mut self, value: u8) {
+        let access = (value >> 4) & 0b11;
+        if access == ACCESS_LATCH {
+            self.latch.get_or_insert(self.count as u16);
+            return;
+        }
+        self.access = access;
+        // Modes 6 and 7 are aliases of 2 and 3
+        self.mode = match (value >> 1) & 0b111 {
+            6 => 2,
+            7 => 3,
+            mode => mode,
+        };
+        // Modes 1, 4 and 5 are approximated by mode 0
+        self.output = matches!(self.mode, 2 | 3);
+        self.counting = false;
+        self.latch = None;
+        self.read_high = false;
+        self.write_high = false;
+    }
+
+    fn write(&mut self, value: u8) {
+        match self.access {
+            ACCESS_LOW => self.load(value as u16),
+            ACCESS_HIGH => self.load((value as u16) << 8),
+            _ if self.write_high => {
+                self.write_high = false;
+                self.load(u16::from_le_bytes([self.write_low, value]));
+            }
+            _ => {
+                self.write_high = true;
+                self.write_low = value;
+            }
+        }
+    }
+
+    fn load(&mut self, reload: u16) {
+        self.reload = reload;
+        self.count = self.period();
+        self.counting = true;
+        if !matches!(self.mode, 2 | 3) {
+            self.output = false;
+        }
+    }
+
+    fn period(&self) -> u32 {
+        match self.reload {
+            0 => 0x10000,
+            reload => reload as u32,
+        }
+    }
+
+    fn read(&mut self) -> u8 {
+        let [low, high] = self.latch.unwrap_or(self.count as u16).to_le_bytes();
+        match self.access {
+            ACCESS_LOW => {
+                self.latch = None;
+                low
+            }
+            ACCESS_HIGH => {
+                self.latch = None;
+                high
+            }
+            _ => {
+                self.read_high = !self.read_high;
+                if self.read_high {
+                    low
+                } else {
+                    self.latch = None;
+                    high
+                }
+            }
+        }
+    }
+
+    fn set_gate(&mut self, gate: bool) {
+        if matches!(self.mode, 2 | 3) {
+            if gate && !self.gate {
+                // A rising gate restarts the period
+                self.count = self.period();
+            }
+            if !gate {
+                self.output = true;
+            }
+        }
+        self.gate = gate;
+    }
+
+    // Advances one input clock, returns true on a rising edge of the output
+    fn tick(&mut self) -> bool {
+        if !self.counting || !self.gate {
+            return false;
+        }
+        let before = self.output;
+        match self.mode {
+            2 => {
+                self.count -= 1;
+                match self.count {
+                    1 => self.output = false,
+                    0 => {
+                        self.output = true;
+                        self.count = self.period();
+                    }
+                    _ => {}
+                }
+            }
+            3 => {
+                // Counts down by two each clock and flips the output every half period
+                self.count = self.count.saturating_sub(2);
+                if self.count == 0 {
+                    self.output = !self.output;
+                    self.count = self.period();
+                }
+            }
+            _ => {
+                self.count = match self.count {
+                    0 => 0xffff,
+                    count => count - 1,
+                };
+                if self.count == 0 {
+                    self.output = true;
+                }
+            }
+        }
+        !before && self.output
+    }
+}
+
+// Intel 8253/8254 programmable interval timer, clocked at 1.193182 MHz.
+// On the PC, gates 0 and 1 are tied high and gate 2 is bit 0 of port 0x61.
+pub struct Pit {
+    channels: [Channel; 3],
+}
+
+impl Pit {
+    pub fn new() -> Self {
+        Pit {
+            channels: [Channel::new(true), Channel::new(true), Channel::new(false)],
+        }
+    }
+
+    pub fn set_gate(&mut self, channel: usize, gate: bool) {
+        self.channels[channel].set_gate(gate);
+    }
+
+    pub fn output(&self, channel: usize) -> bool {
+        self.channels[channel].output
+    }
+
+    pub fn is_counting(&self, channel: usize) -> bool {
+        self.channels[channel].counting
+    }
+
+    // Advances all channels, returns true if channel 0 raised its output (IRQ0)
+    pub fn tick(&mut self, ticks: u64) -> bool {
+        let mut irq = false;
+        for _ in 0..ticks {
+            for (i, channel) in self.channels.iter_mut().enumerate() {
+                if channel.tick() && i == 0 {
+                    irq = true;
+                }
+            }
+        }
+        irq
+    }
+}
+
+impl PortBus for Pit {
+    fn read8(&mut self, port: u16) -> u8 {
+        match port {
+            PIT_CONTROL_PORT => 0xff,
+            _ => self.channels[(port - PIT_BASE_PORT) as usize].read(),
+        }
+    }
+
+    fn write8(&mut self, port: u16, value: u8) {
+        match port {
+            PIT_CONTROL_PORT => {
+                let channel = (value >> 6) as usize;
+                // The 8254 read-back command (channel 3) is not supported
+                if channel < 3 {
+                    self.channels[channel].control(value);
+                }
+            }
+            _ => self.channels[(port - PIT_BASE_PORT) as usize].write(value),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use test_case::test_case;
+
+    fn program(pit: &mut Pit, mode: u8, count: u16) {
+        pit.write8(PIT_CONTROL_PORT, 0x30 | mode << 1);
+        let [low, high] = count.to_le_bytes();
+        pit.write8(PIT_BASE_PORT, low);
+        pit.write8(PIT_BASE_PORT, high);
+    }
+
+    // Clocks until the first and second rising edge of channel 0
+    #[test_case(0, 100, 100, 0; "Mode 0 fires once")]
+    #[test_case(2, 100, 100, 100; "Mode 2 rate generator")]
+    #[test_case(3, 100, 100, 100; "Mode 3 square wave")]
+    fn test_irq0_period(mode: u8, count: u16, first: u64, second: u64) {
+        let mut pit = Pit::new();
+        program(&mut pit, mode, count);
+        let edge = |pit: &mut Pit| (1..=1000).find(|_| pit.tick(1)).unwrap_or(0);
+        assert_eq!(edge(&mut pit), first);
+        assert_eq!(edge(&mut pit), second);
+    }
+
+    #[test]
+    fn test_gate_holds_channel_2() {
+        let mut pit = Pit::new();
+        pit.write8(PIT_CONTROL_PORT, 0xb6);
+        pit.write8(PIT_BASE_PORT + 2, 4);
+        pit.write8(PIT_BASE_PORT + 2, 0);
+        pit.tick(10);
+        assert!(pit.output(2));
+        pit.set_gate(2, true);
+        // Square wave of period 4: two clocks high, two clocks low
+        let levels: Vec<bool> = (0..4)
+            .map(|_| {
+                pit.tick(1);
+                pit.output(2)
+            })
+            .collect();
+        assert_eq!(levels, [true, false, false, true]);
+    }
+
+    #[test]
+    fn test_latch() {
+        let mut pit = Pit::new();
+        program(&mut pit, 2, 1000);
+        pit.tick(10);
+        pit.write8(PIT_CONTROL_PORT, 0x00);
+        pit.tick(10);
+        let low = pit.read8(PIT_BASE_PORT);
+        let high = pit.read8(PIT_BASE_PORT);
+        assert_eq!(u16::from_le_bytes([low, high]), 990);
+    }
+}
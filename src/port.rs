@@ -0,0 +1,172 @@
+use std::{
+    cell::RefCell,
+    io::{stdout, Write},
+    ops::RangeInclusive,
+    rc::Rc,
+};
+
+use crate::dump::Dump;
+
+// Bochs and QEMU style debug console
+pub const DEBUG_CONSOLE_PORT: u16 = 0xe9;
+
+// The 8086 I/O space: 64K byte ports, word accesses touch port and port + 1
+pub trait PortBus {
+    fn read8(&mut self, port: u16) -> u8;
+
+    fn write8(&mut self, port: u16, value: u8);
+
+    fn read16(&mut self, port: u16) -> u16 {
+        let low = self.read8(port);
+        let high = self.read8(port.wrapping_add(1));
+        u16::from_le_bytes([low, high])
+    }
+
+    fn write16(&mut self, port: u16, value: u16) {
+        let [low, high] = value.to_le_bytes();
+        self.write8(port, low);
+        self.write8(port.wrapping_add(1), high);
+    }
+}
+
+// Lets the machine keep a handle on a device it also has to poll
+impl<T: PortBus> PortBus for Rc<RefCell<T>> {
+    fn read8(&mut self, port: u16) -> u8 {
+        self.borrow_mut().read8(port)
+    }
+
+    fn write8(&mut self, port: u16, value: u8) {
+        self.borrow_mut().write8(port, value)
+    }
+
+    fn read16(&mut self, port: u16) -> u16 {
+        self.borrow_mut().read16(port)
+    }
+
+    fn write16(&mut self, port: u16, value: u16) {
+        self.borrow_mut().write16(port, value)
+    }
+}
+
+// Routes each access to the device registered for the port
+pub struct PortMap {
+    devices: Vec<(RangeInclusive<u16>, Box<dyn PortBus>)>,
+    dump: Dump,
+}
+
+impl PortMap {
+    pub fn new(debug: bool) -> Self {
+        PortMap {
+            devices: Vec::new(),
+            dump: Dump::new(debug),
+        }
+    }
+
+    pub fn attach(&mut self, ports: RangeInclusive<u16>, device: Box<dyn PortBus>) {
+        if let Some((range, _)) = self
+            .devices
+            .iter()
+            .find(|(range, _)| range.start() <= ports.end() && ports.start() <= range.end())
+        {
+            panic!(
+                "Ports {:04x}-{:04x} overlap {:04x}-{:04x}",
+                ports.start(),
+                ports.end(),
+                range.start(),
+                range.end()
+            );
+        }
+        self.devices.push((ports, device));
+    }
+
+    fn device(&mut self, port: u16) -> Option<&mut Box<dyn PortBus>> {
+        self.devices
+            .iter_mut()
+            .find(|(range, _)| range.contains(&port))
+            .map(|(_, device)| device)
+    }
+}
+
+impl PortBus for PortMap {
+    fn read8(&mut self, port: u16) -> u8 {
+        if let Some(device) = self.device(port) {
+            return device.read8(port);
+        }
+        // Nothing drives the data bus, so it floats high
+        self.dump.port(false, port, 0xff);
+        0xff
+    }
+
+    fn write8(&mut self, port: u16, value: u8) {
+        match self.device(port) {
+            Some(device) => device.write8(port, value),
+            None => self.dump.port(true, port, value as u16),
+        }
+    }
+
+    fn read16(&mut self, port: u16) -> u16 {
+        if let Some(device) = self.device(port) {
+            return device.read16(port);
+        }
+        self.dump.port(false, port, 0xffff);
+        0xffff
+    }
+
+    fn write16(&mut self, port: u16, value: u16) {
+        match self.device(port) {
+            Some(device) => device.write16(port, value),
+            None => self.dump.port(true, port, value),
+        }
+    }
+}
+
+// Copies every byte written to the port to stdout
+pub struct DebugConsole;
+
+impl PortBus for DebugConsole {
+    fn read8(&mut self, _port: u16) -> u8 {
+        // Reading back the port number tells the guest the console is present
+        DEBUG_CONSOLE_PORT as u8
+    }
+
+    fn write8(&mut self, _port: u16, value: u8) {
+        let mut out = stdout();
+        out.write_all(&[value]).expect("Failed to write to stdout");
+        out.flush().expect("Failed to flush stdout");
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use test_case::test_case;
+
+    struct Latch(u8);
+
+    impl PortBus for Latch {
+        fn read8(&mut self, _port: u16) -> u8 {
+            self.0
+        }
+
+        fn write8(&mut self, _port: u16, value: u8) {
+            self.0 = value;
+        }
+    }
+
+    #[test_case(0x60, 0x42; "Mapped")]
+    #[test_case(0x61, 0xff; "Unmapped")]
+    fn test_read8(port: u16, expected: u8) {
+        let mut ports = PortMap::new(false);
+        ports.attach(0x60..=0x60, Box::new(Latch(0x42)));
+        assert_eq!(ports.read8(port), expected);
+    }
+
+    #[test]
+    fn test_write16_splits_bytes() {
+        let mut ports = PortMap::new(false);
+        ports.attach(0x70..=0x71, Box::new(Latch(0)));
+        ports.write16(0x70, 0x1234);
+        // Both bytes land in the same latch, the high byte last
+        assert_eq!(ports.read16(0x70), 0x1212);
+    }
+}
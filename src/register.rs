@@ -56,8 +56,7 @@ impl Register {
                 Register8Bit::DH => self.dh,
                 Register8Bit::BH => self.bh,
             }
-            .try_into()
-            .expect("Invalid byte register conversion"),
+            .into(),
             RegisterType::Segment(seg) => match seg {
                 SegmentRegister::ES => self.es,
                 SegmentRegister::CS => self.cs,
@@ -279,7 +278,10 @@ pub fn effective_address(rm: u8, mod_rm: u8, disp: u16, w: u8) -> String {
             if disp_signed >= 0 {
                 format!("[{base}+{disp_signed:x}]")
             } else {
-                format!("[{base}-{disp_signed:x}]", disp_signed = disp_signed.abs())
+                format!(
+                    "[{base}-{disp_signed:x}]",
+                    disp_signed = disp_signed.unsigned_abs()
+                )
             }
         }
         0b10 => {
@@ -287,7 +289,7 @@ pub fn effective_address(rm: u8, mod_rm: u8, disp: u16, w: u8) -> String {
             if disp_signed >= 0 {
                 format!("[{base}+{disp:x}]", disp = disp_signed)
             } else {
-                format!("[{base}-{disp:x}]", disp = disp_signed.abs())
+                format!("[{base}-{disp:x}]", disp = disp_signed.unsigned_abs())
             }
         }
         0b11 => format!("{reg}", reg = RegisterType::new(rm, w)),
@@ -306,14 +308,11 @@ pub fn calc_relative_disp(offset: usize, disp: u16, is_2byte_disp: bool) -> u16
     } else {
         (disp as i8).into()
     };
-    if signed_disp >= 0 {
-        offset + signed_disp as u16
-    } else {
-        offset - signed_disp.abs() as u16
-    }
+    // IP wraps around within the code segment
+    offset.wrapping_add_signed(signed_disp)
 }
 
-#[derive(Debug, Clone, Copy)]
+#[derive(Debug, Clone, Copy, PartialEq)]
 pub enum SegmentRegister {
     ES = 0,
     CS = 1,
@@ -352,9 +351,11 @@ mod tests {
 
     #[test_case(0b000, 0b11, 0, 0, "AL" ; "REG 1")]
     #[test_case(0b001, 0b11, 0, 1, "CX" ; "REG 2")]
-    #[test_case(0b100, 0b00, 0, 0, "SI"; "No disp 1")]
+    #[test_case(0b100, 0b00, 0, 0, "[SI]"; "No disp 1")]
     #[test_case(0b000, 0b00, 0, 0, "[BX+SI]"; "No disp 2")]
     #[test_case(0b110, 0b01, 0xee, 0, "[BP-12]" ; "Sign-extended disp")]
+    #[test_case(0b110, 0b01, 0x80, 0, "[BP-80]" ; "Most negative disp")]
+    #[test_case(0b111, 0b10, 0x8000, 0, "[BX-8000]" ; "Most negative word disp")]
     #[test_case(0b110, 0b10, 0x0f, 0, "[BP+f]" ; "r/m + Disp")]
     #[test_case(0b110, 0b00, 0x0f, 0, "[000f]" ; "only Disp")]
     #[test_case(0b110, 0b01, 0x04, 0, "[BP+4]" ; "mod=0b01")]
@@ -0,0 +1,156 @@
+use std::{cell::RefCell, fs, rc::Rc};
+
+use crate::{pit::Pit, port::PortBus};
+
+pub const SYSTEM_CONTROL_PORT: u16 = 0x61;
+
+// Port 0x61 bits
+const TIMER_2_GATE: u8 = 0x01;
+const SPEAKER_DATA: u8 = 0x02;
+const REFRESH_TOGGLE: u8 = 0x10;
+const TIMER_2_OUTPUT: u8 = 0x20;
+
+const PIT_HZ: u64 = 1_193_182;
+const SAMPLE_RATE: u64 = 44_100;
+// 8-bit unsigned PCM levels for the speaker cone in and out
+const LOW: u8 = 0x40;
+const HIGH: u8 = 0xc0;
+
+// Port 0x61: the PIT channel 2 gate and the speaker enable, with the speaker
+// output optionally captured to a WAV file
+pub struct Speaker {
+    pit: Rc<RefCell<Pit>>,
+    control: u8,
+    refresh: bool,
+    capture: Option<String>,
+    samples: Vec<u8>,
+    // PIT clocks since reset
+    ticks: u64,
+}
+
+impl Speaker {
+    pub fn new(pit: Rc<RefCell<Pit>>) -> Self {
+        Speaker {
+            pit,
+            control: 0,
+            refresh: false,
+            capture: None,
+            samples: Vec::new(),
+            ticks: 0,
+        }
+    }
+
+    pub fn capture(&mut self, path: &str) {
+        self.capture = Some(path.to_string());
+    }
+
+    // The cone follows channel 2 when both bits are set, or bit 1 alone with the gate off
+    fn level(&self) -> bool {
+        match self.control & (TIMER_2_GATE | SPEAKER_DATA) {
+            0x03 => self.pit.borrow().output(2),
+            SPEAKER_DATA => true,
+            _ => false,
+        }
+    }
+
+    // Samples the current level at every sample point within the next PIT clocks
+    pub fn tick(&mut self, ticks: u64) {
+        if self.capture.is_none() {
+            return;
+        }
+        let before = self.ticks * SAMPLE_RATE / PIT_HZ;
+        self.ticks += ticks;
+        let after = self.ticks * SAMPLE_RATE / PIT_HZ;
+        let sample = if self.level() { HIGH } else { LOW };
+        self.samples
+            .extend(std::iter::repeat(sample).take((after - before) as usize));
+    }
+
+    pub fn finish(&self) {
+        if let Some(path) = &self.capture {
+            fs::write(path, wav(&self.samples))
+                .unwrap_or_else(|e| panic!("Failed to write speaker capture {}: {}", path, e));
+        }
+    }
+}
+
+// Mono 8-bit PCM RIFF file
+fn wav(samples: &[u8]) -> Vec<u8> {
+    let rate = SAMPLE_RATE as u32;
+    let mut out = Vec::with_capacity(44 + samples.len());
+    out.extend_from_slice(b"RIFF");
+    out.extend_from_slice(&(36 + samples.len() as u32).to_le_bytes());
+    out.extend_from_slice(b"WAVEfmt ");
+    out.extend_from_slice(&16u32.to_le_bytes());
+    // PCM, one channel
+    out.extend_from_slice(&1u16.to_le_bytes());
+    out.extend_from_slice(&1u16.to_le_bytes());
+    out.extend_from_slice(&rate.to_le_bytes());
+    // Byte rate, block align and bits per sample
+    out.extend_from_slice(&rate.to_le_bytes());
+    out.extend_from_slice(&1u16.to_le_bytes());
+    out.extend_from_slice(&8u16.to_le_bytes());
+    out.extend_from_slice(b"data");
+    out.extend_from_slice(&(samples.len() as u32).to_le_bytes());
+    out.extend_from_slice(samples);
+    out
+}
+
+impl PortBus for Speaker {
+    fn read8(&mut self, _port: u16) -> u8 {
+        // DRAM refresh flips bit 4 every 15 us; guests poll it for short delays
+        self.refresh = !self.refresh;
+        let refresh = if self.refresh { REFRESH_TOGGLE } else { 0 };
+        let output = if self.pit.borrow().output(2) {
+            TIMER_2_OUTPUT
+        } else {
+            0
+        };
+        self.control & 0x0f | refresh | output
+    }
+
+    fn write8(&mut self, _port: u16, value: u8) {
+        self.control = value;
+        self.pit.borrow_mut().set_gate(2, value & TIMER_2_GATE != 0);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::pit::{PIT_BASE_PORT, PIT_CONTROL_PORT};
+
+    #[test]
+    fn test_square_wave_capture() {
+        let pit = Rc::new(RefCell::new(Pit::new()));
+        let mut speaker = Speaker::new(pit.clone());
+        speaker.capture("unused.wav");
+        // About 1 kHz: 1193 clocks per period
+        let mut p = pit.borrow_mut();
+        p.write8(PIT_CONTROL_PORT, 0xb6);
+        p.write8(PIT_BASE_PORT + 2, 0xa9);
+        p.write8(PIT_BASE_PORT + 2, 0x04);
+        drop(p);
+        speaker.write8(SYSTEM_CONTROL_PORT, TIMER_2_GATE | SPEAKER_DATA);
+        for _ in 0..PIT_HZ / 100 {
+            pit.borrow_mut().tick(1);
+            speaker.tick(1);
+        }
+        // Just under 10 ms at 44.1 kHz, and 10 cycles each starting high
+        assert_eq!(speaker.samples.len(), 440);
+        let rising = speaker
+            .samples
+            .windows(2)
+            .filter(|pair| pair[0] == LOW && pair[1] == HIGH)
+            .count();
+        assert_eq!(rising, 9);
+    }
+
+    #[test]
+    fn test_wav_header() {
+        let file = wav(&[LOW, HIGH]);
+        assert_eq!(&file[0..4], b"RIFF");
+        assert_eq!(u32::from_le_bytes(file[40..44].try_into().unwrap()), 2);
+        assert_eq!(file.len(), 46);
+    }
+}
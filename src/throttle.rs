@@ -0,0 +1,72 @@
+use std::{
+    thread,
+    time::{Duration, Instant},
+};
+
+// The host catches up with emulated time every 10 ms
+const SLICES_PER_SECOND: u64 = 100;
+
+// Paces the CPU clock against the host clock. Unthrottled it only measures
+// the effective speed; throttled it sleeps whenever emulation runs ahead.
+pub struct Throttle {
+    hz: u64,
+    limit: bool,
+    start: Instant,
+    // CPU clock at which the next slice ends
+    next: u64,
+}
+
+impl Throttle {
+    pub fn new(hz: u64, limit: bool) -> Self {
+        Throttle {
+            hz,
+            limit,
+            start: Instant::now(),
+            next: 0,
+        }
+    }
+
+    // Starts the host clock at CPU clock 0
+    pub fn start(&mut self) {
+        self.start = Instant::now();
+        self.next = self.hz / SLICES_PER_SECOND;
+    }
+
+    pub fn advance(&mut self, clock: u64) {
+        if !self.limit || clock < self.next {
+            return;
+        }
+        let due = Duration::from_nanos((clock as u128 * 1_000_000_000 / self.hz as u128) as u64);
+        let elapsed = self.start.elapsed();
+        if due > elapsed {
+            thread::sleep(due - elapsed);
+        }
+        self.next = clock + self.hz / SLICES_PER_SECOND;
+    }
+
+    // CPU clocks per host second so far, in MHz
+    pub fn effective_mhz(&self, clock: u64) -> f64 {
+        let seconds = self.start.elapsed().as_secs_f64();
+        if seconds == 0.0 {
+            return 0.0;
+        }
+        clock as f64 / seconds / 1_000_000.0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_throttled() {
+        // 50 ms at 1 MHz
+        let mut throttle = Throttle::new(1_000_000, true);
+        throttle.start();
+        for clock in (0..=50_000).step_by(100) {
+            throttle.advance(clock);
+        }
+        assert!(throttle.start.elapsed() >= Duration::from_millis(50));
+        assert!(throttle.effective_mhz(50_000) <= 1.0);
+    }
+}
@@ -0,0 +1,320 @@
+use crate::{
+    cpu::Cpu,
+    opcode,
+    operation::{OperandType, Operation, OperationType},
+};
+
+// Clocks the 8088 spends on each word it moves over its 8-bit bus
+const BYTE_BUS_PENALTY: u64 = 4;
+
+// What the machine saw while executing an operation
+pub struct Execution {
+    // Iterations of a REP, or CX before the operation for shifts by CL
+    pub count: u16,
+    // A conditional jump, LOOP or INTO transferred control
+    pub taken: bool,
+    pub segment_override: bool,
+}
+
+// Clocks of an executed operation, from the 8086/8088 instruction timing
+// tables. The 80186 and V20/V30 models are timed the same way, with 80186
+// clocks for the instructions the 8086 does not have. Words are assumed to be
+// aligned, and MUL/DIV take the middle of their data dependent range.
+pub fn clocks(op: &Operation, cpu: Cpu, execution: &Execution) -> u64 {
+    let memory = opcode::lookup(op.raws[0], cpu).has_mod_rm() && op.mod_rm != 0b11;
+    let (base, transfers) = base_clocks(op, cpu, memory, execution);
+    let mut clocks = base;
+    if memory {
+        clocks += ea_clocks(op);
+        if execution.segment_override {
+            clocks += 2;
+        }
+    }
+    if cpu.has_byte_bus() {
+        clocks += BYTE_BUS_PENALTY * transfers;
+    }
+    clocks
+}
+
+// Effective address calculation by addressing mode
+fn ea_clocks(op: &Operation) -> u64 {
+    match (op.mod_rm, op.rm) {
+        // Displacement only
+        (0b00, 0b110) => 6,
+        // Base or index only
+        (0b00, 0b100..=0b111) => 5,
+        // BX+SI, BP+DI
+        (0b00, 0b000 | 0b011) => 7,
+        // BX+DI, BP+SI
+        (0b00, _) => 8,
+        // Displacement + base or index
+        (_, 0b100..=0b111) => 9,
+        // Displacement + base + index
+        (_, 0b000 | 0b011) => 11,
+        _ => 12,
+    }
+}
+
+// Clocks without the effective address, and the number of word transfers
+fn base_clocks(op: &Operation, cpu: Cpu, memory: bool, execution: &Execution) -> (u64, u64) {
+    // Byte operations move no words
+    let w = op.w as u64;
+    let n = execution.count as u64;
+    let jump = |taken, not_taken| {
+        if execution.taken {
+            (taken, 0)
+        } else {
+            (not_taken, 0)
+        }
+    };
+    match op.operation_type {
+        OperationType::Mov => match op.raws[0] {
+            0xa0..=0xa3 => (10, w),
+            0xb0..=0xbf => (4, 0),
+            0xc6 | 0xc7 if memory => (10, w),
+            0xc6 | 0xc7 => (4, 0),
+            0x8c if memory => (9, 1),
+            0x8e if memory => (8, 1),
+            _ if !memory => (2, 0),
+            _ if op.first == OperandType::EA => (9, w),
+            _ => (8, w),
+        },
+        OperationType::Push => match op.first {
+            OperandType::Reg => (11, 1),
+            OperandType::SegReg | OperandType::Imm => (10, 1),
+            _ if memory => (16, 2),
+            _ => (11, 1),
+        },
+        OperationType::Pop if memory => (17, 2),
+        OperationType::Pop => (8, 1),
+        OperationType::Pushf => (10, 1),
+        OperationType::Popf => (8, 1),
+        OperationType::Pusha => (36, 8),
+        OperationType::Popa => (51, 8),
+        OperationType::Xchg => match op.raws[0] {
+            0x90..=0x97 => (3, 0),
+            _ if memory => (17, 2 * w),
+            _ => (4, 0),
+        },
+        // Bit 3 selects the DX forms
+        OperationType::In | OperationType::Out => {
+            if op.raws[0] & 0b1000 != 0 {
+                (8, w)
+            } else {
+                (10, w)
+            }
+        }
+        OperationType::Xlat => (11, 0),
+        OperationType::Lea => (2, 0),
+        OperationType::Lds | OperationType::Les => (16, 2),
+        OperationType::Lahf | OperationType::Sahf => (4, 0),
+        OperationType::Add
+        | OperationType::Adc
+        | OperationType::Sub
+        | OperationType::Sbb
+        | OperationType::And
+        | OperationType::Or
+        | OperationType::Xor
+        | OperationType::Cmp
+        | OperationType::Test => {
+            // CMP and TEST only read their destination
+            let writes = !matches!(op.operation_type, OperationType::Cmp | OperationType::Test);
+            match (op.second, memory) {
+                (OperandType::Imm, _) if op.first == OperandType::Reg => (4, 0),
+                (OperandType::Imm, false) if op.operation_type == OperationType::Test => (5, 0),
+                (OperandType::Imm, false) => (4, 0),
+                (OperandType::Imm, true) if writes => (17, 2 * w),
+                (OperandType::Imm, true) if op.operation_type == OperationType::Test => (11, w),
+                (OperandType::Imm, true) => (10, w),
+                (_, false) => (3, 0),
+                _ if writes && op.first == OperandType::EA => (16, 2 * w),
+                _ => (9, w),
+            }
+        }
+        OperationType::Inc | OperationType::Dec => match op.raws[0] {
+            0x40..=0x4f => (2, 0),
+            _ if memory => (15, 2 * w),
+            _ => (3 - w, 0),
+        },
+        OperationType::Neg | OperationType::Not => {
+            if memory {
+                (16, 2 * w)
+            } else {
+                (3, 0)
+            }
+        }
+        OperationType::Aaa | OperationType::Aas | OperationType::Daa | OperationType::Das => (4, 0),
+        OperationType::Aad => (60, 0),
+        OperationType::Aam => (83, 0),
+        OperationType::Cbw => (2, 0),
+        OperationType::Cwd => (5, 0),
+        OperationType::Salc => (3, 0),
+        OperationType::Imul if op.first == OperandType::Reg => (if memory { 27 } else { 24 }, w),
+        OperationType::Mul | OperationType::Imul | OperationType::Div | OperationType::Idiv => {
+            let clocks = match (op.operation_type, op.w) {
+                (OperationType::Mul, 0) => 73,
+                (OperationType::Mul, _) => 125,
+                (OperationType::Imul, 0) => 89,
+                (OperationType::Imul, _) => 141,
+                (OperationType::Div, 0) => 85,
+                (OperationType::Div, _) => 153,
+                (OperationType::Idiv, 0) => 106,
+                _ => 174,
+            };
+            if memory {
+                (clocks + 6, w)
+            } else {
+                (clocks, 0)
+            }
+        }
+        OperationType::ShlSal
+        | OperationType::Shr
+        | OperationType::Sar
+        | OperationType::Rol
+        | OperationType::Ror
+        | OperationType::Rcl
+        | OperationType::Rcr => {
+            let count = if cpu.masks_shift_count() {
+                n & 0x1f
+            } else {
+                n & 0xff
+            };
+            match (op.raws[0] & 0xfe, op.v, memory) {
+                (0xc0, _, false) => (5 + (op.data as u64 & 0x1f), 0),
+                (0xc0, _, true) => (17 + (op.data as u64 & 0x1f), 2 * w),
+                (_, 0, false) => (2, 0),
+                (_, 0, true) => (15, 2 * w),
+                (_, _, false) => (8 + 4 * count, 0),
+                (_, _, true) => (20 + 4 * count, 2 * w),
+            }
+        }
+        OperationType::Rep => match op.rep_operation_type {
+            OperationType::Movs => (9 + 17 * n, 2 * w * n),
+            OperationType::Cmps => (9 + 22 * n, 2 * w * n),
+            OperationType::Scas => (9 + 15 * n, w * n),
+            OperationType::Lods => (9 + 13 * n, w * n),
+            OperationType::Stos => (9 + 10 * n, w * n),
+            _ => (8 + 8 * n, w * n),
+        },
+        OperationType::Movs => (18, 2 * w),
+        OperationType::Cmps => (22, 2 * w),
+        OperationType::Scas => (15, w),
+        OperationType::Lods => (12, w),
+        OperationType::Stos => (11, w),
+        OperationType::Ins | OperationType::Outs => (14, w),
+        OperationType::Call => match op.raws[0] {
+            0xe8 => (19, 1),
+            0x9a => (28, 2),
+            _ if op.is_far() => (37, 4),
+            _ if memory => (21, 2),
+            _ => (16, 1),
+        },
+        OperationType::Jmp => match op.raws[0] {
+            0xe9..=0xeb => (15, 0),
+            _ if op.is_far() => (24, 2),
+            _ if memory => (18, 1),
+            _ => (11, 0),
+        },
+        OperationType::Ret => match (op.is_far(), op.first) {
+            (true, OperandType::Imm) => (17, 2),
+            (true, _) => (18, 2),
+            (false, OperandType::Imm) => (12, 1),
+            (false, _) => (8, 1),
+        },
+        OperationType::JeJz
+        | OperationType::JlJnge
+        | OperationType::JleJng
+        | OperationType::JbJnae
+        | OperationType::JbeJna
+        | OperationType::JpJpe
+        | OperationType::Jo
+        | OperationType::Js
+        | OperationType::JneJnz
+        | OperationType::JnlJge
+        | OperationType::JnleJg
+        | OperationType::JnbJae
+        | OperationType::JnbeJa
+        | OperationType::JnpJpo
+        | OperationType::Jno
+        | OperationType::Jns => jump(16, 4),
+        OperationType::Jcxz => jump(18, 6),
+        OperationType::Loop => jump(17, 5),
+        OperationType::LoopzLoope => jump(18, 6),
+        OperationType::LoopnzLoopne => jump(19, 5),
+        OperationType::Int if op.raws[0] == 0xcc => (52, 5),
+        OperationType::Int => (51, 5),
+        OperationType::Into if execution.taken => (53, 5),
+        OperationType::Into => (4, 0),
+        OperationType::Iret => (24, 3),
+        // Level in disp: 15 clocks at level 0, 25 at level 1, then 16 per level
+        OperationType::Enter => match op.disp & 0x1f {
+            0 => (15, 1),
+            1 => (25, 3),
+            level => (22 + 16 * (level as u64 - 1), 2 * level as u64 + 1),
+        },
+        OperationType::Leave => (8, 1),
+        OperationType::Bound => (34, 2),
+        OperationType::Clc
+        | OperationType::Cmc
+        | OperationType::Stc
+        | OperationType::Cld
+        | OperationType::Std
+        | OperationType::Cli
+        | OperationType::Sti
+        | OperationType::Hlt
+        | OperationType::Lock
+        | OperationType::Segment => (2, 0),
+        OperationType::Wait => (3, 0),
+        OperationType::Esc if memory => (8, 1),
+        OperationType::Esc => (2, 0),
+        // Only reached when the 80186 traps it to INT 6
+        OperationType::Undefined => (51, 5),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::operation::Raws;
+    use test_case::test_case;
+
+    fn operation(raws: &[u8], operation_type: OperationType) -> Operation {
+        let mut op = Operation::new();
+        op.raws = Raws::from(raws);
+        op.operation_type = operation_type;
+        op
+    }
+
+    #[test_case(0b00, 0b110, 6 ; "direct")]
+    #[test_case(0b00, 0b111, 5 ; "base")]
+    #[test_case(0b00, 0b000, 7 ; "bx si")]
+    #[test_case(0b00, 0b001, 8 ; "bx di")]
+    #[test_case(0b01, 0b110, 9 ; "bp disp")]
+    #[test_case(0b10, 0b011, 11 ; "bp di disp")]
+    #[test_case(0b01, 0b010, 12 ; "bp si disp")]
+    fn test_ea_clocks(mod_rm: u8, rm: u8, clocks: u64) {
+        let mut op = operation(&[0x8b, mod_rm << 6 | rm], OperationType::Mov);
+        op.set_mod_reg_rm(mod_rm << 6 | rm);
+        op.first = OperandType::Reg;
+        op.second = OperandType::EA;
+        let execution = Execution {
+            count: 0,
+            taken: false,
+            segment_override: false,
+        };
+        assert_eq!(super::clocks(&op, Cpu::I8086, &execution), 8 + clocks);
+    }
+
+    #[test_case(Cpu::I8086, 9 + 17 * 10 ; "8086")]
+    #[test_case(Cpu::I8088, 9 + 25 * 10 ; "8088")]
+    fn test_rep_movsw(cpu: Cpu, clocks: u64) {
+        let mut op = operation(&[0xf3, 0xa5], OperationType::Rep);
+        op.rep_operation_type = OperationType::Movs;
+        let execution = Execution {
+            count: 10,
+            taken: false,
+            segment_override: false,
+        };
+        assert_eq!(super::clocks(&op, cpu, &execution), clocks);
+    }
+}
@@ -0,0 +1,271 @@
+use std::{
+    fs::File,
+    io::{stdout, BufReader, Read, Write},
+    os::unix::net::UnixListener,
+    sync::mpsc::{self, Receiver, TryRecvError},
+};
+
+use crate::port::PortBus;
+
+// COM1 and COM2: base port and IRQ line
+pub const COM_PORTS: [(u16, u8); 2] = [(0x3f8, 4), (0x2f8, 3)];
+
+// Interrupt enable bits
+const IER_RECEIVED: u8 = 0x01;
+const IER_TRANSMIT_EMPTY: u8 = 0x02;
+
+// Interrupt identification values, lowest priority last
+const IIR_NONE: u8 = 0x01;
+const IIR_RECEIVED: u8 = 0x04;
+const IIR_TRANSMIT_EMPTY: u8 = 0x02;
+
+const LCR_DLAB: u8 = 0x80;
+const MCR_OUT2: u8 = 0x08;
+const MCR_LOOPBACK: u8 = 0x10;
+const LSR_DATA_READY: u8 = 0x01;
+const LSR_TRANSMIT_EMPTY: u8 = 0x60;
+// CTS, DSR and DCD: a terminal is always attached
+const MSR_CONNECTED: u8 = 0xb0;
+
+// Where the serial line goes on the host
+pub struct Backend {
+    input: Option<Receiver<u8>>,
+    output: Box<dyn Write>,
+}
+
+impl Backend {
+    // `stdio`, `file:PATH` (output only) or `unix:PATH` (waits for one client to connect)
+    pub fn open(spec: &str) -> Result<Self, String> {
+        if spec == "stdio" {
+            return Ok(Backend {
+                input: Some(spawn_reader(std::io::stdin())),
+                output: Box::new(stdout()),
+            });
+        }
+        if let Some(path) = spec.strip_prefix("file:") {
+            let file = File::create(path).map_err(|e| format!("{}: {}", path, e))?;
+            return Ok(Backend {
+                input: None,
+                output: Box::new(file),
+            });
+        }
+        if let Some(path) = spec.strip_prefix("unix:") {
+            // A stale socket file from an earlier run would make bind fail
+            let _ = std::fs::remove_file(path);
+            let listener = UnixListener::bind(path).map_err(|e| format!("{}: {}", path, e))?;
+            eprintln!("Waiting for a connection on {}", path);
+            let (stream, _) = listener.accept().map_err(|e| format!("{}: {}", path, e))?;
+            let reader = stream.try_clone().map_err(|e| format!("{}: {}", path, e))?;
+            return Ok(Backend {
+                input: Some(spawn_reader(reader)),
+                output: Box::new(stream),
+            });
+        }
+        Err(format!("Unknown serial backend: {}", spec))
+    }
+
+    fn receive(&mut self) -> Option<u8> {
+        match self.input.as_ref()?.try_recv() {
+            Ok(byte) => Some(byte),
+            Err(TryRecvError::Empty) => None,
+            Err(TryRecvError::Disconnected) => {
+                self.input = None;
+                None
+            }
+        }
+    }
+
+    fn send(&mut self, byte: u8) {
+        self.output
+            .write_all(&[byte])
+            .and_then(|_| self.output.flush())
+            .expect("Failed to write to the serial backend");
+    }
+}
+
+fn spawn_reader(source: impl Read + Send + 'static) -> Receiver<u8> {
+    let (tx, rx) = mpsc::channel();
+    std::thread::spawn(move || {
+        for byte in BufReader::new(source).bytes() {
+            match byte {
+                Ok(byte) if tx.send(byte).is_ok() => {}
+                _ => break,
+            }
+        }
+    });
+    rx
+}
+
+// National Semiconductor 8250 UART. Characters go out as soon as they are written.
+pub struct Uart {
+    base: u16,
+    backend: Backend,
+    rbr: Option<u8>,
+    ier: u8,
+    lcr: u8,
+    mcr: u8,
+    scratch: u8,
+    divisor: u16,
+    // Transmitter empty interrupt, cleared by reading IIR or writing THR
+    thre_pending: bool,
+    irq_line: bool,
+}
+
+impl Uart {
+    pub fn new(base: u16, backend: Backend) -> Self {
+        Uart {
+            base,
+            backend,
+            rbr: None,
+            ier: 0,
+            lcr: 0,
+            mcr: 0,
+            scratch: 0,
+            divisor: 12,
+            thre_pending: false,
+            irq_line: false,
+        }
+    }
+
+    fn interrupt_id(&self) -> u8 {
+        if self.ier & IER_RECEIVED != 0 && self.rbr.is_some() {
+            IIR_RECEIVED
+        } else if self.ier & IER_TRANSMIT_EMPTY != 0 && self.thre_pending {
+            IIR_TRANSMIT_EMPTY
+        } else {
+            IIR_NONE
+        }
+    }
+
+    // Whether an idle guest could still be woken by received data
+    pub fn may_interrupt(&self) -> bool {
+        self.mcr & MCR_OUT2 != 0
+            && (self.interrupt_id() != IIR_NONE
+                || self.ier & IER_RECEIVED != 0 && self.backend.input.is_some())
+    }
+
+    // Pulls in host input, returns true when the IRQ line rises
+    pub fn poll(&mut self) -> bool {
+        // On the PC, OUT2 gates the UART interrupt onto the bus
+        let line = self.mcr & MCR_OUT2 != 0 && self.interrupt_id() != IIR_NONE;
+        let rising = line && !self.irq_line;
+        self.irq_line = line;
+        // The next character arrives a poll later, so the line drops between characters
+        if self.rbr.is_none() && self.mcr & MCR_LOOPBACK == 0 {
+            self.rbr = self.backend.receive();
+        }
+        rising
+    }
+
+    fn dlab(&self) -> bool {
+        self.lcr & LCR_DLAB != 0
+    }
+}
+
+impl PortBus for Uart {
+    fn read8(&mut self, port: u16) -> u8 {
+        match port - self.base {
+            0 if self.dlab() => self.divisor as u8,
+            0 => self.rbr.take().unwrap_or(0),
+            1 if self.dlab() => (self.divisor >> 8) as u8,
+            1 => self.ier,
+            2 => {
+                let id = self.interrupt_id();
+                if id == IIR_TRANSMIT_EMPTY {
+                    self.thre_pending = false;
+                }
+                id
+            }
+            3 => self.lcr,
+            4 => self.mcr,
+            5 => {
+                let ready = if self.rbr.is_some() {
+                    LSR_DATA_READY
+                } else {
+                    0
+                };
+                LSR_TRANSMIT_EMPTY | ready
+            }
+            6 => MSR_CONNECTED,
+            _ => self.scratch,
+        }
+    }
+
+    fn write8(&mut self, port: u16, value: u8) {
+        match port - self.base {
+            0 if self.dlab() => self.divisor = self.divisor & 0xff00 | value as u16,
+            0 => {
+                if self.mcr & MCR_LOOPBACK != 0 {
+                    self.rbr = Some(value);
+                } else {
+                    self.backend.send(value);
+                }
+                self.thre_pending = true;
+            }
+            1 if self.dlab() => self.divisor = self.divisor & 0x00ff | (value as u16) << 8,
+            1 => {
+                // Enabling the transmitter interrupt with THR empty raises it right away
+                if value & IER_TRANSMIT_EMPTY != 0 && self.ier & IER_TRANSMIT_EMPTY == 0 {
+                    self.thre_pending = true;
+                }
+                self.ier = value & 0x0f;
+            }
+            3 => self.lcr = value,
+            4 => self.mcr = value & 0x1f,
+            7 => self.scratch = value,
+            _ => {
+                // IIR, LSR and MSR are read only
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn uart() -> Uart {
+        let backend = Backend {
+            input: None,
+            output: Box::new(std::io::sink()),
+        };
+        Uart::new(0x3f8, backend)
+    }
+
+    #[test]
+    fn test_loopback_receive_interrupt() {
+        let mut uart = uart();
+        uart.write8(0x3fc, MCR_OUT2 | MCR_LOOPBACK);
+        uart.write8(0x3f9, IER_RECEIVED);
+        assert!(!uart.poll());
+        uart.write8(0x3f8, b'A');
+        assert!(uart.poll());
+        assert!(!uart.poll());
+        assert_eq!(uart.read8(0x3fa), IIR_RECEIVED);
+        assert_eq!(uart.read8(0x3fd) & LSR_DATA_READY, LSR_DATA_READY);
+        assert_eq!(uart.read8(0x3f8), b'A');
+        assert_eq!(uart.read8(0x3fa), IIR_NONE);
+    }
+
+    #[test]
+    fn test_transmit_empty_interrupt() {
+        let mut uart = uart();
+        uart.write8(0x3fc, MCR_OUT2);
+        uart.write8(0x3f9, IER_TRANSMIT_EMPTY);
+        assert!(uart.poll());
+        // Reading IIR acknowledges it
+        assert_eq!(uart.read8(0x3fa), IIR_TRANSMIT_EMPTY);
+        assert_eq!(uart.read8(0x3fa), IIR_NONE);
+    }
+
+    #[test]
+    fn test_divisor_latch() {
+        let mut uart = uart();
+        uart.write8(0x3fb, LCR_DLAB);
+        uart.write8(0x3f8, 0x0c);
+        uart.write8(0x3f9, 0x00);
+        uart.write8(0x3fb, 0x03);
+        assert_eq!(uart.divisor, 12);
+        assert_eq!(uart.read8(0x3fb), 0x03);
+    }
+}
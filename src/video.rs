@@ -0,0 +1,445 @@
+use std::{
+    fs,
+    io::{stdout, Write},
+    ops::{Range, RangeInclusive},
+};
+
+use crate::{machine::CLOCK_HZ, memory::Mmio, port::PortBus};
+
+pub const COLUMNS: usize = 80;
+pub const ROWS: usize = 25;
+
+// CRTC registers
+const CRTC_CURSOR_START: usize = 0x0a;
+const CRTC_START_HIGH: usize = 0x0c;
+const CRTC_START_LOW: usize = 0x0d;
+const CRTC_CURSOR_HIGH: usize = 0x0e;
+const CRTC_CURSOR_LOW: usize = 0x0f;
+
+// Redraw the terminal at most 30 times per emulated second
+const CLOCKS_PER_FRAME: u64 = CLOCK_HZ / 30;
+
+// Code page 437 as shown by the adapter's character ROM
+const CP437: &str = concat!(
+    " ☺☻♥♦♣♠•◘○◙♂♀♪♫☼►◄↕‼¶§▬↨↑↓→←∟↔▲▼",
+    " !\"#$%&'()*+,-./0123456789:;<=>?",
+    "@ABCDEFGHIJKLMNOPQRSTUVWXYZ[\\]^_",
+    "`abcdefghijklmnopqrstuvwxyz{|}~⌂",
+    "ÇüéâäàåçêëèïîìÄÅÉæÆôöòûùÿÖÜ¢£¥₧ƒ",
+    "áíóúñÑªº¿⌐¬½¼¡«»░▒▓│┤╡╢╖╕╣║╗╝╜╛┐",
+    "└┴┬├─┼╞╟╚╔╩╦╠═╬╧╨╤╥╙╘╒╓╫╪┘┌█▄▌▐▀",
+    "αßΓπΣσµτΦΘΩδ∞φε∩≡±≥≤⌠⌡÷≈°∙·√ⁿ²■\u{a0}",
+);
+
+// CGA color order to ANSI color order
+const ANSI_COLOR: [u8; 8] = [0, 4, 2, 6, 1, 5, 3, 7];
+
+const HTML_COLOR: [&str; 16] = [
+    "#000000", "#0000aa", "#00aa00", "#00aaaa", "#aa0000", "#aa00aa", "#aa5500", "#aaaaaa",
+    "#555555", "#5555ff", "#55ff55", "#55ffff", "#ff5555", "#ff55ff", "#ffff55", "#ffffff",
+];
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Adapter {
+    // Color Graphics Adapter in 80x25 text mode
+    Cga,
+    // Monochrome Display Adapter
+    Mda,
+}
+
+impl Adapter {
+    pub fn segment(self) -> u16 {
+        match self {
+            Adapter::Cga => 0xb800,
+            Adapter::Mda => 0xb000,
+        }
+    }
+
+    pub fn memory(self) -> Range<usize> {
+        let base = (self.segment() as usize) << 4;
+        match self {
+            Adapter::Cga => base..base + 0x4000,
+            Adapter::Mda => base..base + 0x1000,
+        }
+    }
+
+    // CRTC index and data, mode control, color select and status
+    pub fn ports(self) -> RangeInclusive<u16> {
+        match self {
+            Adapter::Cga => 0x3d4..=0x3da,
+            Adapter::Mda => 0x3b4..=0x3ba,
+        }
+    }
+
+    pub fn mode(self) -> u8 {
+        match self {
+            Adapter::Cga => 0x03,
+            Adapter::Mda => 0x07,
+        }
+    }
+}
+
+// Text mode video memory and the 6845 CRTC registers that matter for text
+pub struct TextVideo {
+    adapter: Adapter,
+    ram: Vec<u8>,
+    crtc_index: usize,
+    crtc: [u8; 18],
+    mode_control: u8,
+    color_select: u8,
+    retrace: bool,
+    dirty: bool,
+}
+
+impl TextVideo {
+    pub fn new(adapter: Adapter) -> Self {
+        let mut ram = vec![0; adapter.memory().len()];
+        // Blank cells are spaces in light gray on black
+        for cell in ram.chunks_mut(2) {
+            cell.copy_from_slice(&[b' ', 0x07]);
+        }
+        let mut crtc = [0; 18];
+        // Underline cursor on scan lines 6-7
+        crtc[CRTC_CURSOR_START] = 0x06;
+        crtc[CRTC_CURSOR_START + 1] = 0x07;
+        TextVideo {
+            adapter,
+            ram,
+            crtc_index: 0,
+            crtc,
+            mode_control: 0x29,
+            color_select: 0,
+            retrace: false,
+            dirty: true,
+        }
+    }
+
+    pub fn adapter(&self) -> Adapter {
+        self.adapter
+    }
+
+    fn start(&self) -> usize {
+        let start = u16::from_be_bytes([self.crtc[CRTC_START_HIGH], self.crtc[CRTC_START_LOW]]);
+        start as usize * 2
+    }
+
+    fn cell_offset(&self, row: usize, col: usize) -> usize {
+        (self.start() + (row * COLUMNS + col) * 2) % self.ram.len()
+    }
+
+    pub fn cell(&self, row: usize, col: usize) -> (u8, u8) {
+        let offset = self.cell_offset(row, col);
+        (self.ram[offset], self.ram[offset + 1])
+    }
+
+    pub fn set_cell(&mut self, row: usize, col: usize, ch: u8, attr: u8) {
+        let offset = self.cell_offset(row, col);
+        self.ram[offset] = ch;
+        self.ram[offset + 1] = attr;
+        self.dirty = true;
+    }
+
+    // Cursor position as (row, column)
+    pub fn cursor(&self) -> (usize, usize) {
+        let location =
+            u16::from_be_bytes([self.crtc[CRTC_CURSOR_HIGH], self.crtc[CRTC_CURSOR_LOW]]);
+        let index = (location as usize).saturating_sub(self.start() / 2);
+        (index / COLUMNS, index % COLUMNS)
+    }
+
+    pub fn set_cursor(&mut self, row: usize, col: usize) {
+        let location = (self.start() / 2 + row * COLUMNS + col) as u16;
+        [self.crtc[CRTC_CURSOR_HIGH], self.crtc[CRTC_CURSOR_LOW]] = location.to_be_bytes();
+        self.dirty = true;
+    }
+
+    fn cursor_visible(&self) -> bool {
+        // Bits 5-6 of the cursor start register select a hidden cursor
+        self.crtc[CRTC_CURSOR_START] & 0x60 != 0x20
+    }
+
+    // Moves rows top..=bottom of columns left..=right up by `lines` (down when negative),
+    // clearing the vacated rows with `attr`. Zero lines clears the whole window.
+    pub fn scroll(&mut self, lines: i32, window: (usize, usize, usize, usize), attr: u8) {
+        let (top, left, bottom, right) = window;
+        let (bottom, right) = (bottom.min(ROWS - 1), right.min(COLUMNS - 1));
+        let height = (bottom + 1).saturating_sub(top) as i32;
+        let lines = if lines == 0 || lines.abs() >= height {
+            height
+        } else {
+            lines
+        };
+        let rows: Vec<usize> = if lines > 0 {
+            (top..=bottom).collect()
+        } else {
+            (top..=bottom).rev().collect()
+        };
+        for &row in &rows {
+            let source = row as i32 + lines;
+            for col in left..=right {
+                let (ch, a) = if (top as i32..=bottom as i32).contains(&source) {
+                    self.cell(source as usize, col)
+                } else {
+                    (b' ', attr)
+                };
+                self.set_cell(row, col, ch, a);
+            }
+        }
+    }
+
+    fn ansi_style(&self, attr: u8) -> String {
+        match self.adapter {
+            Adapter::Cga => {
+                let fg = attr & 0x0f;
+                let bg = (attr >> 4) & 0x07;
+                let fg_base = if fg & 0x08 != 0 { 90 } else { 30 };
+                format!(
+                    "\x1b[0;{};{}m",
+                    fg_base + ANSI_COLOR[(fg & 0x07) as usize],
+                    40 + ANSI_COLOR[bg as usize]
+                )
+            }
+            Adapter::Mda => {
+                let mut style = String::from("\x1b[0");
+                match attr & 0x77 {
+                    0x00 => style.push_str(";8"),
+                    0x70 => style.push_str(";7"),
+                    a if a & 0x07 == 0x01 => style.push_str(";4"),
+                    _ => {}
+                }
+                if attr & 0x08 != 0 {
+                    style.push_str(";1");
+                }
+                style.push('m');
+                style
+            }
+        }
+    }
+
+    // Redraws the whole screen in place with ANSI escapes
+    pub fn render_ansi(&self, glyphs: &[char]) -> String {
+        let mut out = String::from("\x1b[?25l\x1b[H");
+        for row in 0..ROWS {
+            let mut style = None;
+            for col in 0..COLUMNS {
+                let (ch, attr) = self.cell(row, col);
+                if style != Some(attr) {
+                    out.push_str(&self.ansi_style(attr));
+                    style = Some(attr);
+                }
+                out.push(glyphs[ch as usize]);
+            }
+            out.push_str("\x1b[0m\r\n");
+        }
+        if self.cursor_visible() {
+            let (row, col) = self.cursor();
+            out.push_str(&format!("\x1b[{};{}H\x1b[?25h", row + 1, col + 1));
+        }
+        out
+    }
+
+    pub fn render_text(&self, glyphs: &[char]) -> String {
+        let mut out = String::new();
+        for row in 0..ROWS {
+            let line: String = (0..COLUMNS)
+                .map(|col| glyphs[self.cell(row, col).0 as usize])
+                .collect();
+            out.push_str(line.trim_end());
+            out.push('\n');
+        }
+        out
+    }
+
+    pub fn render_html(&self, glyphs: &[char]) -> String {
+        let mut out = String::from(
+            "<!DOCTYPE html>\n<html>\n<body style=\"background:#000000\">\n<pre style=\"font-family:monospace\">",
+        );
+        for row in 0..ROWS {
+            let mut style = None;
+            for col in 0..COLUMNS {
+                let (ch, attr) = self.cell(row, col);
+                if style != Some(attr) {
+                    if style.is_some() {
+                        out.push_str("</span>");
+                    }
+                    out.push_str(&self.html_style(attr));
+                    style = Some(attr);
+                }
+                match glyphs[ch as usize] {
+                    '<' => out.push_str("&lt;"),
+                    '>' => out.push_str("&gt;"),
+                    '&' => out.push_str("&amp;"),
+                    glyph => out.push(glyph),
+                }
+            }
+            out.push_str("</span>\n");
+        }
+        out.push_str("</pre>\n</body>\n</html>\n");
+        out
+    }
+
+    fn html_style(&self, attr: u8) -> String {
+        let (fg, bg, underline) = match self.adapter {
+            Adapter::Cga => (attr & 0x0f, (attr >> 4) & 0x07, false),
+            Adapter::Mda => match attr & 0x77 {
+                0x00 => (0, 0, false),
+                0x70 => (0, 7, false),
+                a => (7 | (attr & 0x08), 0, a & 0x07 == 0x01),
+            },
+        };
+        let decoration = if underline {
+            ";text-decoration:underline"
+        } else {
+            ""
+        };
+        format!(
+            "<span style=\"color:{};background:{}{}\">",
+            HTML_COLOR[fg as usize], HTML_COLOR[bg as usize], decoration
+        )
+    }
+}
+
+impl Mmio for TextVideo {
+    fn read8(&self, offset: usize) -> u8 {
+        self.ram[offset]
+    }
+
+    fn write8(&mut self, offset: usize, value: u8) {
+        self.ram[offset] = value;
+        self.dirty = true;
+    }
+}
+
+impl PortBus for TextVideo {
+    fn read8(&mut self, port: u16) -> u8 {
+        match port - self.adapter.ports().start() {
+            0 => self.crtc_index as u8,
+            1 => self.crtc.get(self.crtc_index).copied().unwrap_or(0),
+            4 => self.mode_control,
+            5 => self.color_select,
+            6 => {
+                // Alternate between display and retrace so polling loops finish
+                self.retrace = !self.retrace;
+                if self.retrace {
+                    0x09
+                } else {
+                    0x00
+                }
+            }
+            _ => 0xff,
+        }
+    }
+
+    fn write8(&mut self, port: u16, value: u8) {
+        match port - self.adapter.ports().start() {
+            0 => self.crtc_index = value as usize & 0x1f,
+            1 => {
+                if let Some(register) = self.crtc.get_mut(self.crtc_index) {
+                    *register = value;
+                    self.dirty = true;
+                }
+            }
+            4 => self.mode_control = value,
+            5 => self.color_select = value,
+            _ => {}
+        }
+    }
+}
+
+// Shows the screen on the host terminal and saves it when the machine stops
+pub struct Display {
+    glyphs: Vec<char>,
+    live: bool,
+    screen_dump: Option<String>,
+    clocks: u64,
+}
+
+impl Display {
+    pub fn new(live: bool, screen_dump: Option<String>) -> Self {
+        if live {
+            print!("\x1b[2J");
+        }
+        Display {
+            glyphs: CP437.chars().collect(),
+            live,
+            screen_dump,
+            clocks: 0,
+        }
+    }
+
+    pub fn tick(&mut self, video: &mut TextVideo, clocks: u64) {
+        self.clocks += clocks;
+        if self.clocks < CLOCKS_PER_FRAME {
+            return;
+        }
+        self.clocks = 0;
+        if self.live && video.dirty {
+            self.draw(video);
+        }
+    }
+
+    fn draw(&self, video: &mut TextVideo) {
+        video.dirty = false;
+        let mut out = stdout();
+        out.write_all(video.render_ansi(&self.glyphs).as_bytes())
+            .and_then(|_| out.flush())
+            .expect("Failed to write to stdout");
+    }
+
+    pub fn finish(&mut self, video: &mut TextVideo) {
+        if self.live {
+            self.draw(video);
+            // Leave the shell prompt below the screen
+            print!("\x1b[{};1H", ROWS + 1);
+        }
+        if let Some(path) = &self.screen_dump {
+            let screen = if path.ends_with(".html") || path.ends_with(".htm") {
+                video.render_html(&self.glyphs)
+            } else {
+                video.render_text(&self.glyphs)
+            };
+            fs::write(path, screen)
+                .unwrap_or_else(|e| panic!("Failed to write screen dump {}: {}", path, e));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn glyphs() -> Vec<char> {
+        CP437.chars().collect()
+    }
+
+    #[test]
+    fn test_cp437_table() {
+        let glyphs = glyphs();
+        assert_eq!(glyphs.len(), 256);
+        assert_eq!(glyphs[b'A' as usize], 'A');
+        assert_eq!(glyphs[0xdb], '█');
+    }
+
+    #[test]
+    fn test_memory_and_cursor() {
+        let mut video = TextVideo::new(Adapter::Cga);
+        let base = Adapter::Cga.memory().start;
+        for (i, byte) in "Hi".bytes().enumerate() {
+            Mmio::write8(&mut video, (80 + i) * 2, byte);
+        }
+        PortBus::write8(&mut video, 0x3d4, 0x0f);
+        PortBus::write8(&mut video, 0x3d5, 82);
+        assert_eq!(video.cursor(), (1, 2));
+        assert!(video.render_text(&glyphs()).starts_with("\nHi\n"));
+        assert_eq!(base, 0xb8000);
+    }
+
+    #[test]
+    fn test_scroll_up() {
+        let mut video = TextVideo::new(Adapter::Cga);
+        video.set_cell(1, 0, b'x', 0x07);
+        video.scroll(1, (0, 0, ROWS - 1, COLUMNS - 1), 0x07);
+        assert_eq!(video.cell(0, 0), (b'x', 0x07));
+        assert_eq!(video.cell(1, 0), (b' ', 0x07));
+    }
+}